@@ -14,11 +14,15 @@ const TARGET_NAME: &str = "kraken_static";
 fn main() {
     let os = env::consts::OS;
 
-    if os == "linux" {
+    if env::var_os("CARGO_FEATURE_NATIVE_KRAKEN").is_none() {
+        // pure Rust build, nothing to link
+    } else if os == "linux" {
         let link_path = std::env::current_dir().unwrap().join("lib").join(os);
         // link rustc
         println!("cargo:rustc-link-search=native={}", link_path.display());
         println!("cargo:rustc-link-lib-l=static={}", TARGET_NAME);
+        // the prebuilt library is C++ and needs the runtime
+        println!("cargo:rustc-link-lib=dylib=stdc++");
     } else {
         // cmake config
         let mut cfg = Config::new(LIB_NAME);
@@ -51,6 +55,9 @@ fn main() {
     }
 
     // extract resources
+    if env::var_os("CARGO_FEATURE_METADATA_RESOURCES").is_none() {
+        return;
+    }
     let file_path = PathBuf::from("src/metadata-resources.csv");
     if file_path.exists() {
         p!("file exists: {}", file_path.display());
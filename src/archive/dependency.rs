@@ -20,7 +20,6 @@ pub struct Dependency {
 //         Ok(())
 //     }
 // }
-#[warn(dead_code)]
 
 impl FromReader for Dependency {
     fn from_reader<R: Read>(reader: &mut R) -> Result<Self> {
@@ -17,8 +17,6 @@ pub(crate) struct Index {
     resource_dependency_count: u32,
 }
 
-#[warn(dead_code)]
-
 impl Index {
     pub(crate) fn file_entry_count(&self) -> u32 {
         self.file_entry_count
@@ -1,11 +1,14 @@
 use std::{
     cmp::Ordering,
-    io::{Cursor, Error, ErrorKind, Read, Result, Write},
+    io::{Cursor, Error, Read, Result, Write},
 };
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
-use crate::{io::*, kraken::*};
+use crate::{
+    codec::{Codec, KrakenCodec},
+    io::*,
+};
 
 #[derive(Debug, Clone)]
 pub(crate) struct LxrsFooter {
@@ -21,8 +24,8 @@ impl LxrsFooter {
     const MAGIC: u32 = 0x4C585253;
     const VERSION: u32 = 1;
 
-    pub(crate) fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
-        writer.write_u32::<LittleEndian>(self.files.len() as u32)?;
+    pub(crate) fn write<W: Write>(&self, writer: &mut W, codec: &dyn Codec) -> Result<()> {
+        writer.write_u32::<LittleEndian>(LxrsFooter::MAGIC)?;
         writer.write_u32::<LittleEndian>(LxrsFooter::VERSION)?;
 
         // write strings to buffer
@@ -31,16 +34,16 @@ impl LxrsFooter {
             write_null_terminated_string(&mut buffer, f.to_owned())?;
         }
 
-        // compress
-        let size = buffer.len();
-        let compressed_size_needed = get_compressed_buffer_size_needed(size as u64);
-        let mut compressed_buffer = vec![0; compressed_size_needed as usize];
-        let zsize = compress(&buffer, &mut compressed_buffer, CompressionLevel::Normal);
-        assert!((zsize as u32) <= size as u32);
-        compressed_buffer.resize(zsize as usize, 0);
+        // compress, the buffer is stored as is if it doesn't get smaller
+        let compressed_buffer = codec
+            .compress(&buffer)?
+            .filter(|compressed_buffer| compressed_buffer.len() < buffer.len());
+        let data = compressed_buffer.as_ref().unwrap_or(&buffer);
 
-        // write to writer
-        writer.write_all(&compressed_buffer)?;
+        writer.write_u32::<LittleEndian>(buffer.len() as u32)?;
+        writer.write_u32::<LittleEndian>(data.len() as u32)?;
+        writer.write_i32::<LittleEndian>(self.files.len() as i32)?;
+        writer.write_all(data)?;
 
         Ok(())
     }
@@ -48,12 +51,19 @@ impl LxrsFooter {
     pub(crate) fn files(&self) -> &[String] {
         self.files.as_ref()
     }
-}
-impl FromReader for LxrsFooter {
-    fn from_reader<R: Read>(reader: &mut R) -> Result<Self> {
+
+    /// Reads a footer, a compressed string buffer is decompressed with `codec`
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if io or decompression fails.
+    pub(crate) fn from_reader_with_codec<R: Read>(
+        reader: &mut R,
+        codec: &dyn Codec,
+    ) -> Result<Self> {
         let magic = reader.read_u32::<LittleEndian>()?;
         if magic != LxrsFooter::MAGIC {
-            return Err(Error::new(ErrorKind::Other, "invalid magic"));
+            return Err(Error::other("invalid magic"));
         }
         let _version = reader.read_u32::<LittleEndian>()?;
         let size = reader.read_u32::<LittleEndian>()?;
@@ -66,9 +76,7 @@ impl FromReader for LxrsFooter {
                 // buffer is compressed
                 let mut compressed_buffer = vec![0; zsize as usize];
                 reader.read_exact(&mut compressed_buffer[..])?;
-                let mut output_buffer = vec![];
-                let result = decompress(compressed_buffer, &mut output_buffer, size as usize);
-                assert_eq!(result as u32, size);
+                let output_buffer = codec.decompress(&compressed_buffer, size as usize)?;
 
                 // read from buffer
                 let mut inner_cursor = Cursor::new(&output_buffer);
@@ -81,7 +89,7 @@ impl FromReader for LxrsFooter {
             }
             Ordering::Less => {
                 // error
-                return Err(Error::new(ErrorKind::Other, "invalid buffer"));
+                return Err(Error::other("invalid buffer"));
             }
             Ordering::Equal => {
                 // no compression
@@ -99,3 +107,44 @@ impl FromReader for LxrsFooter {
         Ok(footer)
     }
 }
+impl FromReader for LxrsFooter {
+    fn from_reader<R: Read>(reader: &mut R) -> Result<Self> {
+        LxrsFooter::from_reader_with_codec(reader, &KrakenCodec::default())
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// TESTS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::StoredCodec;
+
+    #[test]
+    fn roundtrip() {
+        let short = vec![String::from("base\\a.mesh")];
+        let long = (0..200)
+            .map(|i| format!("base\\characters\\garment\\item_{}.mesh", i))
+            .collect::<Vec<_>>();
+
+        for files in [vec![], short, long.clone()] {
+            let mut buffer = Vec::new();
+            LxrsFooter::new(files.clone())
+                .write(&mut buffer, &KrakenCodec::default())
+                .unwrap();
+            let footer = LxrsFooter::from_reader(&mut Cursor::new(&buffer)).unwrap();
+            assert_eq!(files, footer.files());
+        }
+
+        // stored footers can be read with any codec
+        let mut buffer = Vec::new();
+        LxrsFooter::new(long.clone())
+            .write(&mut buffer, &StoredCodec)
+            .unwrap();
+        let footer =
+            LxrsFooter::from_reader_with_codec(&mut Cursor::new(&buffer), &StoredCodec).unwrap();
+        assert_eq!(long, footer.files());
+    }
+}
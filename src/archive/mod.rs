@@ -14,8 +14,9 @@ use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use strum::IntoEnumIterator;
 use walkdir::WalkDir;
 
-use crate::kraken::*;
-use crate::{cr2w::*, *};
+use crate::codec::{Codec, KrakenCodec};
+use crate::kraken::{self, CompressionLevel};
+use crate::{cr2w::*, hash::ResourceHash, hashdb::HashDb, *};
 use crate::{fnv1a64_hash_string, io::FromReader};
 
 use self::{dependency::*, file_entry::*, file_segment::*, header::*, index::*, lxrs::*};
@@ -44,50 +45,52 @@ mod lxrs;
 
 /// Creates an archive in the specified stream that contains the files and directories from the specified directory.
 ///
+/// Files are compressed with `codec`, or with Kraken at the normal level if none is given.
+///
 /// # Errors
 ///
 /// This function will return an error if any io fails.
 pub fn create_from_directory<P, W>(
     source_directory_name: &P,
     destination: W,
-    hash_map: Option<HashMap<u64, String>>,
+    hash_db: Option<&HashDb>,
+    codec: Option<&dyn Codec>,
 ) -> Result<()>
 where
     P: AsRef<Path>,
     W: Write + Seek,
 {
-    let map = if let Some(hash_map) = hash_map {
-        hash_map
-    } else {
-        get_red4_hashes()
-    };
+    let hash_db = hash_db.unwrap_or_else(|| HashDb::global());
+    let default_codec = KrakenCodec::default();
+    let codec = codec.unwrap_or(&default_codec);
 
-    write_archive(source_directory_name, destination, map)
+    write_archive(source_directory_name, destination, hash_db, codec)
 }
 
 // public static void CreateFromDirectory (string sourceDirectoryName, string destinationArchiveFileName);
 
 /// Creates an archive that contains the files and directories from the specified directory.
 ///
+/// Files are compressed with `codec`, or with Kraken at the normal level if none is given.
+///
 /// # Errors
 ///
 /// This function will return an error if any io fails.
 pub fn create_from_directory_path<P>(
     source_directory_name: &P,
     destination: &P,
-    hash_map: Option<HashMap<u64, String>>,
+    hash_db: Option<&HashDb>,
+    codec: Option<&dyn Codec>,
 ) -> Result<()>
 where
     P: AsRef<Path>,
 {
-    let map = if let Some(hash_map) = hash_map {
-        hash_map
-    } else {
-        get_red4_hashes()
-    };
+    let hash_db = hash_db.unwrap_or_else(|| HashDb::global());
+    let default_codec = KrakenCodec::default();
+    let codec = codec.unwrap_or(&default_codec);
 
     let fs: File = File::create(destination)?;
-    write_archive(source_directory_name, fs, map)
+    write_archive(source_directory_name, fs, hash_db, codec)
 }
 
 // public static void ExtractToDirectory (System.IO.Stream source, string destinationDirectoryName, bool overwriteFiles);
@@ -101,14 +104,14 @@ pub fn extract_to_directory<R, P>(
     source: &mut R,
     destination_directory_name: &P,
     overwrite_files: bool,
-    hash_map: Option<HashMap<u64, String>>,
+    hash_db: Option<&HashDb>,
 ) -> Result<()>
 where
     P: AsRef<Path>,
     R: Read + Seek + 'static,
 {
-    let mut archive = ZipArchive::from_reader_consume(source, ArchiveMode::Read)?;
-    archive.extract_to_directory(destination_directory_name, overwrite_files, hash_map)
+    let mut archive = open_read_stream(source)?;
+    archive.extract_to_directory(destination_directory_name, overwrite_files, hash_db)
 }
 
 // public static void ExtractToDirectory (string sourceArchiveFileName, string destinationDirectoryName, bool overwriteFiles);
@@ -122,14 +125,14 @@ pub fn extract_to_directory_path<P, R>(
     source_archive_file_name: &P,
     destination_directory_name: &P,
     overwrite_files: bool,
-    hash_map: Option<HashMap<u64, String>>,
+    hash_db: Option<&HashDb>,
 ) -> Result<()>
 where
     P: AsRef<Path>,
     R: Read + Seek,
 {
     let mut archive = open_read(source_archive_file_name)?;
-    archive.extract_to_directory(destination_directory_name, overwrite_files, hash_map)
+    archive.extract_to_directory(destination_directory_name, overwrite_files, hash_db)
 }
 
 // public static System.IO.Compression.ZipArchive Open (string archiveFileName, System.IO.Compression.ZipArchiveMode mode);
@@ -143,17 +146,27 @@ pub fn open<P>(archive_file_name: P, mode: ArchiveMode) -> Result<ZipArchive<Fil
 where
     P: AsRef<Path>,
 {
-    match mode {
-        ArchiveMode::Create => {
-            let file = File::create(archive_file_name)?;
-            ZipArchive::from_reader_consume(file, mode)
-        }
-        ArchiveMode::Read => open_read(archive_file_name),
-        ArchiveMode::Update => {
-            let file = File::open(archive_file_name)?;
-            ZipArchive::from_reader_consume(file, mode)
-        }
-    }
+    open_with_codec(archive_file_name, mode, Box::<KrakenCodec>::default())
+}
+
+/// Opens an archive at the specified path and in the specified mode, segments are compressed and decompressed with `codec`.
+///
+/// # Errors
+///
+/// This function will return an error if any io fails.
+pub fn open_with_codec<P>(
+    archive_file_name: P,
+    mode: ArchiveMode,
+    codec: Box<dyn Codec>,
+) -> Result<ZipArchive<File>>
+where
+    P: AsRef<Path>,
+{
+    let file = match mode {
+        ArchiveMode::Create => File::create(archive_file_name)?,
+        ArchiveMode::Read | ArchiveMode::Update => File::open(archive_file_name)?,
+    };
+    ZipArchive::from_reader_consume(file, mode, codec)
 }
 
 // public static System.IO.Compression.ZipArchive OpenRead (string archiveFileName);
@@ -167,8 +180,70 @@ pub fn open_read<P>(archive_file_name: P) -> Result<ZipArchive<File>>
 where
     P: AsRef<Path>,
 {
-    let file = File::open(archive_file_name)?;
-    ZipArchive::from_reader_consume(file, ArchiveMode::Read)
+    open_with_codec(
+        archive_file_name,
+        ArchiveMode::Read,
+        Box::<KrakenCodec>::default(),
+    )
+}
+
+// public ZipArchive (System.IO.Stream stream);
+
+/// Opens an archive for reading from the specified stream.
+///
+/// # Errors
+///
+/// This function will return an error if any io fails.
+pub fn open_read_stream<R>(stream: R) -> Result<ZipArchive<R>>
+where
+    R: Read + Seek,
+{
+    open_read_stream_with_codec(stream, Box::<KrakenCodec>::default())
+}
+
+/// Opens an archive for reading from the specified stream, segments are decompressed with `codec`.
+///
+/// # Errors
+///
+/// This function will return an error if any io fails.
+pub fn open_read_stream_with_codec<R>(stream: R, codec: Box<dyn Codec>) -> Result<ZipArchive<R>>
+where
+    R: Read + Seek,
+{
+    ZipArchive::from_reader_consume(stream, ArchiveMode::Read, codec)
+}
+
+/// Gets all vanilla game archives (archive/pc/content and archive/pc/ep1) in load order.
+pub fn get_game_archives<P: AsRef<Path>>(game_folder: &P) -> Vec<PathBuf> {
+    let archive_folder = game_folder.as_ref().join("archive").join("pc");
+    let mut archives = crate::redmod::get_archives_in_folder(&archive_folder.join("content"));
+    archives.extend(crate::redmod::get_archives_in_folder(
+        &archive_folder.join("ep1"),
+    ));
+    archives
+}
+
+/// Gets all mod archives of a game folder in load order.
+///
+/// Archives in archive/pc/mod are loaded first, followed by the archives of all REDmods in mods/
+/// in REDmod load order.
+///
+/// # Errors
+///
+/// This function will return an error if the REDmod load order can't be read.
+pub fn get_mod_archives<P: AsRef<Path>>(game_folder: &P) -> Result<Vec<PathBuf>> {
+    let mut archives = crate::redmod::get_archives_in_folder(
+        &game_folder.as_ref().join("archive").join("pc").join("mod"),
+    );
+
+    let mods_folder = game_folder.as_ref().join(crate::redmod::MODS_FOLDER_NAME);
+    if mods_folder.exists() {
+        for redmod in crate::redmod::get_mods(&mods_folder)? {
+            archives.extend(redmod.archives());
+        }
+    }
+
+    Ok(archives)
 }
 
 /// Packs redengine 4 resource file in a folder to an archive
@@ -180,7 +255,12 @@ where
 /// # Errors
 ///
 /// This function will return an error if any parsing or IO fails
-fn write_archive<P, W>(in_folder: &P, out_stream: W, hash_map: HashMap<u64, String>) -> Result<()>
+fn write_archive<P, W>(
+    in_folder: &P,
+    out_stream: W,
+    hash_db: &HashDb,
+    codec: &dyn Codec,
+) -> Result<()>
 where
     P: AsRef<Path>,
     W: Write + Seek,
@@ -211,7 +291,7 @@ where
 
     let custom_paths = file_info
         .iter()
-        .filter(|(_p, k)| hash_map.contains_key(k))
+        .filter(|(_p, k)| hash_db.contains(k))
         .filter_map(|(f, _h)| {
             if let Ok(path) = f.strip_prefix(in_folder) {
                 return Some(path.to_string_lossy().to_string());
@@ -220,6 +300,48 @@ where
         })
         .collect::<Vec<_>>();
 
+    let paths = file_info
+        .iter()
+        .map(|(path, hash)| (*hash, path.clone()))
+        .collect::<HashMap<_, _>>();
+    let resources = file_info
+        .iter()
+        .map(|(path, hash)| {
+            let extension = path
+                .extension()
+                .map(|e| e.to_string_lossy().to_string())
+                .unwrap_or_default();
+            (*hash, extension)
+        })
+        .collect::<Vec<_>>();
+
+    write_resources(out_stream, &resources, custom_paths, codec, |hash| {
+        std::fs::read(&paths[&hash])
+    })
+}
+
+/// Packs resources to an archive, the bytes of a resource are read by hash when it is written
+///
+/// `resources` are hashes and file extensions, the extension decides about alignment and compression
+/// of non-cr2w files. `custom_paths` are written to the LXRS table.
+///
+/// # Errors
+///
+/// This function will return an error if reading a resource or any io fails
+pub(crate) fn write_resources<W, F>(
+    out_stream: W,
+    resources: &[(u64, String)],
+    custom_paths: Vec<String>,
+    codec: &dyn Codec,
+    mut read: F,
+) -> Result<()>
+where
+    W: Write + Seek,
+    F: FnMut(u64) -> Result<Vec<u8>>,
+{
+    let mut resources = resources.to_vec();
+    resources.sort_by_key(|(hash, _)| *hash);
+
     // start write
 
     let mut archive_writer = BufWriter::new(out_stream);
@@ -232,22 +354,25 @@ where
     let mut custom_data_length = 0;
     if !custom_paths.is_empty() {
         let wfooter = LxrsFooter::new(custom_paths);
-        wfooter.write(&mut archive_writer)?;
+        wfooter.write(&mut archive_writer, codec)?;
         custom_data_length = archive_writer.stream_position()? - Header::HEADER_EXTENDED_SIZE;
     }
 
     // write files
     //let imports_hash_set: HashSet<String> = HashSet::new();
     let mut entries = HashMap::default();
-    for (path, hash) in file_info {
-        let wrapped_entry = make_entry(path, &mut archive_writer, hash)?;
+    for (hash, extension) in resources {
+        let file_buffer = read(hash)?;
+        let wrapped_entry = make_entry(&file_buffer, &extension, &mut archive_writer, hash, codec)?;
 
         entries.insert(hash, wrapped_entry);
     }
 
-    // run through entries again and enumerate the segments
+    // run through entries again and enumerate the segments in the order of the index
     let mut file_segments_cnt = 0;
-    for (_hash, entry) in entries.iter_mut() {
+    let mut sorted_entries = entries.values_mut().collect::<Vec<_>>();
+    sorted_entries.sort_by_key(|e| e.hash);
+    for entry in sorted_entries {
         let firstoffsetidx = file_segments_cnt;
         file_segments_cnt += entry.buffers.len() + 1;
         let lastoffsetidx = file_segments_cnt;
@@ -283,14 +408,13 @@ where
 }
 
 fn make_entry<W: Write + Seek>(
-    path: PathBuf,
+    file_buffer: &[u8],
+    extension: &str,
     archive_writer: &mut BufWriter<W>,
     hash: u64,
+    codec: &dyn Codec,
 ) -> Result<ZipEntry> {
-    let mut file = File::open(&path)?;
-    let mut file_buffer = Vec::new();
-    file.read_to_end(&mut file_buffer)?;
-    let mut file_cursor = Cursor::new(&file_buffer);
+    let mut file_cursor = Cursor::new(file_buffer);
 
     let mut flags = 0;
     let segment: FileSegment;
@@ -302,32 +426,14 @@ fn make_entry<W: Write + Seek>(
         let size = info.header.objects_end;
         let mut resource_buffer = vec![0; size as usize];
         file_cursor.read_exact(&mut resource_buffer[..])?;
-        // get archive offset before writing
-        let archive_offset = archive_writer.stream_position()?;
 
         // kark file
-        let compressed_size_needed = get_compressed_buffer_size_needed(size as u64);
-        let mut compressed_buffer = vec![0; compressed_size_needed as usize];
-        let zsize = compress(
-            &resource_buffer,
-            &mut compressed_buffer,
-            CompressionLevel::Normal,
-        );
-        assert!((zsize as u32) <= size);
-        compressed_buffer.resize(zsize as usize, 0);
-
-        // write compressed main file archive
-        // KARK header
-        archive_writer.write_u32::<LittleEndian>(kraken::MAGIC)?; //magic
-        archive_writer.write_u32::<LittleEndian>(size)?; //uncompressed buffer length
-        archive_writer.write_all(&compressed_buffer)?;
+        segment = write_compressed(archive_writer, &resource_buffer, codec)?;
 
-        // add metadata to archive
-        segment = FileSegment::new(archive_offset, zsize as u32, size);
-
-        // write buffers (bytes after the main file)
+        // write buffers (bytes after the main file), they are stored as is
         for buffer_info in info.buffers_table.iter() {
             let mut buffer = vec![0; buffer_info.disk_size as usize];
+            file_cursor.seek(SeekFrom::Start(buffer_info.offset as u64))?;
             file_cursor.read_exact(&mut buffer[..])?;
 
             let bsize = buffer_info.mem_size;
@@ -356,39 +462,23 @@ fn make_entry<W: Write + Seek>(
     } else {
         // write non-cr2w file
         file_cursor.seek(SeekFrom::Start(0))?;
-        let os_ext = path.extension().unwrap();
-        let ext = os_ext.to_ascii_lowercase().to_string_lossy().to_string();
+        let ext = extension.to_ascii_lowercase();
         if get_aligned_file_extensions().contains(&ext) {
             pad_until_page(archive_writer)?;
         }
 
-        let offset = archive_writer.stream_position()?;
-        let size = file_buffer.len() as u32;
-        let final_zsize;
         if get_uncompressed_file_extensions().contains(&ext) {
             // direct copy
-            archive_writer.write_all(&file_buffer)?;
-            final_zsize = size;
+            let offset = archive_writer.stream_position()?;
+            let size = file_buffer.len() as u32;
+            archive_writer.write_all(file_buffer)?;
+            segment = FileSegment::new(offset, size, size);
         } else {
             // kark file
-            let compressed_size_needed = get_compressed_buffer_size_needed(size as u64);
-            let mut compressed_buffer = vec![0; compressed_size_needed as usize];
-            let zsize = compress(
-                &file_buffer,
-                &mut compressed_buffer,
-                CompressionLevel::Normal,
-            );
-            assert!((zsize as u32) <= size);
-            compressed_buffer.resize(zsize as usize, 0);
-            final_zsize = zsize as u32;
-            // write
-            archive_writer.write_all(&compressed_buffer)?;
+            segment = write_compressed(archive_writer, file_buffer, codec)?;
         }
-
-        // add metadata to archive
-        segment = FileSegment::new(offset, final_zsize, size);
     }
-    let sha1_hash = sha1_hash_file(&file_buffer);
+    let sha1_hash = sha1_hash_file(&file_buffer.to_vec());
     let entry = FileEntry::new(
         hash,
         0,
@@ -409,7 +499,43 @@ fn make_entry<W: Write + Seek>(
     Ok(wrapped_entry)
 }
 
-fn collect_resource_files<P: AsRef<Path>>(in_folder: &P) -> Vec<PathBuf> {
+/// Writes a buffer compressed with a KARK header, or as is if it doesn't compress
+///
+/// # Errors
+///
+/// This function will return an error if compression or io fails.
+fn write_compressed<W: Write + Seek>(
+    writer: &mut W,
+    buffer: &[u8],
+    codec: &dyn Codec,
+) -> Result<FileSegment> {
+    let offset = writer.stream_position()?;
+    let size = u32::try_from(buffer.len())
+        .map_err(|_| Error::new(ErrorKind::InvalidInput, "file is larger than 4 GiB"))?;
+    let compressed_buffer = codec
+        .compress(buffer)?
+        .filter(|compressed_buffer| compressed_buffer.len() + 8 < buffer.len());
+
+    if let Some(compressed_buffer) = compressed_buffer {
+        // KARK header
+        writer.write_u32::<LittleEndian>(kraken::MAGIC)?; //magic
+        writer.write_u32::<LittleEndian>(size)?; //uncompressed buffer length
+        writer.write_all(&compressed_buffer)?;
+
+        // the compressed size includes the KARK header
+        Ok(FileSegment::new(
+            offset,
+            compressed_buffer.len() as u32 + 8,
+            size,
+        ))
+    } else {
+        // not compressed
+        writer.write_all(buffer)?;
+        Ok(FileSegment::new(offset, size, size))
+    }
+}
+
+pub(crate) fn collect_resource_files<P: AsRef<Path>>(in_folder: &P) -> Vec<PathBuf> {
     // collect files
     let mut included_extensions = ERedExtension::iter()
         .map(|variant| variant.to_string())
@@ -433,15 +559,16 @@ fn collect_resource_files<P: AsRef<Path>>(in_folder: &P) -> Vec<PathBuf> {
     allfiles
 }
 
-/// Decompresses and writes a kraken-compressed segment from an archive to a stream
+/// Decompresses and writes a compressed segment from an archive to a stream
 ///
 /// # Errors
 ///
-/// This function will return an error if .
+/// This function will return an error if io fails or the segment doesn't decompress.
 fn decompress_segment<R: Read + Seek, W: Write>(
     archive_reader: &mut R,
     segment: &FileSegment,
     file_writer: &mut W,
+    codec: &dyn Codec,
 ) -> Result<()> {
     archive_reader.seek(SeekFrom::Start(segment.offset()))?;
 
@@ -455,9 +582,7 @@ fn decompress_segment<R: Read + Seek, W: Write>(
         }
         let mut compressed_buffer = vec![0; segment.z_size() as usize - 8];
         archive_reader.read_exact(&mut compressed_buffer[..])?;
-        let mut output_buffer = vec![];
-        let result = decompress(compressed_buffer, &mut output_buffer, size as usize);
-        assert_eq!(result as u32, size);
+        let output_buffer = codec.decompress(&compressed_buffer, size as usize)?;
 
         // write
         file_writer.write_all(&output_buffer)?;
@@ -525,6 +650,8 @@ pub struct ZipArchive<S> {
     /// The files inside an archive
     entries: HashMap<u64, ZipEntry>,
     pub dependencies: Vec<Dependency>,
+    /// Compresses and decompresses segments
+    codec: Box<dyn Codec>,
 }
 
 impl<S> ZipArchive<S> {
@@ -541,6 +668,16 @@ impl<S> ZipArchive<S> {
     pub fn get_entries(&self) -> &HashMap<u64, ZipEntry> {
         &self.entries
     }
+
+    /// The codec segments are compressed and decompressed with
+    pub fn codec(&self) -> &dyn Codec {
+        self.codec.as_ref()
+    }
+
+    /// Sets the codec segments are compressed and decompressed with
+    pub fn set_codec(&mut self, codec: Box<dyn Codec>) {
+        self.codec = codec;
+    }
 }
 
 impl<R> ZipArchive<R>
@@ -557,9 +694,9 @@ where
         entry: ZipEntry,
         destination_directory_name: &P,
         overwrite_files: bool,
-        hash_map: &HashMap<u64, String>,
+        hash_db: &HashDb,
     ) -> Result<()> {
-        let Some(info) = entry.get_resolved_name(hash_map) else {
+        let Some(info) = entry.get_resolved_name(hash_db) else {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 "Could not get entry info from archive.",
@@ -597,14 +734,14 @@ where
         hash: u64,
         destination_directory_name: &P,
         overwrite_files: bool,
-        hash_map: &HashMap<u64, String>,
+        hash_db: &HashDb,
     ) -> Result<()> {
         if let Some(entry) = self.get_entry_by_hash(&hash) {
             self.extract_entry(
                 entry.clone(),
                 destination_directory_name,
                 overwrite_files,
-                hash_map,
+                hash_db,
             )
         } else {
             Err(io::Error::new(
@@ -624,14 +761,14 @@ where
         name: String,
         destination_directory_name: &P,
         overwrite_files: bool,
-        hash_map: &HashMap<u64, String>,
+        hash_db: &HashDb,
     ) -> Result<()> {
         if let Some(entry) = self.get_entry(&name) {
             self.extract_entry(
                 entry.clone(),
                 destination_directory_name,
                 overwrite_files,
-                hash_map,
+                hash_db,
             )
         } else {
             Err(io::Error::new(
@@ -648,6 +785,35 @@ where
         Ok(())
     }
 
+    /// Reads a cr2w file by hash without extracting it.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the entry cannot be found, is not a cr2w file or any io fails.
+    pub fn read_cr2w(&mut self, hash: u64) -> Result<CR2WFile> {
+        let Some(entry) = self.get_entry_by_hash(&hash) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Could not find entry.",
+            ));
+        };
+
+        let mut buffer = vec![];
+        self.open_entry(entry.clone(), &mut buffer)?;
+        read_cr2w(&mut Cursor::new(&buffer))
+    }
+
+    /// Reads a cr2w file by resource path without extracting it.
+    ///
+    /// The path is hashed like a depot path, see [`ResourceHash::from_depot_path`].
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the entry cannot be found, is not a cr2w file or any io fails.
+    pub fn read_cr2w_by_name(&mut self, name: &str) -> Result<CR2WFile> {
+        self.read_cr2w(ResourceHash::from_depot_path(name).0)
+    }
+
     /// Extracts all entries to the given directory.
     ///
     /// # Errors
@@ -657,13 +823,9 @@ where
         &mut self,
         destination_directory_name: &P,
         overwrite_files: bool,
-        hash_map: Option<HashMap<u64, String>>,
+        hash_db: Option<&HashDb>,
     ) -> Result<()> {
-        let hash_map = if let Some(hash_map) = hash_map {
-            hash_map
-        } else {
-            get_red4_hashes()
-        };
+        let hash_db = hash_db.unwrap_or_else(|| HashDb::global());
 
         // collect info
         let mut entries: Vec<ZipEntry> = vec![];
@@ -672,12 +834,7 @@ where
         }
 
         for entry in entries {
-            self.extract_entry(
-                entry,
-                destination_directory_name,
-                overwrite_files,
-                &hash_map,
-            )?;
+            self.extract_entry(entry, destination_directory_name, overwrite_files, hash_db)?;
         }
 
         Ok(())
@@ -707,7 +864,7 @@ where
             self.reader_mut().read_exact(&mut buffer[..])?;
             writer.write_all(&buffer)?;
         } else {
-            decompress_segment(self.reader_mut(), &segment, &mut writer)?;
+            decompress_segment(&mut self.stream, &segment, &mut writer, self.codec.as_ref())?;
         }
         for segment in buffers {
             self.reader_mut().seek(SeekFrom::Start(segment.offset()))?;
@@ -720,7 +877,11 @@ where
     }
 
     /// Opens an archive, needs to be read-only
-    fn from_reader_consume(mut reader: R, mode: ArchiveMode) -> Result<ZipArchive<R>> {
+    fn from_reader_consume(
+        mut reader: R,
+        mode: ArchiveMode,
+        codec: Box<dyn Codec>,
+    ) -> Result<ZipArchive<R>> {
         // checks
         if mode == ArchiveMode::Create {
             return Ok(ZipArchive::<R> {
@@ -729,6 +890,7 @@ where
                 dirty: true,
                 entries: HashMap::default(),
                 dependencies: Vec::default(),
+                codec,
             });
         }
 
@@ -740,7 +902,8 @@ where
         if let Ok(custom_data_length) = reader.read_u32::<LittleEndian>() {
             if custom_data_length > 0 {
                 reader.seek(io::SeekFrom::Start(Header::HEADER_EXTENDED_SIZE))?;
-                if let Ok(footer) = LxrsFooter::from_reader(&mut reader) {
+                if let Ok(footer) = LxrsFooter::from_reader_with_codec(&mut reader, codec.as_ref())
+                {
                     // add files to hashmap
                     for f in footer.files() {
                         let hash = fnv1a64_hash_string(f);
@@ -805,6 +968,7 @@ where
             entries,
             dependencies,
             dirty: false,
+            codec,
         };
         Ok(archive)
     }
@@ -870,14 +1034,19 @@ pub struct ZipEntry {
 }
 
 impl ZipEntry {
-    fn get_resolved_name(&self, hash_map: &HashMap<u64, String>) -> Option<String> {
+    /// Resolved resource path of that entry from the archive's LXRS table, this may not be available
+    pub fn name(&self) -> Option<&String> {
+        self.name.as_ref()
+    }
+
+    fn get_resolved_name(&self, hash_db: &HashDb) -> Option<String> {
         // get filename
         let resolved = if let Some(name) = &self.name {
             name.to_owned()
         } else {
             let mut name_or_hash: String = format!("{}.bin", self.hash);
             // check vanilla hashes
-            if let Some(name) = hash_map.get(&self.hash) {
+            if let Some(name) = hash_db.get_path(&self.hash) {
                 name_or_hash = name.to_owned();
             }
             name_or_hash
@@ -939,7 +1108,7 @@ fn write_index<W: Write>(
 }
 
 /////////////////////////////////////////////////////////////////////////////////////////
-/// TESTS
+// TESTS
 /////////////////////////////////////////////////////////////////////////////////////////
 
 #[cfg(test)]
@@ -950,7 +1119,8 @@ mod integration_tests {
         path::PathBuf,
     };
 
-    use crate::archive::open_read;
+    use crate::archive::{open_read, open_read_stream_with_codec};
+    use crate::codec::StoredCodec;
 
     use super::FromReader;
     use super::LxrsFooter;
@@ -995,4 +1165,24 @@ mod integration_tests {
         let expected: Vec<String> = vec!["base\\cycleweapons\\localization\\en-us.json".to_owned()];
         assert_eq!(expected, file_names);
     }
+
+    #[test]
+    fn read_with_codec() {
+        let file = fs::File::open(PathBuf::from("tests").join("test1.archive")).unwrap();
+        let mut archive = open_read_stream_with_codec(file, Box::new(StoredCodec)).unwrap();
+        let compressed = archive
+            .entries
+            .values()
+            .find(|e| e.segment.size() != e.segment.z_size())
+            .cloned()
+            .expect("no compressed entry");
+
+        // the stored codec can't decompress kraken segments
+        assert!(archive.open_entry(compressed.clone(), Vec::new()).is_err());
+
+        archive.set_codec(Box::<crate::codec::KrakenCodec>::default());
+        let mut buffer = Vec::new();
+        archive.open_entry(compressed.clone(), &mut buffer).unwrap();
+        assert_eq!(compressed.segment.size() as usize, buffer.len());
+    }
 }
@@ -0,0 +1,391 @@
+/////////////////////////////////////////////////////////////////////////////////////////
+// BUNDLE
+// Collects the dependency closure of resources by following CR2W imports through
+// archives, e.g. .ent -> .app -> .mesh -> .mi -> .xbm, and extracts or packs it as the
+// base of a mod. Imports of embedded resources are never followed, those are stored
+// inside the importing file.
+/////////////////////////////////////////////////////////////////////////////////////////
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs::{create_dir_all, File},
+    io::{BufWriter, Cursor, Read, Result, Seek, Write},
+    path::Path,
+};
+
+use crate::{
+    archive::{open_read, write_resources, ZipArchive},
+    codec::{Codec, KrakenCodec},
+    cr2w::{read_cr2w_header, ImportFlags},
+    hash::ResourceHash,
+    relative_path_from_depot,
+};
+
+/// Which imports are followed when collecting a bundle
+#[derive(Debug, Clone, Default)]
+pub struct BundleOptions {
+    /// Extensions of resources that are bundled without following their imports, e.g. `mi`
+    pub stop_extensions: Vec<String>,
+    /// Extensions of resources that are neither bundled nor reported as missing, e.g. `xbm`
+    pub skip_extensions: Vec<String>,
+    /// Soft imports are not followed
+    pub skip_soft: bool,
+}
+
+/// A resource of a bundle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BundleEntry {
+    pub hash: u64,
+    pub path: String,
+    /// Index of the archive the resource is taken from
+    pub archive: usize,
+}
+
+/// A resource of the closure that none of the archives holds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingDependency {
+    pub hash: u64,
+    pub path: String,
+    /// The resource that imports it, `None` for the resources the bundle starts from
+    pub imported_by: Option<u64>,
+}
+
+/// The dependency closure of resources
+#[derive(Debug, Clone, Default)]
+pub struct Bundle {
+    /// Resources in the order they were found, the ones the bundle starts from first
+    pub entries: Vec<BundleEntry>,
+    pub missing: Vec<MissingDependency>,
+}
+
+/// Collects, extracts and packs bundles from a set of archives
+pub struct Bundler<R> {
+    /// Archives in load order, a resource is taken from the first archive that holds it
+    archives: Vec<ZipArchive<R>>,
+    options: BundleOptions,
+}
+
+impl Bundler<File> {
+    /// Opens archives in load order, e.g. from [`crate::archive::get_game_archives`]
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any archive can't be read.
+    pub fn open<P: AsRef<Path>>(archive_paths: &[P], options: BundleOptions) -> Result<Self> {
+        let archives = archive_paths
+            .iter()
+            .map(open_read)
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self::new(archives, options))
+    }
+}
+
+impl<R: Read + Seek> Bundler<R> {
+    /// Creates a bundler for archives in load order
+    pub fn new(archives: Vec<ZipArchive<R>>, options: BundleOptions) -> Self {
+        Self { archives, options }
+    }
+
+    pub fn options(&self) -> &BundleOptions {
+        &self.options
+    }
+
+    /// Collects the resources and everything they import, recursively
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a resource can't be read from its archive.
+    pub fn collect(&mut self, depot_paths: &[&str]) -> Result<Bundle> {
+        let mut bundle = Bundle::default();
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::new();
+        for path in depot_paths {
+            let hash = ResourceHash::from_depot_path(path).0;
+            if visited.insert(hash) {
+                queue.push_back((hash, path.to_string(), None));
+            }
+        }
+
+        while let Some((hash, path, imported_by)) = queue.pop_front() {
+            let extension = extension(&path);
+            if self.options.skip_extensions.contains(&extension) {
+                continue;
+            }
+            let Some(archive) = self
+                .archives
+                .iter()
+                .position(|archive| archive.get_entry_by_hash(&hash).is_some())
+            else {
+                bundle.missing.push(MissingDependency {
+                    hash,
+                    path,
+                    imported_by,
+                });
+                continue;
+            };
+            bundle.entries.push(BundleEntry {
+                hash,
+                path,
+                archive,
+            });
+            if self.options.stop_extensions.contains(&extension) {
+                continue;
+            }
+
+            let buffer = self.read(archive, hash)?;
+            let Ok(info) = read_cr2w_header(&mut Cursor::new(&buffer)) else {
+                continue;
+            };
+            for import in info.imports {
+                if import.flags.contains(ImportFlags::EMBEDDED)
+                    || (self.options.skip_soft && import.flags.contains(ImportFlags::SOFT))
+                {
+                    continue;
+                }
+                let import_hash = ResourceHash::from_depot_path(&import.depot_path).0;
+                if visited.insert(import_hash) {
+                    queue.push_back((import_hash, import.depot_path, Some(hash)));
+                }
+            }
+        }
+
+        Ok(bundle)
+    }
+
+    /// Extracts the resources of a bundle to a directory, depot paths become relative paths
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a file exists and `overwrite_files` is false, a depot path
+    /// has parent components or any io fails.
+    pub fn extract<P: AsRef<Path>>(
+        &mut self,
+        bundle: &Bundle,
+        destination_directory_name: &P,
+        overwrite_files: bool,
+    ) -> Result<()> {
+        for entry in bundle.entries.iter() {
+            let outfile = destination_directory_name
+                .as_ref()
+                .join(relative_path_from_depot(&entry.path)?);
+            if let Some(parent) = outfile.parent() {
+                create_dir_all(parent)?;
+            }
+
+            let buffer = self.read(entry.archive, entry.hash)?;
+            let file = if overwrite_files {
+                File::create(outfile)?
+            } else {
+                File::options().write(true).create_new(true).open(outfile)?
+            };
+            let mut writer = BufWriter::new(file);
+            writer.write_all(&buffer)?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Packs the resources of a bundle to an archive in the specified stream, their depot paths are
+    /// written to the archive
+    ///
+    /// Files are compressed with `codec`, or with Kraken at the normal level if none is given.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any io fails.
+    pub fn pack<W: Write + Seek>(
+        &mut self,
+        bundle: &Bundle,
+        destination: W,
+        codec: Option<&dyn Codec>,
+    ) -> Result<()> {
+        let default_codec = KrakenCodec::default();
+        let codec = codec.unwrap_or(&default_codec);
+
+        let archives = bundle
+            .entries
+            .iter()
+            .map(|entry| (entry.hash, entry.archive))
+            .collect::<HashMap<_, _>>();
+        let resources = bundle
+            .entries
+            .iter()
+            .map(|entry| (entry.hash, extension(&entry.path)))
+            .collect::<Vec<_>>();
+        let paths = bundle.entries.iter().map(|e| e.path.clone()).collect();
+
+        write_resources(destination, &resources, paths, codec, |hash| {
+            self.read(archives[&hash], hash)
+        })
+    }
+
+    /// Packs the resources of a bundle to an archive file, see [`Bundler::pack`]
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any io fails.
+    pub fn pack_path<P: AsRef<Path>>(
+        &mut self,
+        bundle: &Bundle,
+        destination: &P,
+        codec: Option<&dyn Codec>,
+    ) -> Result<()> {
+        self.pack(bundle, File::create(destination)?, codec)
+    }
+
+    fn read(&mut self, archive: usize, hash: u64) -> Result<Vec<u8>> {
+        let archive = &mut self.archives[archive];
+        let Some(entry) = archive.get_entry_by_hash(&hash).cloned() else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Could not find entry.",
+            ));
+        };
+        let mut buffer = vec![];
+        archive.open_entry(entry, &mut buffer)?;
+        Ok(buffer)
+    }
+}
+
+/// The lowercase extension of a depot path without the dot
+fn extension(path: &str) -> String {
+    path.rsplit(['\\', '/'])
+        .next()
+        .and_then(|name| name.rsplit_once('.'))
+        .map(|(_, extension)| extension.to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// TESTS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+    use crate::archive::open_read_stream;
+    use crate::fixtures::{archive, hash, resource};
+
+    /// a.ent -> b.app -> c.mesh -> d.xbm (missing), a.ent -> e.mesh (soft), a.ent -> a.ent#0 (embedded)
+    ///
+    /// The second archive overrides b.app without imports.
+    fn test_bundler(options: BundleOptions) -> Bundler<Cursor<Vec<u8>>> {
+        let first = archive(vec![
+            (
+                "base\\a.ent",
+                resource(&[
+                    ("base\\b.app", ImportFlags::DEFAULT),
+                    ("base\\e.mesh", ImportFlags::SOFT),
+                    ("base\\a.ent#0", ImportFlags::EMBEDDED),
+                ]),
+            ),
+            (
+                "base\\b.app",
+                resource(&[("base\\c.mesh", ImportFlags::DEFAULT)]),
+            ),
+            (
+                "base\\c.mesh",
+                resource(&[("base\\d.xbm", ImportFlags::DEFAULT)]),
+            ),
+        ]);
+        let second = archive(vec![
+            ("base\\b.app", resource(&[])),
+            ("base\\e.mesh", resource(&[])),
+        ]);
+        Bundler::new(vec![first, second], options)
+    }
+
+    fn paths(bundle: &Bundle) -> Vec<&str> {
+        bundle.entries.iter().map(|e| e.path.as_str()).collect()
+    }
+
+    #[test]
+    fn collect() {
+        let mut bundler = test_bundler(BundleOptions::default());
+        let bundle = bundler.collect(&["base\\a.ent"]).unwrap();
+        assert_eq!(
+            vec!["base\\a.ent", "base\\b.app", "base\\e.mesh", "base\\c.mesh"],
+            paths(&bundle)
+        );
+        // resources are taken from the first archive that holds them
+        assert_eq!(0, bundle.entries[1].archive);
+        assert_eq!(1, bundle.entries[2].archive);
+        assert_eq!(
+            vec![MissingDependency {
+                hash: hash("base\\d.xbm"),
+                path: "base\\d.xbm".to_owned(),
+                imported_by: Some(hash("base\\c.mesh")),
+            }],
+            bundle.missing
+        );
+
+        // depot paths are hashed like the game does
+        let bundle = bundler.collect(&["Base/A.ent"]).unwrap();
+        assert_eq!(4, bundle.entries.len());
+        assert_eq!(hash("base\\a.ent"), bundle.entries[0].hash);
+
+        let bundle = bundler.collect(&["base\\missing.ent"]).unwrap();
+        assert!(bundle.entries.is_empty());
+        assert_eq!(None, bundle.missing[0].imported_by);
+    }
+
+    #[test]
+    fn collect_with_filters() {
+        let mut bundler = test_bundler(BundleOptions {
+            stop_extensions: vec!["app".to_owned()],
+            skip_extensions: vec![],
+            skip_soft: true,
+        });
+        let bundle = bundler.collect(&["base\\a.ent"]).unwrap();
+        assert_eq!(vec!["base\\a.ent", "base\\b.app"], paths(&bundle));
+        assert!(bundle.missing.is_empty());
+
+        let mut bundler = test_bundler(BundleOptions {
+            skip_extensions: vec!["xbm".to_owned()],
+            ..Default::default()
+        });
+        let bundle = bundler.collect(&["base\\a.ent", "base\\c.mesh"]).unwrap();
+        assert_eq!(4, bundle.entries.len());
+        assert!(bundle.missing.is_empty());
+    }
+
+    #[test]
+    fn extract_and_pack() {
+        let mut bundler = test_bundler(BundleOptions::default());
+        let bundle = bundler.collect(&["base\\a.ent"]).unwrap();
+
+        let dst_path = PathBuf::from("tests").join("out_bundle");
+        if dst_path.exists() {
+            fs::remove_dir_all(&dst_path).unwrap();
+        }
+        bundler.extract(&bundle, &dst_path, false).unwrap();
+        for entry in bundle.entries.iter() {
+            let extracted =
+                fs::read(dst_path.join(relative_path_from_depot(&entry.path).unwrap())).unwrap();
+            assert_eq!(bundler.read(entry.archive, entry.hash).unwrap(), extracted);
+        }
+        assert!(bundler.extract(&bundle, &dst_path, false).is_err());
+        bundler.extract(&bundle, &dst_path, true).unwrap();
+        fs::remove_dir_all(&dst_path).unwrap();
+
+        let mut output = Cursor::new(vec![]);
+        bundler.pack(&bundle, &mut output, None).unwrap();
+        let mut packed = open_read_stream(Cursor::new(output.into_inner())).unwrap();
+        assert_eq!(bundle.entries.len(), packed.get_entries().len());
+        for entry in bundle.entries.iter() {
+            let packed_entry = packed.get_entry_by_hash(&entry.hash).unwrap().clone();
+            assert_eq!(Some(&entry.path), packed_entry.name());
+            let mut buffer = vec![];
+            packed.open_entry(packed_entry, &mut buffer).unwrap();
+            assert_eq!(bundler.read(entry.archive, entry.hash).unwrap(), buffer);
+        }
+    }
+
+    #[test]
+    fn helpers() {
+        assert_eq!("mesh", extension("base\\a.b\\C.MESH"));
+        assert_eq!("", extension("base\\a.b\\c"));
+    }
+}
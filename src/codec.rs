@@ -0,0 +1,117 @@
+/////////////////////////////////////////////////////////////////////////////////////////
+// CODEC
+// Compression of archive segments and the LXRS footer.
+/////////////////////////////////////////////////////////////////////////////////////////
+
+use std::{
+    fmt::Debug,
+    io::{Error, ErrorKind, Result},
+};
+
+use crate::kraken::{self, CompressionLevel};
+
+/// Compresses and decompresses archive segments.
+///
+/// Compressed segments are written with a KARK header, archives loaded by the game need a codec
+/// that produces Kraken streams.
+pub trait Codec: Debug + Send + Sync {
+    /// Compresses a buffer. Returns `None` if the buffer should be stored uncompressed.
+    ///
+    /// Callers also store the buffer as is if the compressed buffer is not smaller.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if compression fails.
+    fn compress(&self, buffer: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    /// Decompresses a buffer into `size` bytes
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the buffer is corrupt or can't be decompressed by this codec.
+    fn decompress(&self, compressed_buffer: &[u8], size: usize) -> Result<Vec<u8>>;
+}
+
+/// Kraken compression, with the native library when it is linked
+#[derive(Debug, Clone, Copy)]
+pub struct KrakenCodec {
+    level: CompressionLevel,
+}
+
+impl KrakenCodec {
+    pub fn new(level: CompressionLevel) -> Self {
+        Self { level }
+    }
+
+    pub fn level(&self) -> CompressionLevel {
+        self.level
+    }
+}
+
+impl Default for KrakenCodec {
+    fn default() -> Self {
+        Self::new(CompressionLevel::Normal)
+    }
+}
+
+impl Codec for KrakenCodec {
+    fn compress(&self, buffer: &[u8]) -> Result<Option<Vec<u8>>> {
+        if self.level == CompressionLevel::None {
+            return Ok(None);
+        }
+        kraken::compress(buffer, self.level).map(Some)
+    }
+
+    fn decompress(&self, compressed_buffer: &[u8], size: usize) -> Result<Vec<u8>> {
+        kraken::decompress(compressed_buffer, size)
+    }
+}
+
+/// Stores all buffers uncompressed. Archives packed with it are valid but larger.
+///
+/// Compressed segments can't be read with this codec.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StoredCodec;
+
+impl Codec for StoredCodec {
+    fn compress(&self, _buffer: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    fn decompress(&self, _compressed_buffer: &[u8], _size: usize) -> Result<Vec<u8>> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "stored codec can't decompress buffers",
+        ))
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// TESTS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kraken_roundtrip() {
+        let buffer = b"base\\characters\\".repeat(100);
+        let codec = KrakenCodec::default();
+        let compressed = codec.compress(&buffer).unwrap().unwrap();
+        assert!(compressed.len() < buffer.len());
+        assert_eq!(buffer, codec.decompress(&compressed, buffer.len()).unwrap());
+
+        let codec = KrakenCodec::new(CompressionLevel::None);
+        assert!(codec.compress(&buffer).unwrap().is_none());
+    }
+
+    #[test]
+    fn stored() {
+        let buffer = b"base\\characters\\".repeat(100);
+        assert!(StoredCodec.compress(&buffer).unwrap().is_none());
+
+        let compressed = KrakenCodec::default().compress(&buffer).unwrap().unwrap();
+        assert!(StoredCodec.decompress(&compressed, buffer.len()).is_err());
+    }
+}
@@ -0,0 +1,536 @@
+/////////////////////////////////////////////////////////////////////////////////////////
+// CR2W CHUNKS
+// Export chunks are a zero byte, a list of properties and a zero name index.
+//
+// property: u16 name index, u16 type index, u32 size including the size field, value
+// struct:   serialized like a chunk
+// array:    u32 count, elements
+// enum:     u16 name index of the value, bitfields list names until a zero index
+/////////////////////////////////////////////////////////////////////////////////////////
+
+use std::io::{Cursor, Error, ErrorKind, Read, Result};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use super::{TypeKind, TypeRegistry};
+
+/// An instance of a class, decoded from an export chunk
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    pub class_name: String,
+    pub flags: u16,
+    /// Index of the parent chunk
+    pub parent: Option<usize>,
+    pub template: u32,
+    pub properties: Vec<Property>,
+    /// Bytes after the property list, e.g. class specific binary data
+    pub trailing: Vec<u8>,
+    /// False for chunks that are not a property list, e.g. `AreaShapeOutline`, all their bytes are in `trailing`
+    pub decoded: bool,
+}
+
+impl Chunk {
+    /// Gets a property by name
+    pub fn property(&self, name: &str) -> Option<&Property> {
+        self.properties.iter().find(|p| p.name == name)
+    }
+
+    /// Gets the value of a property by name
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.property(name).map(|p| &p.value)
+    }
+}
+
+/// A named and typed value of a class or struct
+#[derive(Debug, Clone, PartialEq)]
+pub struct Property {
+    pub name: String,
+    /// RED4 type name, e.g. `Float`, `array:CName` or `handle:entIComponent`
+    pub type_name: String,
+    pub value: Value,
+    /// Filled in from the type registry, the property isn't stored in the file and isn't
+    /// written. Clear it to write the value.
+    pub default: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Int8(i8),
+    Uint8(u8),
+    Int16(i16),
+    Uint16(u16),
+    Int32(i32),
+    Uint32(u32),
+    Int64(i64),
+    Uint64(u64),
+    Float(f32),
+    Double(f64),
+    CName(String),
+    String(String),
+    /// Value name of an enum
+    Enum(String),
+    /// Value names of a bitfield
+    Bitfield(Vec<String>),
+    /// Index of the referenced chunk
+    Handle(Option<usize>),
+    /// Index of the referenced chunk
+    WeakHandle(Option<usize>),
+    /// Index of the import with the resource's depot path
+    ResourceReference(Option<usize>),
+    /// Dynamic and static arrays
+    Array(Vec<Value>),
+    Struct(Vec<Property>),
+    /// Bytes of a value that couldn't be decoded
+    Raw(Vec<u8>),
+}
+
+/// Decodes an export chunk to its properties and trailing bytes
+///
+/// With type information, values are decoded with their known types and the defaults of properties
+/// that are not serialized are added.
+///
+/// # Errors
+///
+/// This function will return an error if the chunk is not a property list.
+pub(crate) fn read_chunk(
+    data: &[u8],
+    names: &[String],
+    types: Option<&TypeRegistry>,
+    class_name: &str,
+) -> Result<(Vec<Property>, Vec<u8>)> {
+    let mut cursor = Cursor::new(data);
+    let properties = read_class(&mut cursor, class_name, names, types)?;
+    let trailing = data[cursor.position() as usize..].to_vec();
+
+    Ok((properties, trailing))
+}
+
+/// Reads the properties of a class and adds the defaults of missing ones if the class is known
+fn read_class(
+    cursor: &mut Cursor<&[u8]>,
+    class_name: &str,
+    names: &[String],
+    types: Option<&TypeRegistry>,
+) -> Result<Vec<Property>> {
+    let mut properties = read_properties(cursor, names, types)?;
+    if let Some(types) = types {
+        types.fill_defaults(class_name, &mut properties);
+    }
+    Ok(properties)
+}
+
+/// Reads a zero byte, properties and the terminating zero name index
+fn read_properties(
+    cursor: &mut Cursor<&[u8]>,
+    names: &[String],
+    types: Option<&TypeRegistry>,
+) -> Result<Vec<Property>> {
+    if cursor.read_u8()? != 0 {
+        return Err(invalid_data("property list doesn't start with zero"));
+    }
+
+    let mut properties = vec![];
+    loop {
+        let name_index = cursor.read_u16::<LittleEndian>()?;
+        if name_index == 0 {
+            break;
+        }
+        let type_name = get_name(names, cursor.read_u16::<LittleEndian>()?)?;
+        let size = cursor.read_u32::<LittleEndian>()?;
+        if size < 4 {
+            return Err(invalid_data("property size is too small"));
+        }
+        let remaining = cursor.get_ref().len() - cursor.position() as usize;
+        if size as usize - 4 > remaining {
+            return Err(invalid_data("property is larger than its data"));
+        }
+
+        let mut data = vec![0; size as usize - 4];
+        cursor.read_exact(&mut data)?;
+
+        // values that don't decode are kept as is
+        let value = read_sized_value(&data, type_name, names, types).unwrap_or(Value::Raw(data));
+        properties.push(Property {
+            name: get_name(names, name_index)?.to_owned(),
+            type_name: type_name.to_owned(),
+            value,
+            default: false,
+        });
+    }
+
+    Ok(properties)
+}
+
+/// Reads a value that has to use up all of `data`
+fn read_sized_value(
+    data: &[u8],
+    type_name: &str,
+    names: &[String],
+    types: Option<&TypeRegistry>,
+) -> Result<Value> {
+    let mut cursor = Cursor::new(data);
+    let value = read_value(&mut cursor, type_name, Some(data.len()), names, types)?;
+    if cursor.position() as usize != data.len() {
+        return Err(invalid_data("value is smaller than its property"));
+    }
+
+    Ok(value)
+}
+
+/// Reads a value of a type, `size` is the number of bytes of the value if it is known
+fn read_value(
+    cursor: &mut Cursor<&[u8]>,
+    type_name: &str,
+    size: Option<usize>,
+    names: &[String],
+    types: Option<&TypeRegistry>,
+) -> Result<Value> {
+    let value = match type_name {
+        "Bool" => match cursor.read_u8()? {
+            0 => Value::Bool(false),
+            1 => Value::Bool(true),
+            _ => return Err(invalid_data("invalid bool")),
+        },
+        "Int8" => Value::Int8(cursor.read_i8()?),
+        "Uint8" => Value::Uint8(cursor.read_u8()?),
+        "Int16" => Value::Int16(cursor.read_i16::<LittleEndian>()?),
+        "Uint16" => Value::Uint16(cursor.read_u16::<LittleEndian>()?),
+        "Int32" => Value::Int32(cursor.read_i32::<LittleEndian>()?),
+        "Uint32" => Value::Uint32(cursor.read_u32::<LittleEndian>()?),
+        "Int64" => Value::Int64(cursor.read_i64::<LittleEndian>()?),
+        "Uint64" | "TweakDBID" | "CRUID" | "CDateTime" | "gamedataLocKeyWrapper" => {
+            Value::Uint64(cursor.read_u64::<LittleEndian>()?)
+        }
+        "Float" => Value::Float(cursor.read_f32::<LittleEndian>()?),
+        "Double" => Value::Double(cursor.read_f64::<LittleEndian>()?),
+        "CName" => Value::CName(read_name(cursor, names)?),
+        "String" => Value::String(read_string(cursor)?),
+        _ => {
+            if let Some(inner) = type_name.strip_prefix("array:") {
+                read_array(cursor, inner, size, names, types)?
+            } else if let Some((_, inner)) =
+                type_name.strip_prefix('[').and_then(|t| t.split_once(']'))
+            {
+                // static arrays are serialized like arrays
+                read_array(cursor, inner, size, names, types)?
+            } else if type_name.starts_with("handle:") {
+                Value::Handle(read_handle(cursor)?)
+            } else if type_name.starts_with("whandle:") {
+                Value::WeakHandle(read_handle(cursor)?)
+            } else if type_name.starts_with("rRef:") || type_name.starts_with("raRef:") {
+                let index = cursor.read_u16::<LittleEndian>()?;
+                Value::ResourceReference((index as usize).checked_sub(1))
+            } else if type_name.contains(':') {
+                return Err(invalid_data("unsupported generic type"));
+            } else {
+                match types.and_then(|types| types.kind(type_name)) {
+                    Some(TypeKind::Enum) => Value::Enum(read_name(cursor, names)?),
+                    Some(TypeKind::Bitfield) => Value::Bitfield(read_bitfield(cursor, names)?),
+                    Some(TypeKind::Class) => {
+                        Value::Struct(read_class(cursor, type_name, names, types)?)
+                    }
+                    None => read_class_value(cursor, size, names, types)?,
+                }
+            }
+        }
+    };
+
+    Ok(value)
+}
+
+/// Reads a struct, enum or bitfield, without type information these are told apart by their size
+fn read_class_value(
+    cursor: &mut Cursor<&[u8]>,
+    size: Option<usize>,
+    names: &[String],
+    types: Option<&TypeRegistry>,
+) -> Result<Value> {
+    let start = cursor.position() as usize;
+    let next = cursor.get_ref().get(start).copied();
+
+    if size == Some(2) || (size.is_none() && next.is_some_and(|b| b != 0)) {
+        // structs are at least 3 bytes
+        Ok(Value::Enum(read_name(cursor, names)?))
+    } else if next == Some(0) {
+        Ok(Value::Struct(read_properties(cursor, names, types)?))
+    } else if size.is_some() {
+        Ok(Value::Bitfield(read_bitfield(cursor, names)?))
+    } else {
+        Err(invalid_data("unknown value"))
+    }
+}
+
+/// Reads the names of the set flags until a zero name index
+fn read_bitfield(cursor: &mut Cursor<&[u8]>, names: &[String]) -> Result<Vec<String>> {
+    let mut values = vec![];
+    loop {
+        let index = cursor.read_u16::<LittleEndian>()?;
+        if index == 0 {
+            break;
+        }
+        values.push(get_name(names, index)?.to_owned());
+    }
+    Ok(values)
+}
+
+fn read_array(
+    cursor: &mut Cursor<&[u8]>,
+    inner: &str,
+    size: Option<usize>,
+    names: &[String],
+    types: Option<&TypeRegistry>,
+) -> Result<Value> {
+    let count = cursor.read_u32::<LittleEndian>()? as usize;
+    let remaining = cursor.get_ref().len() - cursor.position() as usize;
+    if count > remaining {
+        return Err(invalid_data("array is larger than its data"));
+    }
+
+    // elements have no size, without type information an array of enums is 2 bytes per element
+    let element_size = size
+        .filter(|size| count > 0 && *size == 4 + count * 2)
+        .map(|_| 2);
+    let mut values = Vec::with_capacity(count);
+    for _ in 0..count {
+        values.push(read_value(cursor, inner, element_size, names, types)?);
+    }
+
+    Ok(Value::Array(values))
+}
+
+fn read_handle(cursor: &mut Cursor<&[u8]>) -> Result<Option<usize>> {
+    let handle = cursor.read_i32::<LittleEndian>()?;
+    if handle < 0 {
+        return Err(invalid_data("negative handle"));
+    }
+
+    // handles are 1-based, 0 is null
+    Ok((handle as usize).checked_sub(1))
+}
+
+fn read_name(cursor: &mut Cursor<&[u8]>, names: &[String]) -> Result<String> {
+    let index = cursor.read_u16::<LittleEndian>()?;
+    get_name(names, index).map(|name| name.to_owned())
+}
+
+fn get_name(names: &[String], index: u16) -> Result<&str> {
+    names
+        .get(index as usize)
+        .map(|name| name.as_str())
+        .ok_or_else(|| invalid_data("name index out of range"))
+}
+
+/// Reads a string with a variable length prefix, negative lengths are ASCII and positive ones UTF-16
+fn read_string(cursor: &mut Cursor<&[u8]>) -> Result<String> {
+    let first = cursor.read_u8()?;
+    let ascii = first & 0x80 != 0;
+    let mut length = (first & 0x3F) as usize;
+    let mut next = first & 0x40 != 0;
+    let mut shift = 6;
+    while next {
+        if shift > 27 {
+            return Err(invalid_data("invalid string length"));
+        }
+        let b = cursor.read_u8()?;
+        length |= ((b & 0x7F) as usize) << shift;
+        next = b & 0x80 != 0;
+        shift += 7;
+    }
+
+    let remaining = cursor.get_ref().len() - cursor.position() as usize;
+    if ascii {
+        if length > remaining {
+            return Err(invalid_data("string is larger than its data"));
+        }
+        let mut buffer = vec![0; length];
+        cursor.read_exact(&mut buffer)?;
+        if !buffer.is_ascii() {
+            return Err(invalid_data("invalid ascii string"));
+        }
+        Ok(String::from_utf8_lossy(&buffer).to_string())
+    } else {
+        if length * 2 > remaining {
+            return Err(invalid_data("string is larger than its data"));
+        }
+        let mut buffer = vec![0; length];
+        cursor.read_u16_into::<LittleEndian>(&mut buffer)?;
+        String::from_utf16(&buffer).map_err(|_| invalid_data("invalid utf-16 string"))
+    }
+}
+
+/// The type of the elements of a dynamic or static array
+pub(crate) fn element_type(type_name: &str) -> &str {
+    type_name
+        .strip_prefix("array:")
+        .or_else(|| type_name.split_once(']').map(|(_, inner)| inner))
+        .unwrap_or(type_name)
+}
+
+fn invalid_data(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// TESTS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::super::{BitfieldMember, BitfieldType, ClassType, PropertyType};
+    use super::*;
+
+    fn names() -> Vec<String> {
+        [
+            "",
+            "a",
+            "Float",
+            "String",
+            "EEnum",
+            "Value",
+            "SStruct",
+            "array:EEnum",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect()
+    }
+
+    #[test]
+    fn values() {
+        let names = names();
+        let data = [0xCD, 0xCC, 0x8C, 0x3F];
+        assert_eq!(
+            Value::Float(1.1),
+            read_sized_value(&data, "Float", &names, None).unwrap()
+        );
+        let data = [0x83, b'a', b'b', b'c'];
+        assert_eq!(
+            Value::String("abc".to_owned()),
+            read_sized_value(&data, "String", &names, None).unwrap()
+        );
+        let data = [0x02, 0xE4, 0x00, 0x61, 0x00];
+        assert_eq!(
+            Value::String("äa".to_owned()),
+            read_sized_value(&data, "String", &names, None).unwrap()
+        );
+        let data = [0x05, 0x00];
+        assert_eq!(
+            Value::Enum("Value".to_owned()),
+            read_sized_value(&data, "EEnum", &names, None).unwrap()
+        );
+        let data = [0x05, 0x00, 0x01, 0x00, 0x00, 0x00];
+        assert_eq!(
+            Value::Bitfield(vec!["Value".to_owned(), "a".to_owned()]),
+            read_sized_value(&data, "EEnum", &names, None).unwrap()
+        );
+        let data = [0x02, 0x00, 0x00, 0x00, 0x05, 0x00, 0x01, 0x00];
+        assert_eq!(
+            Value::Array(vec![
+                Value::Enum("Value".to_owned()),
+                Value::Enum("a".to_owned())
+            ]),
+            read_sized_value(&data, "array:EEnum", &names, None).unwrap()
+        );
+        let data = [0x02, 0x00, 0x00, 0x00];
+        assert_eq!(
+            Value::Handle(Some(1)),
+            read_sized_value(&data, "handle:SStruct", &names, None).unwrap()
+        );
+        assert!(read_sized_value(&[0x07], "Bool", &names, None).is_err());
+    }
+
+    #[test]
+    fn properties() {
+        let names = names();
+        #[rustfmt::skip]
+        let data = [
+            0x00,
+            // a: Float
+            0x01, 0x00, 0x02, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x3F,
+            // a: SStruct { a: EEnum }
+            0x01, 0x00, 0x06, 0x00, 0x11, 0x00, 0x00, 0x00,
+            0x00, 0x01, 0x00, 0x04, 0x00, 0x06, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00,
+            // a: Float, too short
+            0x01, 0x00, 0x02, 0x00, 0x06, 0x00, 0x00, 0x00, 0x01, 0x02,
+            0x00, 0x00,
+            0xAA, 0xBB,
+        ];
+        let (properties, trailing) = read_chunk(&data, &names, None, "").unwrap();
+        assert_eq!(vec![0xAA, 0xBB], trailing);
+        assert_eq!(3, properties.len());
+        assert_eq!(Value::Float(1.0), properties[0].value);
+        assert_eq!(
+            Value::Struct(vec![Property {
+                name: "a".to_owned(),
+                type_name: "EEnum".to_owned(),
+                value: Value::Enum("Value".to_owned()),
+                default: false,
+            }]),
+            properties[1].value
+        );
+        assert_eq!(Value::Raw(vec![0x01, 0x02]), properties[2].value);
+
+        assert!(read_chunk(&[0x01, 0x00, 0x00], &names, None, "").is_err());
+        assert!(read_chunk(&data[..20], &names, None, "").is_err());
+        // sizes beyond the data are rejected before reading
+        let data = [0x00, 0x01, 0x00, 0x02, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0x00];
+        assert!(read_chunk(&data, &names, None, "").is_err());
+    }
+
+    #[test]
+    fn typed_values() {
+        let names = names();
+        let mut types = TypeRegistry::new();
+        types.insert_bitfield(BitfieldType {
+            name: "EEnum".to_owned(),
+            members: vec![BitfieldMember {
+                name: "Value".to_owned(),
+                bit: 0,
+            }],
+        });
+        types.insert_class(ClassType {
+            name: "SStruct".to_owned(),
+            parent: None,
+            properties: vec![
+                PropertyType {
+                    name: "a".to_owned(),
+                    type_name: "EEnum".to_owned(),
+                    default: None,
+                },
+                PropertyType {
+                    name: "Value".to_owned(),
+                    type_name: "Float".to_owned(),
+                    default: Some(2.5.into()),
+                },
+            ],
+        });
+
+        // a bitfield without flags has the size of an enum
+        let data = [0x00, 0x00];
+        assert_eq!(
+            Value::Enum("".to_owned()),
+            read_sized_value(&data, "EEnum", &names, None).unwrap()
+        );
+        assert_eq!(
+            Value::Bitfield(vec![]),
+            read_sized_value(&data, "EEnum", &names, Some(&types)).unwrap()
+        );
+
+        // properties that are not serialized get their defaults
+        #[rustfmt::skip]
+        let data = [
+            0x00,
+            // a: EEnum
+            0x01, 0x00, 0x04, 0x00, 0x08, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00,
+            0x00, 0x00,
+        ];
+        let (properties, _) = read_chunk(&data, &names, Some(&types), "SStruct").unwrap();
+        assert_eq!(2, properties.len());
+        assert_eq!(
+            Value::Bitfield(vec!["Value".to_owned()]),
+            properties[0].value
+        );
+        assert_eq!(Value::Float(2.5), properties[1].value);
+    }
+}
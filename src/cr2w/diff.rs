@@ -0,0 +1,485 @@
+/////////////////////////////////////////////////////////////////////////////////////////
+// CR2W DIFF
+// Structural differences of two CR2W files for review. Compared are the header version,
+// flags and build version, imports by depot path, chunks by index, properties by name,
+// array elements by index and buffers by content. Offsets, CRCs and the time stamp are
+// derived on write and not compared. Resource references are compared by depot path.
+/////////////////////////////////////////////////////////////////////////////////////////
+
+use std::{
+    fmt,
+    fs::File,
+    io::{BufReader, Read, Result, Seek},
+    path::Path,
+};
+
+use super::{read_cr2w, CR2WFile, Property, Value};
+use crate::archive::ZipArchive;
+
+/// A difference at a path, e.g. `chunks[0].appearances[2].name`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    pub path: String,
+    /// The value in the old file, `None` if it was added
+    pub old: Option<String>,
+    /// The value in the new file, `None` if it was removed
+    pub new: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DifferenceKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+impl Difference {
+    pub fn kind(&self) -> DifferenceKind {
+        match (&self.old, &self.new) {
+            (None, _) => DifferenceKind::Added,
+            (_, None) => DifferenceKind::Removed,
+            _ => DifferenceKind::Changed,
+        }
+    }
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.old, &self.new) {
+            (Some(old), Some(new)) => write!(f, "~ {}: {} -> {}", self.path, old, new),
+            (None, Some(new)) => write!(f, "+ {}: {}", self.path, new),
+            (Some(old), None) => write!(f, "- {}: {}", self.path, old),
+            (None, None) => write!(f, "  {}", self.path),
+        }
+    }
+}
+
+/// Compares two CR2W files
+pub fn diff_cr2w(old: &CR2WFile, new: &CR2WFile) -> Vec<Difference> {
+    let mut diff = Diff {
+        old,
+        new,
+        differences: vec![],
+    };
+    diff.header();
+    diff.imports();
+    diff.chunks();
+    diff.buffers();
+    diff.differences
+}
+
+/// Compares two loose CR2W files
+///
+/// # Errors
+///
+/// This function will return an error if a file can't be read or parsed.
+pub fn diff_cr2w_files<P: AsRef<Path>, Q: AsRef<Path>>(
+    old: &P,
+    new: &Q,
+) -> Result<Vec<Difference>> {
+    let old = read_cr2w(&mut BufReader::new(File::open(old)?))?;
+    let new = read_cr2w(&mut BufReader::new(File::open(new)?))?;
+    Ok(diff_cr2w(&old, &new))
+}
+
+/// Compares the CR2W files of two archive entries, e.g. a game file and a mod's override
+///
+/// # Errors
+///
+/// This function will return an error if an archive doesn't hold the entry or it can't be parsed.
+pub fn diff_archive_entries<R: Read + Seek, S: Read + Seek>(
+    old: &mut ZipArchive<R>,
+    old_hash: u64,
+    new: &mut ZipArchive<S>,
+    new_hash: u64,
+) -> Result<Vec<Difference>> {
+    let old = old.read_cr2w(old_hash)?;
+    let new = new.read_cr2w(new_hash)?;
+    Ok(diff_cr2w(&old, &new))
+}
+
+struct Diff<'a> {
+    old: &'a CR2WFile,
+    new: &'a CR2WFile,
+    differences: Vec<Difference>,
+}
+
+impl Diff<'_> {
+    fn push(&mut self, path: String, old: Option<String>, new: Option<String>) {
+        self.differences.push(Difference { path, old, new });
+    }
+
+    fn compare<T: PartialEq + ToString>(&mut self, path: &str, old: T, new: T) {
+        if old != new {
+            self.push(
+                path.to_owned(),
+                Some(old.to_string()),
+                Some(new.to_string()),
+            );
+        }
+    }
+
+    fn header(&mut self) {
+        let (old, new) = (&self.old.info.header, &self.new.info.header);
+        self.compare("header.version", old.version, new.version);
+        self.compare("header.flags", old.flags, new.flags);
+        self.compare("header.build_version", old.build_version, new.build_version);
+    }
+
+    fn imports(&mut self) {
+        let (old, new) = (&self.old.info.imports, &self.new.info.imports);
+        for import in old.iter() {
+            let path = format!("imports[{}]", import.depot_path);
+            match new.iter().find(|i| i.depot_path == import.depot_path) {
+                Some(other) => {
+                    self.compare(&format!("{}.flags", path), import.flags, other.flags);
+                    self.compare(
+                        &format!("{}.class_name", path),
+                        &import.class_name,
+                        &other.class_name,
+                    );
+                }
+                None => self.push(path, Some(import.flags.to_string()), None),
+            }
+        }
+        for import in new.iter() {
+            if !old.iter().any(|i| i.depot_path == import.depot_path) {
+                let path = format!("imports[{}]", import.depot_path);
+                self.push(path, None, Some(import.flags.to_string()));
+            }
+        }
+    }
+
+    fn chunks(&mut self) {
+        let count = self.old.chunks.len().max(self.new.chunks.len());
+        for index in 0..count {
+            let path = format!("chunks[{}]", index);
+            let (old, new) = match (self.old.chunks.get(index), self.new.chunks.get(index)) {
+                (Some(old), Some(new)) => (old, new),
+                (Some(old), None) => {
+                    self.push(path, Some(old.class_name.clone()), None);
+                    continue;
+                }
+                (None, Some(new)) => {
+                    self.push(path, None, Some(new.class_name.clone()));
+                    continue;
+                }
+                (None, None) => continue,
+            };
+
+            // a chunk of another class is replaced as a whole
+            if old.class_name != new.class_name {
+                self.push(
+                    path,
+                    Some(old.class_name.clone()),
+                    Some(new.class_name.clone()),
+                );
+                continue;
+            }
+            self.compare(
+                &format!("{}.parent", path),
+                chunk_text(old.parent),
+                chunk_text(new.parent),
+            );
+            self.compare(&format!("{}.flags", path), old.flags, new.flags);
+            self.compare(&format!("{}.template", path), old.template, new.template);
+            self.properties(&path, &old.properties, &new.properties);
+            if old.trailing != new.trailing {
+                self.push(
+                    format!("{}.trailing", path),
+                    Some(bytes_text(&old.trailing)),
+                    Some(bytes_text(&new.trailing)),
+                );
+            }
+        }
+    }
+
+    fn properties(&mut self, path: &str, old: &[Property], new: &[Property]) {
+        for property in old.iter() {
+            let path = format!("{}.{}", path, property.name);
+            match new.iter().find(|p| p.name == property.name) {
+                Some(other) if other.type_name != property.type_name => self.push(
+                    path,
+                    Some(self.typed_text(self.old, property)),
+                    Some(self.typed_text(self.new, other)),
+                ),
+                Some(other) => self.value(&path, &property.value, &other.value),
+                // defaults filled in from the type registry are not in the file
+                None if property.default => {}
+                None => {
+                    let old = value_text(self.old, &property.value);
+                    self.push(path, Some(old), None);
+                }
+            }
+        }
+        for property in new.iter() {
+            if !property.default && !old.iter().any(|p| p.name == property.name) {
+                let path = format!("{}.{}", path, property.name);
+                let new = value_text(self.new, &property.value);
+                self.push(path, None, Some(new));
+            }
+        }
+    }
+
+    fn value(&mut self, path: &str, old: &Value, new: &Value) {
+        match (old, new) {
+            (Value::Struct(old), Value::Struct(new)) => self.properties(path, old, new),
+            (Value::Array(old), Value::Array(new)) => {
+                for index in 0..old.len().max(new.len()) {
+                    let path = format!("{}[{}]", path, index);
+                    match (old.get(index), new.get(index)) {
+                        (Some(old), Some(new)) => self.value(&path, old, new),
+                        (Some(old), None) => {
+                            let old = value_text(self.old, old);
+                            self.push(path, Some(old), None);
+                        }
+                        (None, Some(new)) => {
+                            let new = value_text(self.new, new);
+                            self.push(path, None, Some(new));
+                        }
+                        (None, None) => {}
+                    }
+                }
+            }
+            // import indices differ between files, references are compared by depot path
+            (Value::ResourceReference(_), Value::ResourceReference(_)) => {
+                let old = value_text(self.old, old);
+                let new = value_text(self.new, new);
+                if old != new {
+                    self.push(path.to_owned(), Some(old), Some(new));
+                }
+            }
+            // the text is only for display, e.g. all raw values of the same size have the same text
+            _ => {
+                if !same_value(old, new) {
+                    let old = value_text(self.old, old);
+                    let new = value_text(self.new, new);
+                    self.push(path.to_owned(), Some(old), Some(new));
+                }
+            }
+        }
+    }
+
+    fn buffers(&mut self) {
+        let count = self.old.buffers.len().max(self.new.buffers.len());
+        for index in 0..count {
+            let path = format!("buffers[{}]", index);
+            match (self.old.buffers.get(index), self.new.buffers.get(index)) {
+                (Some(old), Some(new)) => {
+                    self.compare(&format!("{}.flags", path), old.flags, new.flags);
+                    if old.data != new.data {
+                        self.push(
+                            path,
+                            Some(bytes_text(&old.data)),
+                            Some(bytes_text(&new.data)),
+                        );
+                    }
+                }
+                (Some(old), None) => self.push(path, Some(bytes_text(&old.data)), None),
+                (None, Some(new)) => self.push(path, None, Some(bytes_text(&new.data))),
+                (None, None) => {}
+            }
+        }
+    }
+
+    fn typed_text(&self, file: &CR2WFile, property: &Property) -> String {
+        format!(
+            "{} {}",
+            property.type_name,
+            value_text(file, &property.value)
+        )
+    }
+}
+
+/// A value as text, references are resolved in the file
+pub(crate) fn value_text(file: &CR2WFile, value: &Value) -> String {
+    match value {
+        Value::Bool(value) => value.to_string(),
+        Value::Int8(value) => value.to_string(),
+        Value::Uint8(value) => value.to_string(),
+        Value::Int16(value) => value.to_string(),
+        Value::Uint16(value) => value.to_string(),
+        Value::Int32(value) => value.to_string(),
+        Value::Uint32(value) => value.to_string(),
+        Value::Int64(value) => value.to_string(),
+        Value::Uint64(value) => value.to_string(),
+        Value::Float(value) => value.to_string(),
+        Value::Double(value) => value.to_string(),
+        Value::CName(value) | Value::String(value) => format!("{:?}", value),
+        Value::Enum(value) => value.to_owned(),
+        Value::Bitfield(values) if values.is_empty() => "0".to_owned(),
+        Value::Bitfield(values) => values.join(" | "),
+        Value::Handle(index) | Value::WeakHandle(index) => match index {
+            Some(index) => match file.chunks.get(*index) {
+                Some(chunk) => format!("chunks[{}] {}", index, chunk.class_name),
+                None => chunk_text(Some(*index)),
+            },
+            None => "null".to_owned(),
+        },
+        Value::ResourceReference(index) => match index.and_then(|i| file.info.imports.get(i)) {
+            Some(import) => import.depot_path.clone(),
+            None => "null".to_owned(),
+        },
+        Value::Array(values) => {
+            let values = values
+                .iter()
+                .map(|v| value_text(file, v))
+                .collect::<Vec<_>>();
+            format!("[{}]", values.join(", "))
+        }
+        Value::Struct(properties) => {
+            let properties = properties
+                .iter()
+                .map(|p| format!("{}: {}", p.name, value_text(file, &p.value)))
+                .collect::<Vec<_>>();
+            format!("{{{}}}", properties.join(", "))
+        }
+        Value::Raw(bytes) => bytes_text(bytes),
+    }
+}
+
+/// Values are the same if they are equal, floats are compared bitwise so that NaN is unchanged
+fn same_value(old: &Value, new: &Value) -> bool {
+    match (old, new) {
+        (Value::Float(old), Value::Float(new)) => old.to_bits() == new.to_bits(),
+        (Value::Double(old), Value::Double(new)) => old.to_bits() == new.to_bits(),
+        _ => old == new,
+    }
+}
+
+fn chunk_text(index: Option<usize>) -> String {
+    index.map_or("null".to_owned(), |index| format!("chunks[{}]", index))
+}
+
+fn bytes_text(bytes: &[u8]) -> String {
+    format!("<{} bytes>", bytes.len())
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// TESTS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::{
+        cr2w::ImportFlags,
+        fixtures::{chunk, entity, import, property},
+    };
+
+    fn path() -> PathBuf {
+        PathBuf::from("tests")
+            .join("data")
+            .join("base/cycleweapons/localization/en-us.json")
+    }
+
+    #[test]
+    fn identical() {
+        assert!(diff_cr2w(&entity(), &entity()).is_empty());
+        assert!(diff_cr2w_files(&path(), &path()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn changes() {
+        let old = entity();
+        let mut new = entity();
+        // reordered imports with changed flags are compared by depot path
+        new.info.imports = vec![
+            import("base\\b.app", ImportFlags::DEFAULT),
+            import("base\\a.mesh", ImportFlags::DEFAULT),
+            import("base\\c.mesh", ImportFlags::DEFAULT),
+        ];
+        new.chunks[1].properties[0].value = Value::ResourceReference(Some(2));
+        new.chunks[1].properties.remove(1);
+        new.chunks[1]
+            .properties
+            .push(property("castShadows", "Bool", Value::Bool(false)));
+        new.chunks[1].properties[1].value = Value::Float(2.5);
+        if let Value::Array(appearances) = &mut new.chunks[0].properties[1].value {
+            appearances[0] = Value::Struct(vec![
+                property("name", "CName", Value::CName("damaged".to_owned())),
+                property(
+                    "appearanceResource",
+                    "raRef:appearanceAppearanceResource",
+                    Value::ResourceReference(Some(0)),
+                ),
+            ]);
+            appearances.push(Value::Struct(vec![property(
+                "name",
+                "CName",
+                Value::CName("clean".to_owned()),
+            )]));
+        }
+        new.chunks
+            .push(chunk("entSkinnedMeshComponent", Some(0), vec![]));
+
+        let report = diff_cr2w(&old, &new)
+            .iter()
+            .map(|d| d.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "~ imports[base\\b.app].flags: Soft -> Default",
+                "+ imports[base\\c.mesh]: Default",
+                "~ chunks[0].appearances[0].name: \"default\" -> \"damaged\"",
+                // the index stays, the import it refers to moved
+                "~ chunks[0].appearances[1].appearanceResource: base\\b.app -> base\\a.mesh",
+                "+ chunks[0].appearances[2]: {name: \"clean\"}",
+                "~ chunks[1].mesh: base\\a.mesh -> base\\c.mesh",
+                "- chunks[1].visible: true",
+                "~ chunks[1].scale: 1 -> 2.5",
+                "+ chunks[1].castShadows: false",
+                "+ chunks[3]: entSkinnedMeshComponent",
+            ],
+            report
+        );
+
+        let differences = diff_cr2w(&new, &old);
+        assert_eq!(DifferenceKind::Removed, differences[1].kind());
+        assert_eq!(DifferenceKind::Changed, differences[2].kind());
+        assert_eq!(DifferenceKind::Added, differences[8].kind());
+    }
+
+    #[test]
+    fn raw_values() {
+        let mut old = entity();
+        old.chunks[1]
+            .properties
+            .push(property("data", "DataBuffer", Value::Raw(vec![1, 2])));
+        let mut new = old.clone();
+        assert!(diff_cr2w(&old, &new).is_empty());
+
+        // values with the same text are still compared by their bytes
+        new.chunks[1].properties.last_mut().unwrap().value = Value::Raw(vec![3, 4]);
+        let differences = diff_cr2w(&old, &new);
+        assert_eq!(1, differences.len());
+        assert_eq!(
+            "~ chunks[1].data: <2 bytes> -> <2 bytes>",
+            differences[0].to_string()
+        );
+    }
+
+    #[test]
+    fn types_and_classes() {
+        let old = entity();
+        let mut new = entity();
+        new.chunks[1].properties[2] = property("scale", "Double", Value::Double(1.0));
+        new.chunks[0].class_name = "entEntity".to_owned();
+        new.chunks[1].trailing = vec![0; 4];
+
+        let report = diff_cr2w(&old, &new)
+            .iter()
+            .map(|d| d.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "~ chunks[0]: entEntityTemplate -> entEntity",
+                "~ chunks[1].scale: Float 1 -> Double 1",
+                "~ chunks[1].trailing: <0 bytes> -> <4 bytes>",
+            ],
+            report
+        );
+    }
+}
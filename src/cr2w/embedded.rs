@@ -0,0 +1,558 @@
+/////////////////////////////////////////////////////////////////////////////////////////
+// CR2W EMBEDDED FILES
+// Resources can be stored inline in another file. An entry of the embeds table points to
+// an import with the `Embedded` flag that holds the depot path of the resource, and to the
+// root chunk of the resource. The resource is made of the root chunk and all chunks that
+// are reachable from it through handles or as children, chunks whose parent is part of it.
+//
+// DataBuffer values refer to buffers as u32 0x80000000 | 1-based buffer index.
+/////////////////////////////////////////////////////////////////////////////////////////
+
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+
+use super::*;
+use crate::hash::ResourceHash;
+
+/// Bit of DataBuffer values that refer to a buffer of the file
+const BUFFER_REFERENCE: u32 = 0x8000_0000;
+
+/// A resource that is stored inside a cr2w file
+#[derive(Debug, Clone, PartialEq)]
+pub struct CR2WEmbeddedFile {
+    /// Index of the import with the depot path of the resource
+    pub import: usize,
+    pub depot_path: String,
+    /// Index of the root chunk of the resource
+    pub chunk: usize,
+    pub class_name: String,
+}
+
+impl CR2WFile {
+    /// Gets the files of the embeds table
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if an entry refers to an import or chunk that doesn't exist.
+    pub fn embedded_files(&self) -> Result<Vec<CR2WEmbeddedFile>> {
+        (0..self.info.embeds_table.len())
+            .map(|index| self.embedded_file(index))
+            .collect()
+    }
+
+    /// Gets a file of the embeds table by its index
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the index is out of range or the entry refers to an import
+    /// or chunk that doesn't exist.
+    pub fn embedded_file(&self, index: usize) -> Result<CR2WEmbeddedFile> {
+        let embed = self
+            .info
+            .embeds_table
+            .get(index)
+            .ok_or_else(|| invalid_entry("embed", index, "out of range"))?;
+
+        // import indices are 1-based
+        let import = (embed.import_index as usize)
+            .checked_sub(1)
+            .filter(|import| *import < self.info.imports.len())
+            .ok_or_else(|| {
+                invalid_entry(
+                    "embed",
+                    index,
+                    &format!("import {} out of range", embed.import_index),
+                )
+            })?;
+        let chunk = embed.chunk_index as usize;
+        let root = self.chunks.get(chunk).ok_or_else(|| {
+            invalid_entry("embed", index, &format!("chunk {} out of range", chunk))
+        })?;
+
+        Ok(CR2WEmbeddedFile {
+            import,
+            depot_path: self.info.imports[import].depot_path.clone(),
+            chunk,
+            class_name: root.class_name.clone(),
+        })
+    }
+
+    /// Indices of the chunks of an embedded file in ascending order, the root chunk and all chunks
+    /// reachable from it through handles or as children
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the entry of the embeds table is invalid.
+    pub fn embedded_chunks(&self, index: usize) -> Result<Vec<usize>> {
+        let root = self.embedded_file(index)?.chunk;
+
+        let mut visited = vec![false; self.chunks.len()];
+        let mut stack = vec![root];
+        while let Some(index) = stack.pop() {
+            // handles to chunks that don't exist are reported when they are remapped
+            if index >= self.chunks.len() || visited[index] {
+                continue;
+            }
+            visited[index] = true;
+
+            stack.extend(self.children(index));
+            for property in self.chunks[index].properties.iter() {
+                collect_handles(&property.value, &mut stack);
+            }
+        }
+
+        Ok(visited
+            .iter()
+            .enumerate()
+            .filter(|(_, visited)| **visited)
+            .map(|(index, _)| index)
+            .collect())
+    }
+
+    /// Pulls an embedded file out as a standalone file
+    ///
+    /// Its root chunk becomes the first chunk and only the imports and buffers it refers to are kept.
+    /// The header fields and names are taken from this file, so that undecoded data keeps referring to
+    /// the right names. Buffers that are only referred to from undecoded data are not carried over.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the entry of the embeds table is invalid or a chunk of the
+    /// embedded file refers to an import or buffer that doesn't exist.
+    pub fn extract_embedded(&self, index: usize) -> Result<CR2WFile> {
+        let embedded = self.embedded_file(index)?;
+        let mut indices = self.embedded_chunks(index)?;
+        // the root chunk comes first
+        indices.retain(|index| *index != embedded.chunk);
+        indices.insert(0, embedded.chunk);
+        let chunk_map: HashMap<usize, usize> = indices
+            .iter()
+            .enumerate()
+            .map(|(new, old)| (*old, new))
+            .collect();
+
+        // old indices of the imports and buffers in their new order
+        let mut imports = vec![];
+        let mut buffers = vec![];
+        let mut map = |reference: Reference| match reference {
+            Reference::Chunk(index) => chunk_map.get(&index).copied().ok_or_else(|| {
+                invalid_data(format!("chunk {} is not part of the embedded file", index))
+            }),
+            Reference::Import(index) if index < self.info.imports.len() => {
+                Ok(position_or_push(&mut imports, index))
+            }
+            Reference::Buffer(index) if index < self.buffers.len() => {
+                Ok(position_or_push(&mut buffers, index))
+            }
+            Reference::Import(index) => Err(invalid_data(format!("import {} out of range", index))),
+            Reference::Buffer(index) => Err(invalid_data(format!("buffer {} out of range", index))),
+        };
+
+        let mut chunks = vec![];
+        for index in indices.iter() {
+            let mut chunk = self.chunks[*index].clone();
+            chunk.parent = chunk
+                .parent
+                .and_then(|parent| chunk_map.get(&parent).copied());
+            remap_properties(&mut chunk.properties, &mut map)
+                .map_err(|e| invalid_chunk(*index, &e.to_string()))?;
+            chunks.push(chunk);
+        }
+
+        // embedded files of the embedded file
+        let mut embeds_table = vec![];
+        for embed in self.info.embeds_table.iter() {
+            let Some(chunk) = chunk_map.get(&(embed.chunk_index as usize)) else {
+                continue;
+            };
+            if embed.chunk_index as usize == embedded.chunk {
+                continue;
+            }
+            let import = (embed.import_index as usize)
+                .checked_sub(1)
+                .ok_or_else(|| invalid_data("embed without import".to_owned()))?;
+            embeds_table.push(CR2WEmbeddedInfo {
+                import_index: map(Reference::Import(import))? as u32 + 1,
+                chunk_index: *chunk as u32,
+                path_hash: embed.path_hash,
+            });
+        }
+
+        let info = CR2WFileInfo {
+            header: self.info.header,
+            names_table: vec![],
+            imports_table: vec![],
+            properties_table: vec![],
+            exports_table: vec![],
+            buffers_table: vec![],
+            embeds_table,
+            strings: HashMap::default(),
+            names: self.info.names.clone(),
+            imports: imports
+                .iter()
+                .map(|index| self.info.imports[*index].clone())
+                .collect(),
+        };
+        let mut file = CR2WFile {
+            info,
+            chunks,
+            buffers: buffers
+                .iter()
+                .map(|index| self.buffers[*index].clone())
+                .collect(),
+        };
+        file.update_buffers_table();
+        Ok(file)
+    }
+
+    /// Embeds a standalone file, its chunks, imports and buffers are appended to this file
+    ///
+    /// The first chunk of `file` is the root chunk of the embedded file. Returns the index of the new
+    /// entry of the embeds table.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the depot path is already embedded, if `file` has no chunks
+    /// or refers to chunks, imports or buffers it doesn't have, or if it has undecoded data and its names
+    /// don't line up with the names of this file.
+    pub fn embed(&mut self, depot_path: &str, file: &CR2WFile) -> Result<usize> {
+        if file.chunks.is_empty() {
+            return Err(invalid_input("the embedded file has no chunks"));
+        }
+        if self.info.imports.iter().any(|import| {
+            import.depot_path == depot_path && import.flags.contains(ImportFlags::EMBEDDED)
+        }) {
+            return Err(invalid_input("the depot path is already embedded"));
+        }
+
+        // undecoded data refers to names by index, these can only be kept if the name tables agree
+        let common = self.info.names.len().min(file.info.names.len());
+        if self.info.names[..common] == file.info.names[..common] {
+            self.info
+                .names
+                .extend_from_slice(&file.info.names[common..]);
+        } else if has_undecoded_data(file) {
+            return Err(invalid_input(
+                "the embedded file has undecoded data and its names don't match the names of this file",
+            ));
+        }
+
+        let chunk_offset = self.chunks.len();
+        let buffer_offset = self.buffers.len();
+        let mut import_map: HashMap<usize, usize> = HashMap::default();
+        let imports = &mut self.info.imports;
+        let mut map = |reference: Reference| match reference {
+            Reference::Chunk(index) if index < file.chunks.len() => Ok(chunk_offset + index),
+            Reference::Buffer(index) if index < file.buffers.len() => Ok(buffer_offset + index),
+            Reference::Import(index) if index < file.info.imports.len() => {
+                if let Some(mapped) = import_map.get(&index) {
+                    return Ok(*mapped);
+                }
+                let import = &file.info.imports[index];
+                let mapped = match imports.iter().position(|existing| {
+                    existing.depot_path == import.depot_path && existing.flags == import.flags
+                }) {
+                    Some(existing) => existing,
+                    None => {
+                        imports.push(import.clone());
+                        imports.len() - 1
+                    }
+                };
+                import_map.insert(index, mapped);
+                Ok(mapped)
+            }
+            Reference::Chunk(index) => Err(invalid_data(format!("chunk {} out of range", index))),
+            Reference::Import(index) => Err(invalid_data(format!("import {} out of range", index))),
+            Reference::Buffer(index) => Err(invalid_data(format!("buffer {} out of range", index))),
+        };
+
+        let mut chunks = vec![];
+        for (index, chunk) in file.chunks.iter().enumerate() {
+            let mut chunk = chunk.clone();
+            chunk.parent = match chunk.parent {
+                Some(parent) => Some(map(Reference::Chunk(parent))?),
+                None => None,
+            };
+            remap_properties(&mut chunk.properties, &mut map)
+                .map_err(|e| invalid_chunk(index, &e.to_string()))?;
+            chunks.push(chunk);
+        }
+
+        // embedded files of the embedded file
+        let mut embeds_table = vec![];
+        for (index, embed) in file.info.embeds_table.iter().enumerate() {
+            let import = (embed.import_index as usize)
+                .checked_sub(1)
+                .ok_or_else(|| invalid_entry("embed", index, "no import"))?;
+            embeds_table.push(CR2WEmbeddedInfo {
+                import_index: map(Reference::Import(import))? as u32 + 1,
+                chunk_index: map(Reference::Chunk(embed.chunk_index as usize))? as u32,
+                path_hash: embed.path_hash,
+            });
+        }
+
+        // the import has the class of the root chunk
+        self.info.imports.push(Import {
+            class_name: file.chunks[0].class_name.clone(),
+            depot_path: depot_path.to_owned(),
+            flags: ImportFlags::EMBEDDED,
+        });
+        let index = self.info.embeds_table.len();
+        self.info.embeds_table.push(CR2WEmbeddedInfo {
+            import_index: self.info.imports.len() as u32,
+            chunk_index: chunk_offset as u32,
+            path_hash: ResourceHash::from_depot_path(depot_path).0,
+        });
+        self.info.embeds_table.extend(embeds_table);
+        self.chunks.extend(chunks);
+        self.buffers.extend_from_slice(&file.buffers);
+        self.update_buffers_table();
+
+        Ok(index)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// HELPERS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+/// An index that a value refers to
+pub(super) enum Reference {
+    Chunk(usize),
+    Import(usize),
+    Buffer(usize),
+}
+
+/// Replaces the chunk, import and buffer indices of values with the result of `map`
+pub(super) fn remap_properties<F>(properties: &mut [Property], map: &mut F) -> Result<()>
+where
+    F: FnMut(Reference) -> Result<usize>,
+{
+    for property in properties.iter_mut() {
+        remap_value(&mut property.value, &property.type_name, map)
+            .map_err(|e| invalid_data(format!("{}: {}", property.name, e)))?;
+    }
+    Ok(())
+}
+
+pub(super) fn remap_value<F>(value: &mut Value, type_name: &str, map: &mut F) -> Result<()>
+where
+    F: FnMut(Reference) -> Result<usize>,
+{
+    match value {
+        Value::Handle(Some(index)) | Value::WeakHandle(Some(index)) => {
+            *index = map(Reference::Chunk(*index))?;
+        }
+        Value::ResourceReference(Some(index)) => *index = map(Reference::Import(*index))?,
+        Value::Raw(bytes) if type_name == "DataBuffer" => {
+            if let Some(index) = buffer_reference(bytes) {
+                let index = map(Reference::Buffer(index))?;
+                *bytes = (BUFFER_REFERENCE | (index as u32 + 1))
+                    .to_le_bytes()
+                    .to_vec();
+            }
+        }
+        Value::Array(values) => {
+            let inner = element_type(type_name);
+            for value in values.iter_mut() {
+                remap_value(value, inner, map)?;
+            }
+        }
+        Value::Struct(properties) => remap_properties(properties, map)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// The 0-based index of the buffer that a DataBuffer value refers to
+fn buffer_reference(bytes: &[u8]) -> Option<usize> {
+    let value = u32::from_le_bytes(bytes.try_into().ok()?);
+    if value & BUFFER_REFERENCE == 0 {
+        return None;
+    }
+    ((value & !BUFFER_REFERENCE) as usize).checked_sub(1)
+}
+
+fn collect_handles(value: &Value, handles: &mut Vec<usize>) {
+    match value {
+        Value::Handle(Some(index)) | Value::WeakHandle(Some(index)) => handles.push(*index),
+        Value::Array(values) => values.iter().for_each(|v| collect_handles(v, handles)),
+        Value::Struct(properties) => properties
+            .iter()
+            .for_each(|p| collect_handles(&p.value, handles)),
+        _ => {}
+    }
+}
+
+/// True if the file has bytes that might refer to names by index
+pub(super) fn has_undecoded_data(file: &CR2WFile) -> bool {
+    fn is_raw(value: &Value, type_name: &str) -> bool {
+        match value {
+            Value::Raw(_) => type_name != "DataBuffer",
+            Value::Array(values) => values
+                .iter()
+                .any(|value| is_raw(value, element_type(type_name))),
+            Value::Struct(properties) => properties.iter().any(|p| is_raw(&p.value, &p.type_name)),
+            _ => false,
+        }
+    }
+
+    file.chunks.iter().any(|chunk| {
+        !chunk.decoded
+            || !chunk.trailing.is_empty()
+            || chunk
+                .properties
+                .iter()
+                .any(|p| is_raw(&p.value, &p.type_name))
+    })
+}
+
+fn position_or_push(indices: &mut Vec<usize>, index: usize) -> usize {
+    match indices.iter().position(|i| *i == index) {
+        Some(position) => position,
+        None => {
+            indices.push(index);
+            indices.len() - 1
+        }
+    }
+}
+
+fn invalid_data(msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn invalid_input(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidInput, msg)
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// TESTS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Cursor, path::PathBuf};
+
+    use super::super::validation::validate_cr2w;
+    use super::super::writer::{tests::cr2w_test_files, write_cr2w};
+    use super::*;
+
+    fn files_with_embeds() -> Vec<CR2WFile> {
+        cr2w_test_files()
+            .into_iter()
+            .map(|(_, buffer)| read_cr2w(&mut Cursor::new(&buffer)).unwrap())
+            .filter(|file| !file.info.embeds_table.is_empty())
+            .collect()
+    }
+
+    /// Writes and reads a file, the written file has to be valid
+    fn rewrite(file: &CR2WFile) -> CR2WFile {
+        let mut output = vec![];
+        write_cr2w(&mut output, file).unwrap();
+        assert!(validate_cr2w(&mut Cursor::new(&output)).unwrap().is_empty());
+        read_cr2w(&mut Cursor::new(&output)).unwrap()
+    }
+
+    fn assert_same_file(expected: &CR2WFile, actual: &CR2WFile) {
+        assert_eq!(expected.chunks, actual.chunks);
+        assert_eq!(
+            format!("{:?}", expected.info.imports),
+            format!("{:?}", actual.info.imports)
+        );
+        assert_eq!(
+            expected.buffers.iter().map(|b| &b.data).collect::<Vec<_>>(),
+            actual.buffers.iter().map(|b| &b.data).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn extract() {
+        let files = files_with_embeds();
+        assert!(!files.is_empty());
+        for file in files {
+            for (index, embedded) in file.embedded_files().unwrap().into_iter().enumerate() {
+                let import = &file.info.imports[embedded.import];
+                assert!(import.flags.contains(ImportFlags::EMBEDDED));
+                assert_eq!("None", import.class_name);
+                assert_eq!(None, file.chunks[embedded.chunk].parent);
+
+                let chunks = file.embedded_chunks(index).unwrap();
+                assert!(chunks.contains(&embedded.chunk));
+
+                let extracted = file.extract_embedded(index).unwrap();
+                assert_eq!(chunks.len(), extracted.chunks.len());
+                assert_eq!(embedded.class_name, extracted.chunks[0].class_name);
+                assert_eq!(vec![0], extracted.roots().collect::<Vec<_>>());
+                assert!(extracted.buffers.len() <= file.buffers.len());
+                assert_same_file(&extracted, &rewrite(&extracted));
+            }
+        }
+        assert!(files_with_embeds()[0].extract_embedded(usize::MAX).is_err());
+    }
+
+    #[test]
+    fn embed_into_parent() {
+        let mut file = files_with_embeds().remove(0);
+        let embedded = file.embedded_file(0).unwrap();
+        let extracted = file.extract_embedded(0).unwrap();
+
+        // the depot path is already embedded
+        assert!(file.embed(&embedded.depot_path, &extracted).is_err());
+
+        let depot_path = format!("{}_copy", embedded.depot_path);
+        let index = file.embed(&depot_path, &extracted).unwrap();
+        let written = rewrite(&file);
+        let copy = written.embedded_file(index).unwrap();
+        assert_eq!(depot_path, copy.depot_path);
+        assert_eq!(embedded.class_name, copy.class_name);
+        assert_eq!(
+            copy.class_name,
+            written.info.imports[copy.import].class_name
+        );
+        assert_eq!(
+            ResourceHash::from_depot_path(&depot_path).0,
+            written.info.embeds_table[index].path_hash
+        );
+        assert_same_file(&extracted, &written.extract_embedded(index).unwrap());
+    }
+
+    #[test]
+    fn embed_into_other_file() {
+        let buffer = fs::read(
+            PathBuf::from("tests")
+                .join("data")
+                .join("base/cycleweapons/localization/en-us.json"),
+        )
+        .unwrap();
+        let mut file = read_cr2w(&mut Cursor::new(&buffer)).unwrap();
+        assert!(file.embedded_files().unwrap().is_empty());
+
+        // names of undecoded data can't be remapped, embed a file with buffers and without undecoded data
+        let extracted = files_with_embeds()
+            .iter()
+            .flat_map(|file| {
+                (0..file.info.embeds_table.len()).map(|i| file.extract_embedded(i).unwrap())
+            })
+            .filter(|extracted| !has_undecoded_data(extracted))
+            .max_by_key(|extracted| extracted.buffers.len())
+            .unwrap();
+        assert!(!extracted.buffers.is_empty());
+        let depot_path = "base\\embedded\\resource";
+        let chunks = file.chunks.len();
+        assert_eq!(0, file.embed(depot_path, &extracted).unwrap());
+        assert_eq!(chunks + extracted.chunks.len(), file.chunks.len());
+
+        let written = rewrite(&file);
+        let embedded = written.embedded_files().unwrap();
+        assert_eq!(1, embedded.len());
+        assert_eq!(depot_path, embedded[0].depot_path);
+        assert_eq!(chunks, embedded[0].chunk);
+        assert_same_file(&extracted, &written.extract_embedded(0).unwrap());
+
+        // the original chunks are unchanged
+        assert_eq!(
+            read_cr2w(&mut Cursor::new(&buffer)).unwrap().chunks,
+            written.chunks[..chunks]
+        );
+    }
+}
@@ -0,0 +1,1353 @@
+/////////////////////////////////////////////////////////////////////////////////////////
+// CR2W JSON
+// Converts cr2w files to and from the JSON of WolvenKit:
+//
+// {
+//   "Header": { "WolvenKitVersion", "WKitJsonVersion", "DataType": "CR2W", "ArchiveFileName" },
+//   "Data":   { "Version", "BuildVersion", "RootChunk", "EmbeddedFiles": [{ "FileName", "Content" }] }
+// }
+//
+// Chunks are objects with a `$type`. A handle inlines the chunk the first time it is used as
+// {"HandleId", "Data"}, later uses are {"HandleRefId"}. Handle ids count up from 0 in the
+// order they are given out. Enums are member names, bitfields are member names separated by
+// commas and 64 bit integers are strings.
+//
+// The JSON has no types of properties, these are taken from the lossless section, from a
+// type registry or from the `$type` of the value, in that order. Without the lossless
+// section, chunks are numbered in the order they are read and properties that are equal to
+// their default in the registry are marked as defaults.
+//
+// The lossless section holds what WolvenKit recomputes, so a file is written back
+// identically:
+//
+// "Red4lib": {
+//   "Flags", "TimeStamp", "NumChunks", "Names", "Imports", "Properties", "Buffers", "Embeds",
+//   "Types":     classes with the types of their properties, as in an RTTI dump
+//   "Bitfields": names of the bitfield types
+//   "Chunks":    export info in the order chunks are read: "Index", "HandleId", "Parent",
+//                "Flags", "Template", "Trailing", "Raw", "Defaults" (paths of properties that
+//                are not in the file) and "Data" for chunks that no handle refers to
+// }
+//
+// Values that couldn't be decoded are {"$raw": base64}.
+/////////////////////////////////////////////////////////////////////////////////////////
+
+use std::io::{Error, ErrorKind, Read, Result, Write};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::{json, Map, Number, Value as Json};
+
+use super::embedded::{remap_properties, Reference};
+use super::*;
+use crate::hash::ResourceHash;
+
+/// The WolvenKit release whose layout is written
+const WOLVENKIT_VERSION: &str = "8.14.0";
+const JSON_VERSION: &str = "0.0.8";
+/// `num_chunks` of the header of files without a lossless section, the number of tables after
+/// the string table
+const NUM_CHUNKS: u32 = 6;
+/// Key of the lossless section
+const LOSSLESS: &str = "Red4lib";
+
+/// Converts a cr2w file to the JSON of WolvenKit
+///
+/// # Errors
+///
+/// This function will return an error if a handle or resource reference is out of range.
+pub fn cr2w_to_json(file: &CR2WFile) -> Result<Json> {
+    JsonWriter::new(file).write(false)
+}
+
+/// Converts a cr2w file to the JSON of WolvenKit with a lossless section, the JSON is
+/// converted back to an identical file
+///
+/// # Errors
+///
+/// This function will return an error if a handle or resource reference is out of range.
+pub fn cr2w_to_json_lossless(file: &CR2WFile) -> Result<Json> {
+    JsonWriter::new(file).write(true)
+}
+
+/// Converts JSON back to a cr2w file, types come from the lossless section or the `$type` of
+/// values
+///
+/// Only the header fields and the tables that `write_cr2w` doesn't rebuild are filled in.
+///
+/// # Errors
+///
+/// This function will return an error if the JSON doesn't describe a cr2w file or the type of
+/// a property is unknown.
+pub fn cr2w_from_json(json: &Json) -> Result<CR2WFile> {
+    read_json(json, None)
+}
+
+/// Converts JSON back to a cr2w file, types that are not in the lossless section come from
+/// the registry
+///
+/// # Errors
+///
+/// This function will return an error if the JSON doesn't describe a cr2w file or the type of
+/// a property is unknown.
+pub fn cr2w_from_json_with_types(json: &Json, types: &TypeRegistry) -> Result<CR2WFile> {
+    read_json(json, Some(types))
+}
+
+/// Writes a cr2w file as pretty printed JSON of WolvenKit
+///
+/// # Errors
+///
+/// This function will return an error if io fails or a handle or resource reference is out of
+/// range.
+pub fn write_cr2w_json<W: Write>(writer: &mut W, file: &CR2WFile) -> Result<()> {
+    let json = cr2w_to_json(file)?;
+    serde_json::to_writer_pretty(writer, &json).map_err(Error::other)
+}
+
+/// Writes a cr2w file as pretty printed JSON of WolvenKit with a lossless section
+///
+/// # Errors
+///
+/// This function will return an error if io fails or a handle or resource reference is out of
+/// range.
+pub fn write_cr2w_json_lossless<W: Write>(writer: &mut W, file: &CR2WFile) -> Result<()> {
+    let json = cr2w_to_json_lossless(file)?;
+    serde_json::to_writer_pretty(writer, &json).map_err(Error::other)
+}
+
+/// Reads a cr2w file from JSON
+///
+/// # Errors
+///
+/// This function will return an error if io fails, the JSON doesn't describe a cr2w file or
+/// the type of a property is unknown.
+pub fn read_cr2w_json<R: Read>(reader: &mut R) -> Result<CR2WFile> {
+    cr2w_from_json(&parse_json(reader)?)
+}
+
+/// Reads a cr2w file from JSON, types that are not in the lossless section come from the
+/// registry
+///
+/// # Errors
+///
+/// This function will return an error if io fails, the JSON doesn't describe a cr2w file or
+/// the type of a property is unknown.
+pub fn read_cr2w_json_with_types<R: Read>(
+    reader: &mut R,
+    types: &TypeRegistry,
+) -> Result<CR2WFile> {
+    cr2w_from_json_with_types(&parse_json(reader)?, types)
+}
+
+fn parse_json<R: Read>(reader: &mut R) -> Result<Json> {
+    serde_json::from_reader(reader).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// CR2W TO JSON
+/////////////////////////////////////////////////////////////////////////////////////////
+
+struct JsonWriter<'a> {
+    file: &'a CR2WFile,
+    /// Handle ids of chunks, given out in the order handles are written
+    handles: Vec<Option<usize>>,
+    next_handle: usize,
+    /// Chunks that are already written and chunks that are written inside a handle
+    written: Vec<bool>,
+    inlined: Vec<bool>,
+    /// Export info in the order chunks are written, and the position of each chunk in it
+    exports: Vec<Map<String, Json>>,
+    positions: Vec<usize>,
+    /// Types of the properties of classes and structs
+    classes: Vec<ClassType>,
+    bitfields: Vec<String>,
+}
+
+impl<'a> JsonWriter<'a> {
+    fn new(file: &'a CR2WFile) -> Self {
+        let count = file.chunks.len();
+        Self {
+            file,
+            handles: vec![None; count],
+            next_handle: 0,
+            written: vec![false; count],
+            inlined: vec![false; count],
+            exports: vec![],
+            positions: vec![0; count],
+            classes: vec![],
+            bitfields: vec![],
+        }
+    }
+
+    fn write(mut self, lossless: bool) -> Result<Json> {
+        let file = self.file;
+        let root = if file.chunks.is_empty() {
+            Json::Null
+        } else {
+            self.chunk(0)?
+        };
+
+        let mut embedded_files = vec![];
+        for (index, embed) in file.info.embeds_table.iter().enumerate() {
+            let import = (embed.import_index as usize)
+                .checked_sub(1)
+                .and_then(|i| file.info.imports.get(i))
+                .ok_or_else(|| invalid_json(format!("embed {} has no import", index)))?;
+            let chunk = embed.chunk_index as usize;
+            if chunk >= file.chunks.len() || self.written[chunk] {
+                return Err(invalid_json(format!(
+                    "embed {}: chunk {} is out of range or already written",
+                    index, chunk
+                )));
+            }
+            embedded_files.push(json!({
+                "FileName": resource_path(&import.depot_path),
+                "Content": self.chunk(chunk)?,
+            }));
+        }
+
+        let mut json = json!({
+            "Header": {
+                "WolvenKitVersion": WOLVENKIT_VERSION,
+                "WKitJsonVersion": JSON_VERSION,
+                "DataType": "CR2W",
+                "ArchiveFileName": "",
+            },
+            "Data": {
+                "Version": file.info.header.version,
+                "BuildVersion": file.info.header.build_version,
+                "RootChunk": root,
+                "EmbeddedFiles": embedded_files,
+            },
+        });
+        if lossless {
+            json[LOSSLESS] = self.lossless()?;
+        }
+        Ok(json)
+    }
+
+    /// The lossless section, chunks that are not written yet are added with their data
+    fn lossless(mut self) -> Result<Json> {
+        let file = self.file;
+        for index in 0..file.chunks.len() {
+            if !self.written[index] {
+                let position = self.exports.len();
+                let data = self.chunk(index)?;
+                self.exports[position].insert("Data".to_owned(), data);
+            }
+        }
+        for (index, handle) in self.handles.iter().enumerate() {
+            if let (Some(id), false) = (handle, self.inlined[index]) {
+                self.exports[self.positions[index]]
+                    .insert("HandleId".to_owned(), id.to_string().into());
+            }
+        }
+
+        let info = &file.info;
+        let imports = info
+            .imports
+            .iter()
+            .map(|import| {
+                json!({
+                    "ClassName": import.class_name,
+                    "DepotPath": import.depot_path,
+                    "Flags": import.flags.to_string(),
+                })
+            })
+            .collect::<Vec<_>>();
+        let properties = info
+            .properties_table
+            .iter()
+            .map(|property| {
+                json!({
+                    "ClassName": property.class_name,
+                    "ClassFlags": property.class_flags,
+                    "PropertyName": property.property_name,
+                    "PropertyFlags": property.property_flags,
+                    "Hash": property.hash.to_string(),
+                })
+            })
+            .collect::<Vec<_>>();
+        let buffers = file
+            .buffers
+            .iter()
+            .map(|buffer| {
+                json!({
+                    "Index": buffer.index,
+                    "Flags": buffer.flags,
+                    "MemSize": buffer.mem_size,
+                    "Data": STANDARD.encode(&buffer.data),
+                })
+            })
+            .collect::<Vec<_>>();
+        let embeds = info
+            .embeds_table
+            .iter()
+            .map(|embed| {
+                json!({
+                    "ImportIndex": embed.import_index,
+                    "ChunkIndex": embed.chunk_index,
+                    "PathHash": embed.path_hash.to_string(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        Ok(json!({
+            "Flags": info.header.flags,
+            "TimeStamp": info.header.time_stamp.to_string(),
+            "NumChunks": info.header.num_chunks,
+            "Names": info.names,
+            "Imports": imports,
+            "Properties": properties,
+            "Buffers": buffers,
+            "Embeds": embeds,
+            "Types": serde_json::to_value(&self.classes).map_err(Error::other)?,
+            "Bitfields": self.bitfields,
+            "Chunks": self.exports,
+        }))
+    }
+
+    fn chunk(&mut self, index: usize) -> Result<Json> {
+        let chunk = &self.file.chunks[index];
+        self.written[index] = true;
+        let position = self.exports.len();
+        self.positions[index] = position;
+
+        let mut export = Map::new();
+        export.insert("Index".to_owned(), index.into());
+        if let Some(parent) = chunk.parent {
+            export.insert("Parent".to_owned(), parent.into());
+        }
+        if chunk.flags != 0 {
+            export.insert("Flags".to_owned(), chunk.flags.into());
+        }
+        if chunk.template != 0 {
+            export.insert("Template".to_owned(), chunk.template.into());
+        }
+        if !chunk.decoded {
+            export.insert("Raw".to_owned(), STANDARD.encode(&chunk.trailing).into());
+        } else if !chunk.trailing.is_empty() {
+            export.insert(
+                "Trailing".to_owned(),
+                STANDARD.encode(&chunk.trailing).into(),
+            );
+        }
+        self.exports.push(export);
+
+        let mut object = Map::new();
+        object.insert("$type".to_owned(), chunk.class_name.clone().into());
+        if chunk.decoded {
+            let mut defaults = vec![];
+            self.properties(
+                &chunk.class_name,
+                &chunk.properties,
+                "",
+                &mut object,
+                &mut defaults,
+            )
+            .map_err(|e| context(e, &format!("chunk {}", index)))?;
+            if !defaults.is_empty() {
+                self.exports[position].insert("Defaults".to_owned(), defaults.into());
+            }
+        }
+
+        Ok(object.into())
+    }
+
+    /// Adds the properties to `object`, the paths of defaults are added to `defaults`
+    fn properties(
+        &mut self,
+        class_name: &str,
+        properties: &[Property],
+        path: &str,
+        object: &mut Map<String, Json>,
+        defaults: &mut Vec<String>,
+    ) -> Result<()> {
+        for property in properties {
+            self.add_type(class_name, property);
+            let path = property_path(path, &property.name);
+            if property.default {
+                defaults.push(path.clone());
+            }
+            let value = self
+                .value(&property.value, &property.type_name, &path, defaults)
+                .map_err(|e| context(e, &property.name))?;
+            object.insert(property.name.clone(), value);
+        }
+        Ok(())
+    }
+
+    fn value(
+        &mut self,
+        value: &Value,
+        type_name: &str,
+        path: &str,
+        defaults: &mut Vec<String>,
+    ) -> Result<Json> {
+        let json = match value {
+            Value::Bool(v) => (*v).into(),
+            Value::Int8(v) => (*v).into(),
+            Value::Uint8(v) => (*v).into(),
+            Value::Int16(v) => (*v).into(),
+            Value::Uint16(v) => (*v).into(),
+            Value::Int32(v) => (*v).into(),
+            Value::Uint32(v) => (*v).into(),
+            // 64 bit integers are strings, they don't fit into a JavaScript number
+            Value::Int64(v) => v.to_string().into(),
+            Value::Uint64(v) if type_name == "TweakDBID" => json!({
+                "$type": type_name,
+                "$storage": "uint64",
+                "$value": v.to_string(),
+            }),
+            Value::Uint64(v) => v.to_string().into(),
+            // the shortest representation of the f32, not of the f64 with the same value
+            Value::Float(v) => float_to_json(v.to_string().parse().unwrap_or(f64::NAN)),
+            Value::Double(v) => float_to_json(*v),
+            Value::CName(v) => json!({
+                "$type": "CName",
+                "$storage": "string",
+                "$value": v,
+            }),
+            Value::String(v) | Value::Enum(v) => v.clone().into(),
+            Value::Bitfield(v) => {
+                if !self.bitfields.iter().any(|name| name == type_name) {
+                    self.bitfields.push(type_name.to_owned());
+                }
+                if v.is_empty() {
+                    "0".into()
+                } else {
+                    v.join(", ").into()
+                }
+            }
+            Value::Handle(Some(index)) | Value::WeakHandle(Some(index))
+                if *index >= self.file.chunks.len() =>
+            {
+                return Err(invalid_json(format!("chunk {} is out of range", index)));
+            }
+            Value::Handle(Some(index)) if !self.written[*index] => {
+                let id = self.handle(*index);
+                self.inlined[*index] = true;
+                json!({
+                    "HandleId": id.to_string(),
+                    "Data": self.chunk(*index)?,
+                })
+            }
+            Value::Handle(Some(index)) | Value::WeakHandle(Some(index)) => {
+                json!({ "HandleRefId": self.handle(*index).to_string() })
+            }
+            Value::Handle(None) => json!({ "HandleId": "-1", "Data": null }),
+            Value::WeakHandle(None) => json!({ "HandleRefId": "-1" }),
+            Value::ResourceReference(Some(index)) => {
+                let import =
+                    self.file.info.imports.get(*index).ok_or_else(|| {
+                        invalid_json(format!("import {} is out of range", index + 1))
+                    })?;
+                json!({
+                    "DepotPath": resource_path(&import.depot_path),
+                    "Flags": import.flags.to_string(),
+                })
+            }
+            Value::ResourceReference(None) => json!({
+                "DepotPath": {
+                    "$type": "ResourcePath",
+                    "$storage": "uint64",
+                    "$value": "0",
+                },
+                "Flags": ImportFlags::DEFAULT.to_string(),
+            }),
+            Value::Array(values) => {
+                let inner = element_type(type_name);
+                let mut elements = vec![];
+                for (i, value) in values.iter().enumerate() {
+                    let path = format!("{}[{}]", path, i);
+                    elements.push(
+                        self.value(value, inner, &path, defaults)
+                            .map_err(|e| context(e, &i.to_string()))?,
+                    );
+                }
+                elements.into()
+            }
+            Value::Struct(properties) => {
+                let mut object = Map::new();
+                object.insert("$type".to_owned(), type_name.into());
+                self.properties(type_name, properties, path, &mut object, defaults)?;
+                object.into()
+            }
+            Value::Raw(bytes) => json!({ "$raw": STANDARD.encode(bytes) }),
+        };
+
+        Ok(json)
+    }
+
+    /// The handle id of a chunk, a new id is given out the first time
+    fn handle(&mut self, index: usize) -> usize {
+        match self.handles[index] {
+            Some(id) => id,
+            None => {
+                let id = self.next_handle;
+                self.next_handle += 1;
+                self.handles[index] = Some(id);
+                id
+            }
+        }
+    }
+
+    /// Adds the type of a property to its class
+    fn add_type(&mut self, class_name: &str, property: &Property) {
+        let class = match self.classes.iter().position(|c| c.name == class_name) {
+            Some(position) => &mut self.classes[position],
+            None => {
+                self.classes.push(ClassType {
+                    name: class_name.to_owned(),
+                    ..Default::default()
+                });
+                self.classes.last_mut().unwrap()
+            }
+        };
+        if !class.properties.iter().any(|p| p.name == property.name) {
+            class.properties.push(PropertyType {
+                name: property.name.clone(),
+                type_name: property.type_name.clone(),
+                default: None,
+            });
+        }
+    }
+}
+
+fn resource_path(depot_path: &str) -> Json {
+    json!({
+        "$type": "ResourcePath",
+        "$storage": "string",
+        "$value": depot_path,
+    })
+}
+
+/// Non-finite numbers are named like in WolvenKit
+fn float_to_json(value: f64) -> Json {
+    match Number::from_f64(value) {
+        Some(number) => number.into(),
+        None if value.is_nan() => "NaN".into(),
+        None if value > 0.0 => "Infinity".into(),
+        None => "-Infinity".into(),
+    }
+}
+
+/// The path of a property in a chunk, like the paths of [`Difference`]
+fn property_path(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}.{}", path, name)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// JSON TO CR2W
+/////////////////////////////////////////////////////////////////////////////////////////
+
+fn read_json(json: &Json, types: Option<&TypeRegistry>) -> Result<CR2WFile> {
+    if let Some(data_type) = json.get("Header").and_then(|h| h.get("DataType")) {
+        if data_type != "CR2W" {
+            return Err(invalid_json(format!("{} is not a cr2w file", data_type)));
+        }
+    }
+    let data = json
+        .get("Data")
+        .and_then(Json::as_object)
+        .ok_or_else(|| invalid_json("Data is missing"))?;
+    let field = |name: &str| {
+        data.get(name)
+            .ok_or_else(|| invalid_json(format!("{} is missing", name)))
+    };
+    let lossless = json.get(LOSSLESS);
+    let extra = |name: &str| lossless.and_then(|section| section.get(name));
+    let list = |name: &str| -> Result<&[Json]> {
+        extra(name).map_or(Ok(&[]), |json| Ok(array(json)?.as_slice()))
+    };
+
+    let header = CR2WFileHeader {
+        version: integer(field("Version")?)?,
+        flags: extra("Flags").map_or(Ok(0), integer)?,
+        time_stamp: extra("TimeStamp").map_or(Ok(0), integer)?,
+        build_version: integer(field("BuildVersion")?)?,
+        objects_end: 0,
+        buffers_end: 0,
+        crc32: 0,
+        num_chunks: extra("NumChunks").map_or(Ok(NUM_CHUNKS), integer)?,
+    };
+    let names = list("Names")?
+        .iter()
+        .map(string)
+        .collect::<Result<Vec<_>>>()?;
+    let imports = list("Imports")?
+        .iter()
+        .map(|import| {
+            Ok(Import {
+                class_name: string(member(import, "ClassName")?)?,
+                depot_path: string(member(import, "DepotPath")?)?,
+                flags: import_flags_from_json(member(import, "Flags")?)?,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let properties_table = list("Properties")?
+        .iter()
+        .map(|property| {
+            Ok(CR2WPropertyInfo {
+                class_name: integer(member(property, "ClassName")?)?,
+                class_flags: integer(member(property, "ClassFlags")?)?,
+                property_name: integer(member(property, "PropertyName")?)?,
+                property_flags: integer(member(property, "PropertyFlags")?)?,
+                hash: integer(member(property, "Hash")?)?,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let buffers = list("Buffers")?
+        .iter()
+        .map(|buffer| {
+            Ok(CR2WBuffer {
+                flags: integer(member(buffer, "Flags")?)?,
+                index: integer(member(buffer, "Index")?)?,
+                mem_size: integer(member(buffer, "MemSize")?)?,
+                data: bytes(member(buffer, "Data")?)?,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut file_types = TypeRegistry::new();
+    for class in list("Types")? {
+        let class: ClassType = serde_json::from_value(class.clone())
+            .map_err(|e| invalid_json(format!("Types: {}", e)))?;
+        file_types.insert_class(class);
+    }
+    let bitfields = list("Bitfields")?
+        .iter()
+        .map(string)
+        .collect::<Result<Vec<_>>>()?;
+
+    // chunks
+    let mut reader = JsonReader {
+        types,
+        file_types,
+        bitfields,
+        exports: list("Chunks")?,
+        lossless: lossless.is_some(),
+        read: 0,
+        chunks: vec![],
+        handles: HashMap::new(),
+        imports,
+    };
+    let root = field("RootChunk")?;
+    if !root.is_null() {
+        reader.chunk(root, None)?;
+    }
+    let mut embedded_files = vec![];
+    for (index, embedded) in data
+        .get("EmbeddedFiles")
+        .map_or(Ok(&vec![]), array)?
+        .iter()
+        .enumerate()
+    {
+        let depot_path = string(storage_value(member(embedded, "FileName")?))
+            .map_err(|e| context(e, &format!("embedded file {}", index)))?;
+        let chunk = reader.chunk(member(embedded, "Content")?, None)?;
+        embedded_files.push((depot_path, chunk));
+    }
+    // chunks that no handle refers to
+    while let Some(export) = reader.exports.get(reader.read) {
+        reader.chunk(member(export, "Data")?, None)?;
+    }
+
+    let mut chunks = reader
+        .chunks
+        .into_iter()
+        .enumerate()
+        .map(|(i, chunk)| chunk.ok_or_else(|| invalid_json(format!("chunk {} is missing", i))))
+        .collect::<Result<Vec<_>>>()?;
+    // handles refer to handle ids until all chunks are read
+    let handles = reader.handles;
+    for (index, chunk) in chunks.iter_mut().enumerate() {
+        remap_properties(&mut chunk.properties, &mut |reference| match reference {
+            Reference::Chunk(id) => handles
+                .get(&id)
+                .copied()
+                .ok_or_else(|| invalid_json(format!("handle {} has no chunk", id))),
+            Reference::Import(index) | Reference::Buffer(index) => Ok(index),
+        })
+        .map_err(|e| context(e, &format!("chunk {}", index)))?;
+    }
+
+    let mut imports = reader.imports;
+    let embeds_table = match extra("Embeds") {
+        Some(embeds) => array(embeds)?
+            .iter()
+            .map(|embed| {
+                Ok(CR2WEmbeddedInfo {
+                    import_index: integer(member(embed, "ImportIndex")?)?,
+                    chunk_index: integer(member(embed, "ChunkIndex")?)?,
+                    path_hash: integer(member(embed, "PathHash")?)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?,
+        // the import of an embedded file has the class of its root chunk
+        None => embedded_files
+            .into_iter()
+            .map(|(depot_path, chunk)| {
+                let path_hash = ResourceHash::from_depot_path(&depot_path).0;
+                imports.push(Import {
+                    class_name: chunks[chunk].class_name.clone(),
+                    depot_path,
+                    flags: ImportFlags::EMBEDDED,
+                });
+                CR2WEmbeddedInfo {
+                    import_index: imports.len() as u32,
+                    chunk_index: chunk as u32,
+                    path_hash,
+                }
+            })
+            .collect(),
+    };
+
+    let info = CR2WFileInfo {
+        header,
+        names_table: vec![],
+        imports_table: vec![],
+        properties_table,
+        exports_table: vec![],
+        buffers_table: vec![],
+        embeds_table,
+        strings: HashMap::default(),
+        names,
+        imports,
+    };
+    Ok(CR2WFile {
+        info,
+        chunks,
+        buffers,
+    })
+}
+
+struct JsonReader<'a> {
+    types: Option<&'a TypeRegistry>,
+    /// Types and bitfields of the lossless section
+    file_types: TypeRegistry,
+    bitfields: Vec<String>,
+    /// Export info of the lossless section in the order chunks are read
+    exports: &'a [Json],
+    lossless: bool,
+    /// Number of chunks read so far
+    read: usize,
+    chunks: Vec<Option<Chunk>>,
+    /// Chunk indices by handle id
+    handles: HashMap<usize, usize>,
+    /// Imports of the file, resource references to new depot paths are appended
+    imports: Vec<Import>,
+}
+
+impl JsonReader<'_> {
+    /// Reads a chunk and returns its index
+    fn chunk(&mut self, json: &Json, handle: Option<usize>) -> Result<usize> {
+        let position = self.read;
+        self.read += 1;
+        let export = match self.exports.get(position) {
+            Some(export) => Some(export),
+            None if self.lossless => {
+                return Err(invalid_json(format!(
+                    "{}: chunk {} is missing",
+                    LOSSLESS, position
+                )))
+            }
+            None => None,
+        };
+        let index = export
+            .and_then(|export| export.get("Index"))
+            .map_or(Ok(position), integer)?;
+        let handle = match handle {
+            Some(id) => Some(id),
+            None => export
+                .and_then(|export| export.get("HandleId"))
+                .map(integer)
+                .transpose()?,
+        };
+        if let Some(id) = handle {
+            if self.handles.insert(id, index).is_some() {
+                return Err(invalid_json(format!("handle {} is defined twice", id)));
+            }
+        }
+
+        let chunk = self
+            .read_chunk(json, export)
+            .map_err(|e| context(e, &format!("chunk {}", index)))?;
+        if self.chunks.len() <= index {
+            self.chunks.resize(index + 1, None);
+        }
+        if self.chunks[index].is_some() {
+            return Err(invalid_json(format!("chunk {} is defined twice", index)));
+        }
+        self.chunks[index] = Some(chunk);
+        Ok(index)
+    }
+
+    fn read_chunk(&mut self, json: &Json, export: Option<&Json>) -> Result<Chunk> {
+        let object = json
+            .as_object()
+            .ok_or_else(|| invalid_json("expected an object"))?;
+        let class_name = string(member(json, "$type")?)?;
+        let field = |name: &str| export.and_then(|export| export.get(name));
+        let parent = field("Parent").map(integer).transpose()?;
+        let flags = field("Flags").map_or(Ok(0), integer)?;
+        let template = field("Template").map_or(Ok(0), integer)?;
+        let defaults = field("Defaults")
+            .map_or(Ok(&vec![]), array)?
+            .iter()
+            .map(string)
+            .collect::<Result<Vec<_>>>()?;
+
+        let (properties, trailing, decoded) = match field("Raw") {
+            Some(raw) => (vec![], bytes(raw)?, false),
+            None => (
+                self.properties(&class_name, object, "", &defaults)?,
+                field("Trailing").map_or(Ok(vec![]), bytes)?,
+                true,
+            ),
+        };
+
+        Ok(Chunk {
+            class_name,
+            flags,
+            parent,
+            template,
+            properties,
+            trailing,
+            decoded,
+        })
+    }
+
+    /// Reads the keys that don't start with `$` as properties
+    fn properties(
+        &mut self,
+        class_name: &str,
+        object: &Map<String, Json>,
+        path: &str,
+        defaults: &[String],
+    ) -> Result<Vec<Property>> {
+        let mut properties = vec![];
+        for (name, json) in object.iter().filter(|(name, _)| !name.starts_with('$')) {
+            let type_name = self
+                .type_name(class_name, name, json)
+                .ok_or_else(|| invalid_json(format!("{}: type is unknown", name)))?;
+            let path = property_path(path, name);
+            let value = self
+                .value(json, &type_name, &path, defaults)
+                .map_err(|e| context(e, name))?;
+            let default = if self.lossless {
+                defaults.contains(&path)
+            } else {
+                self.types
+                    .and_then(|types| types.default_value(class_name, name))
+                    .is_some_and(|default| default == value)
+            };
+            properties.push(Property {
+                name: name.to_owned(),
+                type_name,
+                value,
+                default,
+            });
+        }
+        Ok(properties)
+    }
+
+    /// The type of a property from the lossless section, the registry or the value
+    fn type_name(&self, class_name: &str, name: &str, json: &Json) -> Option<String> {
+        let declared = |types: &TypeRegistry| {
+            types
+                .properties(class_name)
+                .into_iter()
+                .find(|p| p.name == name)
+                .map(|p| p.type_name.clone())
+        };
+        declared(&self.file_types)
+            .or_else(|| self.types.and_then(declared))
+            .or_else(|| value_type(json))
+    }
+
+    fn is_bitfield(&self, type_name: &str) -> bool {
+        self.bitfields.iter().any(|name| name == type_name)
+            || self.types.and_then(|types| types.kind(type_name)) == Some(TypeKind::Bitfield)
+    }
+
+    fn value(
+        &mut self,
+        json: &Json,
+        type_name: &str,
+        path: &str,
+        defaults: &[String],
+    ) -> Result<Value> {
+        if let Some(raw) = json.get("$raw") {
+            return Ok(Value::Raw(bytes(raw)?));
+        }
+
+        let value = match type_name {
+            "Bool" => Value::Bool(
+                json.as_bool()
+                    .ok_or_else(|| invalid_json("expected a bool"))?,
+            ),
+            "Int8" => Value::Int8(integer(json)?),
+            "Uint8" => Value::Uint8(integer(json)?),
+            "Int16" => Value::Int16(integer(json)?),
+            "Uint16" => Value::Uint16(integer(json)?),
+            "Int32" => Value::Int32(integer(json)?),
+            "Uint32" => Value::Uint32(integer(json)?),
+            "Int64" => Value::Int64(integer(json)?),
+            "Uint64" | "TweakDBID" | "CRUID" | "CDateTime" | "gamedataLocKeyWrapper" => {
+                Value::Uint64(integer(storage_value(json))?)
+            }
+            "Float" => Value::Float(float(json)? as f32),
+            "Double" => Value::Double(float(json)?),
+            "CName" => Value::CName(string(storage_value(json))?),
+            "String" => Value::String(string(json)?),
+            _ => {
+                if type_name.starts_with("array:") || type_name.starts_with('[') {
+                    let inner = element_type(type_name);
+                    let mut values = vec![];
+                    for (i, element) in array(json)?.iter().enumerate() {
+                        let path = format!("{}[{}]", path, i);
+                        values.push(
+                            self.value(element, inner, &path, defaults)
+                                .map_err(|e| context(e, &i.to_string()))?,
+                        );
+                    }
+                    Value::Array(values)
+                } else if type_name.starts_with("handle:") {
+                    Value::Handle(self.handle(json)?)
+                } else if type_name.starts_with("whandle:") {
+                    Value::WeakHandle(match json {
+                        Json::Null => None,
+                        _ => handle_id(member(json, "HandleRefId")?)?,
+                    })
+                } else if let Some(inner) = type_name
+                    .strip_prefix("rRef:")
+                    .or_else(|| type_name.strip_prefix("raRef:"))
+                {
+                    Value::ResourceReference(self.import(json, inner)?)
+                } else if type_name.contains(':') {
+                    return Err(invalid_json("unsupported generic type"));
+                } else {
+                    match json {
+                        Json::String(names) if self.is_bitfield(type_name) => Value::Bitfield(
+                            names
+                                .split(',')
+                                .map(str::trim)
+                                .filter(|name| !name.is_empty() && *name != "0")
+                                .map(str::to_owned)
+                                .collect(),
+                        ),
+                        Json::String(name) => Value::Enum(name.to_owned()),
+                        Json::Object(object) => {
+                            Value::Struct(self.properties(type_name, object, path, defaults)?)
+                        }
+                        _ => return Err(invalid_json("expected a string or an object")),
+                    }
+                }
+            }
+        };
+
+        Ok(value)
+    }
+
+    /// Reads a handle, the referenced chunk is read if it is inlined. The handle id is kept
+    /// until all chunks are read.
+    fn handle(&mut self, json: &Json) -> Result<Option<usize>> {
+        if json.is_null() {
+            return Ok(None);
+        }
+        if let Some(id) = json.get("HandleRefId") {
+            return handle_id(id);
+        }
+        let id = handle_id(member(json, "HandleId")?)?;
+        if let (Some(id), Some(data)) = (id, json.get("Data")) {
+            if !data.is_null() {
+                self.chunk(data, Some(id))?;
+            }
+        }
+        Ok(id)
+    }
+
+    /// Finds the import of a resource reference, new depot paths are added to the imports
+    fn import(&mut self, json: &Json, class_name: &str) -> Result<Option<usize>> {
+        let path = member(json, "DepotPath")?;
+        let depot_path = string(storage_value(path))?;
+        if path.get("$storage").and_then(Json::as_str) == Some("uint64") {
+            if depot_path == "0" {
+                return Ok(None);
+            }
+            return Err(invalid_json("depot path hashes can't be resolved"));
+        }
+        let flags = json
+            .get("Flags")
+            .map_or(Ok(ImportFlags::DEFAULT), import_flags_from_json)?;
+
+        let index = match self
+            .imports
+            .iter()
+            .position(|import| import.depot_path == depot_path && import.flags == flags)
+        {
+            Some(index) => index,
+            None => {
+                self.imports.push(Import {
+                    class_name: class_name.to_owned(),
+                    depot_path,
+                    flags,
+                });
+                self.imports.len() - 1
+            }
+        };
+        Ok(Some(index))
+    }
+}
+
+/// The type of a value that has a `$type`, like structs, names and inlined handles
+fn value_type(json: &Json) -> Option<String> {
+    match json {
+        Json::Bool(_) => Some("Bool".to_owned()),
+        Json::Array(values) => values
+            .first()
+            .and_then(value_type)
+            .map(|inner| format!("array:{}", inner)),
+        Json::Object(object) if object.contains_key("HandleId") => object
+            .get("Data")
+            .and_then(|data| data.get("$type"))
+            .and_then(Json::as_str)
+            .map(|class_name| format!("handle:{}", class_name)),
+        Json::Object(object) => object
+            .get("$type")
+            .and_then(Json::as_str)
+            .map(str::to_owned),
+        _ => None,
+    }
+}
+
+fn import_flags_from_json(json: &Json) -> Result<ImportFlags> {
+    string(json)?.parse()
+}
+
+/// Unwraps `{"$storage": ..., "$value": value}`
+fn storage_value(json: &Json) -> &Json {
+    json.get("$value").unwrap_or(json)
+}
+
+/// A handle id, `-1` is null
+fn handle_id(json: &Json) -> Result<Option<usize>> {
+    let id: i64 = integer(json)?;
+    if id == -1 {
+        Ok(None)
+    } else {
+        usize::try_from(id)
+            .map(Some)
+            .map_err(|_| invalid_json(format!("invalid handle id {}", id)))
+    }
+}
+
+fn member<'a>(json: &'a Json, name: &str) -> Result<&'a Json> {
+    json.get(name)
+        .ok_or_else(|| invalid_json(format!("{} is missing", name)))
+}
+
+fn array(json: &Json) -> Result<&Vec<Json>> {
+    json.as_array()
+        .ok_or_else(|| invalid_json("expected an array"))
+}
+
+fn string(json: &Json) -> Result<String> {
+    json.as_str()
+        .map(str::to_owned)
+        .ok_or_else(|| invalid_json("expected a string"))
+}
+
+/// An integer from a number or a string
+fn integer<T: std::str::FromStr>(json: &Json) -> Result<T> {
+    let text = match json {
+        Json::Number(number) => number.to_string(),
+        Json::String(text) => text.to_owned(),
+        _ => return Err(invalid_json("expected an integer")),
+    };
+    text.parse()
+        .map_err(|_| invalid_json(format!("invalid integer {}", text)))
+}
+
+fn float(json: &Json) -> Result<f64> {
+    match json {
+        Json::Number(number) => number
+            .as_f64()
+            .ok_or_else(|| invalid_json("invalid number")),
+        Json::String(text) => match text.as_str() {
+            "NaN" => Ok(f64::NAN),
+            "Infinity" => Ok(f64::INFINITY),
+            "-Infinity" => Ok(f64::NEG_INFINITY),
+            _ => Err(invalid_json(format!("invalid number {}", text))),
+        },
+        _ => Err(invalid_json("expected a number")),
+    }
+}
+
+fn bytes(json: &Json) -> Result<Vec<u8>> {
+    STANDARD
+        .decode(string(json)?)
+        .map_err(|e| invalid_json(format!("invalid base64: {}", e)))
+}
+
+fn invalid_json(msg: impl std::fmt::Display) -> Error {
+    Error::new(ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Prefixes an error with the position in the file
+fn context(error: Error, at: &str) -> Error {
+    Error::new(error.kind(), format!("{}: {}", at, error))
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// TESTS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::cr2w::writer::{tests::cr2w_test_files, write_cr2w};
+
+    const TYPES: &str = r#"{
+        "classes": [
+            { "name": "ISerializable" },
+            {
+                "name": "CResource",
+                "parent": "ISerializable",
+                "properties": [
+                    { "name": "cookingPlatform", "type": "ECookingPlatform" },
+                    { "name": "platforms", "type": "EPlatformFlags" }
+                ]
+            },
+            {
+                "name": "JsonResource",
+                "parent": "CResource",
+                "properties": [{ "name": "root", "type": "handle:ISerializable" }]
+            },
+            {
+                "name": "localizationPersistenceOnScreenEntries",
+                "parent": "ISerializable",
+                "properties": [
+                    { "name": "entries", "type": "array:localizationPersistenceOnScreenEntry" }
+                ]
+            },
+            {
+                "name": "localizationPersistenceOnScreenEntry",
+                "properties": [
+                    { "name": "primaryKey", "type": "Uint64" },
+                    { "name": "secondaryKey", "type": "String" },
+                    { "name": "femaleVariant", "type": "String" },
+                    { "name": "maleVariant", "type": "String" }
+                ]
+            }
+        ],
+        "enums": [
+            {
+                "name": "ECookingPlatform",
+                "members": [{ "name": "PLATFORM_None", "value": 0 }, { "name": "PLATFORM_PC", "value": 1 }]
+            }
+        ],
+        "bitfields": [
+            {
+                "name": "EPlatformFlags",
+                "members": [{ "name": "PC", "bit": 0 }, { "name": "Console", "bit": 1 }]
+            }
+        ]
+    }"#;
+
+    fn path() -> PathBuf {
+        PathBuf::from("tests")
+            .join("data")
+            .join("base")
+            .join("cycleweapons")
+            .join("localization")
+            .join("en-us.json")
+    }
+
+    fn roundtrip(buffer: &[u8]) -> Vec<u8> {
+        let file = read_cr2w(&mut Cursor::new(buffer)).unwrap();
+        let mut text = vec![];
+        write_cr2w_json_lossless(&mut text, &file).unwrap();
+
+        let file = read_cr2w_json(&mut Cursor::new(&text)).unwrap();
+        let mut output = vec![];
+        write_cr2w(&mut output, &file).unwrap();
+        output
+    }
+
+    #[test]
+    fn data_roundtrip() {
+        let data = PathBuf::from("tests").join("data");
+        for path in [
+            path(),
+            data.join("base")
+                .join("sound")
+                .join("metadata")
+                .join("cooked_metadata.audio_metadata"),
+            data.join("ep1")
+                .join("sound")
+                .join("metadata")
+                .join("cooked_metadata.audio_metadata"),
+        ] {
+            let buffer = std::fs::read(&path).unwrap();
+            let output = roundtrip(&buffer);
+            assert!(
+                buffer == output,
+                "{} is not converted back identically",
+                path.display()
+            );
+        }
+    }
+
+    #[test]
+    fn archive_roundtrip() {
+        for (hash, buffer) in cr2w_test_files() {
+            let file = read_cr2w(&mut Cursor::new(&buffer)).unwrap();
+            let file = cr2w_from_json(&cr2w_to_json_lossless(&file).unwrap()).unwrap();
+            let mut output = vec![];
+            write_cr2w(&mut output, &file).unwrap();
+            assert!(
+                buffer == output,
+                "{} is not converted back identically",
+                hash
+            );
+        }
+    }
+
+    #[test]
+    fn wolvenkit_layout() {
+        let buffer = std::fs::read(path()).unwrap();
+        let file = read_cr2w(&mut Cursor::new(&buffer)).unwrap();
+        let json = cr2w_to_json(&file).unwrap();
+
+        assert_eq!(WOLVENKIT_VERSION, json["Header"]["WolvenKitVersion"]);
+        assert_eq!("CR2W", json["Header"]["DataType"]);
+        assert!(json.get(LOSSLESS).is_none());
+        let keys = json["Data"].as_object().unwrap().keys().collect::<Vec<_>>();
+        assert_eq!(
+            vec!["Version", "BuildVersion", "RootChunk", "EmbeddedFiles"],
+            keys
+        );
+        let root = &json["Data"]["RootChunk"];
+        assert_eq!("JsonResource", root["$type"]);
+        assert!(root.get("$types").is_none());
+        assert_eq!("PLATFORM_PC", root["cookingPlatform"]);
+        // handle ids count up from 0
+        assert_eq!("0", root["root"]["HandleId"]);
+        let entries = &root["root"]["Data"];
+        assert_eq!("localizationPersistenceOnScreenEntries", entries["$type"]);
+        assert!(entries["entries"].as_array().is_some_and(|e| !e.is_empty()));
+
+        // the types of enums and strings are not in the JSON
+        let error = cr2w_from_json(&json).unwrap_err();
+        assert_eq!(
+            "chunk 0: cookingPlatform: type is unknown",
+            error.to_string()
+        );
+        let types = TypeRegistry::from_reader(TYPES.as_bytes()).unwrap();
+        let read = cr2w_from_json_with_types(&json, &types).unwrap();
+        assert!(diff_cr2w(&file, &read).is_empty());
+    }
+
+    #[test]
+    fn wolvenkit_export() {
+        let buffer = std::fs::read(path()).unwrap();
+        let file = read_cr2w(&mut Cursor::new(&buffer)).unwrap();
+        // exported by WolvenKit from the localization file of the test data
+        let json_path = PathBuf::from("tests").join("json").join("en-us.json.json");
+        let text = std::fs::read(json_path).unwrap();
+
+        let types = TypeRegistry::from_reader(TYPES.as_bytes()).unwrap();
+        let exported = read_cr2w_json_with_types(&mut Cursor::new(&text), &types).unwrap();
+        assert_eq!(file.chunks.len(), exported.chunks.len());
+        // WolvenKit writes all properties, the ones with their default value are not in the file
+        let Some(Value::Array(entries)) = exported.chunks[1].get("entries") else {
+            panic!("entries is not an array");
+        };
+        let Value::Struct(entry) = &entries[0] else {
+            panic!("entry is not a struct");
+        };
+        assert!(entry.iter().any(|p| p.name == "maleVariant" && p.default));
+        assert!(entry
+            .iter()
+            .any(|p| p.name == "femaleVariant" && !p.default));
+        assert!(diff_cr2w(&file, &exported).is_empty());
+
+        let mut output = vec![];
+        write_cr2w(&mut output, &exported).unwrap();
+        let written = read_cr2w(&mut Cursor::new(&output)).unwrap();
+        assert!(diff_cr2w(&file, &written).is_empty());
+    }
+
+    #[test]
+    fn bitfields() {
+        let buffer = std::fs::read(path()).unwrap();
+        let mut file = read_cr2w(&mut Cursor::new(&buffer)).unwrap();
+        file.chunks[0].properties.push(Property {
+            name: "platforms".to_owned(),
+            type_name: "EPlatformFlags".to_owned(),
+            value: Value::Bitfield(vec!["PC".to_owned(), "Console".to_owned()]),
+            default: false,
+        });
+        let json = cr2w_to_json(&file).unwrap();
+        assert_eq!("PC, Console", json["Data"]["RootChunk"]["platforms"]);
+
+        let types = TypeRegistry::from_reader(TYPES.as_bytes()).unwrap();
+        let read = cr2w_from_json_with_types(&json, &types).unwrap();
+        assert_eq!(
+            file.chunks[0].get("platforms"),
+            read.chunks[0].get("platforms")
+        );
+
+        file.chunks[0].properties[2].value = Value::Bitfield(vec![]);
+        let json = cr2w_to_json_lossless(&file).unwrap();
+        assert_eq!("0", json["Data"]["RootChunk"]["platforms"]);
+        let read = cr2w_from_json(&json).unwrap();
+        assert_eq!(file.chunks, read.chunks);
+    }
+
+    #[test]
+    fn edited_json() {
+        let buffer = std::fs::read(path()).unwrap();
+        let file = read_cr2w(&mut Cursor::new(&buffer)).unwrap();
+        let mut json = cr2w_to_json_lossless(&file).unwrap();
+
+        let root = &mut json["Data"]["RootChunk"];
+        root["cookingPlatform"] = "PLATFORM_PS5".into();
+        let entry = &mut root["root"]["Data"]["entries"][0];
+        entry["femaleVariant"] = "edited".into();
+
+        let edited = cr2w_from_json(&json).unwrap();
+        let mut output = vec![];
+        write_cr2w(&mut output, &edited).unwrap();
+        let written = read_cr2w(&mut Cursor::new(&output)).unwrap();
+        assert_eq!(
+            Some(&Value::Enum("PLATFORM_PS5".to_owned())),
+            written.chunks[0].get("cookingPlatform")
+        );
+        let Some(Value::Array(entries)) = written.chunks[1].get("entries") else {
+            panic!("entries is not an array");
+        };
+        let Value::Struct(entry) = &entries[0] else {
+            panic!("entry is not a struct");
+        };
+        assert!(entry
+            .iter()
+            .any(|p| p.name == "femaleVariant" && p.value == Value::String("edited".to_owned())));
+
+        // errors point to the broken value
+        json["Data"]["RootChunk"]["root"]["Data"]["entries"][0]["femaleVariant"] = 1.into();
+        let error = cr2w_from_json(&json).unwrap_err();
+        assert_eq!(
+            "chunk 0: root: chunk 1: entries: 0: femaleVariant: expected a string",
+            error.to_string()
+        );
+    }
+}
@@ -0,0 +1,840 @@
+/////////////////////////////////////////////////////////////////////////////////////////
+// CR2W MERGE
+// Three-way merge of two edited versions of a CR2W file with their common base, e.g. two
+// mods that edit the same vanilla resource. A change of one side is taken as is, values
+// that both sides changed differently are conflicts and keep the value of `ours`.
+//
+// Chunks are matched by index, properties by name and elements of arrays by index if the
+// array keeps its length on both sides. Resource references are compared by depot path.
+// Imports, chunks and buffers that only `theirs` adds are appended after those of `ours`
+// and references to them are remapped. Embedded files are those of `ours`.
+/////////////////////////////////////////////////////////////////////////////////////////
+
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+
+use super::chunk::element_type;
+use super::diff::value_text;
+use super::embedded::{has_undecoded_data, remap_value, Reference};
+use super::*;
+
+/// A value that both sides changed differently, the merged file keeps the value of `ours`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeConflict {
+    /// Path of the value, as in [`Difference`]
+    pub path: String,
+    /// The values as text, `None` if the value doesn't exist on that side
+    pub base: Option<String>,
+    pub ours: Option<String>,
+    pub theirs: Option<String>,
+}
+
+impl fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = |value: &Option<String>| value.clone().unwrap_or_else(|| "none".to_owned());
+        write!(
+            f,
+            "! {}: {} -> {} | {}",
+            self.path,
+            text(&self.base),
+            text(&self.ours),
+            text(&self.theirs)
+        )
+    }
+}
+
+/// A merged file and its conflicts
+#[derive(Debug, Clone)]
+pub struct MergeResult {
+    pub file: CR2WFile,
+    pub conflicts: Vec<MergeConflict>,
+}
+
+/// Merges the changes of `ours` and `theirs` to `base`
+///
+/// # Errors
+///
+/// This function will return an error if `theirs` has undecoded data and its names don't line up with
+/// the names of `ours`, since undecoded data refers to names by index.
+pub fn merge_cr2w(base: &CR2WFile, ours: &CR2WFile, theirs: &CR2WFile) -> Result<MergeResult> {
+    let mut names = ours.info.names.clone();
+    let common = ours.info.names.len().min(theirs.info.names.len());
+    if ours.info.names[..common] == theirs.info.names[..common] {
+        names.extend_from_slice(&theirs.info.names[common..]);
+    } else if has_undecoded_data(theirs) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "theirs has undecoded data and its names don't match the names of ours",
+        ));
+    }
+
+    let mut merge = Merge {
+        base,
+        ours,
+        theirs,
+        imports: vec![],
+        chunks: vec![],
+        buffers: vec![],
+        conflicts: vec![],
+    };
+    merge.imports();
+    let buffers = merge.buffers();
+    let kept = merge.plan_chunks();
+    let chunks = merge.chunks(kept);
+
+    let mut info = ours.info.clone();
+    info.names = names;
+    let (b, o, t) = (&base.info.header, &ours.info.header, &theirs.info.header);
+    info.header.version = merge.merge3("header.version", &b.version, &o.version, &t.version);
+    info.header.flags = merge.merge3("header.flags", &b.flags, &o.flags, &t.flags);
+    info.header.build_version = merge.merge3(
+        "header.build_version",
+        &b.build_version,
+        &o.build_version,
+        &t.build_version,
+    );
+    info.imports = std::mem::take(&mut merge.imports);
+
+    let mut file = CR2WFile {
+        info,
+        chunks,
+        buffers,
+    };
+    file.update_buffers_table();
+    Ok(MergeResult {
+        file,
+        conflicts: merge.conflicts,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Base,
+    Ours,
+    Theirs,
+}
+
+struct Merge<'a> {
+    base: &'a CR2WFile,
+    ours: &'a CR2WFile,
+    theirs: &'a CR2WFile,
+    /// Imports of the merged file, those of ours followed by the new ones of theirs
+    imports: Vec<Import>,
+    /// Indices of the chunks of theirs in the merged file, `None` if removed
+    chunks: Vec<Option<usize>>,
+    /// Indices of the buffers of theirs in the merged file, `None` if removed
+    buffers: Vec<Option<usize>>,
+    conflicts: Vec<MergeConflict>,
+}
+
+impl Merge<'_> {
+    fn file(&self, side: Side) -> &CR2WFile {
+        match side {
+            Side::Base => self.base,
+            Side::Ours => self.ours,
+            Side::Theirs => self.theirs,
+        }
+    }
+
+    fn conflict(
+        &mut self,
+        path: &str,
+        base: Option<String>,
+        ours: Option<String>,
+        theirs: Option<String>,
+    ) {
+        self.conflicts.push(MergeConflict {
+            path: path.to_owned(),
+            base,
+            ours,
+            theirs,
+        });
+    }
+
+    /// Merges a value that can't be merged any further
+    fn merge3<T: PartialEq + Clone + ToString>(
+        &mut self,
+        path: &str,
+        base: &T,
+        ours: &T,
+        theirs: &T,
+    ) -> T {
+        if ours == base {
+            theirs.clone()
+        } else if theirs != base && theirs != ours {
+            self.conflict(
+                path,
+                Some(base.to_string()),
+                Some(ours.to_string()),
+                Some(theirs.to_string()),
+            );
+            ours.clone()
+        } else {
+            ours.clone()
+        }
+    }
+
+    /////////////////////////////////////////////////////////////////////////////////////
+    // IMPORTS AND BUFFERS
+
+    fn imports(&mut self) {
+        self.imports = self.ours.info.imports.clone();
+        for (index, import) in self.ours.info.imports.iter().enumerate() {
+            let base = self
+                .base
+                .info
+                .imports
+                .iter()
+                .find(|i| i.depot_path == import.depot_path);
+            let theirs = self
+                .theirs
+                .info
+                .imports
+                .iter()
+                .find(|i| i.depot_path == import.depot_path);
+            if let (Some(base), Some(theirs)) = (base, theirs) {
+                let path = format!("imports[{}].flags", import.depot_path);
+                self.imports[index].flags =
+                    self.merge3(&path, &base.flags, &import.flags, &theirs.flags);
+            }
+        }
+        // new imports of theirs, even if only undecoded data refers to them
+        for import in self.theirs.info.imports.iter() {
+            if !import.flags.contains(ImportFlags::EMBEDDED)
+                && !self
+                    .base
+                    .info
+                    .imports
+                    .iter()
+                    .any(|i| i.depot_path == import.depot_path)
+                && !self
+                    .imports
+                    .iter()
+                    .any(|i| i.depot_path == import.depot_path)
+            {
+                self.imports.push(import.clone());
+            }
+        }
+    }
+
+    /// Merges the buffers that exist in base and appends the new ones of theirs
+    fn buffers(&mut self) -> Vec<CR2WBuffer> {
+        let count = self.base.buffers.len();
+        let mut buffers = self.ours.buffers.clone();
+        for (index, buffer) in buffers.iter_mut().enumerate().take(count) {
+            let Some(theirs) = self.theirs.buffers.get(index) else {
+                continue;
+            };
+            let base = &self.base.buffers[index];
+            let same = |a: &CR2WBuffer, b: &CR2WBuffer| a.flags == b.flags && a.data == b.data;
+            if same(buffer, base) {
+                *buffer = theirs.clone();
+            } else if !same(theirs, base) && !same(theirs, buffer) {
+                let text = |b: &CR2WBuffer| Some(format!("<{} bytes>", b.data.len()));
+                let path = format!("buffers[{}]", index);
+                self.conflict(&path, text(base), text(buffer), text(theirs));
+            }
+        }
+
+        let appended = buffers.len();
+        self.buffers = (0..self.theirs.buffers.len())
+            .map(|index| match index < count {
+                true => (index < appended).then_some(index),
+                false => Some(appended + index - count),
+            })
+            .collect();
+        buffers.extend(self.theirs.buffers.iter().skip(count).cloned());
+        buffers
+    }
+
+    /////////////////////////////////////////////////////////////////////////////////////
+    // CHUNKS
+
+    /// The number of base chunks that are kept, and where the chunks of theirs end up
+    ///
+    /// Chunks are only removed from the end, so that the indices of the others stay valid.
+    fn plan_chunks(&mut self) -> usize {
+        let count = self.base.chunks.len();
+        let mut kept = count;
+        if self.ours.chunks.len() <= count {
+            for index in (0..count).rev() {
+                let removed = match (self.ours.chunks.get(index), self.theirs.chunks.get(index)) {
+                    (None, Some(theirs)) => {
+                        if !self.same_chunk(
+                            Side::Theirs,
+                            theirs,
+                            Side::Base,
+                            &self.base.chunks[index],
+                        ) {
+                            let path = format!("chunks[{}]", index);
+                            let base = Some(self.base.chunks[index].class_name.clone());
+                            self.conflict(&path, base, None, Some(theirs.class_name.clone()));
+                        }
+                        true
+                    }
+                    (None, None) => true,
+                    (Some(ours), None) => {
+                        self.same_chunk(Side::Ours, ours, Side::Base, &self.base.chunks[index])
+                    }
+                    (Some(_), Some(_)) => false,
+                };
+                if !removed {
+                    break;
+                }
+                kept = index;
+            }
+        }
+
+        let appended = match self.ours.chunks.len() > count {
+            true => self.ours.chunks.len(),
+            false => kept,
+        };
+        self.chunks = (0..self.theirs.chunks.len())
+            .map(|index| match index < count {
+                true => (index < kept).then_some(index),
+                false => Some(appended + index - count),
+            })
+            .collect();
+        kept
+    }
+
+    fn chunks(&mut self, kept: usize) -> Vec<Chunk> {
+        let mut chunks = vec![];
+        for index in 0..kept {
+            let path = format!("chunks[{}]", index);
+            let base = &self.base.chunks[index];
+            let chunk = match (self.ours.chunks.get(index), self.theirs.chunks.get(index)) {
+                (Some(ours), Some(theirs)) => self.merge_chunk(&path, base, ours, theirs),
+                (Some(ours), None) => {
+                    // removed by theirs but changed by ours or followed by chunks that are kept
+                    let (base, ours) =
+                        (Some(base.class_name.clone()), Some(ours.class_name.clone()));
+                    self.conflict(&path, base, ours.clone(), None);
+                    self.ours.chunks[index].clone()
+                }
+                _ => base.clone(),
+            };
+            chunks.push(chunk);
+        }
+        if self.ours.chunks.len() > self.base.chunks.len() {
+            chunks.extend(self.ours.chunks[self.base.chunks.len()..].iter().cloned());
+        }
+        for index in self.base.chunks.len()..self.theirs.chunks.len() {
+            let path = format!("chunks[{}]", index);
+            let chunk = self.take_chunk(&path, &self.theirs.chunks[index]);
+            chunks.push(chunk);
+        }
+        chunks
+    }
+
+    fn merge_chunk(&mut self, path: &str, base: &Chunk, ours: &Chunk, theirs: &Chunk) -> Chunk {
+        if self.same_chunk(Side::Ours, ours, Side::Base, base) {
+            return self.take_chunk(path, theirs);
+        }
+        if self.same_chunk(Side::Theirs, theirs, Side::Base, base)
+            || self.same_chunk(Side::Ours, ours, Side::Theirs, theirs)
+        {
+            return ours.clone();
+        }
+        // a chunk of another class or without properties is replaced as a whole
+        if ours.class_name != base.class_name
+            || theirs.class_name != base.class_name
+            || !ours.decoded
+            || !theirs.decoded
+        {
+            let text = |chunk: &Chunk| Some(chunk.class_name.clone());
+            self.conflict(path, text(base), text(ours), text(theirs));
+            return ours.clone();
+        }
+
+        let mut chunk = ours.clone();
+        let parent = theirs.parent.map(|p| self.chunks.get(p).copied().flatten());
+        chunk.parent = match parent {
+            Some(None) => {
+                self.conflict(&format!("{}.parent", path), None, None, None);
+                ours.parent
+            }
+            Some(Some(parent)) => self.merge_parent(path, base.parent, ours.parent, Some(parent)),
+            None => self.merge_parent(path, base.parent, ours.parent, None),
+        };
+        chunk.flags = self.merge3(
+            &format!("{}.flags", path),
+            &base.flags,
+            &ours.flags,
+            &theirs.flags,
+        );
+        chunk.template = self.merge3(
+            &format!("{}.template", path),
+            &base.template,
+            &ours.template,
+            &theirs.template,
+        );
+        if ours.trailing == base.trailing {
+            chunk.trailing = theirs.trailing.clone();
+        } else if theirs.trailing != base.trailing && theirs.trailing != ours.trailing {
+            let text = |chunk: &Chunk| Some(format!("<{} bytes>", chunk.trailing.len()));
+            self.conflict(
+                &format!("{}.trailing", path),
+                text(base),
+                text(ours),
+                text(theirs),
+            );
+        }
+        chunk.properties =
+            self.merge_properties(path, &base.properties, &ours.properties, &theirs.properties);
+        chunk
+    }
+
+    fn merge_parent(
+        &mut self,
+        path: &str,
+        base: Option<usize>,
+        ours: Option<usize>,
+        theirs: Option<usize>,
+    ) -> Option<usize> {
+        if ours == base {
+            theirs
+        } else {
+            if theirs != base && theirs != ours {
+                let text = |parent: Option<usize>| {
+                    Some(parent.map_or("null".to_owned(), |p| format!("chunks[{}]", p)))
+                };
+                self.conflict(
+                    &format!("{}.parent", path),
+                    text(base),
+                    text(ours),
+                    text(theirs),
+                );
+            }
+            ours
+        }
+    }
+
+    /// A chunk of theirs with remapped references, properties with references that can't be remapped
+    /// are conflicts and dropped
+    fn take_chunk(&mut self, path: &str, theirs: &Chunk) -> Chunk {
+        let mut chunk = theirs.clone();
+        chunk.parent = theirs.parent.and_then(|parent| {
+            let mapped = self.chunks.get(parent).copied().flatten();
+            if mapped.is_none() {
+                let theirs = Some(format!("chunks[{}]", parent));
+                self.conflict(&format!("{}.parent", path), None, None, theirs);
+            }
+            mapped
+        });
+        chunk.properties = theirs
+            .properties
+            .iter()
+            .filter_map(|property| {
+                let path = format!("{}.{}", path, property.name);
+                self.take(&path, None, None, property)
+            })
+            .collect();
+        chunk
+    }
+
+    /////////////////////////////////////////////////////////////////////////////////////
+    // PROPERTIES
+
+    fn merge_properties(
+        &mut self,
+        path: &str,
+        base: &[Property],
+        ours: &[Property],
+        theirs: &[Property],
+    ) -> Vec<Property> {
+        let mut names = ours.iter().map(|p| p.name.as_str()).collect::<Vec<_>>();
+        for property in theirs.iter().chain(base.iter()) {
+            if !names.contains(&property.name.as_str()) {
+                names.push(&property.name);
+            }
+        }
+
+        let find =
+            |properties: &[Property], name: &str| properties.iter().position(|p| p.name == name);
+        names
+            .into_iter()
+            .filter_map(|name| {
+                let path = format!("{}.{}", path, name);
+                let base = find(base, name).map(|i| &base[i]);
+                let ours = find(ours, name).map(|i| &ours[i]);
+                let theirs = find(theirs, name).map(|i| &theirs[i]);
+                self.merge_property(&path, base, ours, theirs)
+            })
+            .collect()
+    }
+
+    fn merge_property(
+        &mut self,
+        path: &str,
+        base: Option<&Property>,
+        ours: Option<&Property>,
+        theirs: Option<&Property>,
+    ) -> Option<Property> {
+        if self.same(Side::Ours, ours, Side::Base, base) {
+            return match theirs {
+                Some(theirs) => self.take(path, base, ours, theirs),
+                None => None,
+            };
+        }
+        if self.same(Side::Theirs, theirs, Side::Base, base)
+            || self.same(Side::Ours, ours, Side::Theirs, theirs)
+        {
+            return ours.cloned();
+        }
+
+        if let (Some(base), Some(ours), Some(theirs)) = (base, ours, theirs) {
+            if ours.type_name == base.type_name && theirs.type_name == base.type_name {
+                let value = match (&base.value, &ours.value, &theirs.value) {
+                    (Value::Struct(b), Value::Struct(o), Value::Struct(t)) => {
+                        Some(Value::Struct(self.merge_properties(path, b, o, t)))
+                    }
+                    (Value::Array(b), Value::Array(o), Value::Array(t))
+                        if b.len() == o.len() && o.len() == t.len() =>
+                    {
+                        let type_name = element_type(&base.type_name);
+                        let element = |value: &Value| Property {
+                            name: String::new(),
+                            type_name: type_name.to_owned(),
+                            value: value.clone(),
+                            default: false,
+                        };
+                        let values = (0..b.len())
+                            .map(|i| {
+                                let path = format!("{}[{}]", path, i);
+                                let (b, o, t) = (element(&b[i]), element(&o[i]), element(&t[i]));
+                                self.merge_property(&path, Some(&b), Some(&o), Some(&t))
+                                    .map_or(o.value, |p| p.value)
+                            })
+                            .collect();
+                        Some(Value::Array(values))
+                    }
+                    _ => None,
+                };
+                if let Some(value) = value {
+                    return Some(Property {
+                        value,
+                        default: false,
+                        ..ours.clone()
+                    });
+                }
+            }
+        }
+
+        let base_text = base.map(|p| value_text(self.base, &p.value));
+        let ours_text = ours.map(|p| value_text(self.ours, &p.value));
+        let theirs_text = theirs.map(|p| value_text(self.theirs, &p.value));
+        self.conflict(path, base_text, ours_text, theirs_text);
+        ours.cloned()
+    }
+
+    /// A property of theirs with remapped references, a conflict that keeps ours if a reference can't be
+    /// remapped
+    fn take(
+        &mut self,
+        path: &str,
+        base: Option<&Property>,
+        ours: Option<&Property>,
+        theirs: &Property,
+    ) -> Option<Property> {
+        let mut property = theirs.clone();
+        let imports = &self.theirs.info.imports;
+        let result = remap_value(&mut property.value, &theirs.type_name, &mut |reference| {
+            let index = match reference {
+                Reference::Chunk(index) => self.chunks.get(index).copied().flatten(),
+                Reference::Import(index) => imports.get(index).map(|import| {
+                    match self
+                        .imports
+                        .iter()
+                        .position(|i| i.depot_path == import.depot_path)
+                    {
+                        Some(position) => position,
+                        None => {
+                            self.imports.push(import.clone());
+                            self.imports.len() - 1
+                        }
+                    }
+                }),
+                Reference::Buffer(index) => self.buffers.get(index).copied().flatten(),
+            };
+            index.ok_or_else(|| Error::new(ErrorKind::InvalidData, "removed"))
+        });
+        if result.is_err() {
+            let base_text = base.map(|p| value_text(self.base, &p.value));
+            let ours_text = ours.map(|p| value_text(self.ours, &p.value));
+            let theirs_text = Some(value_text(self.theirs, &theirs.value));
+            self.conflict(path, base_text, ours_text, theirs_text);
+            return ours.cloned();
+        }
+        Some(property)
+    }
+
+    /////////////////////////////////////////////////////////////////////////////////////
+    // COMPARISON
+
+    fn same(&self, a_side: Side, a: Option<&Property>, b_side: Side, b: Option<&Property>) -> bool {
+        match (a, b) {
+            (Some(a), Some(b)) => {
+                a.type_name == b.type_name
+                    && self.canonical(a_side, &a.value) == self.canonical(b_side, &b.value)
+            }
+            (None, None) => true,
+            // defaults filled in from the type registry are the same as missing properties
+            (Some(p), None) | (None, Some(p)) => p.default,
+        }
+    }
+
+    fn same_chunk(&self, a_side: Side, a: &Chunk, b_side: Side, b: &Chunk) -> bool {
+        a.class_name == b.class_name
+            && a.flags == b.flags
+            && a.template == b.template
+            && a.decoded == b.decoded
+            && a.trailing == b.trailing
+            && a.parent.map(|p| self.chunk(a_side, p)) == b.parent.map(|p| self.chunk(b_side, p))
+            && written(a).eq(written(b))
+            && a.properties
+                .iter()
+                .chain(b.properties.iter())
+                .all(|p| self.same(a_side, a.property(&p.name), b_side, b.property(&p.name)))
+    }
+
+    /// A chunk index to compare, indices of base chunks are the same on all sides and new chunks of
+    /// theirs get their index in the merged file
+    fn chunk(&self, side: Side, index: usize) -> Option<usize> {
+        match side {
+            Side::Theirs if index >= self.base.chunks.len() => {
+                self.chunks.get(index).copied().flatten()
+            }
+            _ => Some(index),
+        }
+    }
+
+    /// A value with depot paths instead of import indices and chunk indices of the merged file
+    fn canonical(&self, side: Side, value: &Value) -> Value {
+        match value {
+            Value::ResourceReference(Some(index)) => match self.file(side).info.imports.get(*index)
+            {
+                Some(import) => Value::String(import.depot_path.clone()),
+                None => value.clone(),
+            },
+            Value::Handle(Some(index)) => Value::Handle(self.chunk(side, *index)),
+            Value::WeakHandle(Some(index)) => Value::WeakHandle(self.chunk(side, *index)),
+            Value::Array(values) => {
+                Value::Array(values.iter().map(|v| self.canonical(side, v)).collect())
+            }
+            Value::Struct(properties) => Value::Struct(
+                properties
+                    .iter()
+                    .map(|p| Property {
+                        value: self.canonical(side, &p.value),
+                        ..p.clone()
+                    })
+                    .collect(),
+            ),
+            _ => value.clone(),
+        }
+    }
+}
+
+/// The names of the properties of a chunk that are written, in order
+fn written(chunk: &Chunk) -> impl Iterator<Item = &str> {
+    chunk
+        .properties
+        .iter()
+        .filter(|p| !p.default)
+        .map(|p| p.name.as_str())
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// TESTS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{appearance, chunk, entity, import, property};
+
+    fn components(file: &CR2WFile) -> &Vec<Value> {
+        match file.chunks[0].get("components") {
+            Some(Value::Array(values)) => values,
+            _ => panic!("no components"),
+        }
+    }
+
+    fn depot_path(file: &CR2WFile, value: Option<&Value>) -> String {
+        match value {
+            Some(Value::ResourceReference(Some(index))) => {
+                file.info.imports[*index].depot_path.clone()
+            }
+            _ => panic!("no resource reference"),
+        }
+    }
+
+    #[test]
+    fn unchanged() {
+        let result = merge_cr2w(&entity(), &entity(), &entity()).unwrap();
+        assert!(result.conflicts.is_empty());
+        assert_eq!(entity().chunks, result.file.chunks);
+        assert!(diff_cr2w(&entity(), &result.file).is_empty());
+    }
+
+    #[test]
+    fn merge_changes() {
+        let base = entity();
+        let mut ours = entity();
+        let mut theirs = entity();
+
+        // property changes on both sides
+        ours.chunks[1].properties[2].value = Value::Float(2.0);
+        theirs.chunks[1].properties.remove(1);
+        // a new import in theirs, with an import that ours added at the same index
+        ours.info
+            .imports
+            .push(import("base\\ours.mi", ImportFlags::DEFAULT));
+        theirs
+            .info
+            .imports
+            .push(import("base\\c.mesh", ImportFlags::DEFAULT));
+        theirs.chunks[1].properties[0].value = Value::ResourceReference(Some(2));
+        // both add a chunk, theirs refers to its chunk
+        ours.chunks
+            .push(chunk("entSkinnedMeshComponent", Some(0), vec![]));
+        theirs.chunks.push(chunk(
+            "entLightComponent",
+            Some(0),
+            vec![property("intensity", "Float", Value::Float(4.0))],
+        ));
+        if let Some(Value::Array(values)) =
+            theirs.chunks[0].properties.get_mut(0).map(|p| &mut p.value)
+        {
+            values.push(Value::Handle(Some(3)));
+        }
+        // array elements of arrays that keep their length
+        if let Value::Array(values) = &mut ours.chunks[0].properties[1].value {
+            values[0] = appearance("clean", 1);
+        }
+        if let Value::Array(values) = &mut theirs.chunks[0].properties[1].value {
+            values[1] = appearance("burnt", 1);
+        }
+
+        let result = merge_cr2w(&base, &ours, &theirs).unwrap();
+        assert!(result.conflicts.is_empty(), "{:?}", result.conflicts);
+        let file = result.file;
+
+        assert_eq!(Some(&Value::Float(2.0)), file.chunks[1].get("scale"));
+        assert_eq!(None, file.chunks[1].get("visible"));
+        assert_eq!(
+            "base\\c.mesh",
+            depot_path(&file, file.chunks[1].get("mesh"))
+        );
+        assert_eq!(4, file.info.imports.len());
+
+        assert_eq!(5, file.chunks.len());
+        assert_eq!("entSkinnedMeshComponent", file.chunks[3].class_name);
+        assert_eq!("entLightComponent", file.chunks[4].class_name);
+        assert_eq!(Some(0), file.chunks[4].parent);
+        assert_eq!(
+            &vec![
+                Value::Handle(Some(1)),
+                Value::Handle(Some(2)),
+                Value::Handle(Some(4))
+            ],
+            components(&file)
+        );
+        assert_eq!(
+            Some(&Value::Array(vec![
+                appearance("clean", 1),
+                appearance("burnt", 1)
+            ])),
+            file.chunks[0].get("appearances")
+        );
+
+        // the merged file can be written
+        let mut buffer = vec![];
+        write_cr2w(&mut buffer, &file).unwrap();
+        assert!(validate_cr2w(&mut Cursor::new(&buffer)).unwrap().is_empty());
+        let read = read_cr2w(&mut Cursor::new(&buffer)).unwrap();
+        assert!(diff_cr2w(&file, &read).is_empty());
+    }
+
+    #[test]
+    fn conflicts() {
+        let base = entity();
+        let mut ours = entity();
+        let mut theirs = entity();
+        ours.chunks[1].properties[2].value = Value::Float(2.0);
+        theirs.chunks[1].properties[2].value = Value::Float(3.0);
+        // same change on both sides
+        ours.chunks[1].properties[1].value = Value::Bool(false);
+        theirs.chunks[1].properties[1].value = Value::Bool(false);
+        // arrays that change their length on both sides
+        if let Value::Array(values) = &mut ours.chunks[0].properties[1].value {
+            values.pop();
+        }
+        if let Value::Array(values) = &mut theirs.chunks[0].properties[1].value {
+            values.push(appearance("burnt", 0));
+        }
+        ours.chunks[1].class_name = "entSkinnedMeshComponent".to_owned();
+        theirs.info.header.version += 1;
+
+        let result = merge_cr2w(&base, &ours, &theirs).unwrap();
+        let report = result
+            .conflicts
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "! chunks[0].appearances: [{name: \"default\", appearanceResource: base\\b.app}, \
+                 {name: \"damaged\", appearanceResource: base\\b.app}] -> \
+                 [{name: \"default\", appearanceResource: base\\b.app}] | \
+                 [{name: \"default\", appearanceResource: base\\b.app}, \
+                 {name: \"damaged\", appearanceResource: base\\b.app}, \
+                 {name: \"burnt\", appearanceResource: base\\a.mesh}]",
+                "! chunks[1]: entMeshComponent -> entSkinnedMeshComponent | entMeshComponent",
+            ],
+            report
+        );
+        // conflicts keep ours, other changes are merged
+        assert_eq!(ours.chunks, result.file.chunks);
+        assert_eq!(
+            base.info.header.version + 1,
+            result.file.info.header.version
+        );
+
+        let mut ours = entity();
+        ours.chunks[1].properties[2].value = Value::Float(2.0);
+        let result = merge_cr2w(&base, &ours, &theirs_with_scale(3.0)).unwrap();
+        assert_eq!(
+            vec![MergeConflict {
+                path: "chunks[1].scale".to_owned(),
+                base: Some("1".to_owned()),
+                ours: Some("2".to_owned()),
+                theirs: Some("3".to_owned()),
+            }],
+            result.conflicts
+        );
+    }
+
+    fn theirs_with_scale(scale: f32) -> CR2WFile {
+        let mut theirs = entity();
+        theirs.chunks[1].properties[2].value = Value::Float(scale);
+        theirs
+    }
+
+    #[test]
+    fn removed_chunks() {
+        let base = entity();
+        let mut ours = entity();
+        ours.chunks.truncate(1);
+        ours.chunks[0].properties[0].value = Value::Array(vec![]);
+        let result = merge_cr2w(&base, &ours, &entity()).unwrap();
+        assert!(result.conflicts.is_empty());
+        assert_eq!(1, result.file.chunks.len());
+
+        // theirs changed the chunk that ours removed
+        let result = merge_cr2w(&base, &ours, &theirs_with_scale(3.0)).unwrap();
+        assert_eq!(1, result.file.chunks.len());
+        assert_eq!("chunks[1]", result.conflicts[0].path);
+    }
+}
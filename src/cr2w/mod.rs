@@ -0,0 +1,1056 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::ops::{BitOr, BitOrAssign};
+use std::str::FromStr;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::codec::Codec;
+use crate::hash::crc32;
+use crate::io::FromReader;
+use crate::kraken;
+
+pub use self::chunk::*;
+pub use self::diff::*;
+pub use self::embedded::*;
+pub use self::json::*;
+pub use self::merge::*;
+pub use self::rtti::*;
+pub use self::validation::*;
+pub use self::writer::*;
+
+mod chunk;
+mod diff;
+mod embedded;
+mod json;
+mod merge;
+mod rtti;
+mod validation;
+mod writer;
+
+// DTOs
+
+/// A CR2W resource with its export chunks decoded
+#[derive(Debug, Clone)]
+pub struct CR2WFile {
+    pub info: CR2WFileInfo,
+    /// One chunk per entry of the exports table
+    pub chunks: Vec<Chunk>,
+    /// One buffer per entry of the buffers table
+    pub buffers: Vec<CR2WBuffer>,
+}
+
+/// A buffer stored after the chunks
+#[derive(Debug, Clone)]
+pub struct CR2WBuffer {
+    pub flags: u32,
+    pub index: u32,
+    pub mem_size: u32,
+    /// The bytes as stored in the file, compressed if smaller than `mem_size`
+    pub data: Vec<u8>,
+}
+
+impl CR2WFile {
+    /// Gets the uncompressed bytes of a buffer
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the index is out of range or the buffer doesn't decompress.
+    pub fn buffer(&self, index: usize) -> io::Result<Vec<u8>> {
+        self.buffers
+            .get(index)
+            .ok_or_else(|| invalid_entry("buffer", index, "out of range"))?
+            .decompress()
+    }
+
+    /// Replaces the bytes of a buffer and updates the buffers table
+    ///
+    /// The bytes are compressed with `codec` if that makes them smaller.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the index is out of range or compression fails.
+    pub fn set_buffer(&mut self, index: usize, data: &[u8], codec: &dyn Codec) -> io::Result<()> {
+        let buffer = self
+            .buffers
+            .get_mut(index)
+            .ok_or_else(|| invalid_entry("buffer", index, "out of range"))?;
+        *buffer = CR2WBuffer::new(buffer.flags, buffer.index, data, codec)?;
+        self.update_buffers_table();
+        Ok(())
+    }
+
+    /// Recomputes the buffers table and `buffers_end`, buffers are stored in order after the chunks
+    fn update_buffers_table(&mut self) {
+        let mut offset = self.info.header.objects_end;
+        self.info.buffers_table = self
+            .buffers
+            .iter()
+            .map(|buffer| {
+                let info = CR2WBufferInfo {
+                    flags: buffer.flags,
+                    index: buffer.index,
+                    offset,
+                    disk_size: buffer.data.len() as u32,
+                    mem_size: buffer.mem_size,
+                    crc32: crc32(&buffer.data),
+                };
+                offset += info.disk_size;
+                info
+            })
+            .collect();
+        self.info.header.buffers_end = offset;
+    }
+
+    /// Indices of the chunks without a parent
+    pub fn roots(&self) -> impl Iterator<Item = usize> + '_ {
+        self.children_of(None)
+    }
+
+    /// Indices of the direct children of a chunk
+    pub fn children(&self, index: usize) -> impl Iterator<Item = usize> + '_ {
+        self.children_of(Some(index))
+    }
+
+    fn children_of(&self, parent: Option<usize>) -> impl Iterator<Item = usize> + '_ {
+        self.chunks
+            .iter()
+            .enumerate()
+            .filter(move |(_, chunk)| chunk.parent == parent)
+            .map(|(i, _)| i)
+    }
+}
+
+impl CR2WBuffer {
+    /// Creates a buffer, the bytes are compressed with `codec` behind a KARK header if that makes
+    /// them smaller
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if compression fails.
+    pub fn new(flags: u32, index: u32, data: &[u8], codec: &dyn Codec) -> io::Result<Self> {
+        let mem_size = data.len() as u32;
+        let data = match codec.compress(data)? {
+            Some(compressed) if compressed.len() + 8 < data.len() => {
+                let mut buffer = Vec::with_capacity(compressed.len() + 8);
+                buffer.write_u32::<LittleEndian>(kraken::MAGIC)?;
+                buffer.write_u32::<LittleEndian>(data.len() as u32)?;
+                buffer.extend_from_slice(&compressed);
+                buffer
+            }
+            _ => data.to_vec(),
+        };
+
+        Ok(Self {
+            flags,
+            index,
+            mem_size,
+            data,
+        })
+    }
+
+    /// A buffer is compressed if it is stored with fewer bytes than it has in memory
+    pub fn is_compressed(&self) -> bool {
+        self.data.len() != self.mem_size as usize
+    }
+
+    /// Gets the uncompressed bytes of the buffer
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a compressed buffer has no KARK header or doesn't decompress.
+    pub fn decompress(&self) -> io::Result<Vec<u8>> {
+        if !self.is_compressed() {
+            return Ok(self.data.clone());
+        }
+
+        let mut cursor = Cursor::new(&self.data);
+        if cursor.read_u32::<LittleEndian>()? != kraken::MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "compressed buffer without KARK header",
+            ));
+        }
+        let size = cursor.read_u32::<LittleEndian>()?;
+        if size != self.mem_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "size in KARK header doesn't match the buffer",
+            ));
+        }
+        kraken::decompress(&self.data[8..], size as usize)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Import {
+    pub class_name: String,
+    pub depot_path: String,
+    pub flags: ImportFlags,
+}
+
+/// Flags of an import, a set of [`ImportFlags::OBLIGATORY`], [`ImportFlags::TEMPLATE`],
+/// [`ImportFlags::SOFT`], [`ImportFlags::EMBEDDED`] and [`ImportFlags::INPLACE`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ImportFlags(u16);
+
+impl ImportFlags {
+    pub const DEFAULT: Self = Self(0);
+    pub const OBLIGATORY: Self = Self(1);
+    pub const TEMPLATE: Self = Self(2);
+    pub const SOFT: Self = Self(4);
+    /// The import is the depot path of a resource embedded in the file
+    pub const EMBEDDED: Self = Self(8);
+    pub const INPLACE: Self = Self(16);
+
+    /// Names of the flags by bit, as in WolvenKit
+    const NAMES: [(Self, &'static str); 5] = [
+        (Self::OBLIGATORY, "Obligatory"),
+        (Self::TEMPLATE, "Template"),
+        (Self::SOFT, "Soft"),
+        (Self::EMBEDDED, "Embedded"),
+        (Self::INPLACE, "Inplace"),
+    ];
+
+    /// Creates flags from their raw value, unknown bits are kept
+    pub const fn from_bits(bits: u16) -> Self {
+        Self(bits)
+    }
+
+    /// The raw value of the flags
+    pub const fn bits(self) -> u16 {
+        self.0
+    }
+
+    /// True if all flags of `other` are set
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for ImportFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for ImportFlags {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// Comma separated flag names like `Obligatory, Soft`, no flags are `Default`
+impl fmt::Display for ImportFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = Self::NAMES
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, name)| *name)
+            .collect::<Vec<_>>();
+        if names.is_empty() {
+            write!(f, "Default")
+        } else {
+            write!(f, "{}", names.join(", "))
+        }
+    }
+}
+
+impl FromStr for ImportFlags {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        let mut flags = Self::DEFAULT;
+        for name in s.split(',').map(str::trim) {
+            flags |= match Self::NAMES.iter().find(|(_, n)| *n == name) {
+                Some((flag, _)) => *flag,
+                None if name == "Default" => Self::DEFAULT,
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unknown import flag {}", name),
+                    ))
+                }
+            };
+        }
+        Ok(flags)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CR2WFileInfo {
+    pub header: CR2WFileHeader,
+    pub names_table: Vec<CR2WNameInfo>,
+    pub imports_table: Vec<CR2WImportInfo>,
+    pub properties_table: Vec<CR2WPropertyInfo>,
+    pub exports_table: Vec<CR2WExportInfo>,
+    pub buffers_table: Vec<CR2WBufferInfo>,
+    pub embeds_table: Vec<CR2WEmbeddedInfo>,
+    // not-serialized
+    pub strings: HashMap<u32, String>,
+    pub names: Vec<String>,
+    pub imports: Vec<Import>,
+}
+
+// Real red4 data
+
+#[derive(Debug, Clone, Copy)]
+pub struct CR2WFileHeader {
+    pub version: u32,
+    pub flags: u32,
+    pub time_stamp: u64,
+    pub build_version: u32,
+    pub objects_end: u32,
+    pub buffers_end: u32,
+    pub crc32: u32,
+    pub num_chunks: u32,
+}
+impl CR2WFileHeader {
+    const MAGIC: u32 = 0x57325243;
+
+    pub(crate) fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_u32::<LittleEndian>(self.version)?;
+        writer.write_u32::<LittleEndian>(self.flags)?;
+        writer.write_u64::<LittleEndian>(self.time_stamp)?;
+        writer.write_u32::<LittleEndian>(self.build_version)?;
+        writer.write_u32::<LittleEndian>(self.objects_end)?;
+        writer.write_u32::<LittleEndian>(self.buffers_end)?;
+        writer.write_u32::<LittleEndian>(self.crc32)?;
+        writer.write_u32::<LittleEndian>(self.num_chunks)?;
+        Ok(())
+    }
+}
+impl FromReader for CR2WFileHeader {
+    fn from_reader<R: Read>(cursor: &mut R) -> io::Result<Self> {
+        Ok(CR2WFileHeader {
+            version: cursor.read_u32::<LittleEndian>()?,
+            flags: cursor.read_u32::<LittleEndian>()?,
+            time_stamp: cursor.read_u64::<LittleEndian>()?,
+            build_version: cursor.read_u32::<LittleEndian>()?,
+            objects_end: cursor.read_u32::<LittleEndian>()?,
+            buffers_end: cursor.read_u32::<LittleEndian>()?,
+            crc32: cursor.read_u32::<LittleEndian>()?,
+            num_chunks: cursor.read_u32::<LittleEndian>()?,
+        })
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy)]
+pub struct CR2WTable {
+    pub offset: u32,
+    pub item_count: u32,
+    pub crc32: u32,
+}
+
+impl CR2WTable {
+    pub(crate) fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_u32::<LittleEndian>(self.offset)?;
+        writer.write_u32::<LittleEndian>(self.item_count)?;
+        writer.write_u32::<LittleEndian>(self.crc32)?;
+        Ok(())
+    }
+}
+impl FromReader for CR2WTable {
+    fn from_reader<R: Read>(cursor: &mut R) -> io::Result<Self> {
+        Ok(CR2WTable {
+            offset: cursor.read_u32::<LittleEndian>()?,
+            item_count: cursor.read_u32::<LittleEndian>()?,
+            crc32: cursor.read_u32::<LittleEndian>()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CR2WNameInfo {
+    pub offset: u32,
+    pub hash: u32,
+}
+impl CR2WNameInfo {
+    pub(crate) fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_u32::<LittleEndian>(self.offset)?;
+        writer.write_u32::<LittleEndian>(self.hash)?;
+        Ok(())
+    }
+}
+impl FromReader for CR2WNameInfo {
+    fn from_reader<R: Read>(cursor: &mut R) -> io::Result<Self> {
+        Ok(CR2WNameInfo {
+            offset: cursor.read_u32::<LittleEndian>()?,
+            hash: cursor.read_u32::<LittleEndian>()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CR2WImportInfo {
+    pub offset: u32,
+    pub class_name: u16,
+    pub flags: u16,
+}
+impl CR2WImportInfo {
+    pub(crate) fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_u32::<LittleEndian>(self.offset)?;
+        writer.write_u16::<LittleEndian>(self.class_name)?;
+        writer.write_u16::<LittleEndian>(self.flags)?;
+        Ok(())
+    }
+}
+impl FromReader for CR2WImportInfo {
+    fn from_reader<R: Read>(cursor: &mut R) -> io::Result<Self> {
+        Ok(CR2WImportInfo {
+            offset: cursor.read_u32::<LittleEndian>()?,
+            class_name: cursor.read_u16::<LittleEndian>()?,
+            flags: cursor.read_u16::<LittleEndian>()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CR2WPropertyInfo {
+    pub class_name: u16,
+    pub class_flags: u16,
+    pub property_name: u16,
+    pub property_flags: u16,
+    pub hash: u64,
+}
+impl CR2WPropertyInfo {
+    pub(crate) fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_u16::<LittleEndian>(self.class_name)?;
+        writer.write_u16::<LittleEndian>(self.class_flags)?;
+        writer.write_u16::<LittleEndian>(self.property_name)?;
+        writer.write_u16::<LittleEndian>(self.property_flags)?;
+        writer.write_u64::<LittleEndian>(self.hash)?;
+        Ok(())
+    }
+}
+impl FromReader for CR2WPropertyInfo {
+    fn from_reader<R: Read>(cursor: &mut R) -> io::Result<Self> {
+        Ok(CR2WPropertyInfo {
+            class_name: cursor.read_u16::<LittleEndian>()?,
+            class_flags: cursor.read_u16::<LittleEndian>()?,
+            property_name: cursor.read_u16::<LittleEndian>()?,
+            property_flags: cursor.read_u16::<LittleEndian>()?,
+            hash: cursor.read_u64::<LittleEndian>()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CR2WExportInfo {
+    pub class_name: u16,
+    pub object_flags: u16,
+    pub parent_id: u32,
+    pub data_size: u32,
+    pub data_offset: u32,
+    pub template: u32,
+    pub crc32: u32,
+}
+impl CR2WExportInfo {
+    pub(crate) fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_u16::<LittleEndian>(self.class_name)?;
+        writer.write_u16::<LittleEndian>(self.object_flags)?;
+        writer.write_u32::<LittleEndian>(self.parent_id)?;
+        writer.write_u32::<LittleEndian>(self.data_size)?;
+        writer.write_u32::<LittleEndian>(self.data_offset)?;
+        writer.write_u32::<LittleEndian>(self.template)?;
+        writer.write_u32::<LittleEndian>(self.crc32)?;
+        Ok(())
+    }
+}
+impl FromReader for CR2WExportInfo {
+    fn from_reader<R: Read>(cursor: &mut R) -> io::Result<Self> {
+        Ok(CR2WExportInfo {
+            class_name: cursor.read_u16::<LittleEndian>()?,
+            object_flags: cursor.read_u16::<LittleEndian>()?,
+            parent_id: cursor.read_u32::<LittleEndian>()?,
+            data_size: cursor.read_u32::<LittleEndian>()?,
+            data_offset: cursor.read_u32::<LittleEndian>()?,
+            template: cursor.read_u32::<LittleEndian>()?,
+            crc32: cursor.read_u32::<LittleEndian>()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CR2WBufferInfo {
+    pub flags: u32,
+    pub index: u32,
+    pub offset: u32,
+    pub disk_size: u32,
+    pub mem_size: u32,
+    pub crc32: u32,
+}
+impl CR2WBufferInfo {
+    pub(crate) fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_u32::<LittleEndian>(self.flags)?;
+        writer.write_u32::<LittleEndian>(self.index)?;
+        writer.write_u32::<LittleEndian>(self.offset)?;
+        writer.write_u32::<LittleEndian>(self.disk_size)?;
+        writer.write_u32::<LittleEndian>(self.mem_size)?;
+        writer.write_u32::<LittleEndian>(self.crc32)?;
+        Ok(())
+    }
+}
+impl FromReader for CR2WBufferInfo {
+    fn from_reader<R: Read>(cursor: &mut R) -> io::Result<Self> {
+        Ok(CR2WBufferInfo {
+            flags: cursor.read_u32::<LittleEndian>()?,
+            index: cursor.read_u32::<LittleEndian>()?,
+            offset: cursor.read_u32::<LittleEndian>()?,
+            disk_size: cursor.read_u32::<LittleEndian>()?,
+            mem_size: cursor.read_u32::<LittleEndian>()?,
+            crc32: cursor.read_u32::<LittleEndian>()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CR2WEmbeddedInfo {
+    pub import_index: u32,
+    pub chunk_index: u32,
+    pub path_hash: u64,
+}
+impl CR2WEmbeddedInfo {
+    pub(crate) fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_u32::<LittleEndian>(self.import_index)?;
+        writer.write_u32::<LittleEndian>(self.chunk_index)?;
+        writer.write_u64::<LittleEndian>(self.path_hash)?;
+        Ok(())
+    }
+}
+impl FromReader for CR2WEmbeddedInfo {
+    fn from_reader<R: Read>(cursor: &mut R) -> io::Result<Self> {
+        Ok(CR2WEmbeddedInfo {
+            import_index: cursor.read_u32::<LittleEndian>()?,
+            chunk_index: cursor.read_u32::<LittleEndian>()?,
+            path_hash: cursor.read_u64::<LittleEndian>()?,
+        })
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// HELPERS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+/// Reads the string table of a CR2W file, the strings are keyed by their offset in the table
+///
+/// # Errors
+///
+/// This function will return an error if the table is not inside the file.
+fn read_strings<R: Read + Seek>(
+    reader: &mut R,
+    table: CR2WTable,
+) -> io::Result<HashMap<u32, String>> {
+    let mut stringtable: HashMap<u32, String> = HashMap::default();
+    if table.item_count == 0 {
+        return Ok(stringtable);
+    }
+
+    // the item count of the string table is its size in bytes
+    let mut buffer = vec![0; table.item_count as usize];
+    reader.seek(SeekFrom::Start(table.offset as u64))?;
+    reader.read_exact(&mut buffer)?;
+
+    let mut offset = 0;
+    for bytes in buffer.split_inclusive(|b| *b == 0) {
+        let bytes = bytes.strip_suffix(&[0]).unwrap_or(bytes);
+        let str = if bytes.is_empty() {
+            "None".to_owned()
+        } else {
+            String::from_utf8_lossy(bytes).to_string()
+        };
+        stringtable.insert(offset, str);
+        offset += bytes.len() as u32 + 1;
+    }
+
+    Ok(stringtable)
+}
+
+fn read_table<R: Read + Seek, T: FromReader>(
+    reader: &mut R,
+    table: CR2WTable,
+) -> io::Result<Vec<T>> {
+    let mut result_table: Vec<T> = vec![];
+    if table.item_count > 0 {
+        reader.seek(SeekFrom::Start(table.offset as u64))?;
+    }
+    for _i in 0..table.item_count {
+        result_table.push(T::from_reader(reader)?);
+    }
+    Ok(result_table)
+}
+
+/// Reads the header info from a cr2w file
+///
+/// # Errors
+///
+/// This function will return an error if any parsing failed downstream or a name or import
+/// refers to a string or name that doesn't exist. Use [`validate_cr2w`] to find all problems
+/// of a broken file.
+pub fn read_cr2w_header<R: Read + Seek>(cursor: &mut R) -> io::Result<CR2WFileInfo> {
+    let magic = cursor.read_u32::<LittleEndian>()?;
+    if magic != CR2WFileHeader::MAGIC {
+        return Err(io::Error::other("invalid magic"));
+    }
+
+    let header = CR2WFileHeader::from_reader(cursor)?;
+    let mut tables: Vec<CR2WTable> = vec![];
+    // Tables [7-9] are not used in cr2w so far.
+    for _i in 0..10 {
+        tables.push(CR2WTable::from_reader(cursor)?);
+    }
+
+    // read strings - block 1 (index 0)
+    let strings = read_strings(cursor, tables[0])?;
+
+    // read the other tables
+    let names_table = read_table::<R, CR2WNameInfo>(cursor, tables[1])?;
+    let imports_table = read_table::<R, CR2WImportInfo>(cursor, tables[2])?;
+    let properties_table = read_table::<R, CR2WPropertyInfo>(cursor, tables[3])?;
+    let exports_table = read_table::<R, CR2WExportInfo>(cursor, tables[4])?;
+    let buffers_table = read_table::<R, CR2WBufferInfo>(cursor, tables[5])?;
+    let embeds_table = read_table::<R, CR2WEmbeddedInfo>(cursor, tables[6])?;
+
+    // Tables [7-9] are not used in cr2w so far.
+
+    // parse names
+    let names = names_table
+        .iter()
+        .enumerate()
+        .map(|(i, f)| {
+            strings
+                .get(&f.offset)
+                .cloned()
+                .ok_or_else(|| invalid_entry("name", i, &format!("no string at {}", f.offset)))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    // parse imports
+    let mut imports: Vec<Import> = vec![];
+    for (i, info) in imports_table.iter().enumerate() {
+        let class_name = names.get(info.class_name as usize).ok_or_else(|| {
+            invalid_entry(
+                "import",
+                i,
+                &format!("name {} out of range", info.class_name),
+            )
+        })?;
+        let depot_path = strings
+            .get(&info.offset)
+            .ok_or_else(|| invalid_entry("import", i, &format!("no string at {}", info.offset)))?;
+        imports.push(Import {
+            class_name: class_name.to_owned(),
+            depot_path: depot_path.to_owned(),
+            flags: ImportFlags::from_bits(info.flags),
+        });
+    }
+
+    let info = CR2WFileInfo {
+        header,
+        names_table,
+        imports_table,
+        properties_table,
+        exports_table,
+        buffers_table,
+        embeds_table,
+        strings,
+        imports,
+        names,
+    };
+    Ok(info)
+}
+
+/// Reads a cr2w file, decodes its export chunks and reads its buffers
+///
+/// # Errors
+///
+/// This function will return an error if the header, a chunk or a buffer can't be read.
+pub fn read_cr2w<R: Read + Seek>(cursor: &mut R) -> io::Result<CR2WFile> {
+    read_cr2w_file(cursor, None)
+}
+
+/// Reads a cr2w file and decodes its export chunks with type information
+///
+/// Values are decoded with their known types instead of being told apart by their size, and the
+/// defaults of properties that are not serialized are added to chunks and structs.
+///
+/// # Errors
+///
+/// This function will return an error if the header or a buffer can't be read, a chunk can't be
+/// decoded or the class of a chunk is unknown.
+pub fn read_cr2w_with_types<R: Read + Seek>(
+    cursor: &mut R,
+    types: &TypeRegistry,
+) -> io::Result<CR2WFile> {
+    read_cr2w_file(cursor, Some(types))
+}
+
+fn read_cr2w_file<R: Read + Seek>(
+    cursor: &mut R,
+    types: Option<&TypeRegistry>,
+) -> io::Result<CR2WFile> {
+    let info = read_cr2w_header(cursor)?;
+    let length = cursor.seek(SeekFrom::End(0))?;
+
+    let mut chunks = vec![];
+    for (i, export) in info.exports_table.iter().enumerate() {
+        let class_name = info
+            .names
+            .get(export.class_name as usize)
+            .ok_or_else(|| invalid_chunk(i, "class name index out of range"))?;
+        if types.is_some_and(|types| types.class(class_name).is_none()) {
+            return Err(invalid_chunk(i, &format!("unknown class {}", class_name)));
+        }
+
+        let data = read_data(cursor, export.data_offset, export.data_size, length)
+            .map_err(|e| invalid_chunk(i, &e.to_string()))?;
+        let (properties, trailing, decoded) =
+            match read_chunk(&data, &info.names, types, class_name) {
+                Ok((properties, trailing)) => (properties, trailing, true),
+                Err(e) if types.is_some() => {
+                    return Err(invalid_chunk(i, &format!("{} {}", class_name, e)))
+                }
+                // classes with custom serialization are kept as is, `decoded` is false
+                Err(_) => (vec![], data, false),
+            };
+
+        chunks.push(Chunk {
+            class_name: class_name.to_owned(),
+            flags: export.object_flags,
+            // parent ids are 1-based, 0 is none
+            parent: (export.parent_id as usize).checked_sub(1),
+            template: export.template,
+            properties,
+            trailing,
+            decoded,
+        });
+    }
+
+    let mut buffers = vec![];
+    for buffer_info in info.buffers_table.iter() {
+        let data = read_data(cursor, buffer_info.offset, buffer_info.disk_size, length)?;
+        buffers.push(CR2WBuffer {
+            flags: buffer_info.flags,
+            index: buffer_info.index,
+            mem_size: buffer_info.mem_size,
+            data,
+        });
+    }
+
+    Ok(CR2WFile {
+        info,
+        chunks,
+        buffers,
+    })
+}
+
+/// Reads `size` bytes at `offset`, sizes beyond the end of the stream are rejected before allocating
+fn read_data<R: Read + Seek>(
+    cursor: &mut R,
+    offset: u32,
+    size: u32,
+    length: u64,
+) -> io::Result<Vec<u8>> {
+    if offset as u64 + size as u64 > length {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "data is larger than the file",
+        ));
+    }
+    cursor.seek(SeekFrom::Start(offset as u64))?;
+    let mut data = vec![0; size as usize];
+    cursor.read_exact(&mut data)?;
+    Ok(data)
+}
+
+fn invalid_entry(table: &str, index: usize, msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{} {}: {}", table, index, msg),
+    )
+}
+
+fn invalid_chunk(index: usize, msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("chunk {}: {}", index, msg),
+    )
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// TESTS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Cursor, path::PathBuf};
+
+    use super::validation::validate_cr2w;
+    use super::writer::{tests::cr2w_test_files, write_cr2w};
+    use super::*;
+    use crate::codec::{KrakenCodec, StoredCodec};
+
+    fn read_test_file(path: &str) -> CR2WFile {
+        let buffer = fs::read(PathBuf::from("tests").join("data").join(path)).unwrap();
+        read_cr2w(&mut Cursor::new(&buffer)).unwrap()
+    }
+
+    fn count_raw(value: &Value) -> usize {
+        match value {
+            Value::Raw(_) => 1,
+            Value::Array(values) => values.iter().map(count_raw).sum(),
+            Value::Struct(properties) => properties.iter().map(|p| count_raw(&p.value)).sum(),
+            _ => 0,
+        }
+    }
+
+    #[test]
+    fn read_tables() {
+        let path = PathBuf::from("tests")
+            .join("data")
+            .join("base/cycleweapons/localization/en-us.json");
+        let buffer = fs::read(path).unwrap();
+        let info = read_cr2w_header(&mut Cursor::new(&buffer)).unwrap();
+
+        assert_eq!(2, info.exports_table.len());
+        let classes = info
+            .exports_table
+            .iter()
+            .map(|export| info.names[export.class_name as usize].as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec!["JsonResource", "localizationPersistenceOnScreenEntries"],
+            classes
+        );
+        assert_eq!(0, info.exports_table[0].parent_id);
+        assert_eq!(info.header.objects_end, {
+            let last = info.exports_table.last().unwrap();
+            last.data_offset + last.data_size
+        });
+    }
+
+    #[test]
+    fn read_json_resource() {
+        let file = read_test_file("base/cycleweapons/localization/en-us.json");
+        assert_eq!(2, file.chunks.len());
+        assert_eq!(vec![0, 1], file.roots().collect::<Vec<_>>());
+
+        let root = &file.chunks[0];
+        assert_eq!("JsonResource", root.class_name);
+        assert_eq!(
+            Some(&Value::Enum("PLATFORM_PC".to_owned())),
+            root.get("cookingPlatform")
+        );
+        assert_eq!(Some(&Value::Handle(Some(1))), root.get("root"));
+
+        let Some(Value::Array(entries)) = file.chunks[1].get("entries") else {
+            panic!("no entries");
+        };
+        assert_eq!(5, entries.len());
+        let Value::Struct(entry) = &entries[0] else {
+            panic!("entry is not a struct");
+        };
+        assert_eq!(
+            Value::String("Mod-CycleWeapons-Burst".to_owned()),
+            entry[0].value
+        );
+    }
+
+    #[test]
+    fn read_audio_metadata() {
+        for path in [
+            "base/sound/metadata/cooked_metadata.audio_metadata",
+            "ep1/sound/metadata/cooked_metadata.audio_metadata",
+        ] {
+            let file = read_test_file(path);
+            assert_eq!(file.info.exports_table.len(), file.chunks.len());
+            assert_eq!("audioCookedMetadataResource", file.chunks[0].class_name);
+
+            // every value is decoded
+            let raw = file
+                .chunks
+                .iter()
+                .flat_map(|c| c.properties.iter())
+                .map(|p| count_raw(&p.value))
+                .sum::<usize>();
+            assert_eq!(0, raw);
+            assert!(file
+                .chunks
+                .iter()
+                .all(|c| c.decoded && c.trailing.is_empty()));
+
+            // the entries of the root are the other chunks
+            let Some(Value::Array(entries)) = file.chunks[0].get("entries") else {
+                panic!("no entries");
+            };
+            for entry in entries {
+                let Value::Handle(Some(index)) = entry else {
+                    panic!("entry is not a handle");
+                };
+                assert!(*index < file.chunks.len());
+            }
+        }
+    }
+
+    #[test]
+    fn read_with_types() {
+        let path = PathBuf::from("tests")
+            .join("data")
+            .join("base/cycleweapons/localization/en-us.json");
+        let buffer = fs::read(path).unwrap();
+        let file = read_cr2w(&mut Cursor::new(&buffer)).unwrap();
+
+        let mut types = TypeRegistry::new();
+        for chunk in file.chunks.iter() {
+            types.insert_class(ClassType {
+                name: chunk.class_name.clone(),
+                ..Default::default()
+            });
+        }
+        types.insert_class(ClassType {
+            name: "JsonResource".to_owned(),
+            parent: None,
+            properties: vec![PropertyType {
+                name: "version".to_owned(),
+                type_name: "Uint32".to_owned(),
+                default: Some(3.into()),
+            }],
+        });
+        let typed = read_cr2w_with_types(&mut Cursor::new(&buffer), &types).unwrap();
+        assert_eq!(file.chunks[1], typed.chunks[1]);
+        assert_eq!(file.chunks[0].properties, typed.chunks[0].properties[..2]);
+        assert_eq!(Some(&Value::Uint32(3)), typed.chunks[0].get("version"));
+        assert!(typed.chunks[0].property("version").unwrap().default);
+
+        // defaults are not written and are no changes
+        let mut output = vec![];
+        write_cr2w(&mut output, &typed).unwrap();
+        assert!(buffer == output);
+        assert!(diff_cr2w(&file, &typed).is_empty());
+        assert!(merge_cr2w(&file, &typed, &file)
+            .unwrap()
+            .conflicts
+            .is_empty());
+
+        // every chunk class has to be known
+        let types = TypeRegistry::new();
+        let error = read_cr2w_with_types(&mut Cursor::new(&buffer), &types).unwrap_err();
+        assert_eq!("chunk 0: unknown class JsonResource", error.to_string());
+    }
+
+    #[test]
+    fn undecoded_chunks() {
+        let path = PathBuf::from("tests")
+            .join("data")
+            .join("base/cycleweapons/localization/en-us.json");
+        let mut buffer = fs::read(path).unwrap();
+        let file = read_cr2w(&mut Cursor::new(&buffer)).unwrap();
+        buffer[file.info.exports_table[1].data_offset as usize] = 1;
+
+        // kept as raw data when reading without types
+        let raw = read_cr2w(&mut Cursor::new(&buffer)).unwrap();
+        assert!(!raw.chunks[1].decoded);
+        assert_eq!(
+            file.info.exports_table[1].data_size as usize,
+            raw.chunks[1].trailing.len()
+        );
+
+        let mut types = TypeRegistry::new();
+        for chunk in file.chunks.iter() {
+            types.insert_class(ClassType {
+                name: chunk.class_name.clone(),
+                ..Default::default()
+            });
+        }
+        let error = read_cr2w_with_types(&mut Cursor::new(&buffer), &types).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        assert!(error.to_string().starts_with("chunk 1: "));
+    }
+
+    #[test]
+    fn corrupt_sizes() {
+        let buffer = cr2w_test_files()
+            .into_iter()
+            .map(|(_, buffer)| buffer)
+            .find(|buffer| {
+                let file = read_cr2w(&mut Cursor::new(buffer)).unwrap();
+                !file.chunks.is_empty() && !file.buffers.is_empty()
+            })
+            .unwrap();
+        let table_offset = |index: usize| {
+            let offset = 40 + index * 12;
+            u32::from_le_bytes(buffer[offset..offset + 4].try_into().unwrap()) as usize
+        };
+
+        // the data size of the first export and the disk size of the first buffer
+        for offset in [table_offset(4) + 8, table_offset(5) + 12] {
+            let mut corrupt = buffer.clone();
+            corrupt[offset..offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+            let error = read_cr2w(&mut Cursor::new(&corrupt)).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        }
+    }
+
+    #[test]
+    fn import_flags() {
+        let flags = ImportFlags::OBLIGATORY | ImportFlags::SOFT;
+        assert_eq!(5, flags.bits());
+        assert!(flags.contains(ImportFlags::SOFT));
+        assert!(!flags.contains(ImportFlags::EMBEDDED));
+        assert_eq!("Obligatory, Soft", flags.to_string());
+        assert_eq!(flags, "Obligatory, Soft".parse().unwrap());
+        assert_eq!("Default", ImportFlags::DEFAULT.to_string());
+        assert_eq!(ImportFlags::DEFAULT, "Default".parse().unwrap());
+        assert!("Obligatory, Hard".parse::<ImportFlags>().is_err());
+
+        // unknown bits are kept
+        assert_eq!(0x8001, ImportFlags::from_bits(0x8001).bits());
+    }
+
+    #[test]
+    fn buffers() {
+        let mut file = cr2w_test_files()
+            .into_iter()
+            .map(|(_, buffer)| read_cr2w(&mut Cursor::new(&buffer)).unwrap())
+            .find(|file| file.buffers.len() > 1 && file.buffers[0].is_compressed())
+            .unwrap();
+        for (i, buffer) in file.buffers.iter().enumerate() {
+            assert_eq!(buffer.mem_size as usize, file.buffer(i).unwrap().len());
+        }
+        assert!(file.buffer(file.buffers.len()).is_err());
+
+        // the following buffers move
+        let data = b"base\\characters\\".repeat(100);
+        file.set_buffer(0, &data, &KrakenCodec::default()).unwrap();
+        assert!(file.buffers[0].is_compressed());
+        assert_eq!(data, file.buffer(0).unwrap());
+
+        let mut output = vec![];
+        write_cr2w(&mut output, &file).unwrap();
+        assert!(validate_cr2w(&mut Cursor::new(&output)).unwrap().is_empty());
+        let written = read_cr2w(&mut Cursor::new(&output)).unwrap();
+        assert_eq!(data, written.buffer(0).unwrap());
+        assert_eq!(
+            format!("{:?}", file.info.buffers_table),
+            format!("{:?}", written.info.buffers_table)
+        );
+        assert_eq!(
+            file.info.header.buffers_end,
+            written.info.header.buffers_end
+        );
+
+        // buffers that don't get smaller are stored uncompressed
+        file.set_buffer(1, &[1, 2, 3], &KrakenCodec::default())
+            .unwrap();
+        assert!(!file.buffers[1].is_compressed());
+        assert_eq!(vec![1, 2, 3], file.buffer(1).unwrap());
+
+        // the codec decides if a buffer is compressed
+        file.set_buffer(0, &data, &StoredCodec).unwrap();
+        assert!(!file.buffers[0].is_compressed());
+        assert_eq!(data, file.buffer(0).unwrap());
+    }
+}
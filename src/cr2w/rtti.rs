@@ -0,0 +1,623 @@
+/////////////////////////////////////////////////////////////////////////////////////////
+// RTTI
+// Type information of the game, loaded from a JSON dump:
+//
+// {
+//   "classes":   [{ "name", "parent"?, "properties": [{ "name", "type", "default"? }] }],
+//   "enums":     [{ "name", "members": [{ "name", "value" }] }],
+//   "bitfields": [{ "name", "members": [{ "name", "bit" }] }]
+// }
+//
+// Defaults are plain JSON: numbers, strings for names and enums, arrays of names for
+// bitfields, arrays and objects for structs. Properties without a default use the zero
+// value of their type.
+/////////////////////////////////////////////////////////////////////////////////////////
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufReader, Error, ErrorKind, Result},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value as Json;
+
+use super::*;
+
+/// A class or struct with its own properties, inherited ones are on the parent
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ClassType {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    #[serde(default)]
+    pub properties: Vec<PropertyType>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PropertyType {
+    pub name: String,
+    /// RED4 type name, e.g. `Float`, `array:CName` or `handle:entIComponent`
+    #[serde(rename = "type")]
+    pub type_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<Json>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EnumType {
+    pub name: String,
+    pub members: Vec<EnumMember>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EnumMember {
+    pub name: String,
+    pub value: i64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BitfieldType {
+    pub name: String,
+    pub members: Vec<BitfieldMember>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BitfieldMember {
+    pub name: String,
+    pub bit: u8,
+}
+
+/// What a type name that is not a fundamental or generic type refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TypeKind {
+    Class,
+    Enum,
+    Bitfield,
+}
+
+/// The layout of the dump file
+#[derive(Default, Serialize, Deserialize)]
+struct Dump {
+    #[serde(default)]
+    classes: Vec<ClassType>,
+    #[serde(default)]
+    enums: Vec<EnumType>,
+    #[serde(default)]
+    bitfields: Vec<BitfieldType>,
+}
+
+/// Classes, enums and bitfields by name
+#[derive(Debug, Clone, Default)]
+pub struct TypeRegistry {
+    classes: HashMap<String, ClassType>,
+    enums: HashMap<String, EnumType>,
+    bitfields: HashMap<String, BitfieldType>,
+}
+
+impl TypeRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads a registry from an RTTI dump
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the json is invalid, a parent class doesn't exist,
+    /// the class hierarchy has a cycle or a default doesn't match the type of its property.
+    pub fn from_reader<R: io::Read>(reader: R) -> Result<Self> {
+        let dump: Dump =
+            serde_json::from_reader(reader).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+        let mut registry = Self::new();
+        for class in dump.classes {
+            registry.insert_class(class);
+        }
+        for enum_type in dump.enums {
+            registry.insert_enum(enum_type);
+        }
+        for bitfield in dump.bitfields {
+            registry.insert_bitfield(bitfield);
+        }
+        registry.check()?;
+
+        Ok(registry)
+    }
+
+    /// Loads a registry from an RTTI dump file
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file can't be read or is not a valid dump.
+    pub fn load<P: AsRef<Path>>(path: &P) -> Result<Self> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    /// Writes the registry as an RTTI dump, types are sorted by name
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if serialization fails.
+    pub fn write<W: io::Write>(&self, writer: W) -> Result<()> {
+        let mut dump = Dump {
+            classes: self.classes.values().cloned().collect(),
+            enums: self.enums.values().cloned().collect(),
+            bitfields: self.bitfields.values().cloned().collect(),
+        };
+        dump.classes.sort_by(|a, b| a.name.cmp(&b.name));
+        dump.enums.sort_by(|a, b| a.name.cmp(&b.name));
+        dump.bitfields.sort_by(|a, b| a.name.cmp(&b.name));
+        serde_json::to_writer_pretty(writer, &dump).map_err(Error::other)
+    }
+
+    pub fn insert_class(&mut self, class: ClassType) {
+        self.classes.insert(class.name.clone(), class);
+    }
+
+    pub fn insert_enum(&mut self, enum_type: EnumType) {
+        self.enums.insert(enum_type.name.clone(), enum_type);
+    }
+
+    pub fn insert_bitfield(&mut self, bitfield: BitfieldType) {
+        self.bitfields.insert(bitfield.name.clone(), bitfield);
+    }
+
+    pub fn class(&self, name: &str) -> Option<&ClassType> {
+        self.classes.get(name)
+    }
+
+    pub fn enum_type(&self, name: &str) -> Option<&EnumType> {
+        self.enums.get(name)
+    }
+
+    pub fn bitfield(&self, name: &str) -> Option<&BitfieldType> {
+        self.bitfields.get(name)
+    }
+
+    /// What a type name refers to, `None` for unknown, fundamental and generic types
+    pub fn kind(&self, name: &str) -> Option<TypeKind> {
+        if self.classes.contains_key(name) {
+            Some(TypeKind::Class)
+        } else if self.enums.contains_key(name) {
+            Some(TypeKind::Enum)
+        } else if self.bitfields.contains_key(name) {
+            Some(TypeKind::Bitfield)
+        } else {
+            None
+        }
+    }
+
+    /// True if the class is `base` or derives from it
+    pub fn is_subclass(&self, name: &str, base: &str) -> bool {
+        self.ancestors(name).any(|class| class.name == base)
+    }
+
+    /// The properties of a class including inherited ones, those of the parents first
+    pub fn properties(&self, name: &str) -> Vec<&PropertyType> {
+        let mut classes = self.ancestors(name).collect::<Vec<_>>();
+        classes.reverse();
+        classes
+            .into_iter()
+            .flat_map(|class| class.properties.iter())
+            .collect()
+    }
+
+    /// The default value of a property of a class, `None` if the property or its type is unknown
+    pub fn default_value(&self, class_name: &str, property_name: &str) -> Option<Value> {
+        let property = self
+            .properties(class_name)
+            .into_iter()
+            .find(|p| p.name == property_name)?;
+        self.property_default(property).ok()
+    }
+
+    /// The zero value of a type, `None` for unknown types
+    pub fn zero_value(&self, type_name: &str) -> Option<Value> {
+        self.value_from_json(type_name, &Json::Null).ok()
+    }
+
+    /// Adds the defaults of the properties of a class that are missing from `properties`, these are
+    /// marked as `default` so they are not written back
+    pub(crate) fn fill_defaults(&self, class_name: &str, properties: &mut Vec<Property>) {
+        for property in self.properties(class_name) {
+            if properties.iter().any(|p| p.name == property.name) {
+                continue;
+            }
+            if let Ok(value) = self.property_default(property) {
+                properties.push(Property {
+                    name: property.name.clone(),
+                    type_name: property.type_name.clone(),
+                    value,
+                    default: true,
+                });
+            }
+        }
+    }
+
+    /// The class and its parents, the class first
+    fn ancestors<'a>(&'a self, name: &str) -> impl Iterator<Item = &'a ClassType> + 'a {
+        let mut next = self.classes.get(name);
+        // stops on cycles, these are rejected when loading a dump
+        let mut remaining = self.classes.len();
+        std::iter::from_fn(move || {
+            let class = next.filter(|_| remaining > 0)?;
+            remaining -= 1;
+            next = class
+                .parent
+                .as_ref()
+                .and_then(|parent| self.classes.get(parent));
+            Some(class)
+        })
+    }
+
+    /// Checks parents and defaults of all classes
+    fn check(&self) -> Result<()> {
+        for class in self.classes.values() {
+            let mut parent = class.parent.as_ref();
+            let mut depth = 0;
+            while let Some(name) = parent {
+                let Some(parent_class) = self.classes.get(name) else {
+                    return Err(invalid_type(
+                        &class.name,
+                        &format!("unknown parent {}", name),
+                    ));
+                };
+                depth += 1;
+                if depth > self.classes.len() {
+                    return Err(invalid_type(&class.name, "the class derives from itself"));
+                }
+                parent = parent_class.parent.as_ref();
+            }
+
+            for property in class.properties.iter() {
+                if property.default.is_some() {
+                    self.property_default(property).map_err(|e| {
+                        invalid_type(
+                            &class.name,
+                            &format!("{}: invalid default: {}", property.name, e),
+                        )
+                    })?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn property_default(&self, property: &PropertyType) -> Result<Value> {
+        self.value_from_json(
+            &property.type_name,
+            property.default.as_ref().unwrap_or(&Json::Null),
+        )
+    }
+
+    /// Converts a default from the dump, null is the zero value of the type
+    fn value_from_json(&self, type_name: &str, json: &Json) -> Result<Value> {
+        let value = match type_name {
+            "Bool" => match json {
+                Json::Null => Value::Bool(false),
+                Json::Bool(value) => Value::Bool(*value),
+                _ => return Err(invalid_default("expected a bool")),
+            },
+            "Int8" => Value::Int8(integer(json)?),
+            "Uint8" => Value::Uint8(integer(json)?),
+            "Int16" => Value::Int16(integer(json)?),
+            "Uint16" => Value::Uint16(integer(json)?),
+            "Int32" => Value::Int32(integer(json)?),
+            "Uint32" => Value::Uint32(integer(json)?),
+            "Int64" => Value::Int64(integer(json)?),
+            "Uint64" | "TweakDBID" | "CRUID" | "CDateTime" | "gamedataLocKeyWrapper" => {
+                Value::Uint64(integer(json)?)
+            }
+            "Float" => Value::Float(float(json)? as f32),
+            "Double" => Value::Double(float(json)?),
+            "CName" => Value::CName(string(json, "None")?),
+            "String" => Value::String(string(json, "")?),
+            _ if type_name.starts_with("array:") || type_name.starts_with('[') => {
+                let inner = element_type(type_name);
+                let values = match json {
+                    Json::Null => vec![],
+                    Json::Array(values) => values
+                        .iter()
+                        .map(|value| self.value_from_json(inner, value))
+                        .collect::<Result<_>>()?,
+                    _ => return Err(invalid_default("expected an array")),
+                };
+                Value::Array(values)
+            }
+            _ if type_name.starts_with("handle:") => Value::Handle(None),
+            _ if type_name.starts_with("whandle:") => Value::WeakHandle(None),
+            _ if type_name.starts_with("rRef:") || type_name.starts_with("raRef:") => {
+                Value::ResourceReference(None)
+            }
+            _ => match self.kind(type_name) {
+                Some(TypeKind::Enum) => {
+                    let members = &self.enums[type_name].members;
+                    let name = match json {
+                        // the zero value is the member with value 0
+                        Json::Null => members
+                            .iter()
+                            .find(|member| member.value == 0)
+                            .or(members.first())
+                            .map(|member| member.name.clone())
+                            .ok_or_else(|| invalid_default("enum without members"))?,
+                        Json::String(name) if members.iter().any(|m| m.name == *name) => {
+                            name.clone()
+                        }
+                        _ => return Err(invalid_default("expected a member of the enum")),
+                    };
+                    Value::Enum(name)
+                }
+                Some(TypeKind::Bitfield) => {
+                    let members = &self.bitfields[type_name].members;
+                    let names = match json {
+                        Json::Null => vec![],
+                        Json::Array(names) => names
+                            .iter()
+                            .map(|name| match name.as_str() {
+                                Some(name) if members.iter().any(|m| m.name == name) => {
+                                    Ok(name.to_owned())
+                                }
+                                _ => Err(invalid_default("expected a member of the bitfield")),
+                            })
+                            .collect::<Result<_>>()?,
+                        _ => return Err(invalid_default("expected an array")),
+                    };
+                    Value::Bitfield(names)
+                }
+                Some(TypeKind::Class) => {
+                    let fields = match json {
+                        Json::Null => None,
+                        Json::Object(fields) => Some(fields),
+                        _ => return Err(invalid_default("expected an object")),
+                    };
+                    let mut properties = vec![];
+                    for property in self.properties(type_name) {
+                        // fields of the default replace the defaults of the struct
+                        let value = match fields.and_then(|fields| fields.get(&property.name)) {
+                            Some(field) => self.value_from_json(&property.type_name, field),
+                            None => self.property_default(property),
+                        }
+                        .map_err(|e| invalid_default(&format!("{}: {}", property.name, e)))?;
+                        properties.push(Property {
+                            name: property.name.clone(),
+                            type_name: property.type_name.clone(),
+                            value,
+                            default: false,
+                        });
+                    }
+                    Value::Struct(properties)
+                }
+                None => return Err(invalid_default(&format!("unknown type {}", type_name))),
+            },
+        };
+
+        Ok(value)
+    }
+}
+
+fn integer<T: TryFrom<i64> + TryFrom<u64> + Default>(json: &Json) -> Result<T> {
+    let value = match json {
+        Json::Null => return Ok(T::default()),
+        Json::Number(number) => match (number.as_i64(), number.as_u64()) {
+            (Some(value), _) => T::try_from(value).ok(),
+            (_, Some(value)) => T::try_from(value).ok(),
+            _ => None,
+        },
+        // 64-bit integers may be strings
+        Json::String(string) => match string.parse::<i64>() {
+            Ok(value) => T::try_from(value).ok(),
+            Err(_) => string.parse::<u64>().ok().and_then(|v| T::try_from(v).ok()),
+        },
+        _ => None,
+    };
+    value.ok_or_else(|| invalid_default("expected an integer"))
+}
+
+fn float(json: &Json) -> Result<f64> {
+    match json {
+        Json::Null => Ok(0.0),
+        _ => json
+            .as_f64()
+            .ok_or_else(|| invalid_default("expected a number")),
+    }
+}
+
+fn string(json: &Json, zero: &str) -> Result<String> {
+    match json {
+        Json::Null => Ok(zero.to_owned()),
+        Json::String(string) => Ok(string.clone()),
+        _ => Err(invalid_default("expected a string")),
+    }
+}
+
+fn invalid_default(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+fn invalid_type(name: &str, msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("{}: {}", name, msg))
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// TESTS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DUMP: &str = r#"{
+        "classes": [
+            { "name": "ISerializable" },
+            {
+                "name": "resResource",
+                "parent": "ISerializable",
+                "properties": [{ "name": "cookingPlatform", "type": "ECookingPlatform" }]
+            },
+            {
+                "name": "Resource",
+                "parent": "resResource",
+                "properties": [
+                    { "name": "scale", "type": "Float", "default": 1.5 },
+                    { "name": "flags", "type": "EFlags", "default": ["Visible"] },
+                    { "name": "point", "type": "Point", "default": { "y": 3 } },
+                    { "name": "tags", "type": "array:CName", "default": ["a", "b"] },
+                    { "name": "id", "type": "Uint64", "default": "18446744073709551615" },
+                    { "name": "child", "type": "handle:ISerializable" }
+                ]
+            },
+            {
+                "name": "Point",
+                "properties": [
+                    { "name": "x", "type": "Int32" },
+                    { "name": "y", "type": "Int32" }
+                ]
+            }
+        ],
+        "enums": [
+            {
+                "name": "ECookingPlatform",
+                "members": [
+                    { "name": "PLATFORM_PS5", "value": 7 },
+                    { "name": "PLATFORM_None", "value": 0 }
+                ]
+            }
+        ],
+        "bitfields": [
+            {
+                "name": "EFlags",
+                "members": [{ "name": "Visible", "bit": 0 }, { "name": "Hidden", "bit": 1 }]
+            }
+        ]
+    }"#;
+
+    fn point(x: i32, y: i32) -> Value {
+        Value::Struct(vec![
+            Property {
+                name: "x".to_owned(),
+                type_name: "Int32".to_owned(),
+                value: Value::Int32(x),
+                default: false,
+            },
+            Property {
+                name: "y".to_owned(),
+                type_name: "Int32".to_owned(),
+                value: Value::Int32(y),
+                default: false,
+            },
+        ])
+    }
+
+    #[test]
+    fn load() {
+        let types = TypeRegistry::from_reader(DUMP.as_bytes()).unwrap();
+        assert_eq!(Some(TypeKind::Class), types.kind("Point"));
+        assert_eq!(Some(TypeKind::Enum), types.kind("ECookingPlatform"));
+        assert_eq!(Some(TypeKind::Bitfield), types.kind("EFlags"));
+        assert_eq!(None, types.kind("Float"));
+        assert!(types.is_subclass("Resource", "ISerializable"));
+        assert!(!types.is_subclass("resResource", "Resource"));
+
+        // inherited properties come first
+        let properties = types
+            .properties("Resource")
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "cookingPlatform",
+                "scale",
+                "flags",
+                "point",
+                "tags",
+                "id",
+                "child"
+            ],
+            properties
+        );
+
+        // the dump is written sorted and reads back the same
+        let mut output = vec![];
+        types.write(&mut output).unwrap();
+        let written = TypeRegistry::from_reader(output.as_slice()).unwrap();
+        assert_eq!(types.class("Resource"), written.class("Resource"));
+        assert_eq!(
+            types.enum_type("ECookingPlatform"),
+            written.enum_type("ECookingPlatform")
+        );
+    }
+
+    #[test]
+    fn defaults() {
+        let types = TypeRegistry::from_reader(DUMP.as_bytes()).unwrap();
+        let default = |name| types.default_value("Resource", name).unwrap();
+        assert_eq!(
+            Value::Enum("PLATFORM_None".to_owned()),
+            default("cookingPlatform")
+        );
+        assert_eq!(Value::Float(1.5), default("scale"));
+        assert_eq!(
+            Value::Bitfield(vec!["Visible".to_owned()]),
+            default("flags")
+        );
+        assert_eq!(point(0, 3), default("point"));
+        assert_eq!(
+            Value::Array(vec![
+                Value::CName("a".to_owned()),
+                Value::CName("b".to_owned())
+            ]),
+            default("tags")
+        );
+        assert_eq!(Value::Uint64(u64::MAX), default("id"));
+        assert_eq!(Value::Handle(None), default("child"));
+        assert_eq!(None, types.default_value("Resource", "missing"));
+
+        assert_eq!(Some(point(0, 0)), types.zero_value("Point"));
+        assert_eq!(
+            Some(Value::CName("None".to_owned())),
+            types.zero_value("CName")
+        );
+        assert_eq!(None, types.zero_value("Unknown"));
+
+        let mut properties = vec![Property {
+            name: "scale".to_owned(),
+            type_name: "Float".to_owned(),
+            value: Value::Float(2.0),
+            default: false,
+        }];
+        types.fill_defaults("Resource", &mut properties);
+        assert_eq!(7, properties.len());
+        assert_eq!(Value::Float(2.0), properties[0].value);
+        assert_eq!("cookingPlatform", properties[1].name);
+    }
+
+    #[test]
+    fn invalid_dumps() {
+        for (dump, error) in [
+            (
+                r#"{ "classes": [{ "name": "A", "parent": "B" }] }"#,
+                "A: unknown parent B",
+            ),
+            (
+                r#"{ "classes": [{ "name": "A", "parent": "B" }, { "name": "B", "parent": "A" }] }"#,
+                "derives from itself",
+            ),
+            (
+                r#"{ "classes": [{ "name": "A", "properties": [{ "name": "a", "type": "Uint8", "default": 300 }] }] }"#,
+                "A: a: invalid default: expected an integer",
+            ),
+            (
+                r#"{ "classes": [{ "name": "A", "properties": [{ "name": "a", "type": "EUnknown", "default": "x" }] }] }"#,
+                "unknown type EUnknown",
+            ),
+            (r#"{ "classes": 1 }"#, "invalid type"),
+        ] {
+            let result = TypeRegistry::from_reader(dump.as_bytes());
+            let message = result.unwrap_err().to_string();
+            assert!(message.contains(error), "{}", message);
+        }
+    }
+}
@@ -0,0 +1,463 @@
+/////////////////////////////////////////////////////////////////////////////////////////
+// CR2W VALIDATION
+// Checks the CRCs, bounds and cross references of a cr2w file. Nothing in the header is
+// trusted, tables are only parsed when they are inside the file.
+/////////////////////////////////////////////////////////////////////////////////////////
+
+use std::{
+    fmt,
+    io::{self, Read, Result, Seek, SeekFrom},
+};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use super::writer::{header_crc32, HEADER_SIZE};
+use super::*;
+use crate::hash::crc32;
+
+/// Names and item sizes of the used tables of the header
+const TABLES: [(&str, u32); 7] = [
+    ("strings", 1),
+    ("names", 8),
+    ("imports", 8),
+    ("properties", 16),
+    ("exports", 24),
+    ("buffers", 24),
+    ("embeds", 16),
+];
+
+/// A problem found in a cr2w file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CR2WDiagnostic {
+    /// The crc32 of the header doesn't match its content
+    HeaderCrc { stored: u32, computed: u32 },
+    /// `objects_end` or `buffers_end` is past the end of the file
+    EndOutOfBounds {
+        field: &'static str,
+        end: u32,
+        file_size: u64,
+    },
+    /// A table is not inside the file, its entries are not checked
+    TableOutOfBounds {
+        table: &'static str,
+        offset: u32,
+        size: u64,
+        file_size: u64,
+    },
+    /// The crc32 of a table doesn't match its content
+    TableCrc {
+        table: &'static str,
+        stored: u32,
+        computed: u32,
+    },
+    /// An entry refers to an offset that is not the start of a string
+    DanglingString {
+        table: &'static str,
+        index: usize,
+        offset: u32,
+    },
+    /// An entry refers to a name that is not in the names table
+    DanglingName {
+        table: &'static str,
+        index: usize,
+        name: u16,
+    },
+    /// An entry refers to an import that is not in the imports table
+    DanglingImport {
+        table: &'static str,
+        index: usize,
+        import: u32,
+    },
+    /// An entry refers to an export that is not in the exports table
+    DanglingExport {
+        table: &'static str,
+        index: usize,
+        export: u32,
+    },
+    /// The data of an export is not between the tables and `objects_end`
+    ExportOutOfBounds {
+        index: usize,
+        offset: u32,
+        size: u32,
+    },
+    /// The data of two exports overlap
+    OverlappingExports { first: usize, second: usize },
+    /// A buffer is not between `objects_end` and `buffers_end`
+    BufferOutOfBounds {
+        index: usize,
+        offset: u32,
+        size: u32,
+    },
+    /// The crc32 of a buffer doesn't match its content
+    BufferCrc {
+        index: usize,
+        stored: u32,
+        computed: u32,
+    },
+}
+
+impl fmt::Display for CR2WDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CR2WDiagnostic::HeaderCrc { stored, computed } => write!(
+                f,
+                "header: crc32 is {:#010x}, expected {:#010x}",
+                stored, computed
+            ),
+            CR2WDiagnostic::EndOutOfBounds {
+                field,
+                end,
+                file_size,
+            } => write!(
+                f,
+                "header: {} {:#x} is past the end of the file ({:#x})",
+                field, end, file_size
+            ),
+            CR2WDiagnostic::TableOutOfBounds {
+                table,
+                offset,
+                size,
+                file_size,
+            } => write!(
+                f,
+                "{} table: {:#x} bytes at {:#x} are past the end of the file ({:#x})",
+                table, size, offset, file_size
+            ),
+            CR2WDiagnostic::TableCrc {
+                table,
+                stored,
+                computed,
+            } => write!(
+                f,
+                "{} table: crc32 is {:#010x}, expected {:#010x}",
+                table, stored, computed
+            ),
+            CR2WDiagnostic::DanglingString {
+                table,
+                index,
+                offset,
+            } => write!(f, "{} {}: no string at offset {}", table, index, offset),
+            CR2WDiagnostic::DanglingName { table, index, name } => {
+                write!(f, "{} {}: name {} is out of range", table, index, name)
+            }
+            CR2WDiagnostic::DanglingImport {
+                table,
+                index,
+                import,
+            } => write!(f, "{} {}: import {} is out of range", table, index, import),
+            CR2WDiagnostic::DanglingExport {
+                table,
+                index,
+                export,
+            } => write!(f, "{} {}: export {} is out of range", table, index, export),
+            CR2WDiagnostic::ExportOutOfBounds {
+                index,
+                offset,
+                size,
+            } => write!(
+                f,
+                "export {}: {:#x} bytes at {:#x} are not between the tables and objects_end",
+                index, size, offset
+            ),
+            CR2WDiagnostic::OverlappingExports { first, second } => {
+                write!(f, "export {}: data overlaps export {}", second, first)
+            }
+            CR2WDiagnostic::BufferOutOfBounds {
+                index,
+                offset,
+                size,
+            } => write!(
+                f,
+                "buffer {}: {:#x} bytes at {:#x} are not between objects_end and buffers_end",
+                index, size, offset
+            ),
+            CR2WDiagnostic::BufferCrc {
+                index,
+                stored,
+                computed,
+            } => write!(
+                f,
+                "buffer {}: crc32 is {:#010x}, expected {:#010x}",
+                index, stored, computed
+            ),
+        }
+    }
+}
+
+/// Validates a cr2w file and returns all problems that were found
+///
+/// # Errors
+///
+/// This function will return an error if io fails or the file doesn't start with a cr2w header.
+pub fn validate_cr2w<R: Read + Seek>(reader: &mut R) -> Result<Vec<CR2WDiagnostic>> {
+    let file_size = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+
+    let magic = reader.read_u32::<LittleEndian>()?;
+    if magic != CR2WFileHeader::MAGIC {
+        return Err(io::Error::other("invalid magic"));
+    }
+    let header = CR2WFileHeader::from_reader(reader)?;
+    let mut tables = [CR2WTable::default(); 10];
+    for table in tables.iter_mut() {
+        *table = CR2WTable::from_reader(reader)?;
+    }
+
+    let mut diagnostics = vec![];
+    let computed = header_crc32(&header, &tables)?;
+    if computed != header.crc32 {
+        diagnostics.push(CR2WDiagnostic::HeaderCrc {
+            stored: header.crc32,
+            computed,
+        });
+    }
+    for (field, end) in [
+        ("objects_end", header.objects_end),
+        ("buffers_end", header.buffers_end),
+    ] {
+        if end as u64 > file_size {
+            diagnostics.push(CR2WDiagnostic::EndOutOfBounds {
+                field,
+                end,
+                file_size,
+            });
+        }
+    }
+
+    // the entries are only checked if all tables can be read
+    let mut tables_end = HEADER_SIZE as u64;
+    let mut in_bounds = true;
+    for (table, (name, item_size)) in tables.iter().zip(TABLES) {
+        if table.item_count == 0 {
+            continue;
+        }
+        let size = table.item_count as u64 * item_size as u64;
+        if table.offset as u64 + size > file_size {
+            diagnostics.push(CR2WDiagnostic::TableOutOfBounds {
+                table: name,
+                offset: table.offset,
+                size,
+                file_size,
+            });
+            in_bounds = false;
+            continue;
+        }
+        tables_end = tables_end.max(table.offset as u64 + size);
+
+        let mut data = vec![0; size as usize];
+        reader.seek(SeekFrom::Start(table.offset as u64))?;
+        reader.read_exact(&mut data)?;
+        let computed = crc32(&data);
+        if computed != table.crc32 {
+            diagnostics.push(CR2WDiagnostic::TableCrc {
+                table: name,
+                stored: table.crc32,
+                computed,
+            });
+        }
+    }
+    if !in_bounds {
+        return Ok(diagnostics);
+    }
+
+    let strings = read_strings(reader, tables[0])?;
+    let names_table = read_table::<R, CR2WNameInfo>(reader, tables[1])?;
+    let imports_table = read_table::<R, CR2WImportInfo>(reader, tables[2])?;
+    let properties_table = read_table::<R, CR2WPropertyInfo>(reader, tables[3])?;
+    let exports_table = read_table::<R, CR2WExportInfo>(reader, tables[4])?;
+    let buffers_table = read_table::<R, CR2WBufferInfo>(reader, tables[5])?;
+    let embeds_table = read_table::<R, CR2WEmbeddedInfo>(reader, tables[6])?;
+
+    // cross references
+    let names_count = names_table.len();
+    let mut check_name = |table, index, name: u16| {
+        if name as usize >= names_count {
+            diagnostics.push(CR2WDiagnostic::DanglingName { table, index, name });
+        }
+    };
+    for (index, export) in exports_table.iter().enumerate() {
+        check_name("export", index, export.class_name);
+    }
+    for (index, import) in imports_table.iter().enumerate() {
+        check_name("import", index, import.class_name);
+    }
+    for (index, property) in properties_table.iter().enumerate() {
+        check_name("property", index, property.class_name);
+        check_name("property", index, property.property_name);
+    }
+
+    for (index, name) in names_table.iter().enumerate() {
+        if !strings.contains_key(&name.offset) {
+            diagnostics.push(CR2WDiagnostic::DanglingString {
+                table: "name",
+                index,
+                offset: name.offset,
+            });
+        }
+    }
+    for (index, import) in imports_table.iter().enumerate() {
+        if !strings.contains_key(&import.offset) {
+            diagnostics.push(CR2WDiagnostic::DanglingString {
+                table: "import",
+                index,
+                offset: import.offset,
+            });
+        }
+    }
+
+    // parent ids and import indices are 1-based
+    for (index, export) in exports_table.iter().enumerate() {
+        if export.parent_id as usize > exports_table.len() {
+            diagnostics.push(CR2WDiagnostic::DanglingExport {
+                table: "export",
+                index,
+                export: export.parent_id,
+            });
+        }
+    }
+    for (index, embed) in embeds_table.iter().enumerate() {
+        if embed.import_index == 0 || embed.import_index as usize > imports_table.len() {
+            diagnostics.push(CR2WDiagnostic::DanglingImport {
+                table: "embed",
+                index,
+                import: embed.import_index,
+            });
+        }
+        if embed.chunk_index as usize >= exports_table.len() {
+            diagnostics.push(CR2WDiagnostic::DanglingExport {
+                table: "embed",
+                index,
+                export: embed.chunk_index,
+            });
+        }
+    }
+
+    // export data
+    for (index, export) in exports_table.iter().enumerate() {
+        let end = export.data_offset as u64 + export.data_size as u64;
+        if (export.data_offset as u64) < tables_end || end > header.objects_end as u64 {
+            diagnostics.push(CR2WDiagnostic::ExportOutOfBounds {
+                index,
+                offset: export.data_offset,
+                size: export.data_size,
+            });
+        }
+    }
+    let mut by_offset = (0..exports_table.len())
+        .filter(|i| exports_table[*i].data_size > 0)
+        .collect::<Vec<_>>();
+    by_offset.sort_by_key(|i| exports_table[*i].data_offset);
+    for pair in by_offset.windows(2) {
+        let (first, second) = (&exports_table[pair[0]], &exports_table[pair[1]]);
+        if first.data_offset as u64 + first.data_size as u64 > second.data_offset as u64 {
+            diagnostics.push(CR2WDiagnostic::OverlappingExports {
+                first: pair[0],
+                second: pair[1],
+            });
+        }
+    }
+
+    // buffers
+    for (index, buffer) in buffers_table.iter().enumerate() {
+        let end = buffer.offset as u64 + buffer.disk_size as u64;
+        if buffer.offset < header.objects_end || end > header.buffers_end as u64 {
+            diagnostics.push(CR2WDiagnostic::BufferOutOfBounds {
+                index,
+                offset: buffer.offset,
+                size: buffer.disk_size,
+            });
+        }
+        if end <= file_size {
+            let mut data = vec![0; buffer.disk_size as usize];
+            reader.seek(SeekFrom::Start(buffer.offset as u64))?;
+            reader.read_exact(&mut data)?;
+            let computed = crc32(&data);
+            if computed != buffer.crc32 {
+                diagnostics.push(CR2WDiagnostic::BufferCrc {
+                    index,
+                    stored: buffer.crc32,
+                    computed,
+                });
+            }
+        }
+    }
+
+    Ok(diagnostics)
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// TESTS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::cr2w::writer::tests::cr2w_test_files;
+
+    #[test]
+    fn valid_files() {
+        for (hash, buffer) in cr2w_test_files() {
+            let diagnostics = validate_cr2w(&mut Cursor::new(&buffer)).unwrap();
+            assert!(diagnostics.is_empty(), "{}: {:?}", hash, diagnostics);
+        }
+    }
+
+    /// Offset and item count of a table
+    fn table(buffer: &[u8], index: usize) -> (usize, usize) {
+        let start = 40 + 12 * index;
+        let value = |i: usize| u32::from_le_bytes(buffer[i..i + 4].try_into().unwrap()) as usize;
+        (value(start), value(start + 4))
+    }
+
+    #[test]
+    fn broken_files() {
+        let (_, buffer) = cr2w_test_files()
+            .into_iter()
+            .find(|(_, buffer)| table(buffer, 4).1 > 1)
+            .unwrap();
+
+        // dangling parent and two exports with the same data
+        let mut broken = buffer.clone();
+        let (exports, _) = table(&buffer, 4);
+        broken[exports + 4..exports + 8].copy_from_slice(&1000u32.to_le_bytes());
+        broken.copy_within(exports + 12..exports + 16, exports + 24 + 12);
+        let diagnostics = validate_cr2w(&mut Cursor::new(&broken)).unwrap();
+        assert!(diagnostics.contains(&CR2WDiagnostic::DanglingExport {
+            table: "export",
+            index: 0,
+            export: 1000
+        }));
+        assert!(diagnostics.contains(&CR2WDiagnostic::OverlappingExports {
+            first: 0,
+            second: 1
+        }));
+        let Some(CR2WDiagnostic::TableCrc { table, .. }) = diagnostics.first() else {
+            panic!("expected a crc mismatch, got {:?}", diagnostics);
+        };
+        assert_eq!(*table, "exports");
+
+        // truncated in the string table
+        let broken = &buffer[..HEADER_SIZE as usize + 4];
+        assert!(read_cr2w_header(&mut Cursor::new(broken)).is_err());
+        let diagnostics = validate_cr2w(&mut Cursor::new(broken)).unwrap();
+        assert!(matches!(
+            diagnostics
+                .iter()
+                .find(|d| matches!(d, CR2WDiagnostic::TableOutOfBounds { .. })),
+            Some(CR2WDiagnostic::TableOutOfBounds {
+                table: "strings",
+                ..
+            })
+        ));
+        assert_eq!(
+            "header: crc32 is 0x00000000, expected 0x12345678",
+            CR2WDiagnostic::HeaderCrc {
+                stored: 0,
+                computed: 0x12345678
+            }
+            .to_string()
+        );
+    }
+}
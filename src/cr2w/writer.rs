@@ -0,0 +1,515 @@
+/////////////////////////////////////////////////////////////////////////////////////////
+// CR2W WRITER
+// The names of the file are kept, names that are new in the chunks are appended. The string
+// table holds the names and import depot paths. Offsets, sizes and CRCs of all tables are
+// recomputed.
+/////////////////////////////////////////////////////////////////////////////////////////
+
+use std::{
+    collections::HashMap,
+    io::{self, Error, ErrorKind, Result, Write},
+};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use super::*;
+use crate::hash::{crc32, CNameHash};
+
+/// Header, ten tables and padding
+pub(super) const HEADER_SIZE: u32 = 160;
+const NONE: &str = "None";
+
+/// Writes a cr2w file
+///
+/// The header fields, imports, properties and embeds are taken from `file.info`, all other
+/// tables are built from the chunks and buffers.
+///
+/// # Errors
+///
+/// This function will return an error if io fails or the file has too many names.
+pub fn write_cr2w<W: Write>(writer: &mut W, file: &CR2WFile) -> Result<()> {
+    let names = collect_names(file)?;
+
+    // string table
+    let mut strings = StringTable::default();
+    for name in names.names.iter() {
+        strings.add(name);
+    }
+    for import in file.info.imports.iter() {
+        strings.add(&import.depot_path);
+    }
+
+    // tables
+    let mut names_buffer = vec![];
+    for name in names.names.iter() {
+        CR2WNameInfo {
+            offset: strings.offset(name),
+            hash: CNameHash::new(name).folded(),
+        }
+        .write(&mut names_buffer)?;
+    }
+
+    let mut imports_buffer = vec![];
+    for import in file.info.imports.iter() {
+        CR2WImportInfo {
+            offset: strings.offset(&import.depot_path),
+            class_name: names.index(&import.class_name)?,
+            flags: import.flags.bits(),
+        }
+        .write(&mut imports_buffer)?;
+    }
+
+    let mut properties_buffer = vec![];
+    for property in file.info.properties_table.iter() {
+        property.write(&mut properties_buffer)?;
+    }
+
+    let mut embeds_buffer = vec![];
+    for embed in file.info.embeds_table.iter() {
+        embed.write(&mut embeds_buffer)?;
+    }
+
+    let mut chunks_buffer = vec![];
+    let mut chunk_ranges = vec![];
+    for chunk in file.chunks.iter() {
+        let start = chunks_buffer.len();
+        if chunk.decoded {
+            write_properties(&mut chunks_buffer, &chunk.properties, &names)?;
+        }
+        chunks_buffer.extend_from_slice(&chunk.trailing);
+        chunk_ranges.push((start as u32, (chunks_buffer.len() - start) as u32));
+    }
+
+    // the exports and buffers tables depend on the position of the data
+    let data_offset = HEADER_SIZE
+        + (strings.buffer.len()
+            + names_buffer.len()
+            + imports_buffer.len()
+            + properties_buffer.len()
+            + file.chunks.len() * 24
+            + file.buffers.len() * 24
+            + embeds_buffer.len()) as u32;
+    let objects_end = data_offset + chunks_buffer.len() as u32;
+
+    let mut exports_buffer = vec![];
+    for (chunk, (offset, size)) in file.chunks.iter().zip(chunk_ranges) {
+        CR2WExportInfo {
+            class_name: names.index(&chunk.class_name)?,
+            object_flags: chunk.flags,
+            parent_id: chunk.parent.map_or(0, |p| p as u32 + 1),
+            data_size: size,
+            data_offset: data_offset + offset,
+            template: chunk.template,
+            crc32: 0,
+        }
+        .write(&mut exports_buffer)?;
+    }
+
+    let mut buffers_buffer = vec![];
+    let mut buffer_offset = objects_end;
+    for buffer in file.buffers.iter() {
+        CR2WBufferInfo {
+            flags: buffer.flags,
+            index: buffer.index,
+            offset: buffer_offset,
+            disk_size: buffer.data.len() as u32,
+            mem_size: buffer.mem_size,
+            crc32: crc32(&buffer.data),
+        }
+        .write(&mut buffers_buffer)?;
+        buffer_offset += buffer.data.len() as u32;
+    }
+
+    // header
+    let tables_data = [
+        (strings.buffer.as_slice(), strings.buffer.len()),
+        (names_buffer.as_slice(), names.names.len()),
+        (imports_buffer.as_slice(), file.info.imports.len()),
+        (
+            properties_buffer.as_slice(),
+            file.info.properties_table.len(),
+        ),
+        (exports_buffer.as_slice(), file.chunks.len()),
+        (buffers_buffer.as_slice(), file.buffers.len()),
+        (embeds_buffer.as_slice(), file.info.embeds_table.len()),
+    ];
+    let mut tables = [CR2WTable::default(); 10];
+    let mut offset = HEADER_SIZE;
+    for (table, (data, count)) in tables.iter_mut().zip(tables_data) {
+        if count > 0 {
+            *table = CR2WTable {
+                offset,
+                item_count: count as u32,
+                crc32: crc32(data),
+            };
+        }
+        offset += data.len() as u32;
+    }
+
+    let mut header = file.info.header;
+    header.objects_end = objects_end;
+    header.buffers_end = buffer_offset;
+    header.crc32 = header_crc32(&header, &tables)?;
+
+    // write
+    writer.write_all(&header_bytes(&header, &tables)?)?;
+    for (data, _) in tables_data {
+        writer.write_all(data)?;
+    }
+    writer.write_all(&chunks_buffer)?;
+    for buffer in file.buffers.iter() {
+        writer.write_all(&buffer.data)?;
+    }
+
+    Ok(())
+}
+
+/// The crc of the header is computed with 0xDEADBEEF in place of the crc
+pub(super) fn header_crc32(header: &CR2WFileHeader, tables: &[CR2WTable]) -> Result<u32> {
+    let mut header = *header;
+    header.crc32 = 0xDEADBEEF;
+    Ok(crc32(&header_bytes(&header, tables)?))
+}
+
+fn header_bytes(header: &CR2WFileHeader, tables: &[CR2WTable]) -> Result<Vec<u8>> {
+    let mut buffer = vec![];
+    buffer.write_u32::<LittleEndian>(CR2WFileHeader::MAGIC)?;
+    header.write(&mut buffer)?;
+    for table in tables {
+        table.write(&mut buffer)?;
+    }
+    Ok(buffer)
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// NAMES
+/////////////////////////////////////////////////////////////////////////////////////////
+
+/// Names of the file followed by new names in the order they are first used, `None` is always
+/// the first name.
+///
+/// Existing names keep their index since class specific data that isn't decoded may refer to them.
+struct NameTable {
+    names: Vec<String>,
+    indices: HashMap<String, u16>,
+}
+
+impl NameTable {
+    fn new(existing: &[String]) -> Self {
+        let mut names = Self {
+            names: vec![],
+            indices: HashMap::default(),
+        };
+        for name in existing {
+            names
+                .indices
+                .entry(name.to_owned())
+                .or_insert(names.names.len() as u16);
+            names.names.push(name.to_owned());
+        }
+        names.add(NONE);
+        names
+    }
+
+    fn add(&mut self, name: &str) {
+        let name = if name.is_empty() { NONE } else { name };
+        if !self.indices.contains_key(name) {
+            self.indices
+                .insert(name.to_owned(), self.names.len() as u16);
+            self.names.push(name.to_owned());
+        }
+    }
+
+    fn index(&self, name: &str) -> Result<u16> {
+        let name = if name.is_empty() { NONE } else { name };
+        self.indices
+            .get(name)
+            .copied()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("unknown name {}", name)))
+    }
+}
+
+fn collect_names(file: &CR2WFile) -> Result<NameTable> {
+    let mut collector = NameCollector {
+        chunks: &file.chunks,
+        visited: vec![false; file.chunks.len()],
+        names: NameTable::new(&file.info.names),
+    };
+    for index in 0..file.chunks.len() {
+        collector.visit_chunk(index);
+    }
+    let mut names = collector.names;
+    for import in file.info.imports.iter() {
+        names.add(&import.class_name);
+    }
+
+    // names are referenced by 16 bit indices
+    if names.names.len() > u16::MAX as usize + 1 {
+        return Err(Error::new(ErrorKind::InvalidInput, "too many names"));
+    }
+    Ok(names)
+}
+
+/// Collects names depth first, chunks are visited when they are first referenced by a handle
+struct NameCollector<'a> {
+    chunks: &'a [Chunk],
+    visited: Vec<bool>,
+    names: NameTable,
+}
+
+impl NameCollector<'_> {
+    fn visit_chunk(&mut self, index: usize) {
+        if self.visited[index] {
+            return;
+        }
+        self.visited[index] = true;
+
+        let chunk = &self.chunks[index];
+        self.names.add(&chunk.class_name);
+        self.visit_properties(&chunk.properties);
+    }
+
+    fn visit_properties(&mut self, properties: &[Property]) {
+        for property in properties.iter().filter(|p| !p.default) {
+            self.names.add(&property.name);
+            self.names.add(&property.type_name);
+            self.visit_value(&property.value);
+        }
+    }
+
+    fn visit_value(&mut self, value: &Value) {
+        match value {
+            Value::CName(name) | Value::Enum(name) => self.names.add(name),
+            Value::Bitfield(values) => values.iter().for_each(|name| self.names.add(name)),
+            Value::Handle(Some(index)) if *index < self.chunks.len() => self.visit_chunk(*index),
+            Value::Array(values) => values.iter().for_each(|v| self.visit_value(v)),
+            Value::Struct(properties) => self.visit_properties(properties),
+            _ => {}
+        }
+    }
+}
+
+/// Null terminated strings, each string is only stored once
+#[derive(Default)]
+struct StringTable {
+    buffer: Vec<u8>,
+    offsets: HashMap<String, u32>,
+}
+
+impl StringTable {
+    fn add(&mut self, string: &str) {
+        // None is stored as an empty string
+        let string = if string == NONE { "" } else { string };
+        if !self.offsets.contains_key(string) {
+            self.offsets
+                .insert(string.to_owned(), self.buffer.len() as u32);
+            self.buffer.extend_from_slice(string.as_bytes());
+            self.buffer.push(0);
+        }
+    }
+
+    fn offset(&self, string: &str) -> u32 {
+        let string = if string == NONE { "" } else { string };
+        self.offsets[string]
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// CHUNKS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+/// Writes a zero byte, the properties and a zero name index, defaults that aren't in the file are
+/// skipped
+fn write_properties(
+    buffer: &mut Vec<u8>,
+    properties: &[Property],
+    names: &NameTable,
+) -> Result<()> {
+    buffer.write_u8(0)?;
+    for property in properties.iter().filter(|p| !p.default) {
+        buffer.write_u16::<LittleEndian>(names.index(&property.name)?)?;
+        buffer.write_u16::<LittleEndian>(names.index(&property.type_name)?)?;
+
+        // the size includes itself
+        let start = buffer.len();
+        buffer.write_u32::<LittleEndian>(0)?;
+        write_value(buffer, &property.value, names)?;
+        let size = (buffer.len() - start) as u32;
+        buffer[start..start + 4].copy_from_slice(&size.to_le_bytes());
+    }
+    buffer.write_u16::<LittleEndian>(0)?;
+
+    Ok(())
+}
+
+fn write_value(buffer: &mut Vec<u8>, value: &Value, names: &NameTable) -> Result<()> {
+    match value {
+        Value::Bool(v) => buffer.write_u8(*v as u8)?,
+        Value::Int8(v) => buffer.write_i8(*v)?,
+        Value::Uint8(v) => buffer.write_u8(*v)?,
+        Value::Int16(v) => buffer.write_i16::<LittleEndian>(*v)?,
+        Value::Uint16(v) => buffer.write_u16::<LittleEndian>(*v)?,
+        Value::Int32(v) => buffer.write_i32::<LittleEndian>(*v)?,
+        Value::Uint32(v) => buffer.write_u32::<LittleEndian>(*v)?,
+        Value::Int64(v) => buffer.write_i64::<LittleEndian>(*v)?,
+        Value::Uint64(v) => buffer.write_u64::<LittleEndian>(*v)?,
+        Value::Float(v) => buffer.write_f32::<LittleEndian>(*v)?,
+        Value::Double(v) => buffer.write_f64::<LittleEndian>(*v)?,
+        Value::CName(name) | Value::Enum(name) => {
+            buffer.write_u16::<LittleEndian>(names.index(name)?)?
+        }
+        Value::String(string) => write_string(buffer, string)?,
+        Value::Bitfield(values) => {
+            for name in values {
+                buffer.write_u16::<LittleEndian>(names.index(name)?)?;
+            }
+            buffer.write_u16::<LittleEndian>(0)?;
+        }
+        // chunk and import indices are 1-based, 0 is none
+        Value::Handle(index) | Value::WeakHandle(index) => {
+            let index = index
+                .map_or(Ok(0), |i| i32::try_from(i + 1))
+                .map_err(|_| Error::new(ErrorKind::InvalidInput, "handle index is out of range"))?;
+            buffer.write_i32::<LittleEndian>(index)?
+        }
+        Value::ResourceReference(index) => {
+            let index = index
+                .map_or(Ok(0), |i| u16::try_from(i + 1))
+                .map_err(|_| Error::new(ErrorKind::InvalidInput, "import index is out of range"))?;
+            buffer.write_u16::<LittleEndian>(index)?
+        }
+        Value::Array(values) => {
+            buffer.write_u32::<LittleEndian>(values.len() as u32)?;
+            for value in values {
+                write_value(buffer, value, names)?;
+            }
+        }
+        Value::Struct(properties) => write_properties(buffer, properties, names)?,
+        Value::Raw(data) => buffer.extend_from_slice(data),
+    }
+
+    Ok(())
+}
+
+/// Writes a string with a variable length prefix, ASCII strings have a negative length
+fn write_string(buffer: &mut Vec<u8>, string: &str) -> io::Result<()> {
+    let ascii = string.is_ascii();
+    let utf16 = string.encode_utf16().collect::<Vec<_>>();
+    let mut length = if ascii { string.len() } else { utf16.len() };
+
+    let mut first = (length & 0x3F) as u8;
+    if ascii {
+        first |= 0x80;
+    }
+    length >>= 6;
+    if length > 0 {
+        first |= 0x40;
+    }
+    buffer.write_u8(first)?;
+    while length > 0 {
+        let mut b = (length & 0x7F) as u8;
+        length >>= 7;
+        if length > 0 {
+            b |= 0x80;
+        }
+        buffer.write_u8(b)?;
+    }
+
+    if ascii {
+        buffer.extend_from_slice(string.as_bytes());
+    } else {
+        for c in utf16 {
+            buffer.write_u16::<LittleEndian>(c)?;
+        }
+    }
+
+    Ok(())
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// TESTS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+pub(super) mod tests {
+    use std::{io::Cursor, path::PathBuf};
+
+    use super::*;
+    use crate::archive::open_read;
+
+    /// All cr2w files in the test archives
+    pub(crate) fn cr2w_test_files() -> Vec<(u64, Vec<u8>)> {
+        let mut files = vec![];
+        for name in ["test1.archive", "nci.archive"] {
+            let mut archive = open_read(PathBuf::from("tests").join(name)).unwrap();
+            let mut entries = archive.get_entries().values().cloned().collect::<Vec<_>>();
+            entries.sort_by_key(|e| e.hash);
+            for entry in entries {
+                let mut buffer = vec![];
+                archive.open_entry(entry.clone(), &mut buffer).unwrap();
+                if buffer.starts_with(b"CR2W") {
+                    files.push((entry.hash, buffer));
+                }
+            }
+        }
+        files
+    }
+
+    #[test]
+    fn roundtrip() {
+        let files = cr2w_test_files();
+        assert!(files.len() > 50);
+        for (hash, buffer) in files {
+            let file = read_cr2w(&mut Cursor::new(&buffer)).unwrap();
+            let mut output = vec![];
+            write_cr2w(&mut output, &file).unwrap();
+            assert!(buffer == output, "{} is not written back identically", hash);
+        }
+    }
+
+    #[test]
+    fn write_edited() {
+        let file_path = PathBuf::from("tests")
+            .join("data")
+            .join("base")
+            .join("cycleweapons")
+            .join("localization")
+            .join("en-us.json");
+        let buffer = std::fs::read(file_path).unwrap();
+        let mut file = read_cr2w(&mut Cursor::new(&buffer)).unwrap();
+        file.chunks[0].properties[0].value = Value::Enum("PLATFORM_PS5".to_owned());
+        file.chunks[1].properties.push(Property {
+            name: "added".to_owned(),
+            type_name: "String".to_owned(),
+            value: Value::String("Näme".to_owned()),
+            default: false,
+        });
+
+        let mut output = vec![];
+        write_cr2w(&mut output, &file).unwrap();
+        let written = read_cr2w(&mut Cursor::new(&output)).unwrap();
+        assert_eq!(file.chunks, written.chunks);
+        assert_eq!(output.len() as u32, written.info.header.objects_end);
+        assert_eq!(
+            written.info.header.objects_end,
+            written.info.header.buffers_end
+        );
+        // existing names keep their index, new names are appended
+        assert_eq!(file.info.names, written.info.names[..file.info.names.len()]);
+        assert!(written.info.names[file.info.names.len()..].contains(&"PLATFORM_PS5".to_owned()));
+
+        // import indices are stored in 16 bits
+        let mut property = Property {
+            name: "reference".to_owned(),
+            type_name: "raRef:CResource".to_owned(),
+            value: Value::ResourceReference(Some(u16::MAX as usize - 1)),
+            default: false,
+        };
+        file.chunks[1].properties.push(property.clone());
+        assert!(write_cr2w(&mut vec![], &file).is_ok());
+        property.value = Value::ResourceReference(Some(u16::MAX as usize));
+        *file.chunks[1].properties.last_mut().unwrap() = property;
+        let error = write_cr2w(&mut vec![], &file).unwrap_err();
+        assert_eq!(ErrorKind::InvalidInput, error.kind());
+    }
+}
@@ -0,0 +1,571 @@
+/////////////////////////////////////////////////////////////////////////////////////////
+// DEPENDENCY GRAPH
+// Which resources load which, built from the imports of CR2W headers across archives.
+// Resources are keyed by the FNV1a64 hash of their depot path, imports of embedded
+// resources are skipped since those are stored inside the importing file.
+/////////////////////////////////////////////////////////////////////////////////////////
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs::File,
+    io::{BufReader, BufWriter, Cursor, Error, ErrorKind, Read, Result, Seek, Write},
+    path::Path,
+};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use serde_json::{json, Value as Json};
+
+use crate::{
+    archive::{open_read, ZipArchive},
+    cr2w::{read_cr2w_header, Import, ImportFlags},
+    hash::ResourceHash,
+    io::{read_null_terminated_string, write_null_terminated_string, FromReader},
+};
+
+/// An import of a resource
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DependencyEdge {
+    /// Hash of the imported resource
+    pub hash: u64,
+    pub flags: ImportFlags,
+}
+
+/// Imports of resources and the reverse lookup
+#[derive(Debug, Clone, Default)]
+pub struct DependencyGraph {
+    /// Imports of every added resource
+    imports: HashMap<u64, Vec<DependencyEdge>>,
+    /// Resources that import a hash, derived from `imports`
+    importers: HashMap<u64, Vec<u64>>,
+    /// Depot paths of hashes, from imports and archive entry names
+    paths: HashMap<u64, String>,
+}
+
+impl DependencyGraph {
+    const MAGIC: u32 = 0x50454452; // RDEP
+    const VERSION: u32 = 1;
+
+    /// Creates an empty graph
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of resources that were added
+    pub fn len(&self) -> usize {
+        self.imports.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.imports.is_empty()
+    }
+
+    /// True if the imports of the resource were added
+    pub fn contains(&self, hash: &u64) -> bool {
+        self.imports.contains_key(hash)
+    }
+
+    /// Hashes of all added resources
+    pub fn resources(&self) -> impl Iterator<Item = &u64> {
+        self.imports.keys()
+    }
+
+    /// Gets the depot path of a hash if it is known
+    pub fn get_path(&self, hash: &u64) -> Option<&str> {
+        self.paths.get(hash).map(|p| p.as_str())
+    }
+
+    /// Adds a depot path, e.g. to name a resource that no other resource imports, and returns its hash
+    pub fn insert_path(&mut self, path: &str) -> u64 {
+        let hash = ResourceHash::from_depot_path(path).0;
+        self.paths.insert(hash, path.to_owned());
+        hash
+    }
+
+    /// Adds the imports of a resource, imports added before for the same resource are replaced
+    pub fn add_resource(&mut self, hash: u64, imports: &[Import]) {
+        let mut edges: Vec<DependencyEdge> = vec![];
+        for import in imports
+            .iter()
+            .filter(|import| !import.flags.contains(ImportFlags::EMBEDDED))
+        {
+            let import_hash = self.insert_path(&import.depot_path);
+            if !edges.iter().any(|edge| edge.hash == import_hash) {
+                edges.push(DependencyEdge {
+                    hash: import_hash,
+                    flags: import.flags,
+                });
+            }
+        }
+        self.insert(hash, edges);
+    }
+
+    /// Adds all cr2w resources of an archive and returns their number
+    ///
+    /// Entries that are not cr2w files or whose header can't be read are skipped.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any entry can't be read.
+    pub fn add_archive<R: Read + Seek>(&mut self, archive: &mut ZipArchive<R>) -> Result<usize> {
+        let entries = archive.get_entries().values().cloned().collect::<Vec<_>>();
+        let mut count = 0;
+        for entry in entries {
+            if let Some(name) = entry.name() {
+                self.paths.insert(entry.hash, name.to_owned());
+            }
+
+            let mut buffer = vec![];
+            archive.open_entry(entry.clone(), &mut buffer)?;
+            if let Ok(info) = read_cr2w_header(&mut Cursor::new(&buffer)) {
+                self.add_resource(entry.hash, &info.imports);
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Direct imports of a resource, empty if the resource was not added
+    pub fn dependencies(&self, hash: &u64) -> &[DependencyEdge] {
+        self.imports.get(hash).map_or(&[], |edges| edges.as_slice())
+    }
+
+    /// Resources that directly import a hash
+    pub fn dependents(&self, hash: &u64) -> &[u64] {
+        self.importers
+            .get(hash)
+            .map_or(&[], |hashes| hashes.as_slice())
+    }
+
+    /// Everything a resource loads, directly or through other resources, in breadth-first order
+    pub fn all_dependencies(&self, hash: &u64) -> Vec<u64> {
+        self.traverse(*hash, |hash| {
+            self.dependencies(&hash)
+                .iter()
+                .map(|edge| edge.hash)
+                .collect()
+        })
+    }
+
+    /// Every resource that loads a hash, directly or through other resources, in breadth-first order
+    pub fn all_dependents(&self, hash: &u64) -> Vec<u64> {
+        self.traverse(*hash, |hash| self.dependents(&hash).to_vec())
+    }
+
+    /// A graph with only the given resources, their imports and the depot paths of both
+    pub fn subgraph(&self, hashes: &[u64]) -> DependencyGraph {
+        let mut graph = DependencyGraph::new();
+        for hash in hashes {
+            let Some(edges) = self.imports.get(hash) else {
+                continue;
+            };
+            for edge in edges.iter() {
+                if let Some(path) = self.paths.get(&edge.hash) {
+                    graph.paths.insert(edge.hash, path.to_owned());
+                }
+            }
+            if let Some(path) = self.paths.get(hash) {
+                graph.paths.insert(*hash, path.to_owned());
+            }
+            graph.insert(*hash, edges.clone());
+        }
+        graph
+    }
+
+    /// Writes the graph in Graphviz DOT format
+    ///
+    /// Nodes are labelled with their depot path or their hash, soft imports are dashed.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any io fails.
+    pub fn write_dot<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writeln!(writer, "digraph dependencies {{")?;
+        for hash in self.nodes() {
+            let label = match self.paths.get(&hash) {
+                Some(path) => path.replace('\\', "\\\\").replace('"', "\\\""),
+                None => hash.to_string(),
+            };
+            writeln!(writer, "    n{} [label=\"{}\"];", hash, label)?;
+        }
+        for (hash, edges) in self.sorted_imports() {
+            for edge in edges.iter() {
+                if edge.flags.contains(ImportFlags::SOFT) {
+                    writeln!(writer, "    n{} -> n{} [style=dashed];", hash, edge.hash)?;
+                } else {
+                    writeln!(writer, "    n{} -> n{};", hash, edge.hash)?;
+                }
+            }
+        }
+        writeln!(writer, "}}")?;
+        Ok(())
+    }
+
+    /// The graph as JSON with `nodes` (hash, path) and `edges` (from, to, flags), hashes are strings
+    pub fn to_json(&self) -> Json {
+        let nodes = self
+            .nodes()
+            .into_iter()
+            .map(|hash| json!({ "hash": hash.to_string(), "path": self.paths.get(&hash) }))
+            .collect::<Vec<_>>();
+        let edges = self
+            .sorted_imports()
+            .into_iter()
+            .flat_map(|(hash, edges)| {
+                edges.iter().map(move |edge| {
+                    json!({
+                        "from": hash.to_string(),
+                        "to": edge.hash.to_string(),
+                        "flags": edge.flags.to_string(),
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        json!({ "nodes": nodes, "edges": edges })
+    }
+
+    /// Writes the graph as JSON, see [`DependencyGraph::to_json`]
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if serialization fails.
+    pub fn write_json<W: Write>(&self, writer: &mut W) -> Result<()> {
+        serde_json::to_writer_pretty(writer, &self.to_json()).map_err(Error::other)
+    }
+
+    /// Writes the graph in binary form
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any io fails.
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_u32::<LittleEndian>(DependencyGraph::MAGIC)?;
+        writer.write_u32::<LittleEndian>(DependencyGraph::VERSION)?;
+
+        let imports = self.sorted_imports();
+        writer.write_u32::<LittleEndian>(imports.len() as u32)?;
+        for (hash, edges) in imports {
+            writer.write_u64::<LittleEndian>(hash)?;
+            writer.write_u32::<LittleEndian>(edges.len() as u32)?;
+            for edge in edges.iter() {
+                writer.write_u64::<LittleEndian>(edge.hash)?;
+                writer.write_u16::<LittleEndian>(edge.flags.bits())?;
+            }
+        }
+
+        let mut paths = self.paths.iter().collect::<Vec<_>>();
+        paths.sort_by_key(|e| e.0);
+        writer.write_u32::<LittleEndian>(paths.len() as u32)?;
+        for (hash, path) in paths {
+            writer.write_u64::<LittleEndian>(*hash)?;
+            write_null_terminated_string(writer, path.to_owned())?;
+        }
+
+        Ok(())
+    }
+
+    /// Saves the graph to an index file
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any io fails.
+    pub fn save<P: AsRef<Path>>(&self, path: &P) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Loads a graph from an index file
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file can't be read or is not an index file.
+    pub fn load<P: AsRef<Path>>(path: &P) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        DependencyGraph::from_reader(&mut reader)
+    }
+
+    fn insert(&mut self, hash: u64, edges: Vec<DependencyEdge>) {
+        if let Some(old) = self.imports.remove(&hash) {
+            for edge in old {
+                if let Some(importers) = self.importers.get_mut(&edge.hash) {
+                    importers.retain(|importer| *importer != hash);
+                    if importers.is_empty() {
+                        self.importers.remove(&edge.hash);
+                    }
+                }
+            }
+        }
+
+        for edge in edges.iter() {
+            self.importers.entry(edge.hash).or_default().push(hash);
+        }
+        self.imports.insert(hash, edges);
+    }
+
+    /// Breadth-first search from a hash, the hash itself is not part of the result
+    fn traverse<F: Fn(u64) -> Vec<u64>>(&self, start: u64, next: F) -> Vec<u64> {
+        let mut visited = HashSet::from([start]);
+        let mut queue = VecDeque::from([start]);
+        let mut result = vec![];
+        while let Some(hash) = queue.pop_front() {
+            for next_hash in next(hash) {
+                if visited.insert(next_hash) {
+                    result.push(next_hash);
+                    queue.push_back(next_hash);
+                }
+            }
+        }
+        result
+    }
+
+    /// Added resources and their imports, sorted by hash
+    fn sorted_imports(&self) -> Vec<(u64, &Vec<DependencyEdge>)> {
+        let mut imports = self
+            .imports
+            .iter()
+            .map(|(hash, edges)| (*hash, edges))
+            .collect::<Vec<_>>();
+        imports.sort_by_key(|(hash, _)| *hash);
+        imports
+    }
+
+    /// Hashes of all added and imported resources, sorted
+    fn nodes(&self) -> Vec<u64> {
+        let mut nodes = self
+            .imports
+            .keys()
+            .chain(self.importers.keys())
+            .copied()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        nodes.sort();
+        nodes
+    }
+}
+
+impl FromReader for DependencyGraph {
+    fn from_reader<R: Read>(reader: &mut R) -> Result<Self> {
+        let magic = reader.read_u32::<LittleEndian>()?;
+        if magic != DependencyGraph::MAGIC {
+            return Err(Error::other("invalid magic"));
+        }
+        let version = reader.read_u32::<LittleEndian>()?;
+        if version != DependencyGraph::VERSION {
+            return Err(Error::new(ErrorKind::InvalidData, "unsupported version"));
+        }
+
+        let mut graph = DependencyGraph::new();
+        let count = reader.read_u32::<LittleEndian>()?;
+        for _i in 0..count {
+            let hash = reader.read_u64::<LittleEndian>()?;
+            let edge_count = reader.read_u32::<LittleEndian>()?;
+            let mut edges = vec![];
+            for _j in 0..edge_count {
+                edges.push(DependencyEdge {
+                    hash: reader.read_u64::<LittleEndian>()?,
+                    flags: ImportFlags::from_bits(reader.read_u16::<LittleEndian>()?),
+                });
+            }
+            graph.insert(hash, edges);
+        }
+
+        let count = reader.read_u32::<LittleEndian>()?;
+        for _i in 0..count {
+            let hash = reader.read_u64::<LittleEndian>()?;
+            let path = read_null_terminated_string(reader)?;
+            graph.paths.insert(hash, path);
+        }
+
+        Ok(graph)
+    }
+}
+
+/// Builds the dependency graph of one or many archives
+///
+/// # Errors
+///
+/// This function will return an error if any archive can't be read.
+pub fn build_dependency_graph<P: AsRef<Path>>(archive_paths: &[P]) -> Result<DependencyGraph> {
+    let mut graph = DependencyGraph::new();
+    for path in archive_paths {
+        let mut archive = open_read(path)?;
+        graph.add_archive(&mut archive)?;
+    }
+    Ok(graph)
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// TESTS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+    use crate::fixtures::{hash, import};
+
+    /// a.ent -> b.app -> c.mesh -> d.mi, a.ent -> c.mesh (soft), e.ent -> c.mesh
+    fn graph() -> DependencyGraph {
+        let mut graph = DependencyGraph::new();
+        graph.insert_path("a.ent");
+        graph.insert_path("e.ent");
+        graph.add_resource(
+            hash("a.ent"),
+            &[
+                import("b.app", ImportFlags::DEFAULT),
+                import("c.mesh", ImportFlags::SOFT),
+                import("a.ent#embedded", ImportFlags::EMBEDDED),
+            ],
+        );
+        graph.add_resource(hash("b.app"), &[import("c.mesh", ImportFlags::DEFAULT)]);
+        graph.add_resource(hash("c.mesh"), &[import("d.mi", ImportFlags::DEFAULT)]);
+        graph.add_resource(hash("e.ent"), &[import("c.mesh", ImportFlags::DEFAULT)]);
+        graph
+    }
+
+    #[test]
+    fn queries() {
+        let graph = graph();
+        assert_eq!(4, graph.len());
+        assert!(graph.contains(&hash("c.mesh")));
+        assert!(!graph.contains(&hash("d.mi")));
+        assert_eq!(Some("d.mi"), graph.get_path(&hash("d.mi")));
+
+        // embedded resources are not dependencies
+        assert_eq!(
+            vec![
+                DependencyEdge {
+                    hash: hash("b.app"),
+                    flags: ImportFlags::DEFAULT
+                },
+                DependencyEdge {
+                    hash: hash("c.mesh"),
+                    flags: ImportFlags::SOFT
+                }
+            ],
+            graph.dependencies(&hash("a.ent"))
+        );
+        assert_eq!(
+            vec![hash("b.app"), hash("c.mesh"), hash("d.mi")],
+            graph.all_dependencies(&hash("a.ent"))
+        );
+        assert!(graph.dependencies(&hash("d.mi")).is_empty());
+
+        let mut dependents = graph.dependents(&hash("c.mesh")).to_vec();
+        dependents.sort();
+        let mut expected = vec![hash("a.ent"), hash("b.app"), hash("e.ent")];
+        expected.sort();
+        assert_eq!(expected, dependents);
+        let mut dependents = graph.all_dependents(&hash("d.mi"));
+        dependents.sort();
+        expected.push(hash("c.mesh"));
+        expected.sort();
+        assert_eq!(expected, dependents);
+
+        // adding a resource again replaces its imports
+        let mut graph = graph;
+        graph.add_resource(hash("e.ent"), &[import("d.mi", ImportFlags::DEFAULT)]);
+        assert!(!graph.dependents(&hash("c.mesh")).contains(&hash("e.ent")));
+        assert!(graph.dependents(&hash("d.mi")).contains(&hash("e.ent")));
+    }
+
+    #[test]
+    fn save_and_load() {
+        let graph = graph();
+        let mut buffer = vec![];
+        graph.write(&mut buffer).unwrap();
+        let loaded = DependencyGraph::from_reader(&mut Cursor::new(&buffer)).unwrap();
+        assert_eq!(graph.len(), loaded.len());
+        for hash in graph.resources() {
+            assert_eq!(graph.dependencies(hash), loaded.dependencies(hash));
+            assert_eq!(graph.get_path(hash), loaded.get_path(hash));
+        }
+        assert_eq!(
+            graph.all_dependents(&hash("d.mi")).len(),
+            loaded.all_dependents(&hash("d.mi")).len()
+        );
+
+        // the output is sorted, so it is the same for equal graphs
+        let mut written = vec![];
+        loaded.write(&mut written).unwrap();
+        assert_eq!(buffer, written);
+
+        buffer[0] = 0;
+        assert!(DependencyGraph::from_reader(&mut Cursor::new(&buffer)).is_err());
+    }
+
+    #[test]
+    fn export() {
+        let graph = graph().subgraph(&[hash("a.ent"), hash("b.app")]);
+        assert_eq!(2, graph.len());
+
+        let mut dot = vec![];
+        graph.write_dot(&mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.starts_with("digraph dependencies {"));
+        assert!(dot.contains(&format!("n{} [label=\"a.ent\"];", hash("a.ent"))));
+        assert!(dot.contains(&format!(
+            "n{} -> n{} [style=dashed];",
+            hash("a.ent"),
+            hash("c.mesh")
+        )));
+        assert!(dot.contains(&format!("n{} -> n{};", hash("b.app"), hash("c.mesh"))));
+        assert!(!dot.contains("d.mi"));
+
+        let json = graph.to_json();
+        assert_eq!(3, json["nodes"].as_array().unwrap().len());
+        assert_eq!(3, json["edges"].as_array().unwrap().len());
+        assert!(json["edges"]
+            .as_array()
+            .unwrap()
+            .contains(&json!({ "from": hash("a.ent").to_string(), "to": hash("c.mesh").to_string(), "flags": "Soft" })));
+    }
+
+    #[test]
+    fn archives() {
+        let archive_paths = [
+            PathBuf::from("tests").join("test1.archive"),
+            PathBuf::from("tests").join("nci.archive"),
+        ];
+        let graph = build_dependency_graph(&archive_paths).unwrap();
+        assert!(!graph.is_empty());
+        assert!(graph
+            .resources()
+            .any(|hash| !graph.dependencies(hash).is_empty()));
+
+        // the imports of every resource are in the graph
+        let mut archive = open_read(&archive_paths[1]).unwrap();
+        let entries = archive.get_entries().values().cloned().collect::<Vec<_>>();
+        for entry in entries {
+            let mut buffer = vec![];
+            archive.open_entry(entry.clone(), &mut buffer).unwrap();
+            let Ok(info) = read_cr2w_header(&mut Cursor::new(&buffer)) else {
+                continue;
+            };
+            assert!(graph.contains(&entry.hash));
+            for import in info
+                .imports
+                .iter()
+                .filter(|i| !i.flags.contains(ImportFlags::EMBEDDED))
+            {
+                let import_hash = hash(&import.depot_path);
+                assert!(graph
+                    .dependencies(&entry.hash)
+                    .iter()
+                    .any(|edge| edge.hash == import_hash));
+                assert!(graph.dependents(&import_hash).contains(&entry.hash));
+            }
+        }
+
+        // the index file loads to the same graph
+        let dst_path = PathBuf::from("tests").join("out_depgraph");
+        fs::create_dir_all(&dst_path).unwrap();
+        let index_path = dst_path.join("dependencies.bin");
+        graph.save(&index_path).unwrap();
+        let loaded = DependencyGraph::load(&index_path).unwrap();
+        assert_eq!(graph.len(), loaded.len());
+        fs::remove_dir_all(&dst_path).unwrap();
+    }
+}
@@ -0,0 +1,224 @@
+/////////////////////////////////////////////////////////////////////////////////////////
+// DISCOVERY
+// Recovers resource paths of unknown archive entries by harvesting candidate depot paths
+// from CR2W headers (imports and string tables) and from json and csv resources.
+/////////////////////////////////////////////////////////////////////////////////////////
+
+use std::{
+    collections::HashSet,
+    io::{Cursor, Read, Result, Seek},
+    path::Path,
+};
+
+use crate::{
+    archive::{open_read, ZipArchive},
+    cr2w::read_cr2w_header,
+    hash::{normalize_depot_path, ResourceHash},
+    hashdb::HashDb,
+};
+
+/// Collects candidate resource paths from resources
+#[derive(Debug, Clone, Default)]
+pub struct Discovery {
+    candidates: HashSet<String>,
+}
+
+impl Discovery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// All candidate paths found so far
+    pub fn candidates(&self) -> &HashSet<String> {
+        &self.candidates
+    }
+
+    /// Collects candidate paths from a single resource buffer
+    pub fn harvest_buffer(&mut self, buffer: &[u8]) {
+        for path in harvest_paths(buffer) {
+            self.candidates.insert(path);
+        }
+    }
+
+    /// Collects candidate paths from every entry of an archive.
+    ///
+    /// Entries that can't be read are skipped, their hashes are returned.
+    pub fn harvest_archive<R: Read + Seek>(&mut self, archive: &mut ZipArchive<R>) -> Vec<u64> {
+        let mut entries = archive.get_entries().values().cloned().collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.hash);
+
+        let mut failed = vec![];
+        for entry in entries {
+            let hash = entry.hash;
+            let mut buffer = vec![];
+            if archive.open_entry(entry, &mut buffer).is_ok() {
+                self.harvest_buffer(&buffer);
+            } else {
+                failed.push(hash);
+            }
+        }
+        failed
+    }
+
+    /// Matches all candidates against a set of unresolved hashes and returns the found names
+    pub fn resolve(&self, unresolved: &HashSet<u64>) -> HashDb {
+        let mut found = HashDb::new();
+        for candidate in self.candidates.iter() {
+            let hash = ResourceHash::from_depot_path(candidate).0;
+            if unresolved.contains(&hash) {
+                found.insert(hash, candidate.to_owned());
+            }
+        }
+        found
+    }
+}
+
+/// Gets the hashes of all entries in an archive that neither the archive nor the hash database can resolve
+pub fn get_unresolved_hashes<R>(archive: &ZipArchive<R>, hash_db: &HashDb) -> HashSet<u64> {
+    archive
+        .get_entries()
+        .values()
+        .filter(|e| e.name().is_none() && !hash_db.contains(&e.hash))
+        .map(|e| e.hash)
+        .collect::<HashSet<_>>()
+}
+
+/// Runs a discovery pass over one or many archives and returns the newly found resource paths.
+///
+/// Candidates from every archive are matched against the unresolved entries of all archives,
+/// the result can be merged into a [`HashDb`]. Entries that can't be read are skipped.
+///
+/// # Errors
+///
+/// This function will return an error if any archive can't be opened.
+pub fn discover_archives<P: AsRef<Path>>(archive_paths: &[P], hash_db: &HashDb) -> Result<HashDb> {
+    let mut discovery = Discovery::new();
+    let mut unresolved = HashSet::new();
+    for path in archive_paths {
+        let mut archive = open_read(path)?;
+        unresolved.extend(get_unresolved_hashes(&archive, hash_db));
+        discovery.harvest_archive(&mut archive);
+    }
+
+    Ok(discovery.resolve(&unresolved))
+}
+
+/// Harvests candidate resource paths from a resource buffer.
+///
+/// CR2W files yield their import paths and string table, json and csv files yield every
+/// string that looks like a depot path.
+pub fn harvest_paths(buffer: &[u8]) -> Vec<String> {
+    let mut strings = vec![];
+    if let Ok(info) = read_cr2w_header(&mut Cursor::new(buffer)) {
+        strings.extend(info.imports.into_iter().map(|i| i.depot_path));
+        strings.extend(info.strings.into_values());
+    } else if let Ok(text) = std::str::from_utf8(buffer) {
+        if let Ok(json) = serde_json::from_str::<serde_json::Value>(text) {
+            collect_json_strings(&json, &mut strings);
+        } else {
+            // csv and other text formats
+            strings.extend(
+                text.split([',', ';', '\n', '\r', '\t', '"'])
+                    .map(|s| s.to_owned()),
+            );
+        }
+    }
+
+    strings.iter().filter_map(|s| normalize_path(s)).collect()
+}
+
+fn collect_json_strings(value: &serde_json::Value, strings: &mut Vec<String>) {
+    match value {
+        serde_json::Value::String(s) => strings.push(s.to_owned()),
+        serde_json::Value::Array(a) => a.iter().for_each(|v| collect_json_strings(v, strings)),
+        serde_json::Value::Object(o) => o.values().for_each(|v| collect_json_strings(v, strings)),
+        _ => {}
+    }
+}
+
+/// Converts a string to a normalized depot path if it looks like one, e.g. base\gameplay\a.ent
+fn normalize_path(s: &str) -> Option<String> {
+    let s = s.trim();
+    if s.len() < 3 || s.chars().any(|c| c.is_control()) {
+        return None;
+    }
+    let path = normalize_depot_path(s);
+    let file_name = path.rsplit('\\').next()?;
+    let (stem, extension) = file_name.rsplit_once('.')?;
+    if stem.is_empty()
+        || extension.is_empty()
+        || !extension
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return None;
+    }
+    Some(path)
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// TESTS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, fs, path::PathBuf};
+
+    use super::*;
+    use crate::{cr2w::ImportFlags, fixtures};
+
+    #[test]
+    fn harvest_text() {
+        let json = br#"{ "a": "base/gameplay/A.ent", "b": [ "not a path", 1, "ep1\\b.mesh" ] }"#;
+        let mut paths = harvest_paths(json);
+        paths.sort();
+        assert_eq!(vec!["base\\gameplay\\a.ent", "ep1\\b.mesh"], paths);
+
+        let csv = b"id,path\n1,base\\c.xbm\n2,none\n";
+        assert_eq!(vec!["base\\c.xbm"], harvest_paths(csv));
+    }
+
+    #[test]
+    fn harvest_cr2w() {
+        let buffer = fixtures::resource(&[
+            ("base\\gameplay\\A.ent", ImportFlags::DEFAULT),
+            ("base/b.mesh", ImportFlags::SOFT),
+        ]);
+        let paths = harvest_paths(&buffer).into_iter().collect::<HashSet<_>>();
+        assert!(paths.contains("base\\gameplay\\a.ent"));
+        assert!(paths.contains("base\\b.mesh"));
+    }
+
+    #[test]
+    fn discover() {
+        let mut discovery = Discovery::new();
+        discovery.harvest_buffer(br#"[ "base\\a.mesh", "base\\b.mesh" ]"#);
+        let hash = fixtures::hash("base\\b.mesh");
+        let found = discovery.resolve(&HashSet::from([hash, 1234]));
+        assert_eq!(1, found.len());
+        assert_eq!(Some("base\\b.mesh"), found.get_path(&hash));
+
+        // the imports of one archive resolve the unnamed entries of another
+        let dst_path = PathBuf::from("tests").join("out_discovery");
+        fs::create_dir_all(&dst_path).unwrap();
+        let entity = fixtures::unnamed_archive_file(vec![(
+            "base\\a.ent",
+            fixtures::resource(&[("base\\A.mesh", ImportFlags::DEFAULT)]),
+        )]);
+        let mesh = fixtures::unnamed_archive_file(vec![("base\\a.mesh", vec![0; 16])]);
+        let archive_paths = [
+            dst_path.join("entity.archive"),
+            dst_path.join("mesh.archive"),
+        ];
+        fs::write(&archive_paths[0], entity).unwrap();
+        fs::write(&archive_paths[1], mesh).unwrap();
+
+        let found = discover_archives(&archive_paths, &HashDb::new()).unwrap();
+        fs::remove_dir_all(&dst_path).unwrap();
+        assert_eq!(1, found.len());
+        assert_eq!(
+            Some("base\\a.mesh"),
+            found.get_path(&fixtures::hash("base\\a.mesh"))
+        );
+    }
+}
@@ -0,0 +1,189 @@
+/////////////////////////////////////////////////////////////////////////////////////////
+// TEST FIXTURES
+// Resources and archives built from the test data, shared by the unit tests.
+/////////////////////////////////////////////////////////////////////////////////////////
+
+use std::{collections::HashMap, fs, io::Cursor, path::PathBuf};
+
+use crate::{
+    archive::{open_read_stream, write_resources, ZipArchive},
+    codec::KrakenCodec,
+    cr2w::{
+        read_cr2w, write_cr2w, CR2WEmbeddedInfo, CR2WFile, Chunk, Import, ImportFlags, Property,
+        Value,
+    },
+    hash::ResourceHash,
+};
+
+pub(crate) fn hash(path: &str) -> u64 {
+    ResourceHash::from_depot_path(path).0
+}
+
+pub(crate) fn property(name: &str, type_name: &str, value: Value) -> Property {
+    Property {
+        name: name.to_owned(),
+        type_name: type_name.to_owned(),
+        value,
+        default: false,
+    }
+}
+
+pub(crate) fn chunk(class_name: &str, parent: Option<usize>, properties: Vec<Property>) -> Chunk {
+    Chunk {
+        class_name: class_name.to_owned(),
+        flags: 0,
+        parent,
+        template: 0,
+        properties,
+        trailing: vec![],
+        decoded: true,
+    }
+}
+
+pub(crate) fn import(depot_path: &str, flags: ImportFlags) -> Import {
+    Import {
+        class_name: "None".to_owned(),
+        depot_path: depot_path.to_owned(),
+        flags,
+    }
+}
+
+/// An appearance of an entity template
+pub(crate) fn appearance(name: &str, resource: usize) -> Value {
+    Value::Struct(vec![
+        property("name", "CName", Value::CName(name.to_owned())),
+        property(
+            "appearanceResource",
+            "raRef:appearanceAppearanceResource",
+            Value::ResourceReference(Some(resource)),
+        ),
+    ])
+}
+
+/// An entity template with a mesh and a light component and two appearances
+pub(crate) fn entity() -> CR2WFile {
+    let mut file = template();
+    file.info.imports = vec![
+        import("base\\a.mesh", ImportFlags::DEFAULT),
+        import("base\\b.app", ImportFlags::SOFT),
+    ];
+    file.chunks = vec![
+        chunk(
+            "entEntityTemplate",
+            None,
+            vec![
+                property(
+                    "components",
+                    "array:handle:entIComponent",
+                    Value::Array(vec![Value::Handle(Some(1)), Value::Handle(Some(2))]),
+                ),
+                property(
+                    "appearances",
+                    "array:entTemplateAppearance",
+                    Value::Array(vec![appearance("default", 1), appearance("damaged", 1)]),
+                ),
+            ],
+        ),
+        chunk(
+            "entMeshComponent",
+            Some(0),
+            vec![
+                property("mesh", "raRef:CMesh", Value::ResourceReference(Some(0))),
+                property("visible", "Bool", Value::Bool(true)),
+                property("scale", "Float", Value::Float(1.0)),
+                property("name", "CName", Value::CName("body".to_owned())),
+            ],
+        ),
+        chunk(
+            "entLightComponent",
+            Some(0),
+            vec![
+                property("name", "CName", Value::CName("lamp".to_owned())),
+                property("type", "ELightType", Value::Enum("LT_Spot".to_owned())),
+            ],
+        ),
+    ];
+    file
+}
+
+/// The localization file of the test data
+pub(crate) fn template() -> CR2WFile {
+    let path = PathBuf::from("tests")
+        .join("data")
+        .join("base/cycleweapons/localization/en-us.json");
+    let buffer = fs::read(path).unwrap();
+    read_cr2w(&mut Cursor::new(&buffer)).unwrap()
+}
+
+/// The template with its imports replaced, embedded imports get an embeds table entry
+pub(crate) fn file(imports: &[(&str, ImportFlags)]) -> CR2WFile {
+    let mut file = template();
+    file.info.imports = imports
+        .iter()
+        .map(|(depot_path, flags)| import(depot_path, *flags))
+        .collect();
+    file.info.embeds_table = imports
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, flags))| flags.contains(ImportFlags::EMBEDDED))
+        .map(|(index, _)| CR2WEmbeddedInfo {
+            import_index: index as u32 + 1,
+            chunk_index: 0,
+            path_hash: 0,
+        })
+        .collect();
+    file
+}
+
+/// A written cr2w file with imports
+pub(crate) fn resource(imports: &[(&str, ImportFlags)]) -> Vec<u8> {
+    let mut output = vec![];
+    write_cr2w(&mut output, &file(imports)).unwrap();
+    output
+}
+
+/// A written cr2w file with imports and an extra name that is a depot path
+pub(crate) fn named_resource(imports: &[(&str, ImportFlags)], name: &str) -> Vec<u8> {
+    let mut file = file(imports);
+    file.info.names.push(name.to_owned());
+    let mut output = vec![];
+    write_cr2w(&mut output, &file).unwrap();
+    output
+}
+
+/// An archive of resources by depot path, the paths are stored as custom paths
+pub(crate) fn archive(resources: Vec<(&str, Vec<u8>)>) -> ZipArchive<Cursor<Vec<u8>>> {
+    let paths = resources.iter().map(|(path, _)| path.to_string()).collect();
+    open_read_stream(Cursor::new(write_archive(resources, paths))).unwrap()
+}
+
+/// An archive of resources by depot path without custom paths, the resources are only known by hash
+pub(crate) fn unnamed_archive(resources: Vec<(&str, Vec<u8>)>) -> ZipArchive<Cursor<Vec<u8>>> {
+    open_read_stream(Cursor::new(write_archive(resources, vec![]))).unwrap()
+}
+
+/// The written file of an [`unnamed_archive`]
+pub(crate) fn unnamed_archive_file(resources: Vec<(&str, Vec<u8>)>) -> Vec<u8> {
+    write_archive(resources, vec![])
+}
+
+fn write_archive(resources: Vec<(&str, Vec<u8>)>, paths: Vec<String>) -> Vec<u8> {
+    let files = resources
+        .into_iter()
+        .map(|(path, buffer)| (hash(path), buffer))
+        .collect::<HashMap<_, _>>();
+    let hashes = files
+        .keys()
+        .map(|hash| (*hash, String::new()))
+        .collect::<Vec<_>>();
+    let mut output = Cursor::new(vec![]);
+    write_resources(
+        &mut output,
+        &hashes,
+        paths,
+        &KrakenCodec::default(),
+        |hash| Ok(files[&hash].clone()),
+    )
+    .unwrap();
+    output.into_inner()
+}
@@ -0,0 +1,380 @@
+/////////////////////////////////////////////////////////////////////////////////////////
+// HASH
+// Hash functions and hash types used by REDengine 4
+/////////////////////////////////////////////////////////////////////////////////////////
+
+use std::{fmt, num::ParseIntError, str::FromStr};
+
+/// Seed of the engine's Murmur3 hashes
+pub const MURMUR3_SEED: u32 = 0x5EEDBA5E;
+
+const FNV1A32_OFFSET: u32 = 0x811C9DC5;
+const FNV1A32_PRIME: u32 = 0x01000193;
+const FNV1A64_OFFSET: u64 = 0xCBF29CE484222325;
+const FNV1A64_PRIME: u64 = 0x00000100000001B3;
+
+/// Calculate FNV1a32 hash of a buffer
+pub fn fnv1a32(data: &[u8]) -> u32 {
+    data.iter().fold(FNV1A32_OFFSET, |hash, b| {
+        (hash ^ *b as u32).wrapping_mul(FNV1A32_PRIME)
+    })
+}
+
+/// Calculate FNV1a64 hash of a buffer
+pub fn fnv1a64(data: &[u8]) -> u64 {
+    data.iter().fold(FNV1A64_OFFSET, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(FNV1A64_PRIME)
+    })
+}
+
+/// Calculate CRC32 (IEEE) of a buffer
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32fast::hash(data)
+}
+
+/// Calculate Murmur3 (x86, 32-bit) hash of a buffer
+pub fn murmur3_32(data: &[u8], seed: u32) -> u32 {
+    const C1: u32 = 0xCC9E2D51;
+    const C2: u32 = 0x1B873593;
+
+    let mut hash = seed;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let mut k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        k = k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        hash ^= k;
+        hash = hash
+            .rotate_left(13)
+            .wrapping_mul(5)
+            .wrapping_add(0xE6546B64);
+    }
+
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        let mut k = tail
+            .iter()
+            .enumerate()
+            .fold(0u32, |k, (i, b)| k | (*b as u32) << (8 * i));
+        k = k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        hash ^= k;
+    }
+
+    hash ^= data.len() as u32;
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x85EBCA6B);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0xC2B2AE35);
+    hash ^= hash >> 16;
+    hash
+}
+
+/// Error returned when parsing a hash from a string fails
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseHashError(String);
+
+impl fmt::Display for ParseHashError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid hash: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseHashError {}
+
+impl From<ParseIntError> for ParseHashError {
+    fn from(value: ParseIntError) -> Self {
+        ParseHashError(value.to_string())
+    }
+}
+
+/// Parses a decimal or 0x-prefixed hexadecimal number
+fn parse_u64(s: &str) -> Result<u64, ParseHashError> {
+    let s = s.trim();
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Ok(u64::from_str_radix(hex, 16)?)
+    } else {
+        Ok(s.parse::<u64>()?)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// ResourceHash
+/////////////////////////////////////////////////////////////////////////////////////////
+
+/// FNV1a64 hash of a resource path, used to identify archive entries and resource references.
+///
+/// Formatted as decimal, e.g. in hash lists and `<hash>.bin` file names.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ResourceHash(pub u64);
+
+impl ResourceHash {
+    /// Hashes a resource path as is
+    pub fn new(path: &str) -> Self {
+        Self(fnv1a64(path.as_bytes()))
+    }
+
+    /// Hashes a depot path the way the game does, see [`normalize_depot_path`]
+    pub fn from_depot_path(path: &str) -> Self {
+        Self::new(&normalize_depot_path(path))
+    }
+}
+
+/// A depot path as the game hashes it: lowercase, backslash separated, without a trailing separator
+pub fn normalize_depot_path(path: &str) -> String {
+    path.replace('/', "\\")
+        .trim_end_matches('\\')
+        .to_lowercase()
+}
+
+impl fmt::Display for ResourceHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for ResourceHash {
+    type Err = ParseHashError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(parse_u64(s)?))
+    }
+}
+
+impl From<u64> for ResourceHash {
+    fn from(value: u64) -> Self {
+        Self(value)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// CNameHash
+/////////////////////////////////////////////////////////////////////////////////////////
+
+/// FNV1a64 hash of a CName, the empty name and `None` hash to 0.
+///
+/// CR2W name tables store the hash folded to 32 bits, see [`CNameHash::folded`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CNameHash(pub u64);
+
+impl CNameHash {
+    pub fn new(name: &str) -> Self {
+        if name.is_empty() || name == "None" {
+            Self(0)
+        } else {
+            Self(fnv1a64(name.as_bytes()))
+        }
+    }
+
+    /// The 32-bit hash stored in CR2W name tables (low ^ high)
+    pub fn folded(&self) -> u32 {
+        (self.0 as u32) ^ ((self.0 >> 32) as u32)
+    }
+}
+
+impl fmt::Display for CNameHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for CNameHash {
+    type Err = ParseHashError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(parse_u64(s)?))
+    }
+}
+
+impl From<u64> for CNameHash {
+    fn from(value: u64) -> Self {
+        Self(value)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// TweakDBID
+/////////////////////////////////////////////////////////////////////////////////////////
+
+/// ID of a TweakDB record or flat: CRC32 of the name in the low 32 bits and the name length in the next 8 bits.
+///
+/// Formatted as `<TDBID:XXXXXXXX:XX>`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TweakDBID(pub u64);
+
+impl TweakDBID {
+    pub fn new(name: &str) -> Self {
+        Self::from_parts(crc32(name.as_bytes()), name.len() as u8)
+    }
+
+    pub fn from_parts(crc32: u32, length: u8) -> Self {
+        Self(crc32 as u64 | (length as u64) << 32)
+    }
+
+    /// CRC32 of the name
+    pub fn crc32(&self) -> u32 {
+        self.0 as u32
+    }
+
+    /// Length of the name
+    pub fn length(&self) -> u8 {
+        (self.0 >> 32) as u8
+    }
+}
+
+impl fmt::Display for TweakDBID {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<TDBID:{:08X}:{:02X}>", self.crc32(), self.length())
+    }
+}
+
+impl FromStr for TweakDBID {
+    type Err = ParseHashError;
+
+    /// Parses `<TDBID:XXXXXXXX:XX>` or a raw decimal or hexadecimal id
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(inner) = s.strip_prefix("<TDBID:").and_then(|s| s.strip_suffix('>')) {
+            let Some((crc, length)) = inner.split_once(':') else {
+                return Err(ParseHashError(s.to_owned()));
+            };
+            let crc = u32::from_str_radix(crc, 16)?;
+            let length = u8::from_str_radix(length, 16)?;
+            Ok(Self::from_parts(crc, length))
+        } else {
+            Ok(Self(parse_u64(s)?))
+        }
+    }
+}
+
+impl From<u64> for TweakDBID {
+    fn from(value: u64) -> Self {
+        Self(value)
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// TESTS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{Cursor, Seek, SeekFrom},
+        path::PathBuf,
+    };
+
+    use byteorder::{LittleEndian, ReadBytesExt};
+
+    use super::*;
+    use crate::{archive::open_read, cr2w::read_cr2w_header};
+
+    fn cr2w_test_files() -> Vec<Vec<u8>> {
+        let data_path = PathBuf::from("tests").join("data");
+        [
+            data_path.join("base/cycleweapons/localization/en-us.json"),
+            data_path.join("base/sound/metadata/cooked_metadata.audio_metadata"),
+            data_path.join("ep1/sound/metadata/cooked_metadata.audio_metadata"),
+        ]
+        .iter()
+        .map(|p| fs::read(p).unwrap())
+        .collect::<Vec<_>>()
+    }
+
+    #[test]
+    fn resource_hash() {
+        let archive = open_read(PathBuf::from("tests").join("test1.archive")).unwrap();
+        let entry = archive
+            .get_entries()
+            .values()
+            .find(|e| e.name().is_some())
+            .unwrap();
+        let hash = ResourceHash::new(entry.name().unwrap());
+        assert_eq!(entry.hash, hash.0);
+        assert_eq!(hash, hash.to_string().parse().unwrap());
+        assert_eq!(hash, format!("{:#x}", hash.0).parse().unwrap());
+    }
+
+    #[test]
+    fn cname_hash() {
+        for buffer in cr2w_test_files() {
+            let info = read_cr2w_header(&mut Cursor::new(&buffer)).unwrap();
+            for (i, name) in info.names.iter().enumerate() {
+                assert_eq!(info.names_table[i].hash, CNameHash::new(name).folded());
+            }
+        }
+        assert_eq!(0, CNameHash::new("").0);
+        assert_eq!(0, CNameHash::new("None").0);
+        let hash = CNameHash::new("JsonResource");
+        assert_eq!(hash, hash.to_string().parse().unwrap());
+    }
+
+    #[test]
+    fn crc32_tables() {
+        const ITEM_SIZES: [u32; 7] = [1, 8, 8, 16, 24, 24, 16];
+        for buffer in cr2w_test_files() {
+            let mut cursor = Cursor::new(&buffer);
+            cursor.seek(SeekFrom::Start(40)).unwrap();
+            for size in ITEM_SIZES {
+                let offset = cursor.read_u32::<LittleEndian>().unwrap() as usize;
+                let count = cursor.read_u32::<LittleEndian>().unwrap();
+                let crc = cursor.read_u32::<LittleEndian>().unwrap();
+                let end = offset + (count * size) as usize;
+                assert_eq!(crc, crc32(&buffer[offset..end]));
+            }
+        }
+    }
+
+    #[test]
+    fn murmur3() {
+        assert_eq!(0, murmur3_32(b"", 0));
+        assert_eq!(0x514E28B7, murmur3_32(b"", 1));
+        assert_eq!(0x248BFA47, murmur3_32(b"hello", 0));
+        assert_eq!(
+            0x2E4FF723,
+            murmur3_32(b"The quick brown fox jumps over the lazy dog", 0)
+        );
+    }
+
+    #[test]
+    fn depot_path_hash() {
+        let hash = ResourceHash::new("base\\a\\b.mesh");
+        assert_eq!(hash, ResourceHash::from_depot_path("base\\a\\b.mesh"));
+        assert_eq!(hash, ResourceHash::from_depot_path("Base/A/B.mesh"));
+        assert_eq!(hash, ResourceHash::from_depot_path("base\\a\\b.mesh\\"));
+        assert_eq!("base\\a", normalize_depot_path("BASE/a/"));
+    }
+
+    #[test]
+    fn tweakdbid() {
+        // the record name in the nci.archive test data
+        const NAME: &str = "Adverts.VistaDelRay";
+        const NAME_ENTRY: u64 = 11738287133402191491;
+
+        let id = TweakDBID::new(NAME);
+        assert_eq!(TweakDBID(0x13_78DE_52C6), id);
+        assert_eq!(0x78DE52C6, id.crc32());
+        assert_eq!(19, id.length());
+        assert_eq!("<TDBID:78DE52C6:13>", id.to_string());
+
+        let mut archive = open_read(PathBuf::from("tests").join("nci.archive")).unwrap();
+        let entry = archive.get_entry_by_hash(&NAME_ENTRY).unwrap().clone();
+        let mut buffer = vec![];
+        archive.open_entry(entry, &mut buffer).unwrap();
+        assert!(buffer
+            .windows(8)
+            .any(|bytes| bytes == id.0.to_le_bytes().as_slice()));
+
+        let formatted = id.to_string();
+        assert_eq!(id, formatted.parse().unwrap());
+        assert_eq!(id, id.0.to_string().parse().unwrap());
+        assert!("<TDBID:XYZ>".parse::<TweakDBID>().is_err());
+    }
+
+    #[test]
+    fn fnv() {
+        assert_eq!(FNV1A32_OFFSET, fnv1a32(b""));
+        assert_eq!(0xCBF29CE484222325, fnv1a64(b""));
+        assert_eq!(0xAF63DC4C8601EC8C, fnv1a64(b"a"));
+        assert_eq!(0x85944171F73967E8, fnv1a64(b"foobar"));
+    }
+}
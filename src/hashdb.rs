@@ -0,0 +1,309 @@
+/////////////////////////////////////////////////////////////////////////////////////////
+// HASH DATABASE
+// Resolves FNV1a64 resource path hashes to depot paths and back.
+/////////////////////////////////////////////////////////////////////////////////////////
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Error, ErrorKind, Read, Result, Write},
+    path::Path,
+    sync::OnceLock,
+};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::{
+    archive::ZipArchive,
+    hash::{normalize_depot_path, ResourceHash},
+    io::{read_null_terminated_string, write_null_terminated_string, FromReader},
+};
+
+static GLOBAL: OnceLock<HashDb> = OnceLock::new();
+
+/// A lookup table of resource path hashes
+#[derive(Debug, Clone, Default)]
+pub struct HashDb {
+    paths: HashMap<u64, String>,
+}
+
+impl HashDb {
+    const MAGIC: u32 = 0x42444852; // RHDB
+    const VERSION: u32 = 1;
+    /// Upper bound of the entries reserved up front when reading, the stored count is not trusted
+    const MAX_RESERVED: usize = 0x100000;
+
+    /// Creates an empty hash database
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a hash database with the vanilla resource paths https://www.cyberpunk.net/en/modding-support
+    ///
+    /// Empty if the crate is built without the `metadata-resources` feature.
+    #[cfg(feature = "metadata-resources")]
+    pub fn vanilla() -> Self {
+        let csv_data = include_bytes!("metadata-resources.csv");
+        let mut db = Self::new();
+        db.merge_csv(&csv_data[..]);
+        db
+    }
+
+    /// Creates a hash database with the vanilla resource paths https://www.cyberpunk.net/en/modding-support
+    ///
+    /// Empty if the crate is built without the `metadata-resources` feature.
+    #[cfg(not(feature = "metadata-resources"))]
+    pub fn vanilla() -> Self {
+        Self::new()
+    }
+
+    /// The shared vanilla hash database, initialized on first use
+    pub fn global() -> &'static HashDb {
+        GLOBAL.get_or_init(HashDb::vanilla)
+    }
+
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    /// Gets the resource path of a hash
+    pub fn get_path(&self, hash: &u64) -> Option<&str> {
+        self.paths.get(hash).map(|p| p.as_str())
+    }
+
+    /// Gets the hash of a resource path if the path is known
+    pub fn get_hash(&self, path: &str) -> Option<u64> {
+        let hash = ResourceHash::from_depot_path(path).0;
+        self.paths.contains_key(&hash).then_some(hash)
+    }
+
+    pub fn contains(&self, hash: &u64) -> bool {
+        self.paths.contains_key(hash)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&u64, &String)> {
+        self.paths.iter()
+    }
+
+    /// Adds a resource path and returns its hash, the path is stored normalized
+    pub fn insert_path(&mut self, path: &str) -> u64 {
+        let path = normalize_depot_path(path);
+        let hash = ResourceHash::new(&path).0;
+        self.paths.insert(hash, path);
+        hash
+    }
+
+    /// Adds a resource path with a precomputed hash
+    pub fn insert(&mut self, hash: u64, path: String) {
+        self.paths.insert(hash, path);
+    }
+
+    /// Adds all entries of another hash database
+    pub fn merge(&mut self, other: &HashDb) {
+        for (hash, path) in other.iter() {
+            self.paths.insert(*hash, path.to_owned());
+        }
+    }
+
+    /// Adds a plain text list with one resource path per line and returns the number of paths read
+    pub fn merge_paths<R: BufRead>(&mut self, reader: R) -> usize {
+        let mut count = 0;
+        for line in reader.lines().map_while(io::Result::ok) {
+            let path = line.trim();
+            if !path.is_empty() {
+                self.insert_path(path);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Adds a csv list with `path,hash` lines (WolvenKit format) and returns the number of paths read.
+    ///
+    /// Lines without a valid hash are hashed from their path, a header line is skipped.
+    pub fn merge_csv<R: BufRead>(&mut self, reader: R) -> usize {
+        let mut count = 0;
+        for line in reader.lines().map_while(io::Result::ok) {
+            let mut split = line.split(',');
+            let Some(name) = split.next() else {
+                continue;
+            };
+            let name = name.trim();
+            match split.next().map(|h| h.trim().parse::<u64>()) {
+                Some(Ok(hash)) => self.insert(hash, name.to_owned()),
+                Some(Err(_)) if count == 0 => continue,
+                _ if name.is_empty() => continue,
+                _ => {
+                    self.insert_path(name);
+                }
+            }
+            count += 1;
+        }
+        count
+    }
+
+    /// Adds all resource paths stored in the LXRS table of an archive and returns their number
+    pub fn merge_archive<R>(&mut self, archive: &ZipArchive<R>) -> usize {
+        let mut count = 0;
+        for entry in archive.get_entries().values() {
+            if let Some(name) = entry.name() {
+                self.insert(entry.hash, name.to_owned());
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Adds a plain text or csv hash list from a file, based on its extension
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file can't be read.
+    pub fn merge_file<P: AsRef<Path>>(&mut self, path: &P) -> Result<usize> {
+        let reader = BufReader::new(File::open(path)?);
+        let is_csv = path
+            .as_ref()
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("csv"));
+        if is_csv {
+            Ok(self.merge_csv(reader))
+        } else {
+            Ok(self.merge_paths(reader))
+        }
+    }
+
+    /// Writes the database in binary form
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any io fails.
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_u32::<LittleEndian>(HashDb::MAGIC)?;
+        writer.write_u32::<LittleEndian>(HashDb::VERSION)?;
+        writer.write_u32::<LittleEndian>(self.paths.len() as u32)?;
+
+        let mut entries = self.paths.iter().collect::<Vec<_>>();
+        entries.sort_by_key(|e| e.0);
+        for (hash, path) in entries {
+            writer.write_u64::<LittleEndian>(*hash)?;
+            write_null_terminated_string(writer, path.to_owned())?;
+        }
+
+        Ok(())
+    }
+
+    /// Saves the database to a binary cache file
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any io fails.
+    pub fn save<P: AsRef<Path>>(&self, path: &P) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Loads a database from a binary cache file
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file can't be read or is not a cache file.
+    pub fn load<P: AsRef<Path>>(path: &P) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        HashDb::from_reader(&mut reader)
+    }
+}
+
+impl FromReader for HashDb {
+    fn from_reader<R: Read>(reader: &mut R) -> Result<Self> {
+        let magic = reader.read_u32::<LittleEndian>()?;
+        if magic != HashDb::MAGIC {
+            return Err(Error::other("invalid magic"));
+        }
+        let version = reader.read_u32::<LittleEndian>()?;
+        if version != HashDb::VERSION {
+            return Err(Error::new(ErrorKind::InvalidData, "unsupported version"));
+        }
+        let count = reader.read_u32::<LittleEndian>()?;
+
+        let mut paths = HashMap::with_capacity((count as usize).min(HashDb::MAX_RESERVED));
+        for _i in 0..count {
+            let hash = reader.read_u64::<LittleEndian>()?;
+            let path = read_null_terminated_string(reader)?;
+            paths.insert(hash, path);
+        }
+
+        Ok(HashDb { paths })
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// TESTS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, path::PathBuf};
+
+    use super::*;
+    use crate::archive::open_read;
+
+    #[test]
+    fn merge_lists() {
+        let mut db = HashDb::new();
+        let count = db.merge_paths("base/a.mesh\n\nbase\\b.mesh\n".as_bytes());
+        assert_eq!(2, count);
+
+        let hash = ResourceHash::new("base\\a.mesh").0;
+        assert_eq!(Some("base\\a.mesh"), db.get_path(&hash));
+        assert_eq!(Some(hash), db.get_hash("base/a.mesh"));
+        assert_eq!(Some(hash), db.get_hash("Base\\A.mesh"));
+        db.insert_path("Base/E.mesh");
+        assert_eq!(
+            Some("base\\e.mesh"),
+            db.get_path(&db.get_hash("base\\e.mesh").unwrap())
+        );
+        assert_eq!(None, db.get_hash("base\\c.mesh"));
+
+        let csv = format!("String,Hash\nbase\\c.mesh,{}\nbase\\d.mesh\n", 1234);
+        assert_eq!(2, db.merge_csv(csv.as_bytes()));
+        assert_eq!(Some("base\\c.mesh"), db.get_path(&1234));
+        assert!(db.get_hash("base\\d.mesh").is_some());
+        assert_eq!(5, db.len());
+    }
+
+    #[test]
+    fn merge_lxrs() {
+        let archive = open_read(PathBuf::from("tests").join("test1.archive")).unwrap();
+        let mut db = HashDb::new();
+        assert_eq!(1, db.merge_archive(&archive));
+        assert!(db
+            .get_hash("base\\cycleweapons\\localization\\en-us.json")
+            .is_some());
+    }
+
+    #[test]
+    fn binary_roundtrip() {
+        let mut db = HashDb::new();
+        db.insert_path("base\\a.mesh");
+        db.insert_path("base\\b.mesh");
+
+        let mut buffer = vec![];
+        db.write(&mut buffer).unwrap();
+        let read = HashDb::from_reader(&mut Cursor::new(buffer)).unwrap();
+
+        assert_eq!(db.paths, read.paths);
+    }
+
+    #[test]
+    fn corrupt_count() {
+        let mut buffer = vec![];
+        HashDb::new().write(&mut buffer).unwrap();
+        buffer[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(HashDb::from_reader(&mut Cursor::new(buffer)).is_err());
+    }
+}
@@ -0,0 +1,777 @@
+/////////////////////////////////////////////////////////////////////////////////////////
+// INSTALLER
+// Installs mods from Nexus-style zip files into a game folder and keeps a manifest of
+// every installed file so that mods can be uninstalled cleanly.
+//
+// <game>/.red4lib/manifests/<mod>.json
+// <game>/.red4lib/backups/<mod>/<relative path>
+/////////////////////////////////////////////////////////////////////////////////////////
+
+use std::{
+    collections::HashSet,
+    fs::{self, create_dir_all, File},
+    io::{BufReader, BufWriter, Cursor, Error, ErrorKind, Read, Result, Seek, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use strum_macros::Display;
+
+use crate::{archive, redmod, sha1_hash_file, validate_mod_name};
+
+/// Folder inside the game folder that holds manifests and backups
+pub const INSTALLER_FOLDER_NAME: &str = ".red4lib";
+const MANIFESTS_FOLDER_NAME: &str = "manifests";
+const BACKUPS_FOLDER_NAME: &str = "backups";
+
+/// Top level folders of a game installation that mods may place files in
+const GAME_ROOTS: [&str; 6] = ["archive", "mods", "r6", "bin", "red4ext", "engine"];
+
+/// The layouts a mod zip can contain, a mod may use more than one
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Display, Serialize, Deserialize,
+)]
+pub enum EModLayout {
+    /// .archive files in archive/pc/mod
+    Archive,
+    /// REDmod folders in mods/<name>
+    RedMod,
+    /// ArchiveXL .xl files
+    ArchiveXl,
+    /// Any other file in a game folder, e.g. r6/scripts or red4ext/plugins
+    Other,
+}
+
+/// A file inside a mod zip and where it will be installed to
+#[derive(Debug, Clone)]
+pub struct PackageFile {
+    /// Index of the file inside the zip
+    index: usize,
+    /// Path inside the zip
+    pub source: String,
+    /// Path relative to the game folder, with forward slashes
+    pub target: String,
+    pub size: u64,
+}
+
+/// An .archive file inside a mod zip
+#[derive(Debug, Clone)]
+pub struct PackageArchive {
+    /// Path relative to the game folder, with forward slashes
+    pub target: String,
+    /// FNV1a64 hashes of all entries in the archive
+    pub hashes: Vec<u64>,
+}
+
+/// A mod zip opened for inspection and installation
+#[derive(Debug)]
+pub struct ModPackage<R> {
+    name: String,
+    zip: zip::ZipArchive<R>,
+    files: Vec<PackageFile>,
+}
+
+impl<R: Read + Seek> ModPackage<R> {
+    /// Opens a mod zip from a reader and detects its layout.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the name is not a valid file name, the zip can't be read
+    /// or two files of the zip install to the same path.
+    pub fn from_reader(reader: R, name: &str) -> Result<Self> {
+        validate_mod_name(name)?;
+        let mut zip = zip::ZipArchive::new(reader).map_err(to_io_error)?;
+
+        let mut files = vec![];
+        let mut targets = HashSet::new();
+        for index in 0..zip.len() {
+            let file = zip.by_index(index).map_err(to_io_error)?;
+            if file.is_dir() {
+                continue;
+            }
+            // reject paths that escape the game folder
+            let Some(enclosed_name) = file.enclosed_name() else {
+                continue;
+            };
+            let source = enclosed_name.to_string_lossy().replace('\\', "/");
+            if let Some(target) = get_install_path(&source) {
+                // the game folder is case-insensitive on Windows
+                if !targets.insert(target.to_ascii_lowercase()) {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("More than one file installs to {}", target),
+                    ));
+                }
+                files.push(PackageFile {
+                    index,
+                    source,
+                    target,
+                    size: file.size(),
+                });
+            }
+        }
+
+        Ok(Self {
+            name: name.to_owned(),
+            zip,
+            files,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The files that will be installed
+    pub fn files(&self) -> &[PackageFile] {
+        &self.files
+    }
+
+    /// Detects the layouts used by this mod
+    pub fn layouts(&self) -> Vec<EModLayout> {
+        let mut layouts = self
+            .files
+            .iter()
+            .map(|f| get_layout(&f.target))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        layouts.sort();
+        layouts
+    }
+
+    /// Reads a file of the mod into memory
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file can't be read from the zip.
+    pub fn read_file(&mut self, file: &PackageFile) -> Result<Vec<u8>> {
+        let mut zip_file = self.zip.by_index(file.index).map_err(to_io_error)?;
+        let mut buffer = Vec::with_capacity(file.size as usize);
+        zip_file.read_to_end(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Opens every .archive inside the mod in memory and lists its entries
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any archive can't be read.
+    pub fn archives(&mut self) -> Result<Vec<PackageArchive>> {
+        let archive_files = self
+            .files
+            .iter()
+            .filter(|f| f.target.to_ascii_lowercase().ends_with(".archive"))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let mut archives = vec![];
+        for file in archive_files {
+            let buffer = self.read_file(&file)?;
+            let archive = archive::open_read_stream(Cursor::new(buffer))?;
+            let mut hashes = archive.get_entries().keys().copied().collect::<Vec<_>>();
+            hashes.sort();
+            archives.push(PackageArchive {
+                target: file.target,
+                hashes,
+            });
+        }
+
+        Ok(archives)
+    }
+
+    /// Installs the mod into a game folder.
+    ///
+    /// Existing files are backed up and restored on uninstall. The manifest is written after the
+    /// backups and before any game file is changed, so a failed install can still be uninstalled.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the mod is already installed or any io fails.
+    pub fn install<P: AsRef<Path>>(&mut self, game_folder: &P) -> Result<Manifest> {
+        let game_folder = game_folder.as_ref();
+        let manifest_path = get_manifest_path(game_folder, &self.name);
+        if manifest_path.exists() {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                "Mod is already installed.",
+            ));
+        }
+
+        let mut manifest = Manifest {
+            name: self.name.to_owned(),
+            layouts: self.layouts(),
+            files: vec![],
+            backups: vec![],
+        };
+        for file in self.files.clone() {
+            let buffer = self.read_file(&file)?;
+            manifest.files.push(ManifestFile {
+                path: file.target.to_owned(),
+                sha1: sha1_to_string(&sha1_hash_file(&buffer)),
+            });
+            if game_folder.join(&file.target).exists() {
+                manifest.backups.push(file.target.to_owned());
+            }
+        }
+
+        // back up any file we would overwrite
+        let backup_folder = get_backup_folder(game_folder, &self.name);
+        let backed_up = manifest.backups.iter().try_for_each(|path| {
+            let backup = backup_folder.join(path);
+            create_parent_dir(&backup)?;
+            fs::copy(game_folder.join(path), backup).map(|_| ())
+        });
+        if let Err(e) = backed_up.and_then(|_| manifest.write(&manifest_path)) {
+            // nothing in the game folder was changed yet
+            if backup_folder.exists() {
+                fs::remove_dir_all(&backup_folder)?;
+            }
+            return Err(e);
+        }
+
+        for file in self.files.clone() {
+            let buffer = self.read_file(&file)?;
+            let target = game_folder.join(&file.target);
+            create_parent_dir(&target)?;
+            fs::write(&target, &buffer)?;
+        }
+
+        Ok(manifest)
+    }
+}
+
+/// A record of all files that an installed mod placed in the game folder
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub name: String,
+    pub layouts: Vec<EModLayout>,
+    pub files: Vec<ManifestFile>,
+    /// Paths of files that were overwritten and backed up on install
+    pub backups: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestFile {
+    /// Path relative to the game folder, with forward slashes
+    pub path: String,
+    /// SHA1 of the installed file
+    pub sha1: String,
+}
+
+impl Manifest {
+    /// Reads a manifest from a file
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file can't be read or is invalid, e.g. a path is
+    /// absolute or has parent components.
+    pub fn from_file<P: AsRef<Path>>(path: &P) -> Result<Self> {
+        let file = File::open(path)?;
+        let manifest: Manifest = serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        validate_mod_name(&manifest.name)?;
+        let paths = manifest.files.iter().map(|f| &f.path);
+        for path in paths.chain(manifest.backups.iter()) {
+            validate_game_path(path)?;
+        }
+        Ok(manifest)
+    }
+
+    fn installs(&self, path: &str) -> bool {
+        self.files.iter().any(|f| f.path == path)
+    }
+
+    fn backs_up(&self, path: &str) -> bool {
+        self.backups.iter().any(|b| b == path)
+    }
+
+    fn write<P: AsRef<Path>>(&self, path: &P) -> Result<()> {
+        create_parent_dir(path.as_ref())?;
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self).map_err(Error::other)?;
+        writer.flush()
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// API
+/////////////////////////////////////////////////////////////////////////////////////////
+
+/// Opens a mod zip from a path, the mod is named after the zip file.
+///
+/// # Errors
+///
+/// This function will return an error if the zip can't be read.
+pub fn open_mod<P: AsRef<Path>>(path: &P) -> Result<ModPackage<BufReader<File>>> {
+    let name = path
+        .as_ref()
+        .file_stem()
+        .map(|f| f.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let file = File::open(path)?;
+    ModPackage::from_reader(BufReader::new(file), &name)
+}
+
+/// Gets the manifests of all installed mods in a game folder
+///
+/// # Errors
+///
+/// This function will return an error if any manifest can't be read.
+pub fn get_installed_mods<P: AsRef<Path>>(game_folder: &P) -> Result<Vec<Manifest>> {
+    let folder = game_folder
+        .as_ref()
+        .join(INSTALLER_FOLDER_NAME)
+        .join(MANIFESTS_FOLDER_NAME);
+    if !folder.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut manifests = vec![];
+    for entry in fs::read_dir(folder)?.flatten() {
+        let path = entry.path();
+        if path.extension().is_some_and(|e| e == "json") {
+            manifests.push(Manifest::from_file(&path)?);
+        }
+    }
+    manifests.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(manifests)
+}
+
+/// Uninstalls a mod from a game folder.
+///
+/// Removes the files of the manifest that are unchanged since the install, restores backed up files
+/// and deletes folders that became empty. Files that were changed since, e.g. by a mod installed
+/// later or by the user, are kept and returned. If a later mod backed up a file of this mod, that
+/// backup is replaced with the file this mod backed up, so uninstalling the later mod doesn't bring
+/// back files of this one.
+///
+/// # Errors
+///
+/// This function will return an error if the name is invalid, the mod is not installed, a manifest
+/// is invalid or any io fails.
+pub fn uninstall<P: AsRef<Path>>(game_folder: &P, name: &str) -> Result<Vec<String>> {
+    validate_mod_name(name)?;
+    let game_folder = game_folder.as_ref();
+    let manifest_path = get_manifest_path(game_folder, name);
+    if !manifest_path.exists() {
+        return Err(Error::new(ErrorKind::NotFound, "Mod is not installed."));
+    }
+    let manifest = Manifest::from_file(&manifest_path)?;
+    let mut others = get_installed_mods(&game_folder)?
+        .into_iter()
+        .filter(|m| m.name != manifest.name)
+        .collect::<Vec<_>>();
+
+    let backup_folder = get_backup_folder(game_folder, name);
+    let mut kept = vec![];
+    for file in manifest.files.iter() {
+        let path = game_folder.join(&file.path);
+        let backed_up = manifest.backs_up(&file.path);
+        if !path.exists() {
+            if backed_up {
+                restore_backup(&backup_folder, game_folder, &file.path)?;
+            }
+            continue;
+        }
+        if sha1_to_string(&sha1_hash_file(&fs::read(&path)?)) == file.sha1 {
+            fs::remove_file(&path)?;
+            if backed_up {
+                restore_backup(&backup_folder, game_folder, &file.path)?;
+            }
+            continue;
+        }
+
+        // changed since the install, a later mod's backup of it is this mod's file
+        kept.push(file.path.to_owned());
+        for other in others
+            .iter_mut()
+            .filter(|m| m.installs(&file.path) && m.backs_up(&file.path))
+        {
+            let other_backup = get_backup_folder(game_folder, &other.name).join(&file.path);
+            if backed_up {
+                fs::copy(backup_folder.join(&file.path), other_backup)?;
+            } else {
+                fs::remove_file(other_backup)?;
+                other.backups.retain(|b| *b != file.path);
+                other.write(&get_manifest_path(game_folder, &other.name))?;
+            }
+        }
+    }
+    if backup_folder.exists() {
+        fs::remove_dir_all(backup_folder)?;
+    }
+
+    // cleanup empty folders
+    for file in manifest.files.iter() {
+        remove_empty_parents(game_folder, &game_folder.join(&file.path));
+    }
+
+    fs::remove_file(manifest_path)?;
+
+    Ok(kept)
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// HELPERS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+/// Gets the install path of a file relative to the game folder.
+///
+/// Game folders must be at the root of the zip, mod zips often wrap them in a single top level
+/// folder which is stripped. Loose .archive and .xl files go to archive/pc/mod.
+fn get_install_path(source: &str) -> Option<String> {
+    let components = source.split('/').collect::<Vec<_>>();
+    for (i, component) in components.iter().enumerate().take(2) {
+        let lower = component.to_ascii_lowercase();
+        if !GAME_ROOTS.contains(&lower.as_str()) || i + 1 == components.len() {
+            continue;
+        }
+        // REDmods must have a mod folder
+        if lower == redmod::MODS_FOLDER_NAME && i + 2 >= components.len() {
+            continue;
+        }
+        return Some(components[i..].join("/"));
+    }
+
+    let file_name = components.last()?;
+    let lower = file_name.to_ascii_lowercase();
+    if lower.ends_with(".archive") || lower.ends_with(".xl") {
+        return Some(format!("archive/pc/mod/{}", file_name));
+    }
+
+    None
+}
+
+fn get_layout(target: &str) -> EModLayout {
+    let lower = target.to_ascii_lowercase();
+    if lower.ends_with(".xl") {
+        EModLayout::ArchiveXl
+    } else if lower.starts_with("mods/") {
+        EModLayout::RedMod
+    } else if lower.starts_with("archive/pc/mod/") {
+        EModLayout::Archive
+    } else {
+        EModLayout::Other
+    }
+}
+
+/// Paths of a manifest are relative to the game folder and must not leave it
+fn validate_game_path(path: &str) -> Result<()> {
+    let relative = !path.is_empty()
+        && Path::new(path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        && !path.split(['/', '\\']).any(|part| part == "..");
+    match relative {
+        true => Ok(()),
+        false => Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("{:?} is not inside the game folder", path),
+        )),
+    }
+}
+
+fn restore_backup(backup_folder: &Path, game_folder: &Path, path: &str) -> Result<()> {
+    let target = game_folder.join(path);
+    create_parent_dir(&target)?;
+    fs::copy(backup_folder.join(path), target).map(|_| ())
+}
+
+fn create_parent_dir(path: &Path) -> Result<()> {
+    match path.parent() {
+        Some(parent) => create_dir_all(parent),
+        None => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} has no parent folder", path.display()),
+        )),
+    }
+}
+
+fn get_manifest_path(game_folder: &Path, name: &str) -> PathBuf {
+    game_folder
+        .join(INSTALLER_FOLDER_NAME)
+        .join(MANIFESTS_FOLDER_NAME)
+        .join(format!("{}.json", name))
+}
+
+fn get_backup_folder(game_folder: &Path, name: &str) -> PathBuf {
+    game_folder
+        .join(INSTALLER_FOLDER_NAME)
+        .join(BACKUPS_FOLDER_NAME)
+        .join(name)
+}
+
+fn remove_empty_parents(root: &Path, path: &Path) {
+    let mut current = path.parent();
+    while let Some(folder) = current {
+        if folder == root || !folder.starts_with(root) {
+            break;
+        }
+        // fails if the folder is not empty
+        if fs::remove_dir(folder).is_err() {
+            break;
+        }
+        current = folder.parent();
+    }
+}
+
+fn sha1_to_string(hash: &[u8; 20]) -> String {
+    hash.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn to_io_error(e: zip::result::ZipError) -> Error {
+    match e {
+        zip::result::ZipError::Io(e) => e,
+        _ => Error::new(ErrorKind::InvalidData, e),
+    }
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// TESTS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{Cursor, Write},
+        path::PathBuf,
+    };
+
+    use zip::write::SimpleFileOptions;
+
+    use super::*;
+
+    fn create_mod_zip() -> Vec<u8> {
+        let archive = fs::read(PathBuf::from("tests").join("test1.archive")).unwrap();
+
+        let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+        let options = SimpleFileOptions::default();
+        zip.start_file("MyMod/archive/pc/mod/test1.archive", options)
+            .unwrap();
+        zip.write_all(&archive).unwrap();
+        zip.start_file("MyMod/archive/pc/mod/test1.archive.xl", options)
+            .unwrap();
+        zip.write_all(b"resource:\n").unwrap();
+        zip.start_file("MyMod/mods/mymod/info.json", options)
+            .unwrap();
+        zip.write_all(br#"{ "name": "mymod", "version": "1.0" }"#)
+            .unwrap();
+        zip.start_file("MyMod/readme.txt", options).unwrap();
+        zip.write_all(b"readme").unwrap();
+        zip.start_file("loose.archive", options).unwrap();
+        zip.write_all(&archive).unwrap();
+
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn detect_layout() {
+        assert_eq!(
+            Some("archive/pc/mod/a.archive".to_owned()),
+            get_install_path("Mod/archive/pc/mod/a.archive")
+        );
+        assert_eq!(
+            Some("archive/pc/mod/a.archive".to_owned()),
+            get_install_path("a.archive")
+        );
+        assert_eq!(
+            Some("r6/scripts/a.reds".to_owned()),
+            get_install_path("r6/scripts/a.reds")
+        );
+        assert_eq!(None, get_install_path("mods/readme.txt"));
+        assert_eq!(None, get_install_path("readme.txt"));
+        // game folders are only found at the root of the zip
+        assert_eq!(
+            Some("bin/x64/a.dll".to_owned()),
+            get_install_path("Mod/bin/x64/a.dll")
+        );
+        assert_eq!(None, get_install_path("Mod/source/bin/a.dll"));
+        assert_eq!(None, get_install_path("Mod/v1/archive/pc/mod/a.xl/b.txt"));
+
+        let mut package = ModPackage::from_reader(Cursor::new(create_mod_zip()), "MyMod").unwrap();
+        assert_eq!(4, package.files().len());
+        assert_eq!(
+            vec![
+                EModLayout::Archive,
+                EModLayout::RedMod,
+                EModLayout::ArchiveXl
+            ],
+            package.layouts()
+        );
+
+        let archives = package.archives().unwrap();
+        assert_eq!(2, archives.len());
+        assert!(archives.iter().all(|a| !a.hashes.is_empty()));
+    }
+
+    #[test]
+    fn reject_invalid_packages() {
+        for name in ["", ".", "..", "../../MyMod", "a/b", "a\\b", "/MyMod"] {
+            let result = ModPackage::from_reader(Cursor::new(create_mod_zip()), name);
+            assert_eq!(ErrorKind::InvalidInput, result.unwrap_err().kind());
+            assert!(uninstall(&PathBuf::from("tests"), name).is_err());
+        }
+
+        // two files with the same target
+        let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+        let options = SimpleFileOptions::default();
+        zip.start_file("loose.archive", options).unwrap();
+        zip.start_file("Mod/archive/pc/mod/LOOSE.archive", options)
+            .unwrap();
+        let buffer = zip.finish().unwrap().into_inner();
+        let result = ModPackage::from_reader(Cursor::new(buffer), "MyMod");
+        assert_eq!(ErrorKind::InvalidData, result.unwrap_err().kind());
+    }
+
+    #[test]
+    fn uninstall_failed_install() {
+        let game_path = PathBuf::from("tests").join("out_install_failed");
+        if game_path.exists() {
+            assert!(fs::remove_dir_all(&game_path).is_ok());
+        }
+        let existing = game_path.join("r6/scripts/a.reds");
+        fs::create_dir_all(existing.parent().unwrap()).unwrap();
+        fs::write(&existing, b"existing").unwrap();
+        // a file where the mod needs a folder
+        fs::write(game_path.join("r6/tweaks"), b"").unwrap();
+
+        let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+        let options = SimpleFileOptions::default();
+        zip.start_file("r6/scripts/a.reds", options).unwrap();
+        zip.write_all(b"mod").unwrap();
+        zip.start_file("r6/tweaks/b.yaml", options).unwrap();
+        let buffer = zip.finish().unwrap().into_inner();
+
+        let mut package = ModPackage::from_reader(Cursor::new(buffer), "MyMod").unwrap();
+        assert!(package.install(&game_path).is_err());
+        assert_eq!(b"mod".to_vec(), fs::read(&existing).unwrap());
+
+        uninstall(&game_path, "MyMod").unwrap();
+        assert_eq!(b"existing".to_vec(), fs::read(&existing).unwrap());
+        assert!(get_installed_mods(&game_path).unwrap().is_empty());
+
+        // cleanup
+        assert!(fs::remove_dir_all(&game_path).is_ok());
+    }
+
+    #[test]
+    fn install_uninstall() {
+        let game_path = PathBuf::from("tests").join("out_install");
+        if game_path.exists() {
+            assert!(fs::remove_dir_all(&game_path).is_ok());
+        }
+        let existing = game_path.join("archive/pc/mod/loose.archive");
+        fs::create_dir_all(existing.parent().unwrap()).unwrap();
+        fs::write(&existing, b"existing").unwrap();
+
+        let mut package = ModPackage::from_reader(Cursor::new(create_mod_zip()), "MyMod").unwrap();
+        let manifest = package.install(&game_path).unwrap();
+        assert_eq!(4, manifest.files.len());
+        assert_eq!(
+            vec!["archive/pc/mod/loose.archive".to_owned()],
+            manifest.backups
+        );
+        assert!(game_path.join("mods/mymod/info.json").exists());
+        assert!(package.install(&game_path).is_err());
+        assert_eq!(1, get_installed_mods(&game_path).unwrap().len());
+
+        uninstall(&game_path, "MyMod").unwrap();
+        assert!(!game_path.join("mods").exists());
+        assert!(!game_path.join("archive/pc/mod/test1.archive").exists());
+        assert_eq!(b"existing".to_vec(), fs::read(&existing).unwrap());
+        assert!(get_installed_mods(&game_path).unwrap().is_empty());
+
+        // cleanup
+        assert!(fs::remove_dir_all(&game_path).is_ok());
+    }
+
+    /// A mod that installs one script
+    fn script_mod(name: &str, content: &[u8]) -> ModPackage<Cursor<Vec<u8>>> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+        zip.start_file("r6/scripts/a.reds", SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(content).unwrap();
+        let buffer = zip.finish().unwrap().into_inner();
+        ModPackage::from_reader(Cursor::new(buffer), name).unwrap()
+    }
+
+    #[test]
+    fn uninstall_overwritten_files() {
+        let game_path = PathBuf::from("tests").join("out_install_overwritten");
+        if game_path.exists() {
+            assert!(fs::remove_dir_all(&game_path).is_ok());
+        }
+        let script = game_path.join("r6/scripts/a.reds");
+        let target = "r6/scripts/a.reds".to_owned();
+
+        // a later mod overwrote the file, it stays and the file of the first mod doesn't come back
+        script_mod("First", b"first").install(&game_path).unwrap();
+        script_mod("Second", b"second").install(&game_path).unwrap();
+        assert_eq!(
+            vec![target.clone()],
+            uninstall(&game_path, "First").unwrap()
+        );
+        assert_eq!(b"second".to_vec(), fs::read(&script).unwrap());
+        assert_eq!(
+            Vec::<String>::new(),
+            uninstall(&game_path, "Second").unwrap()
+        );
+        assert!(!script.exists());
+
+        // the same with a game file, which comes back after both mods are uninstalled
+        fs::create_dir_all(script.parent().unwrap()).unwrap();
+        fs::write(&script, b"game").unwrap();
+        script_mod("First", b"first").install(&game_path).unwrap();
+        script_mod("Second", b"second").install(&game_path).unwrap();
+        assert_eq!(
+            vec![target.clone()],
+            uninstall(&game_path, "First").unwrap()
+        );
+        assert!(uninstall(&game_path, "Second").unwrap().is_empty());
+        assert_eq!(b"game".to_vec(), fs::read(&script).unwrap());
+
+        // a file the user changed stays
+        script_mod("First", b"first").install(&game_path).unwrap();
+        fs::write(&script, b"user").unwrap();
+        assert_eq!(vec![target], uninstall(&game_path, "First").unwrap());
+        assert_eq!(b"user".to_vec(), fs::read(&script).unwrap());
+        assert!(get_installed_mods(&game_path).unwrap().is_empty());
+
+        // cleanup
+        assert!(fs::remove_dir_all(&game_path).is_ok());
+    }
+
+    #[test]
+    fn reject_invalid_manifests() {
+        let game_path = PathBuf::from("tests").join("out_install_manifest");
+        if game_path.exists() {
+            assert!(fs::remove_dir_all(&game_path).is_ok());
+        }
+        let outside = PathBuf::from("tests").join("outside.txt");
+        fs::write(&outside, b"outside").unwrap();
+
+        let manifest_path = get_manifest_path(&game_path, "MyMod");
+        for path in ["../outside.txt", "r6/../../outside.txt", "/outside.txt", ""] {
+            let manifest = Manifest {
+                name: "MyMod".to_owned(),
+                layouts: vec![],
+                files: vec![ManifestFile {
+                    path: path.to_owned(),
+                    sha1: sha1_to_string(&sha1_hash_file(&b"outside".to_vec())),
+                }],
+                backups: vec![],
+            };
+            manifest.write(&manifest_path).unwrap();
+            assert!(Manifest::from_file(&manifest_path).is_err());
+            assert!(uninstall(&game_path, "MyMod").is_err());
+            assert!(outside.exists());
+        }
+
+        // cleanup
+        assert!(fs::remove_dir_all(&game_path).is_ok());
+        assert!(fs::remove_file(&outside).is_ok());
+    }
+}
@@ -0,0 +1,1527 @@
+/////////////////////////////////////////////////////////////////////////////////////////
+// KRAKEN DECODER
+// Pure Rust decoder for Oodle Kraken streams, following the open source ooz decompressor.
+//
+// stream:  per 256k block a 2 byte block header, then a 3 byte quantum header
+// quantum: up to two 128k chunks, each either entropy coded bytes or an lz chunk
+// lz:      literal, command, offset and length streams, then the copy loop
+/////////////////////////////////////////////////////////////////////////////////////////
+
+use std::io::{Error, ErrorKind, Result};
+
+/// Size of a block, every block starts with a block header
+const BLOCK_SIZE: usize = 0x40000;
+/// Size of a chunk inside a quantum
+const CHUNK_SIZE: usize = 0x20000;
+/// Decoder type of Kraken in the block header
+const DECODER_KRAKEN: u8 = 6;
+
+/// Decompresses a Kraken stream into a buffer of exactly `size` bytes.
+///
+/// # Errors
+///
+/// This function will return an error if the stream is not a valid Kraken stream.
+pub(super) fn decompress(src: &[u8], size: usize) -> Result<Vec<u8>> {
+    decode_stream(src, size).map(|(dst, _)| dst)
+}
+
+/// Decodes a Kraken stream and returns the output with the used input size
+fn decode_stream(src: &[u8], size: usize) -> Result<(Vec<u8>, usize)> {
+    let mut dst = vec![0; size];
+    let mut pos = 0;
+    let mut offset = 0;
+    let mut header = BlockHeader::default();
+
+    while offset < size {
+        if offset & (BLOCK_SIZE - 1) == 0 {
+            header = BlockHeader::parse(slice(src, pos, 2)?)?;
+            pos += 2;
+        }
+        let count = (size - offset).min(BLOCK_SIZE);
+
+        if header.uncompressed {
+            dst[offset..offset + count].copy_from_slice(slice(src, pos, count)?);
+            pos += count;
+            offset += count;
+            continue;
+        }
+
+        // quantum header
+        let v = be_bytes(src, pos, 3)?;
+        pos += 3;
+        let compressed_size = v & 0x3FFFF;
+        if compressed_size == 0x3FFFF {
+            if v >> 18 != 1 {
+                return Err(corrupt("invalid quantum header"));
+            }
+            // memset
+            let value = slice(src, pos, 1)?[0];
+            pos += 1;
+            dst[offset..offset + count].fill(value);
+        } else {
+            if header.use_checksums {
+                pos += 3;
+            }
+            let compressed_size = compressed_size + 1;
+            let quantum = slice(src, pos, compressed_size)?;
+            check(compressed_size <= count, "quantum larger than block")?;
+            if compressed_size == count {
+                dst[offset..offset + count].copy_from_slice(quantum);
+            } else {
+                let used = decode_quantum(quantum, &mut dst, offset, count)?;
+                check(used == compressed_size, "quantum size mismatch")?;
+            }
+            pos += compressed_size;
+        }
+        offset += count;
+    }
+
+    Ok((dst, pos))
+}
+
+#[derive(Debug, Default)]
+struct BlockHeader {
+    uncompressed: bool,
+    use_checksums: bool,
+}
+
+impl BlockHeader {
+    fn parse(bytes: &[u8]) -> Result<Self> {
+        let (b1, b2) = (bytes[0], bytes[1]);
+        if (b1 & 0xF) != 0xC || ((b1 >> 4) & 3) != 0 {
+            return Err(corrupt("invalid block header"));
+        }
+        if (b2 & 0x7F) != DECODER_KRAKEN {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("unsupported oodle decoder type {:#x}", b2 & 0x7F),
+            ));
+        }
+        Ok(Self {
+            uncompressed: (b1 >> 6) & 1 == 1,
+            use_checksums: (b2 >> 7) != 0,
+        })
+    }
+}
+
+/// Decodes one quantum of `count` bytes at `offset` of the output and returns the used input size
+fn decode_quantum(src: &[u8], dst: &mut [u8], offset: usize, count: usize) -> Result<usize> {
+    let mut pos = 0;
+    let mut out = offset;
+    let end = offset + count;
+
+    while out < end {
+        let dst_count = (end - out).min(CHUNK_SIZE);
+        check(src.len() - pos >= 4, "truncated chunk")?;
+        let chunk_header = be_bytes(src, pos, 3)?;
+        if chunk_header & 0x800000 == 0 {
+            // entropy coded bytes without any match copying
+            let (bytes, used) = decode_bytes(&src[pos..], dst_count)?;
+            check(bytes.len() == dst_count, "chunk size mismatch")?;
+            dst[out..out + dst_count].copy_from_slice(&bytes);
+            pos += used;
+        } else {
+            pos += 3;
+            let src_used = chunk_header & 0x7FFFF;
+            let mode = (chunk_header >> 19) & 0xF;
+            let chunk = slice(src, pos, src_used)?;
+            if src_used < dst_count {
+                decode_lz(chunk, mode, dst, out, dst_count)?;
+            } else if src_used > dst_count || mode != 0 {
+                return Err(corrupt("invalid chunk header"));
+            } else {
+                dst[out..out + dst_count].copy_from_slice(chunk);
+            }
+            pos += src_used;
+        }
+        out += dst_count;
+    }
+
+    Ok(pos)
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// LZ
+/////////////////////////////////////////////////////////////////////////////////////////
+
+/// The decoded streams of an lz chunk
+struct LzTable {
+    /// Literal and match lengths plus the recent offset index of each copy
+    cmd_stream: Vec<u8>,
+    /// Distances of copies that don't use a recent offset
+    offs_stream: Vec<i32>,
+    lit_stream: Vec<u8>,
+    /// Lengths that don't fit in a command
+    len_stream: Vec<i32>,
+}
+
+fn decode_lz(
+    src: &[u8],
+    mode: usize,
+    dst: &mut [u8],
+    offset: usize,
+    dst_size: usize,
+) -> Result<()> {
+    check(mode <= 1, "invalid lz mode")?;
+    let table = read_lz_table(src, dst, offset, dst_size)?;
+    process_lz_runs(&table, mode == 0, dst, offset, dst_size)
+}
+
+fn read_lz_table(src: &[u8], dst: &mut [u8], offset: usize, dst_size: usize) -> Result<LzTable> {
+    check(src.len() >= 13, "truncated lz table")?;
+    let mut pos = 0;
+
+    // the first bytes of a stream are stored raw
+    if offset == 0 {
+        check(dst_size >= 8, "truncated lz chunk")?;
+        dst[..8].copy_from_slice(&src[..8]);
+        pos += 8;
+    }
+
+    if src[pos] & 0x80 != 0 {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            "kraken excess bytes are not supported",
+        ));
+    }
+
+    let (lit_stream, n) = decode_bytes(&src[pos..], dst_size)?;
+    pos += n;
+    let (cmd_stream, n) = decode_bytes(&src[pos..], dst_size)?;
+    pos += n;
+
+    check(src.len() - pos >= 3, "truncated lz table")?;
+    let mut offs_scaling = 0;
+    let mut offs_stream_extra = None;
+    if src[pos] & 0x80 != 0 {
+        // distances are coded with two tables
+        offs_scaling = src[pos] as i32 - 127;
+        pos += 1;
+    }
+    let (packed_offs_stream, n) = decode_bytes(&src[pos..], cmd_stream.len())?;
+    pos += n;
+    if offs_scaling > 1 {
+        let (extra, n) = decode_bytes(&src[pos..], packed_offs_stream.len())?;
+        check(
+            extra.len() == packed_offs_stream.len(),
+            "offset stream size mismatch",
+        )?;
+        offs_stream_extra = Some(extra);
+        pos += n;
+    }
+
+    let (packed_len_stream, n) = decode_bytes(&src[pos..], dst_size >> 2)?;
+    pos += n;
+
+    let (offs_stream, len_stream) = unpack_offsets(
+        &src[pos..],
+        &packed_offs_stream,
+        offs_stream_extra.as_deref(),
+        offs_scaling,
+        &packed_len_stream,
+    )?;
+
+    Ok(LzTable {
+        cmd_stream,
+        offs_stream,
+        lit_stream,
+        len_stream,
+    })
+}
+
+/// Unpacks the packed 8 bit offsets and lengths into 32 bit
+fn unpack_offsets(
+    src: &[u8],
+    packed_offs_stream: &[u8],
+    packed_offs_stream_extra: Option<&[u8]>,
+    offs_scaling: i32,
+    packed_len_stream: &[u8],
+) -> Result<(Vec<i32>, Vec<i32>)> {
+    let mut bits_a = Bits::new(src);
+    let mut bits_b = Bits::backward(src);
+
+    check(bits_b.peek(32) >= 0x2000, "invalid length stream size")?;
+    let n = bits_b.leading_zeros();
+    bits_b.skip(n);
+    let u32_len_stream_size = bits_b.read(n + 1) as usize - 1;
+
+    let mut offs_stream = Vec::with_capacity(packed_offs_stream.len());
+    if offs_scaling == 0 {
+        for (i, v) in packed_offs_stream.iter().enumerate() {
+            let bits = if i % 2 == 0 { &mut bits_a } else { &mut bits_b };
+            offs_stream.push(-(bits.read_distance(*v as u32) as i32));
+        }
+    } else {
+        for (i, cmd) in packed_offs_stream.iter().enumerate() {
+            let bits = if i % 2 == 0 { &mut bits_a } else { &mut bits_b };
+            let n = (cmd >> 3) as u32;
+            check(n <= 26, "invalid offset")?;
+            let offs = ((8 + (cmd & 7) as u32) << n) | bits.read(n);
+            offs_stream.push(8 - offs as i32);
+        }
+        if let Some(low_bits) = packed_offs_stream_extra {
+            for (offs, low) in offs_stream.iter_mut().zip(low_bits) {
+                *offs = *offs * offs_scaling - *low as i32;
+            }
+        }
+    }
+
+    // max count is 128kb / 256
+    check(u32_len_stream_size <= 512, "invalid length stream size")?;
+    let mut u32_len_stream = Vec::with_capacity(u32_len_stream_size);
+    for i in 0..u32_len_stream_size {
+        let bits = if i % 2 == 0 { &mut bits_a } else { &mut bits_b };
+        u32_len_stream.push(bits.read_length()?);
+    }
+
+    check(
+        bits_a.byte_pos() + bits_b.byte_pos() == src.len(),
+        "offset stream size mismatch",
+    )?;
+
+    let mut u32_lens = u32_len_stream.into_iter();
+    let mut len_stream = Vec::with_capacity(packed_len_stream.len());
+    for v in packed_len_stream {
+        let mut v = *v as u32;
+        if v == 255 {
+            v = u32_lens
+                .next()
+                .ok_or_else(|| corrupt("length stream too short"))?
+                + 255;
+        }
+        len_stream.push((v + 3) as i32);
+    }
+    check(u32_lens.len() == 0, "length stream too long")?;
+
+    Ok((offs_stream, len_stream))
+}
+
+fn process_lz_runs(
+    table: &LzTable,
+    delta_literals: bool,
+    dst: &mut [u8],
+    offset: usize,
+    dst_size: usize,
+) -> Result<()> {
+    let dst_end = offset + dst_size;
+    let mut out = if offset == 0 { 8 } else { offset };
+
+    let lit_stream = &table.lit_stream;
+    let mut lit_pos = 0;
+    let mut len_stream = table.len_stream.iter();
+    let mut offs_pos = 0;
+    let mut recent_offs = [0, 0, 0, -8, -8, -8, 0];
+    let mut last_offset = -8;
+
+    for f in table.cmd_stream.iter() {
+        let f = *f as usize;
+        let mut litlen = f & 3;
+        let offs_index = f >> 6;
+        let matchlen = (f >> 2) & 0xF;
+
+        if litlen == 3 {
+            litlen = next_len(&mut len_stream)?;
+        }
+        recent_offs[6] = table.offs_stream.get(offs_pos).copied().unwrap_or_default();
+
+        check(
+            litlen <= lit_stream.len() - lit_pos,
+            "literal stream too short",
+        )?;
+        check(litlen <= dst_end - out, "literals out of bounds")?;
+        copy_literals(
+            dst,
+            out,
+            &lit_stream[lit_pos..lit_pos + litlen],
+            delta_literals.then_some(last_offset),
+        )?;
+        out += litlen;
+        lit_pos += litlen;
+
+        let offset = recent_offs[offs_index + 3];
+        recent_offs.copy_within(offs_index..offs_index + 3, offs_index + 1);
+        recent_offs[3] = offset;
+        last_offset = offset;
+        if offs_index == 3 {
+            offs_pos += 1;
+        }
+
+        let matchlen = if matchlen != 15 {
+            matchlen + 2
+        } else {
+            14 + next_len(&mut len_stream)?
+        };
+        check(matchlen <= dst_end - out, "match out of bounds")?;
+        let from = out as isize + offset as isize;
+        check(offset < 0 && from >= 0, "invalid match offset")?;
+        let from = from as usize;
+        // byte by byte, matches may overlap
+        for i in 0..matchlen {
+            dst[out + i] = dst[from + i];
+        }
+        out += matchlen;
+    }
+
+    check(
+        offs_pos == table.offs_stream.len(),
+        "offset stream too long",
+    )?;
+    check(len_stream.len() == 0, "length stream too long")?;
+
+    let final_len = dst_end - out;
+    check(
+        final_len == lit_stream.len() - lit_pos,
+        "literal stream size mismatch",
+    )?;
+    copy_literals(
+        dst,
+        out,
+        &lit_stream[lit_pos..],
+        delta_literals.then_some(last_offset),
+    )
+}
+
+/// Copies literals, delta literals are added to the byte at `last_offset`
+fn copy_literals(
+    dst: &mut [u8],
+    out: usize,
+    literals: &[u8],
+    last_offset: Option<i32>,
+) -> Result<()> {
+    match last_offset {
+        Some(last_offset) => {
+            let from = out as isize + last_offset as isize;
+            check(from >= 0, "invalid literal offset")?;
+            let from = from as usize;
+            for (i, lit) in literals.iter().enumerate() {
+                dst[out + i] = lit.wrapping_add(dst[from + i]);
+            }
+        }
+        None => dst[out..out + literals.len()].copy_from_slice(literals),
+    }
+    Ok(())
+}
+
+fn next_len(len_stream: &mut std::slice::Iter<'_, i32>) -> Result<usize> {
+    len_stream
+        .next()
+        .map(|l| *l as usize)
+        .ok_or_else(|| corrupt("length stream too short"))
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// ENTROPY CODED BYTES
+/////////////////////////////////////////////////////////////////////////////////////////
+
+/// Decodes a block of entropy coded bytes and returns them with the used input size
+fn decode_bytes(src: &[u8], output_size: usize) -> Result<(Vec<u8>, usize)> {
+    check(src.len() >= 2, "truncated block")?;
+
+    let chunk_type = (src[0] >> 4) & 0x7;
+    if chunk_type == 0 {
+        // memcopy
+        let (src_size, header_size) = if src[0] >= 0x80 {
+            (be_bytes(src, 0, 2)? & 0xFFF, 2)
+        } else {
+            let size = be_bytes(src, 0, 3)?;
+            check(size & !0x3FFFF == 0, "reserved bits set")?;
+            (size, 3)
+        };
+        check(src_size <= output_size, "block too large")?;
+        let bytes = slice(src, header_size, src_size)?;
+        return Ok((bytes.to_vec(), header_size + src_size));
+    }
+
+    let (src_size, dst_size, header_size) = read_block_sizes(src)?;
+    check(dst_size <= output_size, "block too large")?;
+    let data = slice(src, header_size, src_size)?;
+
+    let bytes = match chunk_type {
+        2 | 4 => decode_huffman(data, dst_size, (chunk_type >> 1) as usize)?,
+        5 => decode_recursive(data, dst_size)?,
+        3 => decode_rle(data, dst_size)?,
+        1 => decode_tans(data, dst_size)?,
+        _ => return Err(corrupt("invalid block type")),
+    };
+    check(bytes.len() == dst_size, "block size mismatch")?;
+
+    Ok((bytes, header_size + src_size))
+}
+
+/// Reads the compressed and decompressed size of a block that is not a memcopy
+fn read_block_sizes(src: &[u8]) -> Result<(usize, usize, usize)> {
+    if src[0] >= 0x80 {
+        // short mode, 10 bit sizes
+        let bits = be_bytes(src, 0, 3)?;
+        let src_size = bits & 0x3FF;
+        let dst_size = src_size + ((bits >> 10) & 0x3FF) + 1;
+        Ok((src_size, dst_size, 3))
+    } else {
+        // long mode, 18 bit sizes
+        let bits = be_bytes(src, 1, 4)?;
+        let src_size = bits & 0x3FFFF;
+        let dst_size = (((bits >> 18) | ((src[0] as usize) << 14)) & 0x3FFFF) + 1;
+        check(src_size < dst_size, "invalid block sizes")?;
+        Ok((src_size, dst_size, 5))
+    }
+}
+
+/// Gets the decompressed size of the next block without decoding it
+fn get_block_size(src: &[u8], output_size: usize) -> Result<usize> {
+    check(!src.is_empty(), "truncated block")?;
+    let chunk_type = (src[0] >> 4) & 0x7;
+    let (src_size, dst_size, header_size) = if chunk_type == 0 {
+        let (size, header_size) = if src[0] >= 0x80 {
+            (be_bytes(src, 0, 2)? & 0xFFF, 2)
+        } else {
+            (be_bytes(src, 0, 3)?, 3)
+        };
+        (size, size, header_size)
+    } else {
+        check(chunk_type < 6, "invalid block type")?;
+        read_block_sizes(src)?
+    };
+    check(src_size <= src.len() - header_size, "truncated block")?;
+    check(dst_size <= output_size, "block too large")?;
+    Ok(dst_size)
+}
+
+fn decode_recursive(src: &[u8], dst_size: usize) -> Result<Vec<u8>> {
+    check(src.len() >= 6, "truncated block")?;
+    let n = src[0] & 0x7F;
+    check(n >= 2, "invalid block count")?;
+
+    if src[0] & 0x80 == 0 {
+        let mut pos = 1;
+        let mut output = Vec::with_capacity(dst_size);
+        for _ in 0..n {
+            let (bytes, used) = decode_bytes(&src[pos..], dst_size - output.len())?;
+            output.extend_from_slice(&bytes);
+            pos += used;
+        }
+        check(pos == src.len(), "block size mismatch")?;
+        Ok(output)
+    } else {
+        let (mut arrays, used) = decode_multi_array(src, dst_size, 1)?;
+        check(used == src.len(), "block size mismatch")?;
+        Ok(arrays.remove(0))
+    }
+}
+
+fn decode_multi_array(
+    src: &[u8],
+    dst_size: usize,
+    num_arrays: usize,
+) -> Result<(Vec<Vec<u8>>, usize)> {
+    check(src.len() >= 4, "truncated block")?;
+    check(src[0] & 0x80 != 0, "invalid multi array")?;
+    let num_arrays_in_file = (src[0] & 0x3F) as usize;
+    let mut pos = 1;
+
+    let mut arrays = Vec::with_capacity(num_arrays);
+    if num_arrays_in_file == 0 {
+        let mut remaining = dst_size;
+        for _ in 0..num_arrays {
+            let (bytes, used) = decode_bytes(&src[pos..], remaining)?;
+            remaining -= bytes.len();
+            pos += used;
+            arrays.push(bytes);
+        }
+        return Ok((arrays, pos));
+    }
+
+    // decode all entropy arrays first
+    let mut entropy_arrays = Vec::with_capacity(num_arrays_in_file);
+    let mut total_size = 0;
+    for _ in 0..num_arrays_in_file {
+        let (bytes, used) = decode_bytes(&src[pos..], usize::MAX)?;
+        total_size += bytes.len();
+        pos += used;
+        entropy_arrays.push((bytes, 0));
+    }
+
+    check(src.len() - pos >= 3, "truncated block")?;
+    let q = le_bytes(src, pos, 2)?;
+    pos += 2;
+
+    let num_indexes = get_block_size(&src[pos..], total_size)?;
+    check(num_indexes >= num_arrays, "invalid multi array")?;
+
+    let interval_lenlog2;
+    let interval_indexes;
+    let num_lens;
+    if q & 0x8000 != 0 {
+        let (bytes, used) = decode_bytes(&src[pos..], num_indexes)?;
+        check(bytes.len() == num_indexes, "invalid multi array")?;
+        pos += used;
+        interval_lenlog2 = bytes.iter().map(|t| t >> 4).collect::<Vec<_>>();
+        interval_indexes = bytes.iter().map(|t| t & 0xF).collect::<Vec<_>>();
+        num_lens = num_indexes;
+    } else {
+        let lenlog2_chunksize = num_indexes - num_arrays;
+        let (bytes, used) = decode_bytes(&src[pos..], num_indexes)?;
+        check(bytes.len() == num_indexes, "invalid multi array")?;
+        pos += used;
+        interval_indexes = bytes;
+
+        let (bytes, used) = decode_bytes(&src[pos..], lenlog2_chunksize)?;
+        check(bytes.len() == lenlog2_chunksize, "invalid multi array")?;
+        check(bytes.iter().all(|b| *b <= 16), "invalid multi array")?;
+        pos += used;
+        interval_lenlog2 = bytes;
+        num_lens = lenlog2_chunksize;
+    }
+
+    // interval lengths, alternating from both ends of the stream
+    let varbits_complen = q & 0x3FFF;
+    let varbits = slice(src, pos, varbits_complen)?;
+    let mut bits_f = Bits::new(varbits);
+    let mut bits_b = Bits::backward(varbits);
+    let mut decoded_intervals = Vec::with_capacity(num_lens);
+    for (i, numbits) in interval_lenlog2[..num_lens].iter().enumerate() {
+        let numbits = *numbits as u32;
+        let bits = if i % 2 == 0 || i == num_lens - 1 && num_lens % 2 == 1 {
+            &mut bits_f
+        } else {
+            &mut bits_b
+        };
+        decoded_intervals.push(((1 << numbits) | bits.read(numbits)) as usize);
+    }
+
+    check(interval_indexes.last() == Some(&0), "invalid multi array")?;
+
+    let mut indi = 0;
+    let mut leni = 0;
+    let mut remaining = dst_size;
+    for _ in 0..num_arrays {
+        let mut array = vec![];
+        loop {
+            check(indi < num_indexes, "invalid multi array")?;
+            let source = interval_indexes[indi] as usize;
+            indi += 1;
+            if source == 0 {
+                break;
+            }
+            check(source <= num_arrays_in_file, "invalid multi array")?;
+            check(leni < num_lens, "invalid multi array")?;
+            let cur_len = decoded_intervals[leni];
+            leni += 1;
+
+            let (bytes, read) = &mut entropy_arrays[source - 1];
+            check(cur_len <= bytes.len() - *read, "invalid multi array")?;
+            check(cur_len <= remaining, "invalid multi array")?;
+            array.extend_from_slice(&bytes[*read..*read + cur_len]);
+            *read += cur_len;
+            remaining -= cur_len;
+        }
+        if q & 0x8000 != 0 {
+            leni += 1;
+        }
+        arrays.push(array);
+    }
+
+    check(indi == num_indexes, "invalid multi array")?;
+    check(leni == num_lens, "invalid multi array")?;
+    check(
+        entropy_arrays
+            .iter()
+            .all(|(bytes, read)| bytes.len() == *read),
+        "invalid multi array",
+    )?;
+
+    Ok((arrays, pos + varbits_complen))
+}
+
+fn decode_rle(src: &[u8], dst_size: usize) -> Result<Vec<u8>> {
+    check(!src.is_empty(), "truncated block")?;
+    if src.len() == 1 {
+        return Ok(vec![src[0]; dst_size]);
+    }
+
+    if src[0] != 0 {
+        // the first part of the command buffer is entropy coded
+        let (mut commands, used) = decode_bytes(src, usize::MAX)?;
+        commands.extend_from_slice(&src[used..]);
+        decode_rle_unpacked(&commands, dst_size)
+    } else {
+        decode_rle_unpacked(&src[1..], dst_size)
+    }
+}
+
+/// Literal bytes are read from the front of the buffer, commands from the back
+fn decode_rle_unpacked(src: &[u8], dst_size: usize) -> Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(dst_size);
+    let mut front = 0;
+    let mut back = src.len();
+    let mut rle_byte = 0;
+
+    while front < back {
+        let cmd = src[back - 1] as usize;
+        let (bytes_to_copy, bytes_to_rle) = if cmd == 0 || cmd > 0x2F {
+            back -= 1;
+            (!cmd & 0xF, cmd >> 4)
+        } else {
+            check(back - front >= 2, "truncated rle commands")?;
+            back -= 2;
+            let data = le_bytes(src, back, 2)?;
+            if cmd >= 0x10 {
+                let data = data - 4096;
+                (data & 0x3F, data >> 6)
+            } else if cmd == 1 {
+                rle_byte = src[front];
+                front += 1;
+                back += 1;
+                (0, 0)
+            } else if cmd >= 9 {
+                (0, (data - 0x8FF) * 128)
+            } else {
+                ((data - 511) * 64, 0)
+            }
+        };
+
+        check(bytes_to_copy <= back - front, "truncated rle literals")?;
+        check(
+            bytes_to_copy + bytes_to_rle <= dst_size - output.len(),
+            "rle out of bounds",
+        )?;
+        output.extend_from_slice(&src[front..front + bytes_to_copy]);
+        front += bytes_to_copy;
+        output.resize(output.len() + bytes_to_rle, rle_byte);
+    }
+
+    check(front == back, "rle size mismatch")?;
+    Ok(output)
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// HUFFMAN
+/////////////////////////////////////////////////////////////////////////////////////////
+
+/// First index of the symbols of each code length in the sorted symbol table
+const BASE_PREFIX: [usize; 12] = [
+    0x0, 0x0, 0x2, 0x6, 0xE, 0x1E, 0x3E, 0x7E, 0xFE, 0x1FE, 0x2FE, 0x3FE,
+];
+
+/// Symbols sorted by code length, canonical order
+struct HuffSymbols {
+    syms: [u8; 1280],
+    code_prefix: [usize; 12],
+}
+
+impl HuffSymbols {
+    fn new() -> Self {
+        Self {
+            syms: [0; 1280],
+            code_prefix: BASE_PREFIX,
+        }
+    }
+
+    fn push(&mut self, codelen: usize, sym: u8) -> Result<()> {
+        check((1..=11).contains(&codelen), "invalid code length")?;
+        let index = self.code_prefix[codelen];
+        check(index < self.syms.len(), "too many symbols")?;
+        self.syms[index] = sym;
+        self.code_prefix[codelen] += 1;
+        Ok(())
+    }
+}
+
+/// Maps 11 bit patterns (read lsb first) to code lengths and symbols
+struct HuffLut {
+    bits2len: [u8; 2048],
+    bits2sym: [u8; 2048],
+}
+
+impl HuffLut {
+    fn new(symbols: &HuffSymbols) -> Result<Self> {
+        let mut len_lut = [0; 2048];
+        let mut sym_lut = [0; 2048];
+
+        let mut currslot = 0;
+        for (i, start) in BASE_PREFIX.iter().copied().enumerate().take(11).skip(1) {
+            let count = symbols.code_prefix[i] - start;
+            if count != 0 {
+                let stepsize = 1 << (11 - i);
+                let num_to_set = count << (11 - i);
+                check(currslot + num_to_set <= 2048, "invalid huffman table")?;
+                len_lut[currslot..currslot + num_to_set].fill(i as u8);
+                for j in 0..count {
+                    let dst = currslot + stepsize * j;
+                    sym_lut[dst..dst + stepsize].fill(symbols.syms[start + j]);
+                }
+                currslot += num_to_set;
+            }
+        }
+        let count = symbols.code_prefix[11] - BASE_PREFIX[11];
+        if count != 0 {
+            check(currslot + count <= 2048, "invalid huffman table")?;
+            len_lut[currslot..currslot + count].fill(11);
+            sym_lut[currslot..currslot + count]
+                .copy_from_slice(&symbols.syms[BASE_PREFIX[11]..BASE_PREFIX[11] + count]);
+            currslot += count;
+        }
+        check(currslot == 2048, "invalid huffman table")?;
+
+        // the codes are stored msb first but read lsb first
+        let reverse = |i: usize| ((i as u16).reverse_bits() >> 5) as usize;
+        Ok(Self {
+            bits2len: std::array::from_fn(|i| len_lut[reverse(i)]),
+            bits2sym: std::array::from_fn(|i| sym_lut[reverse(i)]),
+        })
+    }
+
+    fn decode(&self, bits: &mut Bits<'_>) -> u8 {
+        let k = bits.peek_lsb(11) as usize;
+        bits.skip(self.bits2len[k] as u32);
+        self.bits2sym[k]
+    }
+}
+
+/// Decodes huffman coded bytes, split into one or two halves of three streams each
+fn decode_huffman(src: &[u8], dst_size: usize, halves: usize) -> Result<Vec<u8>> {
+    let mut bits = Bits::new(src);
+    let mut symbols = HuffSymbols::new();
+    let num_syms = if !bits.read_bit() {
+        read_code_lengths_old(&mut bits, &mut symbols)?
+    } else if !bits.read_bit() {
+        read_code_lengths_new(&mut bits, &mut symbols)?
+    } else {
+        return Err(corrupt("invalid huffman table"));
+    };
+    let mut pos = bits.byte_pos();
+
+    if num_syms == 1 {
+        return Ok(vec![symbols.syms[0]; dst_size]);
+    }
+
+    let lut = HuffLut::new(&symbols)?;
+    let mut output = Vec::with_capacity(dst_size);
+    if halves == 1 {
+        check(src.len() - pos >= 3, "truncated huffman streams")?;
+        let split = le_bytes(src, pos, 2)?;
+        pos += 2;
+        decode_huffman_streams(&src[pos..], split, dst_size, &lut, &mut output)?;
+    } else {
+        check(src.len() - pos >= 6, "truncated huffman streams")?;
+        let half_output_size = (dst_size + 1) >> 1;
+        let split_mid = le_bytes(src, pos, 3)?;
+        pos += 3;
+        check(split_mid <= src.len() - pos, "truncated huffman streams")?;
+        let src_mid = pos + split_mid;
+        let split_left = le_bytes(src, pos, 2)?;
+        pos += 2;
+        check(src.len() - src_mid >= 3, "truncated huffman streams")?;
+        let split_right = le_bytes(src, src_mid, 2)?;
+
+        decode_huffman_streams(
+            &src[pos..src_mid],
+            split_left,
+            half_output_size,
+            &lut,
+            &mut output,
+        )?;
+        decode_huffman_streams(
+            &src[src_mid + 2..],
+            split_right,
+            dst_size - half_output_size,
+            &lut,
+            &mut output,
+        )?;
+    }
+
+    Ok(output)
+}
+
+/// Decodes three interleaved streams: one forward from the start, one backward from the end
+/// and one forward from the split point
+fn decode_huffman_streams(
+    src: &[u8],
+    split: usize,
+    count: usize,
+    lut: &HuffLut,
+    output: &mut Vec<u8>,
+) -> Result<()> {
+    check(split <= src.len(), "invalid huffman split")?;
+    let mut bits = [
+        Bits::new(&src[..split]),
+        Bits::backward(&src[split..]),
+        Bits::new(&src[split..]),
+    ];
+    for i in 0..count {
+        output.push(lut.decode(&mut bits[i % 3]));
+    }
+
+    check(bits[0].byte_pos() == split, "huffman stream size mismatch")?;
+    check(
+        bits[1].byte_pos() + bits[2].byte_pos() == src.len() - split,
+        "huffman stream size mismatch",
+    )?;
+    Ok(())
+}
+
+fn read_code_lengths_old(bits: &mut Bits<'_>, symbols: &mut HuffSymbols) -> Result<usize> {
+    if bits.read_bit() {
+        let mut sym = 0;
+        let mut num_symbols = 0;
+        let mut avg_bits_x4 = 32;
+        let forced_bits = bits.read(2) as i32;
+        let thres_for_valid_gamma_bits = 1u32 << (31 - (20 >> forced_bits));
+
+        let mut skip_initial_zeros = bits.read_bit();
+        while sym != 256 {
+            if skip_initial_zeros {
+                skip_initial_zeros = false;
+            } else {
+                // run of zeros
+                check(bits.peek(8) != 0, "invalid huffman table")?;
+                let lz = bits.leading_zeros();
+                sym += bits.read(2 * (lz + 1)) as usize - 2 + 1;
+                if sym >= 256 {
+                    break;
+                }
+            }
+            // gamma value for the number of symbols
+            check(bits.peek(8) != 0, "invalid huffman table")?;
+            let lz = bits.leading_zeros();
+            let mut n = bits.read(2 * (lz + 1)) as usize - 2 + 1;
+            check(sym + n <= 256, "invalid huffman table")?;
+            num_symbols += n;
+            loop {
+                check(
+                    bits.peek(32) >= thres_for_valid_gamma_bits,
+                    "invalid huffman table",
+                )?;
+                let lz = bits.leading_zeros() as i32;
+                let v = bits.read((lz + forced_bits + 1) as u32) as i32 + ((lz - 1) << forced_bits);
+                let codelen = (-(v & 1) ^ (v >> 1)) + ((avg_bits_x4 + 2) >> 2);
+                check((1..=11).contains(&codelen), "invalid code length")?;
+                avg_bits_x4 = codelen + ((3 * avg_bits_x4 + 2) >> 2);
+                symbols.push(codelen as usize, sym as u8)?;
+                sym += 1;
+                n -= 1;
+                if n == 0 {
+                    break;
+                }
+            }
+        }
+        check(sym == 256 && num_symbols >= 2, "invalid huffman table")?;
+        Ok(num_symbols)
+    } else {
+        // sparse symbol encoding
+        let num_symbols = bits.read(8) as usize;
+        check(num_symbols != 0, "invalid huffman table")?;
+        if num_symbols == 1 {
+            symbols.syms[0] = bits.read(8) as u8;
+        } else {
+            let codelen_bits = bits.read(3);
+            check(codelen_bits <= 4, "invalid huffman table")?;
+            for _ in 0..num_symbols {
+                let sym = bits.read(8) as u8;
+                let codelen = bits.read(codelen_bits) as usize + 1;
+                symbols.push(codelen, sym)?;
+            }
+        }
+        Ok(num_symbols)
+    }
+}
+
+fn read_code_lengths_new(bits: &mut Bits<'_>, symbols: &mut HuffSymbols) -> Result<usize> {
+    let forced_bits = bits.read(2);
+    let num_symbols = bits.read(8) as usize + 1;
+    let fluff = bits.read_fluff(num_symbols);
+
+    let mut code_len = [0; 512];
+    decode_golomb_rice_lengths(bits, &mut code_len[..num_symbols + fluff])?;
+    decode_golomb_rice_bits(bits, &mut code_len[..num_symbols], forced_bits);
+
+    let mut running_sum = 0x1E;
+    for len in code_len[..num_symbols].iter_mut() {
+        let v = *len as i32;
+        let v = -(v & 1) ^ (v >> 1);
+        let codelen = v + (running_sum >> 2) + 1;
+        check((1..=11).contains(&codelen), "invalid code length")?;
+        *len = codelen as u8;
+        running_sum += v;
+    }
+
+    let ranges = convert_to_ranges(bits, num_symbols, fluff, &code_len)?;
+    let mut cp = 0;
+    for (symbol, num) in ranges {
+        for (i, codelen) in code_len[cp..cp + num].iter().enumerate() {
+            symbols.push(*codelen as usize, (symbol + i) as u8)?;
+        }
+        cp += num;
+    }
+
+    Ok(num_symbols)
+}
+
+/// Unary coded values: the number of zero bits before the next set bit
+fn decode_golomb_rice_lengths(bits: &mut Bits<'_>, dst: &mut [u8]) -> Result<()> {
+    for value in dst.iter_mut() {
+        let mut count = 0u8;
+        while !bits.read_bit() {
+            check(bits.bits_left() > 0, "truncated golomb rice lengths")?;
+            count = count.wrapping_add(1);
+        }
+        *value = count;
+    }
+    Ok(())
+}
+
+/// Appends `bitcount` low bits to each value
+fn decode_golomb_rice_bits(bits: &mut Bits<'_>, dst: &mut [u8], bitcount: u32) {
+    if bitcount == 0 {
+        return;
+    }
+    for value in dst.iter_mut() {
+        *value = (*value << bitcount).wrapping_add(bits.read(bitcount) as u8);
+    }
+}
+
+/// Converts the symbol ranges after the code lengths into (first symbol, count) pairs
+fn convert_to_ranges(
+    bits: &mut Bits<'_>,
+    num_symbols: usize,
+    fluff: usize,
+    values: &[u8],
+) -> Result<Vec<(usize, usize)>> {
+    let mut sym_idx = 0;
+    let mut next = num_symbols;
+
+    // start with space?
+    if fluff & 1 != 0 {
+        let v = values[next] as u32;
+        next += 1;
+        check(v < 8, "invalid symbol range")?;
+        sym_idx = (bits.read(v + 1) + (1 << (v + 1)) - 1) as usize;
+    }
+
+    let mut syms_used = 0;
+    let num_ranges = fluff >> 1;
+    let mut ranges = Vec::with_capacity(num_ranges + 1);
+    for _ in 0..num_ranges {
+        let v = values[next] as u32;
+        next += 1;
+        check(v < 9, "invalid symbol range")?;
+        let num = (bits.read(v) + (1 << v)) as usize;
+
+        let v = values[next] as u32;
+        next += 1;
+        check(v < 8, "invalid symbol range")?;
+        let space = (bits.read(v + 1) + (1 << (v + 1)) - 1) as usize;
+
+        ranges.push((sym_idx, num));
+        syms_used += num;
+        sym_idx += num + space;
+    }
+
+    check(sym_idx < 256, "invalid symbol range")?;
+    check(syms_used < num_symbols, "invalid symbol range")?;
+    check(
+        sym_idx + num_symbols - syms_used <= 256,
+        "invalid symbol range",
+    )?;
+    ranges.push((sym_idx, num_symbols - syms_used));
+
+    Ok(ranges)
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// TANS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+/// Symbols of a tANS table, `a` holds symbols with weight 1, `b` symbol << 16 | weight
+struct TansData {
+    a: Vec<u8>,
+    b: Vec<u32>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct TansLutEnt {
+    x: u32,
+    bits_x: u8,
+    symbol: u8,
+    w: u16,
+}
+
+fn decode_tans(src: &[u8], dst_size: usize) -> Result<Vec<u8>> {
+    check(src.len() >= 8 && dst_size >= 5, "truncated tans block")?;
+
+    let mut bits = Bits::new(src);
+    check(!bits.read_bit(), "reserved bit set")?;
+    let l_bits = bits.read(2) + 8;
+    let tans_data = decode_tans_table(&mut bits, l_bits)?;
+    let pos = bits.byte_pos();
+    check(pos < src.len(), "truncated tans block")?;
+    let lut = init_tans_lut(&tans_data, l_bits);
+
+    // two streams, one forward and one backward
+    let streams = &src[pos..];
+    let mut bits_f = Bits::new(streams);
+    let mut bits_b = Bits::backward(streams);
+    let mut state = [0; 5];
+    state[0] = bits_f.read_lsb(l_bits) as usize;
+    state[1] = bits_b.read_lsb(l_bits) as usize;
+    state[2] = bits_f.read_lsb(l_bits) as usize;
+    state[3] = bits_b.read_lsb(l_bits) as usize;
+    state[4] = bits_f.read_lsb(l_bits) as usize;
+
+    let mut output = Vec::with_capacity(dst_size);
+    let count = dst_size - 5;
+    'decode: loop {
+        for bits in [&mut bits_f, &mut bits_b] {
+            for s in state.iter_mut() {
+                if output.len() == count {
+                    break 'decode;
+                }
+                let e = lut.get(*s).ok_or_else(|| corrupt("invalid tans state"))?;
+                output.push(e.symbol);
+                *s = (bits.read_lsb(e.bits_x as u32) & e.x) as usize + e.w as usize;
+            }
+        }
+    }
+
+    check(
+        bits_f.byte_pos() + bits_b.byte_pos() == streams.len(),
+        "tans stream size mismatch",
+    )?;
+    check(state.iter().all(|s| *s < 256), "invalid tans final state")?;
+    output.extend(state.iter().map(|s| *s as u8));
+
+    Ok(output)
+}
+
+fn decode_tans_table(bits: &mut Bits<'_>, l_bits: u32) -> Result<TansData> {
+    let l = 1i32 << l_bits;
+    let mut a = Vec::with_capacity(256);
+    let mut b = Vec::with_capacity(256);
+
+    if bits.read_bit() {
+        let q = bits.read(3) as i32;
+        let num_symbols = bits.read(8) as usize + 1;
+        check(num_symbols >= 2, "invalid tans table")?;
+        let fluff = bits.read_fluff(num_symbols);
+        let total_rice_values = num_symbols + fluff;
+        let mut rice = [0; 512 + 16];
+        decode_golomb_rice_lengths(bits, &mut rice[..total_rice_values])?;
+
+        let ranges = convert_to_ranges(bits, num_symbols, fluff, &rice)?;
+
+        let mut rice_values = rice.iter();
+        let mut average = 6;
+        let mut somesum = 0;
+        for (first, num) in ranges {
+            for symbol in first..first + num {
+                let nextra = *rice_values.next().unwrap_or(&0) as i32 + q;
+                check(nextra <= 15, "invalid tans table")?;
+                let mut v = bits.read(nextra as u32) as i32 + (1 << nextra) - (1 << q);
+
+                let average_div4 = average >> 2;
+                let mut limit = 2 * average_div4;
+                if v <= limit {
+                    v = average_div4 + (((v as u32 >> 1) as i32) ^ -(v & 1));
+                }
+                if limit > v {
+                    limit = v;
+                }
+                v += 1;
+                average += limit - average_div4;
+                if v == 1 {
+                    a.push(symbol as u8);
+                } else if v >= 2 {
+                    b.push(((symbol as u32) << 16) + v as u32);
+                }
+                somesum += v;
+            }
+        }
+        check(somesum == l, "invalid tans table")?;
+    } else {
+        let mut seen = [false; 256];
+        let count = bits.read(3) + 1;
+        let bits_per_sym = l_bits.ilog2() + 1;
+        let max_delta_bits = bits.read(bits_per_sym);
+        check(
+            max_delta_bits != 0 && max_delta_bits <= l_bits,
+            "invalid tans table",
+        )?;
+
+        let mut weight = 0;
+        let mut total_weights = 0;
+        for _ in 0..count {
+            let sym = bits.read(8) as usize;
+            check(!seen[sym], "invalid tans table")?;
+            seen[sym] = true;
+
+            weight += bits.read(max_delta_bits) as i32;
+            check(weight != 0, "invalid tans table")?;
+            if weight == 1 {
+                a.push(sym as u8);
+            } else {
+                b.push(((sym as u32) << 16) + weight as u32);
+            }
+            total_weights += weight;
+        }
+
+        let sym = bits.read(8) as usize;
+        check(!seen[sym], "invalid tans table")?;
+        check(
+            l - total_weights >= weight && l - total_weights > 1,
+            "invalid tans table",
+        )?;
+        b.push(((sym as u32) << 16) + (l - total_weights) as u32);
+
+        a.sort_unstable();
+        b.sort_unstable();
+    }
+
+    Ok(TansData { a, b })
+}
+
+fn init_tans_lut(tans_data: &TansData, l_bits: u32) -> Vec<TansLutEnt> {
+    let l_bits = l_bits as i32;
+    let l = 1i32 << l_bits;
+    let len = l as usize;
+    let a_used = tans_data.a.len();
+    let slots_left_to_alloc = len - a_used;
+
+    // the remaining slots are spread over four interleaved pointers
+    let mut pointers = [0; 4];
+    let sa = slots_left_to_alloc >> 2;
+    let mut sb = sa;
+    if (slots_left_to_alloc & 3) > 0 {
+        sb += 1;
+    }
+    pointers[1] = sb;
+    sb += sa;
+    if (slots_left_to_alloc & 3) > 1 {
+        sb += 1;
+    }
+    pointers[2] = sb;
+    sb += sa;
+    if (slots_left_to_alloc & 3) > 2 {
+        sb += 1;
+    }
+    pointers[3] = sb;
+
+    let mut lut = vec![TansLutEnt::default(); len];
+
+    // entries with weight 1
+    for (i, symbol) in tans_data.a.iter().enumerate() {
+        lut[slots_left_to_alloc + i] = TansLutEnt {
+            x: (1 << l_bits) - 1,
+            bits_x: l_bits as u8,
+            symbol: *symbol,
+            w: 0,
+        };
+    }
+
+    // entries with weight >= 2
+    let mut weights_sum = 0;
+    for entry in tans_data.b.iter() {
+        let weight = (entry & 0xFFFF) as i32;
+        let symbol = (entry >> 16) as u8;
+        if weight > 4 {
+            let sym_bits = weight.ilog2() as i32;
+            let mut z = l_bits - sym_bits;
+            let mut le = TansLutEnt {
+                x: (1 << z) - 1,
+                bits_x: z as u8,
+                symbol,
+                w: ((l - 1) & (weight << z)) as u16,
+            };
+            let mut what_to_add = 1 << z;
+            let mut x = (1 << (sym_bits + 1)) - weight;
+
+            for (j, pointer) in pointers.iter_mut().enumerate() {
+                let mut dst = *pointer;
+                let y = (weight + ((weights_sum - j as i32 - 1) & 3)) >> 2;
+                if x >= y {
+                    for _ in 0..y {
+                        lut[dst] = le;
+                        dst += 1;
+                        le.w += what_to_add;
+                    }
+                    x -= y;
+                } else {
+                    for _ in 0..x {
+                        lut[dst] = le;
+                        dst += 1;
+                        le.w += what_to_add;
+                    }
+                    z -= 1;
+                    what_to_add >>= 1;
+                    le.bits_x = z as u8;
+                    le.w = 0;
+                    le.x >>= 1;
+                    for _ in 0..y - x {
+                        lut[dst] = le;
+                        dst += 1;
+                        le.w += what_to_add;
+                    }
+                    x = weight;
+                }
+                *pointer = dst;
+            }
+        } else {
+            let mut bits: u32 = ((1 << weight) - 1) << (weights_sum & 3);
+            bits |= bits >> 4;
+            for ww in weight..2 * weight {
+                let idx = bits.trailing_zeros() as usize;
+                bits &= bits - 1;
+                let dst = pointers[idx];
+                pointers[idx] += 1;
+                let weight_bits = ww.ilog2() as i32;
+                lut[dst] = TansLutEnt {
+                    x: (1 << (l_bits - weight_bits)) - 1,
+                    bits_x: (l_bits - weight_bits) as u8,
+                    symbol,
+                    w: ((l - 1) & (ww << (l_bits - weight_bits))) as u16,
+                };
+            }
+        }
+        weights_sum += weight;
+    }
+
+    lut
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// HELPERS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+/// Bit reader over a byte range, either from the start or backwards from the end.
+///
+/// Reading past the range yields zero bits.
+struct Bits<'a> {
+    data: &'a [u8],
+    /// Position in bits
+    pos: usize,
+    backward: bool,
+}
+
+impl<'a> Bits<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            backward: false,
+        }
+    }
+
+    fn backward(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            backward: true,
+        }
+    }
+
+    fn byte(&self, i: usize) -> u8 {
+        if i >= self.data.len() {
+            0
+        } else if self.backward {
+            self.data[self.data.len() - 1 - i]
+        } else {
+            self.data[i]
+        }
+    }
+
+    /// Peeks up to 32 bits, most significant bit first
+    fn peek(&self, n: u32) -> u32 {
+        if n == 0 {
+            return 0;
+        }
+        let i = self.pos >> 3;
+        let v = (0..5).fold(0u64, |v, k| (v << 8) | self.byte(i + k) as u64);
+        let v = v << (24 + (self.pos & 7));
+        (v >> (64 - n)) as u32
+    }
+
+    /// Reads up to 32 bits, most significant bit first
+    fn read(&mut self, n: u32) -> u32 {
+        let v = self.peek(n);
+        self.skip(n);
+        v
+    }
+
+    fn read_bit(&mut self) -> bool {
+        self.read(1) != 0
+    }
+
+    /// Peeks up to 24 bits, least significant bit first
+    fn peek_lsb(&self, n: u32) -> u32 {
+        let i = self.pos >> 3;
+        let v = (0..4).fold(0u32, |v, k| v | (self.byte(i + k) as u32) << (8 * k));
+        (v >> (self.pos & 7)) & ((1 << n) - 1)
+    }
+
+    /// Reads up to 24 bits, least significant bit first
+    fn read_lsb(&mut self, n: u32) -> u32 {
+        let v = self.peek_lsb(n);
+        self.skip(n);
+        v
+    }
+
+    fn skip(&mut self, n: u32) {
+        self.pos += n as usize;
+    }
+
+    fn leading_zeros(&self) -> u32 {
+        self.peek(32).leading_zeros()
+    }
+
+    fn bits_left(&self) -> isize {
+        (self.data.len() * 8) as isize - self.pos as isize
+    }
+
+    /// Number of bytes touched so far
+    fn byte_pos(&self) -> usize {
+        (self.pos + 7) >> 3
+    }
+
+    /// Reads an offset code parametrized by `v`
+    fn read_distance(&mut self, v: u32) -> u32 {
+        if v < 0xF0 {
+            let n = (v >> 4) + 4;
+            let w = (1 << n) | self.read(n);
+            ((w << 4) + (v & 0xF)).wrapping_sub(248)
+        } else {
+            let n = v - 0xF0 + 4;
+            let w = (1u32 << n) | self.read(n);
+            8322816u32
+                .wrapping_add(w.wrapping_shl(12))
+                .wrapping_add(self.read(12))
+        }
+    }
+
+    /// Reads a length code
+    fn read_length(&mut self) -> Result<u32> {
+        let n = self.leading_zeros();
+        check(n <= 12, "invalid length")?;
+        self.skip(n);
+        Ok(self.read(n + 7) - 64)
+    }
+
+    /// Reads a truncated binary number of extra symbol range values
+    fn read_fluff(&mut self, num_symbols: usize) -> usize {
+        if num_symbols == 256 {
+            return 0;
+        }
+        let x = (257 - num_symbols).min(num_symbols) as u32 * 2;
+        let y = (x - 1).ilog2() + 1;
+        let v = self.peek(y);
+        let z = (1 << y) - x;
+        if (v >> 1) >= z {
+            self.skip(y);
+            (v - z) as usize
+        } else {
+            self.skip(y - 1);
+            (v >> 1) as usize
+        }
+    }
+}
+
+fn corrupt(msg: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("corrupt kraken stream: {}", msg),
+    )
+}
+
+fn check(condition: bool, msg: &str) -> Result<()> {
+    if condition {
+        Ok(())
+    } else {
+        Err(corrupt(msg))
+    }
+}
+
+fn slice(data: &[u8], pos: usize, len: usize) -> Result<&[u8]> {
+    data.get(pos..pos.saturating_add(len))
+        .ok_or_else(|| corrupt("unexpected end of stream"))
+}
+
+fn be_bytes(data: &[u8], pos: usize, len: usize) -> Result<usize> {
+    Ok(slice(data, pos, len)?
+        .iter()
+        .fold(0, |v, b| (v << 8) | *b as usize))
+}
+
+fn le_bytes(data: &[u8], pos: usize, len: usize) -> Result<usize> {
+    Ok(slice(data, pos, len)?
+        .iter()
+        .rev()
+        .fold(0, |v, b| (v << 8) | *b as usize))
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// TESTS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+    use crate::kraken::MAGIC;
+
+    /// Finds all KARK segments in an archive and returns their uncompressed size and stream
+    fn kark_segments(buffer: &[u8]) -> Vec<(usize, &[u8])> {
+        let magic = MAGIC.to_le_bytes();
+        (0..buffer.len().saturating_sub(8))
+            .filter(|i| buffer[*i..*i + 4] == magic && buffer[*i + 8..].starts_with(&[0x8C, 0x06]))
+            .map(|i| {
+                let size = u32::from_le_bytes(buffer[i + 4..i + 8].try_into().unwrap());
+                (size as usize, &buffer[i + 8..])
+            })
+            .collect::<Vec<_>>()
+    }
+
+    fn test_archives() -> Vec<Vec<u8>> {
+        ["test1.archive", "nci.archive"]
+            .iter()
+            .map(|name| fs::read(PathBuf::from("tests").join(name)).unwrap())
+            .collect::<Vec<_>>()
+    }
+
+    #[test]
+    fn decompress_archives() {
+        for buffer in test_archives() {
+            let segments = kark_segments(&buffer);
+            assert!(!segments.is_empty());
+
+            for (size, src) in segments {
+                let (output, used) = decode_stream(src, size).unwrap();
+                assert_eq!(size, output.len());
+                assert!(used <= src.len());
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "native-kraken")]
+    fn decompress_like_native() {
+        for buffer in test_archives() {
+            for (size, src) in kark_segments(&buffer) {
+                let (output, used) = decode_stream(src, size).unwrap();
+
+                // the native library only accepts the exact stream
+                let expected = super::super::native::decompress(&src[..used], size).unwrap();
+                assert_eq!(expected, output);
+            }
+        }
+    }
+
+    #[test]
+    fn decompress_invalid() {
+        assert!(decompress(&[0x8C, 0x06, 0xFF, 0xFF, 0xFF], 16).is_err());
+        assert!(decompress(&[0x8C, 0x07, 0x00], 16).is_err());
+        assert!(decompress(&[], 16).is_err());
+
+        // uncompressed block
+        let mut stored = vec![0xCC, 0x06];
+        stored.extend_from_slice(b"0123456789");
+        assert_eq!(b"0123456789".to_vec(), decompress(&stored, 10).unwrap());
+    }
+}
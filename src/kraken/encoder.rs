@@ -0,0 +1,753 @@
+/////////////////////////////////////////////////////////////////////////////////////////
+// KRAKEN ENCODER
+// Pure Rust encoder for Oodle Kraken streams, the counterpart of the decoder.
+//
+// block:   2 byte block header, then a memset, stored or compressed quantum of up to 256k
+// chunk:   up to 128k, an lz chunk with raw literals or stored bytes
+// streams: memcpy blocks or huffman coded blocks with gamma coded code lengths
+/////////////////////////////////////////////////////////////////////////////////////////
+
+use std::ops::Range;
+
+use super::CompressionLevel;
+
+const BLOCK_SIZE: usize = 0x40000;
+const CHUNK_SIZE: usize = 0x20000;
+const DECODER_KRAKEN: u8 = 6;
+
+/// Offsets below 8 are not supported by the lz copy loop
+const MIN_DISTANCE: usize = 8;
+/// Keeps every offset in the short distance code
+const MAX_DISTANCE: usize = 1 << 22;
+const MIN_MATCH: usize = 4;
+const MIN_REP_MATCH: usize = 3;
+/// Streams shorter than this are not worth a huffman table
+const MIN_HUFFMAN_SIZE: usize = 32;
+const MAX_CODE_LENGTH: usize = 11;
+/// Hash tables hold 32 bit positions, larger inputs are matched in windows of this size
+const WINDOW_SIZE: usize = 1 << 30;
+
+/// Encoder settings of a compression level
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    /// Number of candidates checked per position
+    max_chain: usize,
+    /// Checks the next position for a longer match before emitting one
+    lazy: bool,
+    /// Huffman codes the literal and command streams
+    huffman: bool,
+}
+
+impl Settings {
+    fn new(level: CompressionLevel) -> Option<Self> {
+        match level {
+            CompressionLevel::None => None,
+            CompressionLevel::SuperFast | CompressionLevel::VeryFast | CompressionLevel::Fast => {
+                Some(Self {
+                    max_chain: 1,
+                    lazy: false,
+                    huffman: false,
+                })
+            }
+            CompressionLevel::Normal => Some(Self {
+                max_chain: 16,
+                lazy: true,
+                huffman: true,
+            }),
+            _ => Some(Self {
+                max_chain: 64,
+                lazy: true,
+                huffman: true,
+            }),
+        }
+    }
+}
+
+/// Compresses a buffer into a Kraken stream.
+///
+/// Blocks that don't compress are stored, so the stream can be larger than the input.
+pub(super) fn compress(src: &[u8], level: CompressionLevel) -> Vec<u8> {
+    compress_windowed(src, level, WINDOW_SIZE)
+}
+
+fn compress_windowed(src: &[u8], level: CompressionLevel, window_size: usize) -> Vec<u8> {
+    let settings = Settings::new(level);
+    let mut matcher = None;
+    let mut dst = Vec::with_capacity(src.len() / 2 + 16);
+
+    let mut offset = 0;
+    while offset < src.len() {
+        if offset % window_size == 0 {
+            // matches don't reach back into the previous window
+            let end = (offset + window_size).min(src.len());
+            matcher = settings.map(|s| Matcher::new(src, offset..end, s.max_chain));
+        }
+        let count = (src.len() - offset).min(BLOCK_SIZE);
+        let block = &src[offset..offset + count];
+        // the first block resets the decoder, later blocks keep the history
+        let flags = if offset == 0 { 0x8C } else { 0x0C };
+
+        let memset = block.iter().all(|b| *b == block[0]);
+        let quantum = match (&settings, &mut matcher) {
+            (Some(settings), Some(matcher)) if !memset => {
+                Some(encode_quantum(src, offset, count, settings, matcher))
+            }
+            _ => None,
+        };
+
+        if memset {
+            dst.extend_from_slice(&[flags, DECODER_KRAKEN, 0x07, 0xFF, 0xFF, block[0]]);
+        } else if let Some(quantum) = quantum.filter(|q| q.len() < count) {
+            dst.extend_from_slice(&[flags, DECODER_KRAKEN]);
+            dst.extend_from_slice(&be24(quantum.len() - 1));
+            dst.extend_from_slice(&quantum);
+        } else {
+            // uncompressed block
+            dst.extend_from_slice(&[flags | 0x40, DECODER_KRAKEN]);
+            dst.extend_from_slice(block);
+        }
+
+        offset += count;
+    }
+
+    dst
+}
+
+fn encode_quantum(
+    src: &[u8],
+    offset: usize,
+    count: usize,
+    settings: &Settings,
+    matcher: &mut Matcher<'_>,
+) -> Vec<u8> {
+    let mut quantum = Vec::with_capacity(count);
+    let end = offset + count;
+    let mut start = offset;
+    while start < end {
+        let chunk_end = (start + CHUNK_SIZE).min(end);
+        let dst_count = chunk_end - start;
+
+        // the first 8 bytes of a stream are stored in front of the lz table
+        let table = (start > 0 || dst_count >= 16)
+            .then(|| encode_lz(src, start, chunk_end, settings, matcher))
+            .filter(|t| t.len() < dst_count);
+        match table {
+            Some(table) => {
+                // mode 1: raw literals
+                quantum.extend_from_slice(&be24(0x800000 | (1 << 19) | table.len()));
+                quantum.extend_from_slice(&table);
+            }
+            None => {
+                quantum.extend_from_slice(&be24(0x800000 | dst_count));
+                quantum.extend_from_slice(&src[start..chunk_end]);
+            }
+        }
+
+        start = chunk_end;
+    }
+
+    quantum
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// LZ
+/////////////////////////////////////////////////////////////////////////////////////////
+
+/// Finds matches with hash chains over a window of the input
+struct Matcher<'a> {
+    src: &'a [u8],
+    window: Range<usize>,
+    /// Positions relative to the start of the window
+    head: Vec<u32>,
+    prev: Vec<u32>,
+    max_chain: usize,
+    /// Next position to add to the hash table
+    inserted: usize,
+}
+
+impl<'a> Matcher<'a> {
+    const HASH_BITS: u32 = 16;
+    const EMPTY: u32 = u32::MAX;
+
+    fn new(src: &'a [u8], window: Range<usize>, max_chain: usize) -> Self {
+        Self {
+            src,
+            head: vec![Self::EMPTY; 1 << Self::HASH_BITS],
+            prev: if max_chain > 1 {
+                vec![Self::EMPTY; window.len()]
+            } else {
+                vec![]
+            },
+            max_chain,
+            inserted: window.start,
+            window,
+        }
+    }
+
+    fn hash(&self, p: usize) -> usize {
+        let v = u32::from_le_bytes([
+            self.src[p],
+            self.src[p + 1],
+            self.src[p + 2],
+            self.src[p + 3],
+        ]);
+        (v.wrapping_mul(0x9E3779B1) >> (32 - Self::HASH_BITS)) as usize
+    }
+
+    /// Adds all positions before `p` to the hash table
+    fn update(&mut self, p: usize) {
+        let last = p.min(self.window.end).min(self.src.len().saturating_sub(3));
+        while self.inserted < last {
+            let h = self.hash(self.inserted);
+            let pos = self.inserted - self.window.start;
+            if !self.prev.is_empty() {
+                self.prev[pos] = self.head[h];
+            }
+            self.head[h] = pos as u32;
+            self.inserted += 1;
+        }
+        self.inserted = self.inserted.max(p);
+    }
+
+    /// Finds the longest match at `p` that ends before `end` and returns its length and distance
+    fn find(&mut self, p: usize, end: usize) -> Option<(usize, usize)> {
+        self.update(p);
+        if p + 4 > self.src.len() || end - p < MIN_MATCH {
+            return None;
+        }
+
+        let mut best = None;
+        let mut best_len = MIN_MATCH - 1;
+        let mut candidate = self.head[self.hash(p)];
+        for _ in 0..self.max_chain {
+            if candidate == Self::EMPTY {
+                break;
+            }
+            let c = self.window.start + candidate as usize;
+            let distance = p - c;
+            if distance > MAX_DISTANCE {
+                break;
+            }
+            if distance >= MIN_DISTANCE {
+                let len = match_len(self.src, c, p, end);
+                if len > best_len {
+                    best_len = len;
+                    best = Some((len, distance));
+                    if p + len == end {
+                        break;
+                    }
+                }
+            }
+            if self.prev.is_empty() {
+                break;
+            }
+            candidate = self.prev[c - self.window.start];
+        }
+
+        best
+    }
+}
+
+fn match_len(src: &[u8], from: usize, p: usize, end: usize) -> usize {
+    src[p..end]
+        .iter()
+        .zip(&src[from..])
+        .take_while(|(a, b)| a == b)
+        .count()
+}
+
+/// The streams of an lz chunk
+#[derive(Default)]
+struct LzStreams {
+    literals: Vec<u8>,
+    commands: Vec<u8>,
+    /// Packed offsets, the low bits go to the bit streams
+    offsets: Vec<u8>,
+    offset_bits: Vec<(u32, u32)>,
+    /// Packed lengths, larger values go to the bit streams
+    lengths: Vec<u8>,
+    long_lengths: Vec<u32>,
+}
+
+impl LzStreams {
+    fn push_length(&mut self, value: usize) {
+        let v = value - 3;
+        if v >= 255 {
+            self.lengths.push(255);
+            self.long_lengths.push((v - 255) as u32);
+        } else {
+            self.lengths.push(v as u8);
+        }
+    }
+
+    /// Adds a command: literals, then a copy of `matchlen` bytes with a recent (0..3) or new (3) offset
+    fn push_command(&mut self, literals: &[u8], matchlen: usize, offset_index: usize) {
+        self.literals.extend_from_slice(literals);
+        let litlen = if literals.len() >= 3 {
+            self.push_length(literals.len());
+            3
+        } else {
+            literals.len()
+        };
+        let matchlen = if matchlen >= 17 {
+            // the length stream is read after the literal length
+            self.push_length(matchlen - 14);
+            15
+        } else {
+            matchlen - 2
+        };
+        self.commands
+            .push((offset_index << 6 | matchlen << 2 | litlen) as u8);
+    }
+
+    fn push_offset(&mut self, distance: usize) {
+        // short distance code: ((1 << n | bits) << 4 | low) - 248
+        let t = (distance + 248) as u32;
+        let w = t >> 4;
+        let n = 31 - w.leading_zeros();
+        self.offsets.push(((n - 4) << 4 | (t & 0xF)) as u8);
+        self.offset_bits.push((w & ((1 << n) - 1), n));
+    }
+}
+
+fn encode_lz(
+    src: &[u8],
+    start: usize,
+    end: usize,
+    settings: &Settings,
+    matcher: &mut Matcher<'_>,
+) -> Vec<u8> {
+    let mut streams = LzStreams::default();
+    let mut p = if start == 0 { 8 } else { start };
+    let mut lit_start = p;
+    let mut recent = [MIN_DISTANCE; 3];
+
+    while p + MIN_REP_MATCH <= end {
+        let Some((len, index, distance)) = find_match(src, p, end, &recent, matcher) else {
+            p += 1;
+            continue;
+        };
+
+        if settings.lazy {
+            if let Some((next_len, ..)) = find_match(src, p + 1, end, &recent, matcher) {
+                if next_len > len + 1 {
+                    p += 1;
+                    continue;
+                }
+            }
+        }
+
+        streams.push_command(&src[lit_start..p], len, index);
+        if index == 3 {
+            streams.push_offset(distance);
+        }
+        // move to front
+        recent.copy_within(0..index.min(2), 1);
+        recent[0] = distance;
+
+        p += len;
+        lit_start = p;
+    }
+    matcher.update(end);
+    streams.literals.extend_from_slice(&src[lit_start..end]);
+
+    write_lz_table(src, start, &streams, settings)
+}
+
+/// Finds a match at `p` and returns its length, offset index and distance
+fn find_match(
+    src: &[u8],
+    p: usize,
+    end: usize,
+    recent: &[usize; 3],
+    matcher: &mut Matcher<'_>,
+) -> Option<(usize, usize, usize)> {
+    let mut rep: Option<(usize, usize, usize)> = None;
+    for (i, distance) in recent.iter().enumerate() {
+        if *distance <= p {
+            let len = match_len(src, p - distance, p, end);
+            if len >= MIN_REP_MATCH && rep.is_none_or(|(l, ..)| len > l) {
+                rep = Some((len, i, *distance));
+            }
+        }
+    }
+
+    match (rep, matcher.find(p, end)) {
+        (Some(rep), Some((len, _))) if rep.0 + 1 >= len => Some(rep),
+        (_, Some((len, distance))) => Some((len, 3, distance)),
+        (rep, None) => rep,
+    }
+}
+
+fn write_lz_table(src: &[u8], start: usize, streams: &LzStreams, settings: &Settings) -> Vec<u8> {
+    let mut table = vec![];
+    if start == 0 {
+        table.extend_from_slice(&src[..8]);
+    }
+    table.extend(encode_bytes(&streams.literals, settings.huffman));
+    table.extend(encode_bytes(&streams.commands, settings.huffman));
+    table.extend(encode_bytes(&streams.offsets, settings.huffman));
+    table.extend(encode_bytes(&streams.lengths, settings.huffman));
+
+    // bit streams, one read forward and one backward
+    let mut forward = MsbWriter::default();
+    let mut backward = MsbWriter::default();
+    let count = streams.long_lengths.len() as u32 + 1;
+    let n = 31 - count.leading_zeros();
+    backward.write(0, n);
+    backward.write(count, n + 1);
+    for (i, (bits, n)) in streams.offset_bits.iter().enumerate() {
+        let writer = if i % 2 == 0 {
+            &mut forward
+        } else {
+            &mut backward
+        };
+        writer.write(*bits, *n);
+    }
+    for (i, value) in streams.long_lengths.iter().enumerate() {
+        let writer = if i % 2 == 0 {
+            &mut forward
+        } else {
+            &mut backward
+        };
+        let x = value + 64;
+        let lz = 31 - x.leading_zeros() - 6;
+        writer.write(0, lz);
+        writer.write(x, lz + 7);
+    }
+    table.extend(forward.finish());
+    table.extend(backward.finish().into_iter().rev());
+
+    table
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// ENTROPY CODED BYTES
+/////////////////////////////////////////////////////////////////////////////////////////
+
+/// Encodes a stream as a memcpy block or a huffman block if that is smaller
+fn encode_bytes(data: &[u8], huffman: bool) -> Vec<u8> {
+    let mut block = Vec::with_capacity(data.len() + 3);
+    // the long header keeps the first byte below 0x80
+    block.extend_from_slice(&be24(data.len()));
+    block.extend_from_slice(data);
+
+    if huffman && data.len() >= MIN_HUFFMAN_SIZE {
+        let payload = encode_huffman(data);
+        if payload.len() + 5 < block.len() {
+            let dst_size = data.len() - 1;
+            let bits = ((dst_size as u32 & 0x3FFF) << 18) | payload.len() as u32;
+            block.clear();
+            block.push(0x20 | (dst_size >> 14) as u8);
+            block.extend_from_slice(&bits.to_be_bytes());
+            block.extend_from_slice(&payload);
+        }
+    }
+
+    block
+}
+
+/// Huffman codes a stream into three interleaved streams
+fn encode_huffman(data: &[u8]) -> Vec<u8> {
+    let mut counts = [0usize; 256];
+    for b in data {
+        counts[*b as usize] += 1;
+    }
+    let lengths = code_lengths(&counts);
+
+    let mut header = MsbWriter::default();
+    header.write(0, 1); // old code lengths
+    if lengths.iter().filter(|l| **l != 0).count() == 1 {
+        // sparse table with a single symbol
+        header.write(0, 1);
+        header.write(1, 8);
+        header.write(data[0] as u32, 8);
+        return header.finish();
+    }
+    write_code_lengths(&mut header, &lengths);
+
+    // canonical codes, reversed to be written lsb first
+    let mut codes = [0u32; 256];
+    let mut slot = 0u32;
+    for len in 1..=MAX_CODE_LENGTH {
+        for sym in 0..256 {
+            if lengths[sym] as usize == len {
+                let code = slot >> (MAX_CODE_LENGTH - len);
+                codes[sym] = code.reverse_bits() >> (32 - len);
+                slot += 1 << (MAX_CODE_LENGTH - len);
+            }
+        }
+    }
+
+    let mut streams = [
+        LsbWriter::default(),
+        LsbWriter::default(),
+        LsbWriter::default(),
+    ];
+    for (i, b) in data.iter().enumerate() {
+        streams[i % 3].write(codes[*b as usize], lengths[*b as usize] as u32);
+    }
+    let [s1, s2, s3] = streams.map(|s| s.finish());
+
+    let mut payload = header.finish();
+    payload.extend_from_slice(&(s1.len() as u16).to_le_bytes());
+    payload.extend(s1);
+    payload.extend(s3);
+    payload.extend(s2.into_iter().rev());
+    payload
+}
+
+/// Writes code lengths in the gamma coded form
+fn write_code_lengths(writer: &mut MsbWriter, lengths: &[u8; 256]) {
+    let write_gamma = |writer: &mut MsbWriter, value: u32| {
+        let n = 31 - value.leading_zeros() - 1;
+        writer.write(0, n);
+        writer.write(value, n + 2);
+    };
+
+    writer.write(1, 1);
+    writer.write(0, 2); // forced bits
+    let mut skip_initial_zeros = lengths[0] != 0;
+    writer.write(skip_initial_zeros as u32, 1);
+
+    let mut sym = 0;
+    let mut avg_bits_x4 = 32i32;
+    while sym != 256 {
+        if skip_initial_zeros {
+            skip_initial_zeros = false;
+        } else {
+            let zeros = lengths[sym..].iter().take_while(|l| **l == 0).count();
+            write_gamma(writer, zeros as u32 + 1);
+            sym += zeros;
+            if sym >= 256 {
+                break;
+            }
+        }
+        let n = lengths[sym..].iter().take_while(|l| **l != 0).count();
+        write_gamma(writer, n as u32 + 1);
+        for codelen in &lengths[sym..sym + n] {
+            let codelen = *codelen as i32;
+            let delta = codelen - ((avg_bits_x4 + 2) >> 2);
+            let v = if delta >= 0 {
+                2 * delta
+            } else {
+                -2 * delta - 1
+            };
+            writer.write(0, v as u32);
+            writer.write(1, 1);
+            avg_bits_x4 = codelen + ((3 * avg_bits_x4 + 2) >> 2);
+        }
+        sym += n;
+    }
+}
+
+/// Builds a complete prefix code with at most 11 bits per symbol
+fn code_lengths(counts: &[usize; 256]) -> [u8; 256] {
+    let mut lengths = [0u8; 256];
+    let mut nodes = counts
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| **c > 0)
+        .map(|(sym, c)| (*c, vec![sym]))
+        .collect::<Vec<_>>();
+    if nodes.len() < 2 {
+        if let Some((_, syms)) = nodes.first() {
+            lengths[syms[0]] = 1;
+        }
+        return lengths;
+    }
+
+    // huffman tree, every merge adds one bit to the symbols below
+    while nodes.len() > 1 {
+        nodes.sort_by_key(|n| std::cmp::Reverse(n.0));
+        let (c1, s1) = nodes.pop().unwrap();
+        let (c2, s2) = nodes.pop().unwrap();
+        for sym in s1.iter().chain(&s2) {
+            lengths[*sym] += 1;
+        }
+        nodes.push((c1 + c2, [s1, s2].concat()));
+    }
+
+    // limit the lengths and make the code complete again
+    const TOTAL: usize = 1 << MAX_CODE_LENGTH;
+    let slots = |len: u8| 1 << (MAX_CODE_LENGTH - len as usize);
+    for len in lengths
+        .iter_mut()
+        .filter(|l| **l as usize > MAX_CODE_LENGTH)
+    {
+        *len = MAX_CODE_LENGTH as u8;
+    }
+    let mut total = lengths
+        .iter()
+        .filter(|l| **l != 0)
+        .map(|l| slots(*l))
+        .sum::<usize>();
+    let mut by_count = (0..256).filter(|s| lengths[*s] != 0).collect::<Vec<_>>();
+    by_count.sort_by_key(|s| counts[*s]);
+    while total > TOTAL {
+        // lengthen the rarest symbol that can still grow
+        let sym = *by_count
+            .iter()
+            .find(|s| (lengths[**s] as usize) < MAX_CODE_LENGTH)
+            .unwrap();
+        total -= slots(lengths[sym]) / 2;
+        lengths[sym] += 1;
+    }
+    while total < TOTAL {
+        // shorten the most frequent symbol that still fits
+        let sym = *by_count
+            .iter()
+            .rev()
+            .find(|s| lengths[**s] > 1 && total + slots(lengths[**s]) <= TOTAL)
+            .unwrap();
+        total += slots(lengths[sym]);
+        lengths[sym] -= 1;
+    }
+
+    lengths
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// HELPERS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+/// Bit writer, most significant bit first
+#[derive(Default)]
+struct MsbWriter {
+    bytes: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl MsbWriter {
+    fn write(&mut self, value: u32, n: u32) {
+        if n == 0 {
+            return;
+        }
+        self.bits = (self.bits << n) | (value as u64 & ((1 << n) - 1));
+        self.count += n;
+        while self.count >= 8 {
+            self.count -= 8;
+            self.bytes.push((self.bits >> self.count) as u8);
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push((self.bits << (8 - self.count)) as u8);
+        }
+        self.bytes
+    }
+}
+
+/// Bit writer, least significant bit first
+#[derive(Default)]
+struct LsbWriter {
+    bytes: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl LsbWriter {
+    fn write(&mut self, value: u32, n: u32) {
+        self.bits |= (value as u64 & ((1 << n) - 1)) << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.bytes.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.bits as u8);
+        }
+        self.bytes
+    }
+}
+
+fn be24(v: usize) -> [u8; 3] {
+    [(v >> 16) as u8, (v >> 8) as u8, v as u8]
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// TESTS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+    use crate::kraken::decoder;
+
+    fn test_files() -> Vec<Vec<u8>> {
+        let data_path = PathBuf::from("tests").join("data");
+        walkdir::WalkDir::new(data_path)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .map(|e| fs::read(e.path()).unwrap())
+            .collect::<Vec<_>>()
+    }
+
+    fn levels() -> [CompressionLevel; 3] {
+        [
+            CompressionLevel::None,
+            CompressionLevel::Fast,
+            CompressionLevel::Normal,
+        ]
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut inputs = test_files();
+        inputs.push(vec![]);
+        inputs.push(b"short".to_vec());
+        inputs.push(vec![7; 300000]);
+        inputs.push(
+            (0..600000u32)
+                .map(|i| (i.wrapping_mul(i) >> 7) as u8)
+                .collect(),
+        );
+
+        let mut sizes = [0; 3];
+        for input in inputs.iter() {
+            for (i, level) in levels().into_iter().enumerate() {
+                let compressed = compress(input, level);
+                let output = decoder::decompress(&compressed, input.len()).unwrap();
+                assert_eq!(input, &output);
+                sizes[i] += compressed.len();
+            }
+        }
+
+        // the higher levels compress better
+        assert!(sizes[1] < sizes[0]);
+        assert!(sizes[2] < sizes[1]);
+    }
+
+    #[test]
+    fn windowed() {
+        // matches must not reach back past a window boundary
+        let input = (0..BLOCK_SIZE as u32 * 5)
+            .map(|i| ((i % 5000).wrapping_mul(i % 5000) >> 9) as u8)
+            .collect::<Vec<_>>();
+        let compressed = compress_windowed(&input, CompressionLevel::Normal, BLOCK_SIZE * 2);
+        assert!(compressed.len() < input.len() / 2);
+        let output = decoder::decompress(&compressed, input.len()).unwrap();
+        assert_eq!(input, output);
+    }
+
+    #[test]
+    #[cfg(feature = "native-kraken")]
+    fn native_decompress() {
+        for input in test_files() {
+            for level in levels() {
+                let compressed = compress(&input, level);
+                let output = super::super::native::decompress(&compressed, input.len()).unwrap();
+                assert_eq!(input, output);
+            }
+        }
+    }
+}
@@ -0,0 +1,128 @@
+/////////////////////////////////////////////////////////////////////////////////////////
+// KRAKEN
+// Compression of archive segments. Uses the native kraken library when it is linked and
+// the buffer fits its 32 bit sizes, the pure Rust codec otherwise.
+/////////////////////////////////////////////////////////////////////////////////////////
+
+use std::io::Result;
+
+mod decoder;
+mod encoder;
+#[cfg(feature = "native-kraken")]
+mod native;
+
+pub const MAGIC: u32 = 0x4B52414B;
+
+/// Largest buffer the native library can handle, its sizes are 32 bit signed integers
+#[cfg(feature = "native-kraken")]
+const NATIVE_MAX_SIZE: usize = i32::MAX as usize;
+/// The native library is not used for buffers smaller than this
+#[cfg(feature = "native-kraken")]
+const NATIVE_MIN_SIZE: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionLevel {
+    None = 0,
+    SuperFast = 1,
+    VeryFast = 2,
+    Fast = 3,
+    Normal = 4,
+    Optimal1 = 5,
+    Optimal2 = 6,
+    Optimal3 = 7,
+    Optimal4 = 8,
+    Optimal5 = 9,
+}
+
+/// Decompresses a Kraken stream into a buffer of `size` bytes
+///
+/// # Errors
+///
+/// This function will return an error if the stream is corrupt or doesn't decompress to `size` bytes.
+pub fn decompress(compressed_buffer: &[u8], size: usize) -> Result<Vec<u8>> {
+    #[cfg(feature = "native-kraken")]
+    if use_native(size) {
+        return native::decompress(compressed_buffer, size);
+    }
+
+    decoder::decompress(compressed_buffer, size)
+}
+
+/// Compresses a buffer into a Kraken stream.
+///
+/// The stream can be larger than the buffer if it doesn't compress, callers should store
+/// the buffer as is in that case.
+///
+/// # Errors
+///
+/// This function will return an error if the native library fails.
+pub fn compress(
+    uncompressed_buffer: &[u8],
+    compression_level: CompressionLevel,
+) -> Result<Vec<u8>> {
+    #[cfg(feature = "native-kraken")]
+    if use_native(uncompressed_buffer.len()) && uncompressed_buffer.len() >= NATIVE_MIN_SIZE {
+        return native::compress(uncompressed_buffer, compression_level as i32);
+    }
+
+    Ok(encoder::compress(uncompressed_buffer, compression_level))
+}
+
+/// Worst case size of a compressed buffer
+pub fn get_compressed_buffer_size_needed(size: usize) -> usize {
+    size + 274 * size.div_ceil(0x40000)
+}
+
+#[cfg(feature = "native-kraken")]
+fn use_native(size: usize) -> bool {
+    !cfg!(feature = "rust-kraken") && size <= NATIVE_MAX_SIZE
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// TESTS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+
+    #[test]
+    fn compressed_buffer_size() {
+        assert_eq!(0, get_compressed_buffer_size_needed(0));
+        assert_eq!(10 + 274, get_compressed_buffer_size_needed(10));
+        assert_eq!(0x40000 + 274, get_compressed_buffer_size_needed(0x40000));
+        assert_eq!(0x40001 + 548, get_compressed_buffer_size_needed(0x40001));
+        // no overflow above 4 GiB
+        assert!(get_compressed_buffer_size_needed(1 << 33) > 1 << 33);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let file_path = PathBuf::from("tests")
+            .join("data")
+            .join("base")
+            .join("cycleweapons")
+            .join("localization")
+            .join("en-us.json");
+        let buffer = fs::read(file_path).unwrap();
+
+        for input in [vec![], b"short".to_vec(), buffer] {
+            let compressed = compress(&input, CompressionLevel::Normal).unwrap();
+            assert!(compressed.len() <= get_compressed_buffer_size_needed(input.len()));
+            assert_eq!(input, decompress(&compressed, input.len()).unwrap());
+        }
+    }
+
+    #[test]
+    fn decompress_errors() {
+        let input = (0..1000u32)
+            .map(|i| (i % 7 + i / 100) as u8)
+            .collect::<Vec<_>>();
+        let compressed = compress(&input, CompressionLevel::Fast).unwrap();
+        assert!(decompress(&compressed, input.len() + 1).is_err());
+        assert!(decompress(&compressed[..compressed.len() / 2], input.len()).is_err());
+        assert!(decompress(&[0xFF; 16], 100).is_err());
+    }
+}
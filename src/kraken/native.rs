@@ -0,0 +1,92 @@
+/////////////////////////////////////////////////////////////////////////////////////////
+// NATIVE KRAKEN
+// Bindings to the bundled kraken library
+/////////////////////////////////////////////////////////////////////////////////////////
+
+use std::io::{Error, ErrorKind, Result};
+
+use super::get_compressed_buffer_size_needed;
+
+/// The decoder may write this many bytes past the end of the output
+const SAFE_SPACE: usize = 64;
+
+#[link(name = "kraken_static")]
+extern "C" {
+    // EXPORT int Kraken_Decompress(const byte *src, size_t src_len, byte *dst, size_t dst_len)
+    fn Kraken_Decompress(
+        buffer: *const u8,
+        bufferSize: i64,
+        outputBuffer: *mut u8,
+        outputBufferSize: i64,
+    ) -> i32;
+
+    // EXPORT int Kraken_Compress(uint8* src, size_t src_len, byte* dst, int level)
+    fn Kraken_Compress(
+        buffer: *const u8,
+        bufferSize: i64,
+        outputBuffer: *mut u8,
+        level: i32,
+    ) -> i32;
+}
+
+/// Decompresses a buffer of at most `i32::MAX` bytes
+///
+/// # Errors
+///
+/// This function will return an error if the library fails or returns the wrong size.
+pub(super) fn decompress(compressed_buffer: &[u8], size: usize) -> Result<Vec<u8>> {
+    let mut buffer = vec![0; size + SAFE_SPACE];
+    let result = unsafe {
+        Kraken_Decompress(
+            compressed_buffer.as_ptr(),
+            compressed_buffer.len() as i64,
+            buffer.as_mut_ptr(),
+            size as i64,
+        )
+    };
+
+    if result < 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("kraken decompression failed with {}", result),
+        ));
+    }
+    if result as usize != size {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("kraken decompressed {} bytes, expected {}", result, size),
+        ));
+    }
+
+    buffer.truncate(size);
+    Ok(buffer)
+}
+
+/// Compresses a buffer of at most `i32::MAX` bytes
+///
+/// # Errors
+///
+/// This function will return an error if the library fails.
+pub(super) fn compress(uncompressed_buffer: &[u8], level: i32) -> Result<Vec<u8>> {
+    // the library doesn't take the output size, the buffer must fit the worst case
+    let bound = get_compressed_buffer_size_needed(uncompressed_buffer.len());
+    let mut buffer = vec![0; bound];
+    let result = unsafe {
+        Kraken_Compress(
+            uncompressed_buffer.as_ptr(),
+            uncompressed_buffer.len() as i64,
+            buffer.as_mut_ptr(),
+            level,
+        )
+    };
+
+    if result <= 0 || result as usize > bound {
+        return Err(Error::other(format!(
+            "kraken compression failed with {}",
+            result
+        )));
+    }
+
+    buffer.truncate(result as usize);
+    Ok(buffer)
+}
@@ -1,16 +1,28 @@
 #![warn(clippy::all, rust_2018_idioms)]
 
-mod cr2w;
+#[cfg(test)]
+mod fixtures;
 mod io;
 
 pub mod archive;
+pub mod bundle;
+pub mod codec;
+pub mod cr2w;
+pub mod depgraph;
+pub mod discovery;
+pub mod hash;
+pub mod hashdb;
+pub mod installer;
 pub mod kraken;
+pub mod query;
+pub mod redmod;
+pub mod refactor;
+pub mod refcheck;
+pub mod search;
 
 use std::{
-    collections::HashMap,
-    hash::Hasher,
-    io::{BufRead, BufReader},
-    path::Path,
+    io::{Error, ErrorKind, Result},
+    path::{Component, Path, PathBuf},
 };
 
 use sha1::{Digest, Sha1};
@@ -164,51 +176,88 @@ enum ERedExtension {
 
     wdyn,
 }
-#[warn(non_camel_case_types)]
+
 /////////////////////////////////////////////////////////////////////////////////////////
 // HELPERS
 /////////////////////////////////////////////////////////////////////////////////////////
 
 /// Calculate FNV1a64 hash of a String
 pub fn fnv1a64_hash_string(str: &String) -> u64 {
-    let mut hasher = fnv::FnvHasher::default();
-    hasher.write(str.as_bytes());
-    hasher.finish()
+    hash::fnv1a64(str.as_bytes())
 }
 
 /// Calculate FNV1a64 hash of a PathBuf
 pub fn fnv1a64_hash_path(path: &Path) -> u64 {
-    let path_string = path.to_string_lossy();
-    let mut hasher = fnv::FnvHasher::default();
-    hasher.write(path_string.as_bytes());
-    hasher.finish()
+    hash::fnv1a64(path.to_string_lossy().as_bytes())
 }
 
-pub fn sha1_hash_file(file_buffer: &Vec<u8>) -> [u8; 20] {
-    let mut hasher = Sha1::new();
-    hasher.update(file_buffer);
-    let result = hasher.finalize();
-    result.into()
+/// The depot path of a file path relative to a resource folder, with backslash separators
+pub(crate) fn depot_path_from_relative(relative_path: &Path) -> String {
+    relative_path
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("\\")
+}
+
+/// A single file or folder name without any path, e.g. not `..` or `a/b`
+pub(crate) fn is_file_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    ) && !name.contains(['/', '\\'])
+}
+
+/// Mod names are used as file and folder names and must not contain a path
+///
+/// # Errors
+///
+/// This function will return an error if the name is not a valid file name.
+pub(crate) fn validate_mod_name(name: &str) -> Result<()> {
+    match is_file_name(name) {
+        true => Ok(()),
+        false => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid mod name {:?}", name),
+        )),
+    }
 }
 
-/// Get vanilla resource path hashes https://www.cyberpunk.net/en/modding-support
-pub fn get_red4_hashes() -> HashMap<u64, String> {
-    let csv_data = include_bytes!("metadata-resources.csv");
-    let mut map: HashMap<u64, String> = HashMap::new();
-
-    let reader = BufReader::new(&csv_data[..]);
-    for line in BufRead::lines(reader).map_while(Result::ok) {
-        let mut split = line.split(',');
-        if let Some(name) = split.next() {
-            if let Some(hash_str) = split.next() {
-                if let Ok(hash) = hash_str.parse::<u64>() {
-                    map.insert(hash, name.to_owned());
-                }
+/// The file path of a depot path relative to a resource folder, both separators are accepted
+///
+/// # Errors
+///
+/// This function will return an error if the depot path is empty or has parent components, the file
+/// would not be inside the folder.
+pub(crate) fn relative_path_from_depot(depot_path: &str) -> Result<PathBuf> {
+    let mut path = PathBuf::new();
+    for part in depot_path.split(['\\', '/']) {
+        match part {
+            "" | "." => {}
+            ".." => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{} is outside of the resource folder", depot_path),
+                ))
             }
+            part => path.push(part),
         }
     }
+    if path.as_os_str().is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{:?} is not a depot path", depot_path),
+        ));
+    }
+    Ok(path)
+}
 
-    map
+pub fn sha1_hash_file(file_buffer: &Vec<u8>) -> [u8; 20] {
+    let mut hasher = Sha1::new();
+    hasher.update(file_buffer);
+    let result = hasher.finalize();
+    result.into()
 }
 
 /////////////////////////////////////////////////////////////////////////////////////////
@@ -240,4 +289,21 @@ mod tests {
         //input.sort();
         assert_eq!(correct, input);
     }
+
+    #[test]
+    fn depot_paths() {
+        use std::path::PathBuf;
+
+        use super::relative_path_from_depot;
+
+        let path = PathBuf::from("base").join("a").join("c.mesh");
+        assert_eq!(path, relative_path_from_depot("base\\a\\c.mesh").unwrap());
+        assert_eq!(
+            path,
+            relative_path_from_depot("\\base/a\\.\\c.mesh").unwrap()
+        );
+        assert!(relative_path_from_depot("base\\..\\..\\c.mesh").is_err());
+        assert!(relative_path_from_depot("..").is_err());
+        assert!(relative_path_from_depot("\\").is_err());
+    }
 }
@@ -0,0 +1,677 @@
+/////////////////////////////////////////////////////////////////////////////////////////
+// QUERY
+// A small jq-like language to find values in CR2W resources, e.g. every mesh component
+// of entity templates or every material instance of a base material:
+//
+// entEntityTemplate.components[] == entMeshComponent
+// CMaterialInstance.baseMaterial ~= "metal_base"
+//
+// query:    class path [operator literal]
+// class:    class name of a chunk, `*` matches any characters
+// path:     `.name` property, `.*` any property, `..name` property at any depth,
+//           `[]` any element, `[n]` element n; handles are followed to their chunk
+// operator: `==`, `!=`, `~=` (contains, case-insensitive), `<`, `<=`, `>`, `>=`
+// literal:  a number, `"string"` or a bare word
+//
+// Values compare as text: names and strings without quotes, enums by value name,
+// handles by the class of their chunk and resource references by depot path. Numbers
+// compare numerically.
+/////////////////////////////////////////////////////////////////////////////////////////
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::File,
+    io::{Cursor, Error, ErrorKind, Read, Result, Seek},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{
+    archive::{open_read, ZipArchive, ZipEntry},
+    cr2w::{read_cr2w, value_text, CR2WFile, Property, Value},
+    hashdb::HashDb,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    /// `.name`
+    Property(String),
+    /// `.*`
+    AnyProperty,
+    /// `..name`
+    Descendant(String),
+    /// `[]`
+    AnyElement,
+    /// `[n]`
+    Element(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Equal,
+    NotEqual,
+    Contains,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// A parsed query
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    class: String,
+    steps: Vec<Step>,
+    filter: Option<(Operator, String)>,
+}
+
+/// A value that a query selected in a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryMatch {
+    /// Path of the value, e.g. `chunks[0].components[2]`
+    pub path: String,
+    pub value: String,
+}
+
+/// A value that a query selected in an archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryHit {
+    pub archive: PathBuf,
+    /// Depot path of the resource, or its hash if it couldn't be resolved
+    pub resource: String,
+    pub hash: u64,
+    pub path: String,
+    pub value: String,
+}
+
+impl fmt::Display for QueryHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}: {}: {}",
+            self.archive.display(),
+            self.resource,
+            self.path,
+            self.value
+        )
+    }
+}
+
+impl FromStr for Query {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s)
+    }
+}
+
+/// A node of a file that the query reached
+#[derive(Clone, Copy)]
+enum Node<'a> {
+    Chunk(usize),
+    Value(&'a Value),
+}
+
+impl Query {
+    /// Parses a query
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the query is malformed.
+    pub fn parse(query: &str) -> Result<Self> {
+        let query = query.trim();
+        let class_end = query
+            .find(|c: char| !(c.is_alphanumeric() || "_:*".contains(c)))
+            .unwrap_or(query.len());
+        let class = query[..class_end].to_owned();
+        if class.is_empty() {
+            return Err(invalid_query("expected a class name", 0));
+        }
+
+        let mut steps = vec![];
+        let mut rest = &query[class_end..];
+        loop {
+            let position = query.len() - rest.len();
+            if let Some(after) = rest.strip_prefix("..") {
+                let (name, after) = split_name(after);
+                if name.is_empty() {
+                    return Err(invalid_query("expected a property name", position + 2));
+                }
+                steps.push(Step::Descendant(name.to_owned()));
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix(".*") {
+                steps.push(Step::AnyProperty);
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix('.') {
+                let (name, after) = split_name(after);
+                if name.is_empty() {
+                    return Err(invalid_query("expected a property name", position + 1));
+                }
+                steps.push(Step::Property(name.to_owned()));
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix('[') {
+                let Some((index, after)) = after.split_once(']') else {
+                    return Err(invalid_query("expected ]", position));
+                };
+                let index = index.trim();
+                steps.push(match index {
+                    "" | "*" => Step::AnyElement,
+                    _ => Step::Element(
+                        index
+                            .parse()
+                            .map_err(|_| invalid_query("expected an index", position + 1))?,
+                    ),
+                });
+                rest = after;
+            } else {
+                break;
+            }
+        }
+
+        let rest = rest.trim_start();
+        let filter = if rest.is_empty() {
+            None
+        } else {
+            let position = query.len() - rest.len();
+            let operators = [
+                ("==", Operator::Equal),
+                ("!=", Operator::NotEqual),
+                ("~=", Operator::Contains),
+                ("<=", Operator::LessOrEqual),
+                (">=", Operator::GreaterOrEqual),
+                ("<", Operator::Less),
+                (">", Operator::Greater),
+            ];
+            let Some((operator, literal)) = operators
+                .iter()
+                .find_map(|(token, op)| rest.strip_prefix(token).map(|literal| (*op, literal)))
+            else {
+                return Err(invalid_query("expected an operator", position));
+            };
+            let literal = literal.trim();
+            let literal = match literal.strip_prefix('"') {
+                Some(quoted) => quoted
+                    .strip_suffix('"')
+                    .ok_or_else(|| invalid_query("expected \"", query.len()))?,
+                None => literal,
+            };
+            if matches!(
+                operator,
+                Operator::Less
+                    | Operator::LessOrEqual
+                    | Operator::Greater
+                    | Operator::GreaterOrEqual
+            ) && literal.parse::<f64>().is_err()
+            {
+                return Err(invalid_query("expected a number", position));
+            }
+            Some((operator, literal.to_owned()))
+        };
+
+        Ok(Self {
+            class,
+            steps,
+            filter,
+        })
+    }
+
+    /// Finds the values the query selects in a file
+    pub fn matches(&self, file: &CR2WFile) -> Vec<QueryMatch> {
+        let mut nodes = file
+            .chunks
+            .iter()
+            .enumerate()
+            .filter(|(_, chunk)| glob_match(&self.class, &chunk.class_name))
+            .map(|(index, _)| (format!("chunks[{}]", index), Node::Chunk(index)))
+            .collect::<Vec<_>>();
+        for step in self.steps.iter() {
+            nodes = nodes
+                .into_iter()
+                .flat_map(|(path, node)| apply(file, step, &path, node))
+                .collect();
+        }
+
+        nodes
+            .into_iter()
+            .filter(|(_, node)| self.filter(file, *node))
+            .map(|(path, node)| QueryMatch {
+                path,
+                value: match node {
+                    Node::Chunk(index) => file.chunks[index].class_name.clone(),
+                    Node::Value(value) => value_text(file, value),
+                },
+            })
+            .collect()
+    }
+
+    /// Runs the query over the CR2W resources of archives, hits are read lazily archive by archive
+    ///
+    /// Resource names are resolved with `hash_db`, or the global hash database if none is given.
+    pub fn run<'a, P: AsRef<Path>>(
+        &'a self,
+        archive_paths: &[P],
+        hash_db: Option<&'a HashDb>,
+    ) -> QueryHits<'a> {
+        QueryHits {
+            query: self,
+            hash_db: hash_db.unwrap_or_else(|| HashDb::global()),
+            archive_paths: archive_paths
+                .iter()
+                .map(|p| p.as_ref().to_path_buf())
+                .collect(),
+            current: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn filter(&self, file: &CR2WFile, node: Node<'_>) -> bool {
+        let Some((operator, literal)) = &self.filter else {
+            return true;
+        };
+        let Some(text) = compare_text(file, node) else {
+            return false;
+        };
+        let numbers = text.parse::<f64>().ok().zip(literal.parse::<f64>().ok());
+        match operator {
+            Operator::Equal => numbers.map_or(text == *literal, |(a, b)| a == b),
+            Operator::NotEqual => numbers.map_or(text != *literal, |(a, b)| a != b),
+            Operator::Contains => text.to_lowercase().contains(&literal.to_lowercase()),
+            Operator::Less => numbers.is_some_and(|(a, b)| a < b),
+            Operator::LessOrEqual => numbers.is_some_and(|(a, b)| a <= b),
+            Operator::Greater => numbers.is_some_and(|(a, b)| a > b),
+            Operator::GreaterOrEqual => numbers.is_some_and(|(a, b)| a >= b),
+        }
+    }
+}
+
+/// Hits of a query in archives, see [`Query::run`]
+pub struct QueryHits<'a> {
+    query: &'a Query,
+    hash_db: &'a HashDb,
+    archive_paths: VecDeque<PathBuf>,
+    /// The archive that is searched and its remaining entries
+    current: Option<(PathBuf, ZipArchive<File>, VecDeque<ZipEntry>)>,
+    pending: VecDeque<QueryHit>,
+}
+
+impl Iterator for QueryHits<'_> {
+    type Item = Result<QueryHit>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(hit) = self.pending.pop_front() {
+                return Some(Ok(hit));
+            }
+
+            let Some((archive_path, archive, entries)) = self.current.as_mut() else {
+                let archive_path = self.archive_paths.pop_front()?;
+                let archive = match open_read(&archive_path) {
+                    Ok(archive) => archive,
+                    Err(e) => return Some(Err(e)),
+                };
+                let mut entries = archive.get_entries().values().cloned().collect::<Vec<_>>();
+                entries.sort_by_key(|entry| entry.hash);
+                self.current = Some((archive_path, archive, entries.into()));
+                continue;
+            };
+            let Some(entry) = entries.pop_front() else {
+                self.current = None;
+                continue;
+            };
+
+            match query_entry(self.query, archive, entry, archive_path, self.hash_db) {
+                Ok(hits) => self.pending.extend(hits),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Runs a query over the CR2W resources of an archive stream, see [`Query::run`]
+///
+/// Hits are reported with `archive_path`, resource names are resolved with the archive's LXRS
+/// table and `hash_db`, or the global hash database if none is given. A resource that can't be
+/// read or parsed is reported as an error in place of its hits and the search continues.
+pub fn query_archive<R: Read + Seek, P: AsRef<Path>>(
+    query: &Query,
+    archive: &mut ZipArchive<R>,
+    archive_path: &P,
+    hash_db: Option<&HashDb>,
+) -> Vec<Result<QueryHit>> {
+    let hash_db = hash_db.unwrap_or_else(|| HashDb::global());
+    let mut entries = archive.get_entries().values().cloned().collect::<Vec<_>>();
+    entries.sort_by_key(|entry| entry.hash);
+
+    let mut hits = vec![];
+    for entry in entries {
+        match query_entry(query, archive, entry, archive_path.as_ref(), hash_db) {
+            Ok(entry_hits) => hits.extend(entry_hits.into_iter().map(Ok)),
+            Err(e) => hits.push(Err(e)),
+        }
+    }
+    hits
+}
+
+/// The hits of a query in one archive entry, entries that aren't CR2W resources have none
+fn query_entry<R: Read + Seek>(
+    query: &Query,
+    archive: &mut ZipArchive<R>,
+    entry: ZipEntry,
+    archive_path: &Path,
+    hash_db: &HashDb,
+) -> Result<Vec<QueryHit>> {
+    let hash = entry.hash;
+    let resource = entry
+        .name()
+        .cloned()
+        .or_else(|| hash_db.get_path(&hash).map(|p| p.to_owned()))
+        .unwrap_or_else(|| hash.to_string());
+    let mut buffer = vec![];
+    archive
+        .open_entry(entry, &mut buffer)
+        .map_err(|e| Error::new(e.kind(), format!("{}: {}", resource, e)))?;
+    if !buffer.starts_with(b"CR2W") {
+        return Ok(vec![]);
+    }
+    let file = read_cr2w(&mut Cursor::new(&buffer))
+        .map_err(|e| Error::new(e.kind(), format!("{}: {}", resource, e)))?;
+    Ok(query
+        .matches(&file)
+        .into_iter()
+        .map(|m| QueryHit {
+            archive: archive_path.to_path_buf(),
+            resource: resource.clone(),
+            hash,
+            path: m.path,
+            value: m.value,
+        })
+        .collect())
+}
+
+/// The nodes a step reaches from a node
+fn apply<'a>(
+    file: &'a CR2WFile,
+    step: &Step,
+    path: &str,
+    node: Node<'a>,
+) -> Vec<(String, Node<'a>)> {
+    match step {
+        Step::Property(name) => properties(file, node)
+            .iter()
+            .filter(|p| p.name == *name)
+            .map(|p| (format!("{}.{}", path, p.name), Node::Value(&p.value)))
+            .collect(),
+        Step::AnyProperty => properties(file, node)
+            .iter()
+            .map(|p| (format!("{}.{}", path, p.name), Node::Value(&p.value)))
+            .collect(),
+        Step::Descendant(name) => {
+            let mut nodes = vec![];
+            descendants(properties(file, node), name, path, &mut nodes);
+            nodes
+        }
+        Step::AnyElement => match node {
+            Node::Value(Value::Array(values)) => values
+                .iter()
+                .enumerate()
+                .map(|(i, value)| (format!("{}[{}]", path, i), Node::Value(value)))
+                .collect(),
+            _ => vec![],
+        },
+        Step::Element(index) => match node {
+            Node::Value(Value::Array(values)) => values
+                .get(*index)
+                .map(|value| (format!("{}[{}]", path, index), Node::Value(value)))
+                .into_iter()
+                .collect(),
+            _ => vec![],
+        },
+    }
+}
+
+/// Properties of a chunk or struct, handles are followed to their chunk
+fn properties<'a>(file: &'a CR2WFile, node: Node<'a>) -> &'a [Property] {
+    let chunk = match node {
+        Node::Chunk(index) => Some(index),
+        Node::Value(Value::Handle(index)) | Node::Value(Value::WeakHandle(index)) => *index,
+        Node::Value(Value::Struct(properties)) => return properties,
+        Node::Value(_) => None,
+    };
+    chunk
+        .and_then(|index| file.chunks.get(index))
+        .map_or(&[], |chunk| &chunk.properties)
+}
+
+/// Properties with a name at any depth of structs and arrays, handles are not followed
+fn descendants<'a>(
+    properties: &'a [Property],
+    name: &str,
+    path: &str,
+    nodes: &mut Vec<(String, Node<'a>)>,
+) {
+    fn visit<'a>(value: &'a Value, name: &str, path: &str, nodes: &mut Vec<(String, Node<'a>)>) {
+        match value {
+            Value::Struct(properties) => descendants(properties, name, path, nodes),
+            Value::Array(values) => {
+                for (i, value) in values.iter().enumerate() {
+                    visit(value, name, &format!("{}[{}]", path, i), nodes);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for property in properties {
+        let path = format!("{}.{}", path, property.name);
+        if property.name == name {
+            nodes.push((path.clone(), Node::Value(&property.value)));
+        }
+        visit(&property.value, name, &path, nodes);
+    }
+}
+
+/// The text a value is compared by, `None` for arrays and structs
+fn compare_text(file: &CR2WFile, node: Node<'_>) -> Option<String> {
+    let value = match node {
+        Node::Chunk(index) => return Some(file.chunks[index].class_name.clone()),
+        Node::Value(value) => value,
+    };
+    match value {
+        Value::CName(text) | Value::String(text) => Some(text.clone()),
+        Value::Handle(index) | Value::WeakHandle(index) => Some(
+            index
+                .and_then(|index| file.chunks.get(index))
+                .map_or("null".to_owned(), |chunk| chunk.class_name.clone()),
+        ),
+        Value::ResourceReference(index) => Some(
+            index
+                .and_then(|index| file.info.imports.get(index))
+                .map_or("null".to_owned(), |import| import.depot_path.clone()),
+        ),
+        Value::Array(_) | Value::Struct(_) => None,
+        _ => Some(value_text(file, value)),
+    }
+}
+
+/// Splits a property name, bare or in quotes, from the rest of a query
+fn split_name(query: &str) -> (&str, &str) {
+    if let Some(quoted) = query.strip_prefix('"') {
+        if let Some((name, rest)) = quoted.split_once('"') {
+            return (name, rest);
+        }
+    }
+    let end = query
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(query.len());
+    query.split_at(end)
+}
+
+/// Matches a text against a pattern where `*` matches any characters and `?` one character
+pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    let (mut p, mut t) = (0, 0);
+    // position of the last `*` and the text position it was tried at
+    let mut star = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+fn invalid_query(msg: &str, position: usize) -> Error {
+    Error::new(
+        ErrorKind::InvalidInput,
+        format!("{} at position {}", msg, position),
+    )
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// TESTS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{self, entity};
+
+    fn paths(query: &str) -> Vec<String> {
+        let query = Query::parse(query).unwrap();
+        query
+            .matches(&entity())
+            .into_iter()
+            .map(|m| m.path)
+            .collect()
+    }
+
+    #[test]
+    fn parse() {
+        let query: Query = "ent*Component..name[2].\"x y\" ~= \"a b\"".parse().unwrap();
+        assert_eq!("ent*Component", query.class);
+        assert_eq!(
+            vec![
+                Step::Descendant("name".to_owned()),
+                Step::Element(2),
+                Step::Property("x y".to_owned()),
+            ],
+            query.steps
+        );
+        assert_eq!(Some((Operator::Contains, "a b".to_owned())), query.filter);
+
+        assert!(Query::parse("").is_err());
+        assert!(Query::parse("entMeshComponent.").is_err());
+        assert!(Query::parse("entMeshComponent.scale[").is_err());
+        assert!(Query::parse("entMeshComponent.scale = 1").is_err());
+        assert!(Query::parse("entMeshComponent.scale > big").is_err());
+    }
+
+    #[test]
+    fn select() {
+        assert_eq!(vec!["chunks[1]", "chunks[2]"], paths("ent*Component"));
+        assert_eq!(vec!["chunks[1].name", "chunks[2].name"], paths("*.name"));
+        assert_eq!(
+            vec!["chunks[0].components[0]"],
+            paths("entEntityTemplate.components[] == entMeshComponent")
+        );
+        // handles are followed
+        assert_eq!(
+            vec!["chunks[0].components[1].type"],
+            paths("entEntityTemplate.components[1].type == LT_Spot")
+        );
+        assert_eq!(
+            vec![
+                "chunks[0].appearances[0].name",
+                "chunks[0].appearances[1].name"
+            ],
+            paths("entEntityTemplate..name")
+        );
+        assert_eq!(vec!["chunks[1].mesh"], paths("*.mesh ~= \"A.MESH\""));
+        assert_eq!(vec!["chunks[1].scale"], paths("*.* > 0.5"));
+        assert_eq!(vec!["chunks[1].scale"], paths("*.scale == 1"));
+        assert!(paths("*.scale < 1").is_empty());
+        assert_eq!(4, paths("entMeshComponent.* != lamp").len());
+
+        let query = Query::parse("entMeshComponent.mesh").unwrap();
+        assert_eq!(
+            vec![QueryMatch {
+                path: "chunks[1].mesh".to_owned(),
+                value: "base\\a.mesh".to_owned(),
+            }],
+            query.matches(&entity())
+        );
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("ent*Component", "entMeshComponent"));
+        assert!(glob_match("*mesh*", "base\\a.mesh"));
+        assert!(glob_match("a?c", "abc"));
+        assert!(!glob_match("ent*Component", "entMeshComponents"));
+        assert!(!glob_match("a?c", "ac"));
+    }
+
+    #[test]
+    fn archives() {
+        let archive_path = PathBuf::from("tests").join("test1.archive");
+        let query = Query::parse("JsonResource.root").unwrap();
+        let hash_db = HashDb::new();
+        let hits = query
+            .run(&[&archive_path], Some(&hash_db))
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert!(!hits.is_empty());
+        assert!(hits.iter().all(|hit| hit.archive == archive_path));
+        assert!(hits
+            .iter()
+            .any(|hit| hit.resource == "base\\cycleweapons\\localization\\en-us.json"));
+
+        let mut archive = open_read(&archive_path).unwrap();
+        let archive_hits = query_archive(&query, &mut archive, &archive_path, Some(&hash_db))
+            .into_iter()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(hits, archive_hits);
+    }
+
+    #[test]
+    fn broken_resources() {
+        let mut broken = fixtures::resource(&[]);
+        broken.truncate(64);
+        let mut archive = fixtures::unnamed_archive(vec![
+            ("base\\a.json", fixtures::resource(&[])),
+            ("base\\b.json", broken),
+        ]);
+        let mut hash_db = HashDb::new();
+        hash_db.insert_path("base\\a.json");
+        hash_db.insert_path("base\\b.json");
+
+        // the broken resource is reported and the other one is still searched
+        let query = Query::parse("JsonResource").unwrap();
+        let hits = query_archive(&query, &mut archive, &"test.archive", Some(&hash_db));
+        assert_eq!(2, hits.len());
+        let errors = hits
+            .iter()
+            .filter_map(|hit| hit.as_ref().err())
+            .collect::<Vec<_>>();
+        assert_eq!(1, errors.len());
+        assert!(errors[0].to_string().starts_with("base\\b.json: "));
+        assert!(hits
+            .iter()
+            .filter_map(|hit| hit.as_ref().ok())
+            .all(|hit| hit.resource == "base\\a.json"));
+    }
+}
@@ -0,0 +1,389 @@
+/////////////////////////////////////////////////////////////////////////////////////////
+// REDMOD
+// https://wiki.redmodding.org/cyberpunk-2077-modding/for-mod-users/users-modding-cyberpunk-2077/redmod
+//
+// mods/<name>/
+//   info.json
+//   archives/*.archive
+//   customSounds/*.wav
+/////////////////////////////////////////////////////////////////////////////////////////
+
+use std::{
+    collections::HashMap,
+    fs::{self, create_dir_all, File},
+    io::{self, BufReader, BufWriter, Error, ErrorKind, Result},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{is_file_name, validate_mod_name};
+
+/// Name of the folder in the game folder that holds REDmods
+pub const MODS_FOLDER_NAME: &str = "mods";
+/// Name of the mod description file inside a REDmod folder
+pub const INFO_FILE_NAME: &str = "info.json";
+/// Name of the optional load order file inside the mods folder
+pub const LOAD_ORDER_FILE_NAME: &str = "mods.json";
+/// Sub-folder of a REDmod that holds .archive files
+pub const ARCHIVES_FOLDER_NAME: &str = "archives";
+/// Sub-folder of a REDmod that holds custom sound files
+pub const CUSTOM_SOUNDS_FOLDER_NAME: &str = "customSounds";
+
+/// The info.json of a REDmod
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModInfo {
+    pub name: String,
+    #[serde(default)]
+    pub version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub custom_sounds: Vec<CustomSound>,
+}
+
+impl ModInfo {
+    pub fn new(name: &str, version: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            version: version.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    /// Reads a mod info from a reader
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the json is invalid.
+    pub fn from_reader<R: io::Read>(reader: R) -> Result<Self> {
+        serde_json::from_reader(reader).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    /// Writes the mod info as json to a writer
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if serialization fails.
+    pub fn write<W: io::Write>(&self, writer: W) -> Result<()> {
+        serde_json::to_writer_pretty(writer, self).map_err(Error::other)
+    }
+}
+
+/// A custom sound definition in a REDmod info.json
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CustomSound {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub sound_type: ESoundType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gain: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pitch: Option<f32>,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ESoundType {
+    mod_skip,
+    mod_sfx_2d,
+    mod_sfx_city,
+    mod_sfx_low_occlusion,
+    mod_sfx_occlusion,
+    mod_sfx_radio,
+    mod_sfx_room,
+    mod_sfx_street,
+    mod_sfx_ui,
+}
+
+/// The optional mods.json in the mods folder that overrides the default load order
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LoadOrder {
+    #[serde(default)]
+    pub mods: Vec<LoadOrderEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoadOrderEntry {
+    /// Folder name of the mod inside the mods folder
+    pub folder: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// API
+/////////////////////////////////////////////////////////////////////////////////////////
+
+/// A REDmod folder on disk
+#[derive(Debug, Clone)]
+pub struct RedMod {
+    /// Path to the mod folder, e.g. mods/<name>
+    path: PathBuf,
+    info: ModInfo,
+}
+
+impl RedMod {
+    /// Opens a REDmod folder and parses its info.json
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the info.json is missing or invalid.
+    pub fn from_folder<P: AsRef<Path>>(path: &P) -> Result<Self> {
+        let info_path = path.as_ref().join(INFO_FILE_NAME);
+        let file = File::open(info_path)?;
+        let info = ModInfo::from_reader(BufReader::new(file))?;
+
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            info,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn info(&self) -> &ModInfo {
+        &self.info
+    }
+
+    /// The folder name of this mod inside the mods folder
+    pub fn folder_name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|f| f.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    /// Lists the .archive files of this mod in load order
+    pub fn archives(&self) -> Vec<PathBuf> {
+        get_archives_in_folder(&self.path.join(ARCHIVES_FOLDER_NAME))
+    }
+
+    /// Resolves the custom sound files of this mod on disk
+    ///
+    /// Files that are not plain file names, e.g. `../a.wav`, are not inside the custom sounds folder
+    /// and are skipped.
+    pub fn custom_sound_files(&self) -> Vec<PathBuf> {
+        let folder = self.path.join(CUSTOM_SOUNDS_FOLDER_NAME);
+        self.info
+            .custom_sounds
+            .iter()
+            .filter_map(|s| s.file.as_ref())
+            .filter(|f| is_file_name(f))
+            .map(|f| folder.join(f))
+            .collect::<Vec<_>>()
+    }
+}
+
+/// Gets all REDmods in a mods folder in load order.
+///
+/// Mods listed in a mods.json are loaded first in the listed order, disabled mods are skipped.
+/// All other mods follow in the default (byte-wise alphabetical) order of their folder names.
+/// Mods with an invalid info.json are skipped, the game doesn't load them either.
+///
+/// # Errors
+///
+/// This function will return an error if the folder can't be read or the mods.json is invalid.
+pub fn get_mods<P: AsRef<Path>>(mods_folder: &P) -> Result<Vec<RedMod>> {
+    let mut mods: HashMap<String, RedMod> = HashMap::default();
+    for entry in fs::read_dir(mods_folder)?.flatten() {
+        let path = entry.path();
+        if path.is_dir() && path.join(INFO_FILE_NAME).exists() {
+            if let Ok(redmod) = RedMod::from_folder(&path) {
+                mods.insert(redmod.folder_name(), redmod);
+            }
+        }
+    }
+
+    // custom load order
+    let mut result = vec![];
+    let load_order_path = mods_folder.as_ref().join(LOAD_ORDER_FILE_NAME);
+    if load_order_path.exists() {
+        let file = File::open(load_order_path)?;
+        let load_order: LoadOrder = serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        for entry in load_order.mods {
+            if let Some(redmod) = mods.remove(&entry.folder) {
+                if entry.enabled {
+                    result.push(redmod);
+                }
+            }
+        }
+    }
+
+    // default load order
+    let mut rest = mods.into_values().collect::<Vec<_>>();
+    rest.sort_by(|a, b| a.folder_name().as_bytes().cmp(b.folder_name().as_bytes()));
+    result.extend(rest);
+
+    Ok(result)
+}
+
+/// Creates a REDmod folder layout from a packed archive.
+///
+/// The archive is copied to mods/<name>/archives/ and an info.json is written.
+///
+/// # Errors
+///
+/// This function will return an error if the mod name is not a valid folder name or any io fails.
+pub fn create_from_archive<P, Q>(archive_path: &P, mods_folder: &Q, info: ModInfo) -> Result<RedMod>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let Some(file_name) = archive_path.as_ref().file_name() else {
+        return Err(Error::new(ErrorKind::InvalidInput, "Invalid archive path"));
+    };
+    validate_mod_name(&info.name)?;
+
+    let mod_folder = mods_folder.as_ref().join(&info.name);
+    let archives_folder = mod_folder.join(ARCHIVES_FOLDER_NAME);
+    create_dir_all(&archives_folder)?;
+    fs::copy(archive_path, archives_folder.join(file_name))?;
+
+    let file = File::create(mod_folder.join(INFO_FILE_NAME))?;
+    info.write(BufWriter::new(file))?;
+
+    Ok(RedMod {
+        path: mod_folder,
+        info,
+    })
+}
+
+/// Gets all .archive files in a folder, sorted in load order
+pub(crate) fn get_archives_in_folder<P: AsRef<Path>>(folder: &P) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(folder) else {
+        return vec![];
+    };
+
+    let mut archives = entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.is_file())
+        .filter(|p| {
+            p.extension()
+                .is_some_and(|e| e.eq_ignore_ascii_case("archive"))
+        })
+        .collect::<Vec<_>>();
+    archives.sort_by(|a, b| {
+        a.as_os_str()
+            .as_encoded_bytes()
+            .cmp(b.as_os_str().as_encoded_bytes())
+    });
+    archives
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// TESTS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+
+    #[test]
+    fn read_info() {
+        let json = r#"{
+            "name": "myMod",
+            "version": "1.0.0",
+            "customSounds": [
+                { "name": "ono_v_pain_long", "type": "mod_sfx_2d", "file": "pain.wav", "gain": 1.0 },
+                { "name": "ono_v_effort_short", "type": "mod_skip" }
+            ]
+        }"#;
+        let info = ModInfo::from_reader(json.as_bytes()).unwrap();
+
+        assert_eq!("myMod", info.name);
+        assert_eq!("1.0.0", info.version);
+        assert_eq!(2, info.custom_sounds.len());
+        assert_eq!(ESoundType::mod_sfx_2d, info.custom_sounds[0].sound_type);
+        assert_eq!(Some("pain.wav".to_owned()), info.custom_sounds[0].file);
+        assert_eq!(ESoundType::mod_skip, info.custom_sounds[1].sound_type);
+    }
+
+    #[test]
+    fn custom_sound_files() {
+        let json = r#"{
+            "name": "myMod",
+            "customSounds": [
+                { "name": "a", "type": "mod_sfx_2d", "file": "pain.wav" },
+                { "name": "b", "type": "mod_sfx_2d", "file": "../../evil.wav" },
+                { "name": "c", "type": "mod_sfx_2d", "file": "sub/evil.wav" }
+            ]
+        }"#;
+        let redmod = RedMod {
+            path: PathBuf::from("mods").join("myMod"),
+            info: ModInfo::from_reader(json.as_bytes()).unwrap(),
+        };
+        assert_eq!(
+            vec![PathBuf::from("mods")
+                .join("myMod")
+                .join(CUSTOM_SOUNDS_FOLDER_NAME)
+                .join("pain.wav")],
+            redmod.custom_sound_files()
+        );
+    }
+
+    #[test]
+    fn redmod_load_order() {
+        let archive_path = PathBuf::from("tests").join("test1.archive");
+        let mods_path = PathBuf::from("tests").join("out_redmod");
+        if mods_path.exists() {
+            assert!(fs::remove_dir_all(&mods_path).is_ok());
+        }
+
+        for name in ["b", "a", "C", "d"] {
+            let redmod =
+                create_from_archive(&archive_path, &mods_path, ModInfo::new(name, "1.0")).unwrap();
+            assert_eq!(1, redmod.archives().len());
+        }
+        let names = get_mods(&mods_path)
+            .unwrap()
+            .iter()
+            .map(|m| m.info().name.to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["C", "a", "b", "d"], names);
+
+        // mods.json
+        let json = r#"{ "mods": [ { "folder": "d" }, { "folder": "a", "enabled": false } ] }"#;
+        fs::write(mods_path.join(LOAD_ORDER_FILE_NAME), json).unwrap();
+        let names = get_mods(&mods_path)
+            .unwrap()
+            .iter()
+            .map(|m| m.info().name.to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["d", "C", "b"], names);
+
+        // a broken info.json doesn't hide the other mods
+        fs::write(mods_path.join("b").join(INFO_FILE_NAME), "{").unwrap();
+        let names = get_mods(&mods_path)
+            .unwrap()
+            .iter()
+            .map(|m| m.info().name.to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["d", "C"], names);
+
+        // names from info.json must not leave the mods folder
+        for name in ["", "..", "../e", "e/f", "e\\f"] {
+            assert!(
+                create_from_archive(&archive_path, &mods_path, ModInfo::new(name, "1.0")).is_err()
+            );
+        }
+        assert!(!mods_path.join("e").exists());
+        assert!(!PathBuf::from("tests").join("e").exists());
+
+        // cleanup
+        assert!(fs::remove_dir_all(&mods_path).is_ok());
+    }
+}
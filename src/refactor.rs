@@ -0,0 +1,520 @@
+/////////////////////////////////////////////////////////////////////////////////////////
+// REFACTOR
+// Moves resources of a mod project to new depot paths and rewrites every CR2W file that
+// refers to them. A mapping matches a depot path exactly or as folder prefix, the longest
+// match wins. Import depot paths and names that are depot paths are rewritten in the
+// string table, CR2W files are written again which recomputes their CRCs.
+/////////////////////////////////////////////////////////////////////////////////////////
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::{self, create_dir_all},
+    io::{Cursor, Error, ErrorKind, Read, Result, Seek, Write},
+    path::Path,
+};
+
+use crate::{
+    archive::{write_resources, ZipArchive},
+    codec::{Codec, KrakenCodec},
+    cr2w::{read_cr2w, write_cr2w, CR2WFile},
+    hash::{normalize_depot_path, ResourceHash},
+    refcheck::folder_resources,
+    relative_path_from_depot,
+};
+
+/// Old to new depot paths, a mapping of a folder moves everything below it
+#[derive(Debug, Clone, Default)]
+pub struct DepotPathMap {
+    mappings: Vec<(String, String)>,
+}
+
+impl DepotPathMap {
+    /// Creates an empty map
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.mappings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mappings.is_empty()
+    }
+
+    /// Adds a mapping, both depot paths are normalized, see [`normalize_depot_path`]
+    pub fn insert(&mut self, from: &str, to: &str) {
+        let from = normalize_depot_path(from);
+        let to = normalize_depot_path(to);
+        self.mappings.retain(|(f, _)| *f != from);
+        self.mappings.push((from, to));
+    }
+
+    /// The new depot path of a depot path, `None` if no mapping matches or it maps to itself.
+    ///
+    /// Depot paths match regardless of case and separators, like the game hashes them.
+    pub fn map(&self, path: &str) -> Option<String> {
+        let path = normalize_depot_path(path);
+        let (from, to) = self
+            .mappings
+            .iter()
+            .filter(|(from, _)| {
+                path.strip_prefix(from.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('\\'))
+            })
+            .max_by_key(|(from, _)| from.len())?;
+        let mapped = format!("{}{}", to, &path[from.len()..]);
+        (mapped != path).then_some(mapped)
+    }
+}
+
+/// A change made by a refactoring
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefactorChange {
+    /// A resource is moved to a new depot path
+    Move { from: String, to: String },
+    /// An import of a resource points to a new depot path
+    Import {
+        file: String,
+        index: usize,
+        from: String,
+        to: String,
+    },
+    /// A name of a resource is a depot path that moved
+    Name {
+        file: String,
+        index: usize,
+        from: String,
+        to: String,
+    },
+}
+
+impl fmt::Display for RefactorChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RefactorChange::Move { from, to } => write!(f, "move {} -> {}", from, to),
+            RefactorChange::Import {
+                file,
+                index,
+                from,
+                to,
+            } => write!(f, "{}: import {}: {} -> {}", file, index, from, to),
+            RefactorChange::Name {
+                file,
+                index,
+                from,
+                to,
+            } => write!(f, "{}: name {}: {} -> {}", file, index, from, to),
+        }
+    }
+}
+
+/// Moves and rewrites the resources of a loose folder, relative file paths are the depot paths
+///
+/// With `dry_run` nothing is written, the returned changes are what would be done.
+///
+/// # Errors
+///
+/// This function will return an error if a resource would be moved onto another resource or any io fails.
+pub fn refactor_folder<P: AsRef<Path>>(
+    folder: &P,
+    map: &DepotPathMap,
+    dry_run: bool,
+) -> Result<Vec<RefactorChange>> {
+    let resources = folder_resources(folder);
+    let moves = plan_moves(resources.iter().map(|(path, _)| path.as_str()), map)?;
+
+    let mut changes = vec![];
+    let mut writes = vec![];
+    for (path, file_path) in resources.iter() {
+        let new_path = moves.get(path);
+        let outfile = match new_path {
+            Some(new_path) => {
+                changes.push(RefactorChange::Move {
+                    from: path.to_owned(),
+                    to: new_path.to_owned(),
+                });
+                folder.as_ref().join(relative_path_from_depot(new_path)?)
+            }
+            None => file_path.to_owned(),
+        };
+        let buffer = fs::read(file_path)?;
+        let rewritten = rewrite(path, &buffer, map, &mut changes)?;
+        if new_path.is_some() || rewritten.is_some() {
+            writes.push((outfile, rewritten.unwrap_or(buffer)));
+        }
+    }
+    if dry_run {
+        return Ok(changes);
+    }
+
+    // new files are written before the moved ones are removed, so nothing is lost if writing fails
+    for (outfile, buffer) in writes {
+        if let Some(parent) = outfile.parent() {
+            create_dir_all(parent)?;
+        }
+        fs::write(outfile, buffer)?;
+    }
+    // a resource may have moved to where another one was
+    let targets = moves.values().collect::<HashSet<_>>();
+    for (path, file_path) in resources.iter() {
+        if moves.contains_key(path) && !targets.contains(path) {
+            fs::remove_file(file_path)?;
+        }
+    }
+    Ok(changes)
+}
+
+/// Moves and rewrites the resources of an archive, the result is packed to `destination`
+///
+/// Without a destination nothing is written, the returned changes are what would be done. Only
+/// entries with a resolved name can be moved. Files are compressed with `codec`, or with Kraken at
+/// the normal level if none is given.
+///
+/// # Errors
+///
+/// This function will return an error if a resource would be moved onto another resource or any io fails.
+pub fn refactor_archive<R: Read + Seek, W: Write + Seek>(
+    archive: &mut ZipArchive<R>,
+    map: &DepotPathMap,
+    destination: Option<W>,
+    codec: Option<&dyn Codec>,
+) -> Result<Vec<RefactorChange>> {
+    let mut entries = archive.get_entries().values().cloned().collect::<Vec<_>>();
+    entries.sort_by_key(|entry| entry.hash);
+    let moves = plan_moves(
+        entries.iter().filter_map(|e| e.name()).map(|n| n.as_str()),
+        map,
+    )?;
+
+    let mut changes = vec![];
+    // new hash -> old hash, path and rewritten file
+    let mut resources = HashMap::new();
+    for entry in entries {
+        let hash = entry.hash;
+        let name = entry.name().cloned();
+        let file = name.clone().unwrap_or_else(|| hash.to_string());
+        let new_name = name.as_ref().and_then(|name| moves.get(name)).cloned();
+        if let Some(new_name) = new_name.as_ref() {
+            changes.push(RefactorChange::Move {
+                from: file.clone(),
+                to: new_name.clone(),
+            });
+        }
+        let mut buffer = vec![];
+        archive.open_entry(entry, &mut buffer)?;
+        let rewritten = rewrite(&file, &buffer, map, &mut changes)?;
+
+        let path = new_name.or(name);
+        let new_hash = path
+            .as_ref()
+            .map_or(hash, |path| ResourceHash::from_depot_path(path).0);
+        if resources
+            .insert(new_hash, (hash, path, rewritten))
+            .is_some()
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} is moved onto another resource", file),
+            ));
+        }
+    }
+    let Some(destination) = destination else {
+        return Ok(changes);
+    };
+
+    let mut hashes = resources.keys().copied().collect::<Vec<_>>();
+    hashes.sort();
+    let extensions = hashes
+        .iter()
+        .map(|hash| {
+            let extension = resources[hash]
+                .1
+                .as_ref()
+                .and_then(|path| Path::new(path).extension())
+                .map(|e| e.to_string_lossy().to_string())
+                .unwrap_or_default();
+            (*hash, extension)
+        })
+        .collect::<Vec<_>>();
+    let custom_paths = hashes
+        .iter()
+        .filter_map(|hash| resources[hash].1.clone())
+        .collect();
+
+    let default_codec = KrakenCodec::default();
+    let codec = codec.unwrap_or(&default_codec);
+    write_resources(destination, &extensions, custom_paths, codec, |hash| {
+        let (old_hash, _, rewritten) = resources.remove(&hash).unwrap_or_default();
+        if let Some(rewritten) = rewritten {
+            return Ok(rewritten);
+        }
+        let Some(entry) = archive.get_entry_by_hash(&old_hash).cloned() else {
+            return Err(Error::new(ErrorKind::InvalidData, "Could not find entry."));
+        };
+        let mut buffer = vec![];
+        archive.open_entry(entry, &mut buffer)?;
+        Ok(buffer)
+    })?;
+    Ok(changes)
+}
+
+/// New depot paths of the resources that move
+fn plan_moves<'a>(
+    paths: impl Iterator<Item = &'a str>,
+    map: &DepotPathMap,
+) -> Result<HashMap<String, String>> {
+    let paths = paths.collect::<Vec<_>>();
+    let mut moves = HashMap::new();
+    for path in paths.iter() {
+        if let Some(new_path) = map.map(path) {
+            moves.insert(path.to_string(), new_path);
+        }
+    }
+
+    let mut targets = HashSet::new();
+    for path in paths {
+        let target = moves.get(path).map_or(path, |p| p.as_str());
+        if !targets.insert(normalize_depot_path(target)) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} is moved onto another resource", target),
+            ));
+        }
+    }
+    Ok(moves)
+}
+
+/// Rewrites the imports and names of a CR2W file, `None` if it isn't one or nothing changed
+fn rewrite(
+    file: &str,
+    buffer: &[u8],
+    map: &DepotPathMap,
+    changes: &mut Vec<RefactorChange>,
+) -> Result<Option<Vec<u8>>> {
+    if !buffer.starts_with(b"CR2W") {
+        return Ok(None);
+    }
+    let mut cr2w: CR2WFile = read_cr2w(&mut Cursor::new(buffer))?;
+
+    let count = changes.len();
+    for (index, import) in cr2w.info.imports.iter_mut().enumerate() {
+        if let Some(to) = map.map(&import.depot_path) {
+            changes.push(RefactorChange::Import {
+                file: file.to_owned(),
+                index,
+                from: std::mem::replace(&mut import.depot_path, to.clone()),
+                to,
+            });
+        }
+    }
+    for (index, name) in cr2w.info.names.iter_mut().enumerate() {
+        if let Some(to) = map.map(name) {
+            changes.push(RefactorChange::Name {
+                file: file.to_owned(),
+                index,
+                from: std::mem::replace(name, to.clone()),
+                to,
+            });
+        }
+    }
+    if changes.len() == count {
+        return Ok(None);
+    }
+
+    let mut output = vec![];
+    write_cr2w(&mut output, &cr2w)?;
+    Ok(Some(output))
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// TESTS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::{
+        archive::open_read_stream,
+        cr2w::{validate_cr2w, ImportFlags},
+        fixtures::{self, template},
+    };
+
+    /// The number of names of the template file
+    fn base_names() -> usize {
+        template().info.names.len()
+    }
+
+    /// a.ent -> old\b.app, old\c.mesh; old\b.app -> old\c.mesh; old\c.mesh
+    fn project() -> Vec<(&'static str, Vec<u8>)> {
+        vec![
+            (
+                "base\\a.ent",
+                fixtures::resource(&[
+                    ("base\\old\\b.app", ImportFlags::DEFAULT),
+                    ("base\\old\\c.mesh", ImportFlags::DEFAULT),
+                ]),
+            ),
+            (
+                "base\\old\\b.app",
+                fixtures::named_resource(
+                    &[("base\\old\\c.mesh", ImportFlags::DEFAULT)],
+                    "base\\old\\c.mesh",
+                ),
+            ),
+            (
+                "base\\old\\c.mesh",
+                fixtures::resource(&[("base\\other.mi", ImportFlags::DEFAULT)]),
+            ),
+        ]
+    }
+
+    fn map() -> DepotPathMap {
+        let mut map = DepotPathMap::new();
+        map.insert("base/old/", "base\\new");
+        map.insert("base\\old\\c.mesh", "base\\meshes\\c.mesh");
+        map
+    }
+
+    fn imports(buffer: &[u8]) -> Vec<String> {
+        let file = read_cr2w(&mut Cursor::new(buffer)).unwrap();
+        assert!(validate_cr2w(&mut Cursor::new(buffer)).unwrap().is_empty());
+        file.info
+            .imports
+            .into_iter()
+            .map(|i| i.depot_path)
+            .collect()
+    }
+
+    #[test]
+    fn mapping() {
+        let map = map();
+        assert_eq!(2, map.len());
+        assert_eq!(Some("base\\new".to_owned()), map.map("base\\old"));
+        assert_eq!(
+            Some("base\\new\\x\\y.mi".to_owned()),
+            map.map("base\\old\\x\\y.mi")
+        );
+        assert_eq!(
+            Some("base\\meshes\\c.mesh".to_owned()),
+            map.map("base\\old\\c.mesh")
+        );
+        assert_eq!(
+            Some("base\\new\\x\\y.mi".to_owned()),
+            map.map("Base/Old/X/Y.mi")
+        );
+        assert_eq!(None, map.map("base\\older\\b.app"));
+        assert_eq!(None, map.map("base\\a.ent"));
+    }
+
+    #[test]
+    fn folder() {
+        let dst_path = PathBuf::from("tests").join("out_refactor");
+        if dst_path.exists() {
+            fs::remove_dir_all(&dst_path).unwrap();
+        }
+        for (path, buffer) in project() {
+            let file = dst_path.join(relative_path_from_depot(path).unwrap());
+            create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(file, buffer).unwrap();
+        }
+
+        let changes = refactor_folder(&dst_path, &map(), true).unwrap();
+        let mut report = changes.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        report.sort();
+        let name = format!(
+            "base\\old\\b.app: name {}: base\\old\\c.mesh -> base\\meshes\\c.mesh",
+            base_names()
+        );
+        assert_eq!(
+            vec![
+                "base\\a.ent: import 0: base\\old\\b.app -> base\\new\\b.app",
+                "base\\a.ent: import 1: base\\old\\c.mesh -> base\\meshes\\c.mesh",
+                "base\\old\\b.app: import 0: base\\old\\c.mesh -> base\\meshes\\c.mesh",
+                name.as_str(),
+                "move base\\old\\b.app -> base\\new\\b.app",
+                "move base\\old\\c.mesh -> base\\meshes\\c.mesh",
+            ],
+            report
+        );
+        assert!(dst_path.join("base").join("old").join("b.app").exists());
+
+        refactor_folder(&dst_path, &map(), false).unwrap();
+        let read =
+            |path: &str| fs::read(dst_path.join(relative_path_from_depot(path).unwrap())).unwrap();
+        assert!(!dst_path.join("base").join("old").join("b.app").exists());
+        assert_eq!(
+            vec!["base\\new\\b.app", "base\\meshes\\c.mesh"],
+            imports(&read("base\\a.ent"))
+        );
+        assert_eq!(
+            vec!["base\\meshes\\c.mesh"],
+            imports(&read("base\\new\\b.app"))
+        );
+        // unchanged files are copied as is
+        assert_eq!(project()[2].1, read("base\\meshes\\c.mesh"));
+        assert!(refactor_folder(&dst_path, &map(), false)
+            .unwrap()
+            .is_empty());
+
+        // resources can move to where another one was
+        let mut swap = DepotPathMap::new();
+        swap.insert("base\\a.ent", "base\\new\\b.app");
+        swap.insert("base\\new\\b.app", "base\\a.ent");
+        refactor_folder(&dst_path, &swap, false).unwrap();
+        assert_eq!(vec!["base\\meshes\\c.mesh"], imports(&read("base\\a.ent")));
+        assert_eq!(2, imports(&read("base\\new\\b.app")).len());
+
+        // depot paths outside of the folder are rejected before anything is written
+        let mut outside = DepotPathMap::new();
+        outside.insert("base\\a.ent", "..\\a.ent");
+        assert!(refactor_folder(&dst_path, &outside, true).is_err());
+        assert!(refactor_folder(&dst_path, &outside, false).is_err());
+        assert!(dst_path.join("base").join("a.ent").exists());
+
+        fs::remove_dir_all(&dst_path).unwrap();
+    }
+
+    #[test]
+    fn archive() {
+        let mut archive = fixtures::archive(project());
+
+        let changes =
+            refactor_archive(&mut archive, &map(), None::<Cursor<Vec<u8>>>, None).unwrap();
+        assert_eq!(6, changes.len());
+        assert!(changes.contains(&RefactorChange::Name {
+            file: "base\\old\\b.app".to_owned(),
+            index: base_names(),
+            from: "base\\old\\c.mesh".to_owned(),
+            to: "base\\meshes\\c.mesh".to_owned(),
+        }));
+
+        let mut output = Cursor::new(vec![]);
+        refactor_archive(&mut archive, &map(), Some(&mut output), None).unwrap();
+        let mut refactored = open_read_stream(Cursor::new(output.into_inner())).unwrap();
+        let mut read = |path: &str| {
+            let entry = refactored.get_entry(path).unwrap().clone();
+            let mut buffer = vec![];
+            refactored.open_entry(entry, &mut buffer).unwrap();
+            buffer
+        };
+        assert_eq!(
+            vec!["base\\new\\b.app", "base\\meshes\\c.mesh"],
+            imports(&read("base\\a.ent"))
+        );
+        assert_eq!(
+            vec!["base\\meshes\\c.mesh"],
+            imports(&read("base\\new\\b.app"))
+        );
+        assert_eq!(project()[2].1, read("base\\meshes\\c.mesh"));
+        assert!(refactored.get_entry("base\\old\\b.app").is_none());
+
+        // a resource can't move onto another one
+        let mut map = map();
+        map.insert("base\\old\\b.app", "base\\a.ent");
+        assert!(refactor_archive(&mut archive, &map, None::<Cursor<Vec<u8>>>, None).is_err());
+    }
+}
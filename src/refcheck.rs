@@ -0,0 +1,318 @@
+/////////////////////////////////////////////////////////////////////////////////////////
+// REFERENCE CHECK
+// Finds imports of CR2W resources that point at depot paths which exist nowhere, e.g.
+// after a typo or a renamed file. Imports resolve against the checked mod itself and
+// the archives added to the checker (the game and other mods). Embedded imports
+// resolve against the embeds table of the importing file.
+/////////////////////////////////////////////////////////////////////////////////////////
+
+use std::{
+    collections::HashSet,
+    fs,
+    io::{Cursor, Read, Result, Seek},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    archive::{collect_resource_files, get_game_archives, get_mod_archives, open_read, ZipArchive},
+    cr2w::{read_cr2w_header, CR2WFileInfo, Import, ImportFlags},
+    depot_path_from_relative,
+    hash::ResourceHash,
+};
+
+/// An import that doesn't resolve to any known resource
+#[derive(Debug, Clone)]
+pub struct BrokenReference {
+    /// Depot path of the importing resource, or its hash if the archive has no name for it
+    pub file: String,
+    /// Index into the imports of the importing resource
+    pub import_index: usize,
+    pub import: Import,
+}
+
+impl BrokenReference {
+    /// Hard imports are loaded with the resource, soft imports only on demand
+    pub fn is_hard(&self) -> bool {
+        !self.import.flags.contains(ImportFlags::SOFT)
+    }
+}
+
+/// Resolves imports against a set of known resources
+#[derive(Debug, Clone, Default)]
+pub struct ReferenceChecker {
+    /// Hashes of the depot paths of all added resources
+    known: HashSet<u64>,
+}
+
+impl ReferenceChecker {
+    /// Creates a checker that knows no resources
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a checker that knows the resources of the game and all installed archive mods
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any archive can't be read.
+    pub fn for_game<P: AsRef<Path>>(game_folder: &P) -> Result<Self> {
+        let mut checker = Self::new();
+        checker.add_archive_paths(&get_game_archives(game_folder))?;
+        checker.add_archive_paths(&get_mod_archives(game_folder)?)?;
+        Ok(checker)
+    }
+
+    pub fn len(&self) -> usize {
+        self.known.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.known.is_empty()
+    }
+
+    pub fn contains(&self, hash: &u64) -> bool {
+        self.known.contains(hash)
+    }
+
+    /// Adds the resources of an archive
+    pub fn add_archive<R>(&mut self, archive: &ZipArchive<R>) {
+        self.known.extend(archive.get_entries().keys());
+    }
+
+    /// Adds the resources of archive files
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any archive can't be read.
+    pub fn add_archive_paths<P: AsRef<Path>>(&mut self, archive_paths: &[P]) -> Result<()> {
+        for path in archive_paths {
+            let archive = open_read(path)?;
+            self.add_archive(&archive);
+        }
+        Ok(())
+    }
+
+    /// Adds the resources of a loose folder, relative file paths are the depot paths
+    pub fn add_folder<P: AsRef<Path>>(&mut self, folder: &P) {
+        self.known.extend(
+            folder_resources(folder)
+                .iter()
+                .map(|(path, _)| ResourceHash::from_depot_path(path).0),
+        );
+    }
+
+    /// Checks the imports of every CR2W resource in an archive, the archive's own resources resolve
+    ///
+    /// Broken references are ranked hard imports first, then by file and import index.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a resource can't be read from the archive.
+    pub fn check_archive<R: Read + Seek>(
+        &self,
+        archive: &mut ZipArchive<R>,
+    ) -> Result<Vec<BrokenReference>> {
+        let own = archive
+            .get_entries()
+            .keys()
+            .copied()
+            .collect::<HashSet<_>>();
+        let mut entries = archive.get_entries().values().cloned().collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.hash);
+
+        let mut broken = vec![];
+        for entry in entries {
+            let file = entry
+                .name()
+                .cloned()
+                .unwrap_or_else(|| entry.hash.to_string());
+            let mut buffer = vec![];
+            archive.open_entry(entry, &mut buffer)?;
+            if let Ok(info) = read_cr2w_header(&mut Cursor::new(&buffer)) {
+                self.check_resource(&file, &info, &own, &mut broken);
+            }
+        }
+        rank(&mut broken);
+        Ok(broken)
+    }
+
+    /// Checks the imports of every CR2W resource in a loose folder, the folder's own resources resolve
+    ///
+    /// Broken references are ranked hard imports first, then by file and import index.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a file can't be read.
+    pub fn check_folder<P: AsRef<Path>>(&self, folder: &P) -> Result<Vec<BrokenReference>> {
+        let resources = folder_resources(folder);
+        let own = resources
+            .iter()
+            .map(|(path, _)| ResourceHash::from_depot_path(path).0)
+            .collect::<HashSet<_>>();
+
+        let mut broken = vec![];
+        for (path, file_path) in resources {
+            let buffer = fs::read(file_path)?;
+            if let Ok(info) = read_cr2w_header(&mut Cursor::new(&buffer)) {
+                self.check_resource(&path, &info, &own, &mut broken);
+            }
+        }
+        rank(&mut broken);
+        Ok(broken)
+    }
+
+    fn check_resource(
+        &self,
+        file: &str,
+        info: &CR2WFileInfo,
+        own: &HashSet<u64>,
+        broken: &mut Vec<BrokenReference>,
+    ) {
+        for (index, import) in info.imports.iter().enumerate() {
+            let resolved = if import.flags.contains(ImportFlags::EMBEDDED) {
+                // import indices are 1-based
+                info.embeds_table
+                    .iter()
+                    .any(|embed| embed.import_index as usize == index + 1)
+            } else {
+                let hash = ResourceHash::from_depot_path(&import.depot_path).0;
+                own.contains(&hash) || self.known.contains(&hash)
+            };
+            if !resolved {
+                broken.push(BrokenReference {
+                    file: file.to_owned(),
+                    import_index: index,
+                    import: import.clone(),
+                });
+            }
+        }
+    }
+}
+
+/// Depot paths and file paths of the resources in a loose folder
+pub(crate) fn folder_resources<P: AsRef<Path>>(folder: &P) -> Vec<(String, PathBuf)> {
+    collect_resource_files(folder)
+        .into_iter()
+        .filter_map(|file| {
+            let relative_path = file.strip_prefix(folder).ok()?;
+            Some((depot_path_from_relative(relative_path), file.clone()))
+        })
+        .collect()
+}
+
+fn rank(broken: &mut [BrokenReference]) {
+    broken.sort_by(|a, b| {
+        b.is_hard()
+            .cmp(&a.is_hard())
+            .then_with(|| a.file.cmp(&b.file))
+            .then_with(|| a.import_index.cmp(&b.import_index))
+    });
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// TESTS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{archive, resource};
+
+    /// a.ent -> b.app (mod), c.mesh (game), d.xbm (missing), e.mesh (soft, missing), a.ent#0 (embedded)
+    /// b.app -> x.mi (missing)
+    fn mod_resources() -> Vec<(&'static str, Vec<u8>)> {
+        vec![
+            (
+                "base\\a.ent",
+                resource(&[
+                    ("base\\e.mesh", ImportFlags::SOFT),
+                    // resolves like the game, regardless of case and separators
+                    ("Base/B.app", ImportFlags::DEFAULT),
+                    ("base\\c.mesh", ImportFlags::DEFAULT),
+                    ("base\\d.xbm", ImportFlags::DEFAULT),
+                    ("base\\a.ent#0", ImportFlags::EMBEDDED),
+                ]),
+            ),
+            (
+                "base\\b.app",
+                resource(&[("base\\x.mi", ImportFlags::OBLIGATORY)]),
+            ),
+        ]
+    }
+
+    fn checker() -> ReferenceChecker {
+        let game = archive(vec![("base\\c.mesh", resource(&[]))]);
+        let mut checker = ReferenceChecker::new();
+        checker.add_archive(&game);
+        checker
+    }
+
+    fn summary(broken: &[BrokenReference]) -> Vec<(&str, usize, &str, bool)> {
+        broken
+            .iter()
+            .map(|r| {
+                (
+                    r.file.as_str(),
+                    r.import_index,
+                    r.import.depot_path.as_str(),
+                    r.is_hard(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn check_archive() {
+        let checker = checker();
+        assert_eq!(1, checker.len());
+
+        let mut archive = archive(mod_resources());
+        let broken = checker.check_archive(&mut archive).unwrap();
+        assert_eq!(
+            vec![
+                ("base\\a.ent", 3, "base\\d.xbm", true),
+                ("base\\b.app", 0, "base\\x.mi", true),
+                ("base\\a.ent", 0, "base\\e.mesh", false),
+            ],
+            summary(&broken)
+        );
+        assert_eq!(ImportFlags::OBLIGATORY, broken[1].import.flags);
+
+        // without the game, its resources don't resolve
+        let broken = ReferenceChecker::new().check_archive(&mut archive).unwrap();
+        assert_eq!(4, broken.len());
+    }
+
+    #[test]
+    fn check_folder() {
+        let dst_path = PathBuf::from("tests").join("out_refcheck");
+        if dst_path.exists() {
+            fs::remove_dir_all(&dst_path).unwrap();
+        }
+        for (path, buffer) in mod_resources() {
+            let file = dst_path.join(path.replace('\\', "/"));
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(file, buffer).unwrap();
+        }
+
+        let mut checker = checker();
+        let broken = checker.check_folder(&dst_path).unwrap();
+        assert_eq!(
+            vec![
+                ("base\\a.ent", 3, "base\\d.xbm", true),
+                ("base\\b.app", 0, "base\\x.mi", true),
+                ("base\\a.ent", 0, "base\\e.mesh", false),
+            ],
+            summary(&broken)
+        );
+
+        // other mods resolve too
+        checker.add_folder(&dst_path);
+        let other = archive(vec![("base\\x.mi", vec![0; 4])]);
+        checker.add_archive(&other);
+        let broken = checker.check_folder(&dst_path).unwrap();
+        assert_eq!(2, broken.len());
+
+        fs::remove_dir_all(&dst_path).unwrap();
+    }
+}
@@ -0,0 +1,673 @@
+/////////////////////////////////////////////////////////////////////////////////////////
+// SEARCH INDEX
+// Metadata of the resources of archives for repeated searches without extracting: the
+// resolved name, the root class, import depot paths and the string table of CR2W files.
+// Strings are pooled, a search matches every unique string once. The size and
+// modification time of each archive are stored to detect a stale index.
+//
+// Index file layout:
+// u32 magic, u32 version
+// u32 count, archives: path, u64 size, u64 modification time in nanoseconds
+// u32 count, strings
+// u32 count, entries: u64 hash, u32 archive, u32 name, u32 root class,
+//                     u32 count, import strings, u32 count, string table strings
+// Strings are null-terminated, a missing name or root class is u32::MAX.
+/////////////////////////////////////////////////////////////////////////////////////////
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufReader, BufWriter, Cursor, Error, ErrorKind, Read, Result, Seek, Write},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use regex::Regex;
+
+use crate::{
+    archive::{open_read, ZipArchive},
+    cr2w::read_cr2w_header,
+    hashdb::HashDb,
+    io::{read_null_terminated_string, write_null_terminated_string, FromReader},
+    query::glob_match,
+};
+
+const NONE: u32 = u32::MAX;
+
+/// A resource of the index, strings are indices into the pool of the index
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SearchEntry {
+    hash: u64,
+    archive: u32,
+    name: u32,
+    root_class: u32,
+    imports: Vec<u32>,
+    strings: Vec<u32>,
+}
+
+/// Size and modification time of an archive file when it was indexed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct ArchiveStamp {
+    size: u64,
+    /// Nanoseconds since the unix epoch
+    modified: u64,
+}
+
+impl ArchiveStamp {
+    /// The stamp of an archive file, `None` if the file can't be read
+    fn of<P: AsRef<Path>>(path: &P) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |duration| duration.as_nanos() as u64);
+        Some(ArchiveStamp {
+            size: metadata.len(),
+            modified,
+        })
+    }
+}
+
+/// A resource that matches a search
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchHit<'a> {
+    pub archive: &'a Path,
+    pub hash: u64,
+    /// Resolved name of the resource
+    pub name: Option<&'a str>,
+    /// Class of the first root chunk of a CR2W file
+    pub root_class: Option<&'a str>,
+}
+
+#[derive(Debug, Clone)]
+enum Pattern {
+    Glob(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    fn is_match(&self, text: &str) -> bool {
+        match self {
+            Pattern::Glob(pattern) => glob_match(pattern, text),
+            Pattern::Regex(regex) => regex.is_match(text),
+        }
+    }
+}
+
+/// Criteria of a search, a resource must match all of them
+#[derive(Debug, Clone, Default)]
+pub struct SearchQuery {
+    name: Option<Pattern>,
+    extension: Option<String>,
+    root_class: Option<Pattern>,
+    import: Option<Pattern>,
+    string: Option<String>,
+}
+
+impl SearchQuery {
+    /// Creates a query that matches every resource
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The resolved name matches a glob pattern, `*` matches any characters and `?` one character
+    pub fn name(mut self, pattern: &str) -> Self {
+        self.name = Some(Pattern::Glob(pattern.to_owned()));
+        self
+    }
+
+    /// The resolved name matches a regular expression
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the regular expression is invalid.
+    pub fn name_regex(mut self, pattern: &str) -> Result<Self> {
+        let regex = Regex::new(pattern).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        self.name = Some(Pattern::Regex(regex));
+        Ok(self)
+    }
+
+    /// The resolved name has an extension, without the dot and case-insensitive
+    pub fn extension(mut self, extension: &str) -> Self {
+        self.extension = Some(extension.trim_start_matches('.').to_ascii_lowercase());
+        self
+    }
+
+    /// The root class matches a glob pattern
+    pub fn root_class(mut self, pattern: &str) -> Self {
+        self.root_class = Some(Pattern::Glob(pattern.to_owned()));
+        self
+    }
+
+    /// Any import depot path matches a glob pattern
+    pub fn import(mut self, pattern: &str) -> Self {
+        self.import = Some(Pattern::Glob(pattern.to_owned()));
+        self
+    }
+
+    /// Any string of the string table contains a text, case-insensitive
+    ///
+    /// The string table holds the names, import depot paths and other strings of a CR2W file.
+    pub fn string(mut self, text: &str) -> Self {
+        self.string = Some(text.to_lowercase());
+        self
+    }
+}
+
+/// A prebuilt index of the resources of archives
+#[derive(Debug, Clone, Default)]
+pub struct SearchIndex {
+    archives: Vec<PathBuf>,
+    stamps: Vec<ArchiveStamp>,
+    strings: Vec<String>,
+    entries: Vec<SearchEntry>,
+    // not serialized
+    lookup: HashMap<String, u32>,
+}
+
+impl SearchIndex {
+    const MAGIC: u32 = 0x58495352; // RSIX
+    const VERSION: u32 = 2;
+
+    /// Creates an empty index
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of indexed resources
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Paths of the indexed archives
+    pub fn archives(&self) -> &[PathBuf] {
+        &self.archives
+    }
+
+    /// Paths of the indexed archives that changed since they were indexed, or that no longer exist
+    pub fn stale_archives(&self) -> Vec<&Path> {
+        self.archives
+            .iter()
+            .zip(self.stamps.iter())
+            .filter(|(path, stamp)| ArchiveStamp::of(path) != Some(**stamp))
+            .map(|(path, _)| path.as_path())
+            .collect()
+    }
+
+    /// Adds the resources of an archive, names are resolved with the archive's LXRS table and `hash_db`
+    ///
+    /// The size and modification time of the file at `archive_path` are stored with the index, an
+    /// archive that isn't read from that file is always stale, see [`SearchIndex::stale_archives`].
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a resource can't be read from the archive.
+    pub fn add_archive<R: Read + Seek, P: AsRef<Path>>(
+        &mut self,
+        archive_path: &P,
+        archive: &mut ZipArchive<R>,
+        hash_db: &HashDb,
+    ) -> Result<usize> {
+        let archive_index = self.archives.len() as u32;
+        self.archives.push(archive_path.as_ref().to_path_buf());
+        self.stamps
+            .push(ArchiveStamp::of(archive_path).unwrap_or_default());
+
+        let mut entries = archive.get_entries().values().cloned().collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.hash);
+        let count = entries.len();
+        for entry in entries {
+            let hash = entry.hash;
+            let name = entry
+                .name()
+                .cloned()
+                .or_else(|| hash_db.get_path(&hash).map(|p| p.to_owned()));
+            let mut search_entry = SearchEntry {
+                hash,
+                archive: archive_index,
+                name: name.map_or(NONE, |name| self.intern(&name)),
+                root_class: NONE,
+                imports: vec![],
+                strings: vec![],
+            };
+
+            let mut buffer = vec![];
+            archive.open_entry(entry, &mut buffer)?;
+            if let Ok(info) = read_cr2w_header(&mut Cursor::new(&buffer)) {
+                search_entry.root_class = info
+                    .exports_table
+                    .iter()
+                    .find(|export| export.parent_id == 0)
+                    .and_then(|export| info.names.get(export.class_name as usize))
+                    .map_or(NONE, |class| self.intern(class));
+                search_entry.imports = info
+                    .imports
+                    .iter()
+                    .map(|import| self.intern(&import.depot_path))
+                    .collect();
+                let mut strings = info.strings.iter().collect::<Vec<_>>();
+                strings.sort_by_key(|(offset, _)| **offset);
+                search_entry.strings = strings
+                    .into_iter()
+                    .map(|(_, string)| self.intern(string))
+                    .collect();
+            }
+            self.entries.push(search_entry);
+        }
+        Ok(count)
+    }
+
+    /// Finds the resources that match a query, in the order they were indexed
+    pub fn search(&self, query: &SearchQuery) -> Vec<SearchHit<'_>> {
+        // match every unique string once
+        let matches = |filter: &dyn Fn(&str) -> bool| {
+            self.strings.iter().map(|s| filter(s)).collect::<Vec<_>>()
+        };
+        let names = query.name.as_ref().map(|p| matches(&|s| p.is_match(s)));
+        let extensions = query.extension.as_ref().map(|extension| {
+            matches(&|s| {
+                s.rsplit(['\\', '/'])
+                    .next()
+                    .and_then(|name| name.rsplit_once('.'))
+                    .is_some_and(|(_, e)| e.eq_ignore_ascii_case(extension))
+            })
+        });
+        let classes = query
+            .root_class
+            .as_ref()
+            .map(|p| matches(&|s| p.is_match(s)));
+        let imports = query.import.as_ref().map(|p| matches(&|s| p.is_match(s)));
+        let strings = query
+            .string
+            .as_ref()
+            .map(|text| matches(&|s| s.to_lowercase().contains(text.as_str())));
+
+        let one = |matches: &Option<Vec<bool>>, id: u32| {
+            matches
+                .as_ref()
+                .is_none_or(|m| id != NONE && m[id as usize])
+        };
+        let any = |matches: &Option<Vec<bool>>, ids: &[u32]| {
+            matches
+                .as_ref()
+                .is_none_or(|m| ids.iter().any(|id| m[*id as usize]))
+        };
+        self.entries
+            .iter()
+            .filter(|entry| {
+                one(&names, entry.name)
+                    && one(&extensions, entry.name)
+                    && one(&classes, entry.root_class)
+                    && any(&imports, &entry.imports)
+                    && any(&strings, &entry.strings)
+            })
+            .map(|entry| SearchHit {
+                archive: &self.archives[entry.archive as usize],
+                hash: entry.hash,
+                name: self.string(entry.name),
+                root_class: self.string(entry.root_class),
+            })
+            .collect()
+    }
+
+    /// Writes the index to a stream
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any io fails.
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_u32::<LittleEndian>(SearchIndex::MAGIC)?;
+        writer.write_u32::<LittleEndian>(SearchIndex::VERSION)?;
+
+        writer.write_u32::<LittleEndian>(self.archives.len() as u32)?;
+        for (path, stamp) in self.archives.iter().zip(self.stamps.iter()) {
+            write_null_terminated_string(writer, path.to_string_lossy().to_string())?;
+            writer.write_u64::<LittleEndian>(stamp.size)?;
+            writer.write_u64::<LittleEndian>(stamp.modified)?;
+        }
+        writer.write_u32::<LittleEndian>(self.strings.len() as u32)?;
+        for string in self.strings.iter() {
+            write_null_terminated_string(writer, string.to_owned())?;
+        }
+
+        writer.write_u32::<LittleEndian>(self.entries.len() as u32)?;
+        for entry in self.entries.iter() {
+            writer.write_u64::<LittleEndian>(entry.hash)?;
+            writer.write_u32::<LittleEndian>(entry.archive)?;
+            writer.write_u32::<LittleEndian>(entry.name)?;
+            writer.write_u32::<LittleEndian>(entry.root_class)?;
+            for ids in [&entry.imports, &entry.strings] {
+                writer.write_u32::<LittleEndian>(ids.len() as u32)?;
+                for id in ids.iter() {
+                    writer.write_u32::<LittleEndian>(*id)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Saves the index to a file
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any io fails.
+    pub fn save<P: AsRef<Path>>(&self, path: &P) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Loads an index from a file
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file can't be read, is not an index file or any
+    /// indexed archive changed since the index was built.
+    pub fn load<P: AsRef<Path>>(path: &P) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let index = SearchIndex::from_reader(&mut reader)?;
+        if let Some(stale) = index.stale_archives().first() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{} changed since the index was built", stale.display()),
+            ));
+        }
+        Ok(index)
+    }
+
+    /// Loads the index of archives from a file, or builds and saves it if the file is missing,
+    /// unreadable, stale or indexes other archives
+    ///
+    /// Resource names are resolved with `hash_db`, or the global hash database if none is given.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if any archive can't be read or the index can't be saved.
+    pub fn load_or_build<P: AsRef<Path>, Q: AsRef<Path>>(
+        path: &P,
+        archive_paths: &[Q],
+        hash_db: Option<&HashDb>,
+    ) -> Result<Self> {
+        if let Ok(index) = SearchIndex::load(path) {
+            if index
+                .archives
+                .iter()
+                .eq(archive_paths.iter().map(|p| p.as_ref()))
+            {
+                return Ok(index);
+            }
+        }
+        let index = build_search_index(archive_paths, hash_db)?;
+        index.save(path)?;
+        Ok(index)
+    }
+
+    fn intern(&mut self, string: &str) -> u32 {
+        if let Some(id) = self.lookup.get(string) {
+            return *id;
+        }
+        let id = self.strings.len() as u32;
+        self.strings.push(string.to_owned());
+        self.lookup.insert(string.to_owned(), id);
+        id
+    }
+
+    fn string(&self, id: u32) -> Option<&str> {
+        self.strings.get(id as usize).map(|s| s.as_str())
+    }
+}
+
+impl FromReader for SearchIndex {
+    fn from_reader<R: Read>(reader: &mut R) -> Result<Self> {
+        let magic = reader.read_u32::<LittleEndian>()?;
+        if magic != SearchIndex::MAGIC {
+            return Err(Error::other("invalid magic"));
+        }
+        let version = reader.read_u32::<LittleEndian>()?;
+        if version != SearchIndex::VERSION {
+            return Err(Error::new(ErrorKind::InvalidData, "unsupported version"));
+        }
+
+        let mut index = SearchIndex::new();
+        let count = reader.read_u32::<LittleEndian>()?;
+        for _i in 0..count {
+            index
+                .archives
+                .push(PathBuf::from(read_null_terminated_string(reader)?));
+            index.stamps.push(ArchiveStamp {
+                size: reader.read_u64::<LittleEndian>()?,
+                modified: reader.read_u64::<LittleEndian>()?,
+            });
+        }
+        let count = reader.read_u32::<LittleEndian>()?;
+        for _i in 0..count {
+            let string = read_null_terminated_string(reader)?;
+            index
+                .lookup
+                .insert(string.clone(), index.strings.len() as u32);
+            index.strings.push(string);
+        }
+
+        let invalid = || Error::new(ErrorKind::InvalidData, "string or archive out of range");
+        let string_count = index.strings.len() as u32;
+        let check = |id: u32, optional: bool| match id < string_count || (optional && id == NONE) {
+            true => Ok(id),
+            false => Err(invalid()),
+        };
+        let count = reader.read_u32::<LittleEndian>()?;
+        for _i in 0..count {
+            let hash = reader.read_u64::<LittleEndian>()?;
+            let archive = reader.read_u32::<LittleEndian>()?;
+            if archive as usize >= index.archives.len() {
+                return Err(invalid());
+            }
+            let name = check(reader.read_u32::<LittleEndian>()?, true)?;
+            let root_class = check(reader.read_u32::<LittleEndian>()?, true)?;
+            let mut lists = [vec![], vec![]];
+            for ids in lists.iter_mut() {
+                let id_count = reader.read_u32::<LittleEndian>()?;
+                for _j in 0..id_count {
+                    ids.push(check(reader.read_u32::<LittleEndian>()?, false)?);
+                }
+            }
+            let [imports, strings] = lists;
+            index.entries.push(SearchEntry {
+                hash,
+                archive,
+                name,
+                root_class,
+                imports,
+                strings,
+            });
+        }
+
+        Ok(index)
+    }
+}
+
+/// Builds the search index of one or many archives
+///
+/// Resource names are resolved with `hash_db`, or the global hash database if none is given.
+///
+/// # Errors
+///
+/// This function will return an error if any archive can't be read.
+pub fn build_search_index<P: AsRef<Path>>(
+    archive_paths: &[P],
+    hash_db: Option<&HashDb>,
+) -> Result<SearchIndex> {
+    let hash_db = hash_db.unwrap_or_else(|| HashDb::global());
+    let mut index = SearchIndex::new();
+    for path in archive_paths {
+        let mut archive = open_read(path)?;
+        index.add_archive(path, &mut archive, hash_db)?;
+    }
+    Ok(index)
+}
+
+/////////////////////////////////////////////////////////////////////////////////////////
+// TESTS
+/////////////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+
+    use super::*;
+    use crate::{
+        cr2w::ImportFlags,
+        fixtures::{archive, resource},
+    };
+
+    fn archive_paths() -> Vec<PathBuf> {
+        vec![
+            PathBuf::from("tests").join("test1.archive"),
+            PathBuf::from("tests").join("nci.archive"),
+        ]
+    }
+
+    fn index() -> SearchIndex {
+        build_search_index(&archive_paths(), Some(&HashDb::new())).unwrap()
+    }
+
+    fn names<'a>(hits: &[SearchHit<'a>]) -> Vec<Option<&'a str>> {
+        hits.iter().map(|hit| hit.name).collect()
+    }
+
+    #[test]
+    fn search() {
+        let index = index();
+        assert_eq!(archive_paths(), index.archives());
+        assert_eq!(index.len(), index.search(&SearchQuery::new()).len());
+
+        let json = "base\\cycleweapons\\localization\\en-us.json";
+        let hits = index.search(&SearchQuery::new().name("*\\cycleweapons\\*.json"));
+        assert_eq!(vec![Some(json)], names(&hits));
+        assert_eq!(archive_paths()[0], hits[0].archive);
+        assert_eq!(Some("JsonResource"), hits[0].root_class);
+
+        let regex = SearchQuery::new()
+            .name_regex(r"^base\\cycle.*\.JSON$")
+            .unwrap();
+        assert!(index.search(&regex).is_empty());
+        let regex = SearchQuery::new()
+            .name_regex(r"(?i)^base\\cycle.*\.JSON$")
+            .unwrap();
+        assert_eq!(vec![Some(json)], names(&index.search(&regex)));
+        assert!(SearchQuery::new().name_regex("(").is_err());
+
+        let by_extension = index.search(&SearchQuery::new().extension(".JSON"));
+        assert!(by_extension.iter().any(|hit| hit.name == Some(json)));
+        let by_class = index.search(&SearchQuery::new().root_class("Json*"));
+        assert!(by_class
+            .iter()
+            .all(|hit| hit.root_class == Some("JsonResource")));
+        assert!(by_class.iter().any(|hit| hit.name == Some(json)));
+        assert!(index
+            .search(&SearchQuery::new().extension("json").root_class("CMesh"))
+            .is_empty());
+
+        // strings of the string table
+        let by_string = index.search(&SearchQuery::new().string("JSONRESOURCE"));
+        assert_eq!(by_class.len(), by_string.len());
+        assert!(index.search(&SearchQuery::new().import("*")).len() < index.len());
+    }
+
+    #[test]
+    fn string_table() {
+        let mut archive = archive(vec![
+            (
+                "base\\a.ent",
+                resource(&[("base\\meshes\\b.mesh", ImportFlags::DEFAULT)]),
+            ),
+            ("base\\c.ent", resource(&[])),
+        ]);
+        let mut index = SearchIndex::new();
+        index
+            .add_archive(&"test.archive", &mut archive, &HashDb::new())
+            .unwrap();
+
+        // import depot paths are strings of the string table
+        let hits = index.search(&SearchQuery::new().string("MESHES\\B"));
+        assert_eq!(vec![Some("base\\a.ent")], names(&hits));
+        let hits = index.search(&SearchQuery::new().string("jsonresource"));
+        assert_eq!(2, hits.len());
+
+        // the archive isn't read from a file
+        assert_eq!(vec![Path::new("test.archive")], index.stale_archives());
+    }
+
+    #[test]
+    fn save_and_load() {
+        let index = index();
+        assert!(index.stale_archives().is_empty());
+        let dst_path = PathBuf::from("tests").join("out_search");
+        if dst_path.exists() {
+            fs::remove_dir_all(&dst_path).unwrap();
+        }
+        fs::create_dir_all(&dst_path).unwrap();
+        let path = dst_path.join("search.bin");
+        index.save(&path).unwrap();
+        let loaded = SearchIndex::load(&path).unwrap();
+        fs::remove_dir_all(&dst_path).unwrap();
+
+        assert_eq!(index.archives, loaded.archives);
+        assert_eq!(index.stamps, loaded.stamps);
+        assert_eq!(index.strings, loaded.strings);
+        assert_eq!(index.entries, loaded.entries);
+        let query = SearchQuery::new().root_class("Json*");
+        assert_eq!(index.search(&query), loaded.search(&query));
+
+        // truncated and foreign files
+        let mut buffer = vec![];
+        index.write(&mut buffer).unwrap();
+        buffer.truncate(buffer.len() - 1);
+        assert!(SearchIndex::from_reader(&mut Cursor::new(&buffer)).is_err());
+        assert!(SearchIndex::from_reader(&mut Cursor::new(&[0u8; 8])).is_err());
+    }
+
+    #[test]
+    fn stale() {
+        let dst_path = PathBuf::from("tests").join("out_search_stale");
+        if dst_path.exists() {
+            fs::remove_dir_all(&dst_path).unwrap();
+        }
+        fs::create_dir_all(&dst_path).unwrap();
+        let archive_path = dst_path.join("test1.archive");
+        fs::copy(&archive_paths()[0], &archive_path).unwrap();
+        let path = dst_path.join("search.bin");
+        let archives = [&archive_path];
+
+        let index = SearchIndex::load_or_build(&path, &archives, Some(&HashDb::new())).unwrap();
+        assert!(SearchIndex::load(&path).is_ok());
+
+        // the archive changes after it was indexed
+        OpenOptions::new()
+            .append(true)
+            .open(&archive_path)
+            .unwrap()
+            .write_all(&[0])
+            .unwrap();
+        assert_eq!(vec![archive_path.as_path()], index.stale_archives());
+        assert!(SearchIndex::load(&path).is_err());
+
+        // and is indexed again
+        let rebuilt = SearchIndex::load_or_build(&path, &archives, Some(&HashDb::new())).unwrap();
+        assert!(rebuilt.stale_archives().is_empty());
+        assert_ne!(index.stamps, rebuilt.stamps);
+        assert!(SearchIndex::load(&path).is_ok());
+
+        // an index of other archives is rebuilt too
+        let other = SearchIndex::load_or_build(&path, &archive_paths(), Some(&HashDb::new()));
+        assert_eq!(archive_paths(), other.unwrap().archives());
+
+        fs::remove_dir_all(&dst_path).unwrap();
+    }
+}
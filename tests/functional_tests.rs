@@ -9,16 +9,21 @@ mod tests {
     use std::time::Instant;
     use std::{fs, path::PathBuf};
 
+    use red4lib::codec::StoredCodec;
+    use red4lib::hashdb::HashDb;
     use red4lib::*;
 
     #[test]
     fn time_csv() {
         let start = Instant::now();
-        let hashes = get_red4_hashes();
+        let hashes = HashDb::global();
         assert!(!hashes.is_empty());
         let end = Instant::now();
         let duration = end - start;
         println!("Execution time csv: {:?}", duration);
+
+        // the global database is only initialized once
+        assert!(std::ptr::eq(hashes, HashDb::global()));
     }
 
     #[test]
@@ -26,7 +31,7 @@ mod tests {
         let archive_path = PathBuf::from("tests").join("test1.archive");
         let dst_path = PathBuf::from("tests").join("out");
         let data_path = PathBuf::from("tests").join("data");
-        let hashes = get_red4_hashes();
+        let hashes = HashDb::global();
 
         // delete folder if exists
         if dst_path.exists() {
@@ -112,12 +117,32 @@ mod tests {
         }
         create_dir_all(&dst_path).expect("Could not create folder");
 
-        let result = archive::create_from_directory_path(&data_path, &dst_file, None);
+        let result = archive::create_from_directory_path(&data_path, &dst_file, None, None);
         assert!(result.is_ok());
 
         // checks
         assert!(dst_file.exists());
 
+        // the packed files extract to the originals
+        let out_path = dst_path.join("out");
+        let result = archive::extract_to_directory_path::<PathBuf, File>(
+            &dst_file,
+            &out_path,
+            true,
+            Some(HashDb::global()),
+        );
+        assert!(result.is_ok());
+        for file in get_files_in_folder_recursive(&data_path) {
+            let relative_path = file.strip_prefix(&data_path).unwrap();
+            let mut extracted = out_path.join(relative_path);
+            if !extracted.exists() {
+                // unknown names are extracted by hash
+                let hash = fnv1a64_hash_path(relative_path);
+                extracted = out_path.join(format!("{}.bin", hash));
+            }
+            assert_binary_equality(&file, &extracted);
+        }
+
         // TODO binary equality
         // let existing_path = PathBuf::from("tests").join("test1.archive");
         // assert_binary_equality(&existing_path, &created_path);
@@ -128,6 +153,107 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_pack_archive_stored() {
+        let data_path = PathBuf::from("tests").join("data");
+        let dst_path = PathBuf::from("tests").join("out3");
+        let dst_file = dst_path.join("data.archive");
+
+        // delete folder if exists
+        if dst_path.exists() {
+            assert!(fs::remove_dir_all(&dst_path).is_ok());
+        }
+        create_dir_all(&dst_path).expect("Could not create folder");
+
+        let result =
+            archive::create_from_directory_path(&data_path, &dst_file, None, Some(&StoredCodec));
+        assert!(result.is_ok());
+
+        // nothing is compressed, so the archive reads without kraken
+        let out_path = dst_path.join("out");
+        let mut archive =
+            archive::open_with_codec(&dst_file, archive::ArchiveMode::Read, Box::new(StoredCodec))
+                .expect("Could not open archive");
+        let result = archive.extract_to_directory(&out_path, true, Some(HashDb::global()));
+        assert!(result.is_ok());
+        for file in get_files_in_folder_recursive(&data_path) {
+            let relative_path = file.strip_prefix(&data_path).unwrap();
+            let mut extracted = out_path.join(relative_path);
+            if !extracted.exists() {
+                // unknown names are extracted by hash
+                let hash = fnv1a64_hash_path(relative_path);
+                extracted = out_path.join(format!("{}.bin", hash));
+            }
+            assert_binary_equality(&file, &extracted);
+        }
+
+        // cleanup
+        if dst_path.exists() {
+            assert!(fs::remove_dir_all(&dst_path).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_read_cr2w_from_archive() {
+        let archive_path = PathBuf::from("tests").join("test1.archive");
+        let mut archive = archive::open_read(&archive_path).expect("Could not open archive");
+
+        let name = "base\\cycleweapons\\localization\\en-us.json";
+        let file = archive
+            .read_cr2w_by_name(name)
+            .expect("Could not read cr2w");
+        assert_eq!("JsonResource", file.chunks[0].class_name);
+        assert!(file
+            .info
+            .imports
+            .iter()
+            .all(|import| !import.flags.contains(cr2w::ImportFlags::EMBEDDED)));
+
+        // separators and case don't matter
+        let by_other_name = archive
+            .read_cr2w_by_name("Base/CycleWeapons/Localization/en-US.json")
+            .expect("Could not read cr2w");
+        assert_eq!(file.chunks, by_other_name.chunks);
+
+        // the same file by hash
+        let hash = fnv1a64_hash_string(&name.to_owned());
+        let by_hash = archive.read_cr2w(hash).expect("Could not read cr2w");
+        assert_eq!(file.chunks, by_hash.chunks);
+
+        // and as extracted to disk
+        let data = fs::read(
+            PathBuf::from("tests")
+                .join("data")
+                .join(name.replace('\\', "/")),
+        )
+        .expect("Could not read file");
+        let header = cr2w::read_cr2w_header(&mut std::io::Cursor::new(&data)).unwrap();
+        assert_eq!(header.names, file.info.names);
+
+        assert!(archive.read_cr2w(0).is_err());
+    }
+
+    #[test]
+    fn test_diff_archive_entry_against_loose_file() {
+        let archive_path = PathBuf::from("tests").join("test1.archive");
+        let mut archive = archive::open_read(&archive_path).expect("Could not open archive");
+        let mut other = archive::open_read(&archive_path).expect("Could not open archive");
+
+        let name = "base\\cycleweapons\\localization\\en-us.json";
+        let hash = fnv1a64_hash_string(&name.to_owned());
+        let differences = cr2w::diff_archive_entries(&mut archive, hash, &mut other, hash)
+            .expect("Could not diff entries");
+        assert!(differences.is_empty());
+
+        // the packed file against the loose one
+        let loose = PathBuf::from("tests")
+            .join("data")
+            .join(name.replace('\\', "/"));
+        let packed = archive.read_cr2w(hash).expect("Could not read cr2w");
+        let loose = cr2w::read_cr2w(&mut fs::File::open(loose).unwrap()).unwrap();
+        assert!(cr2w::diff_cr2w(&packed, &loose).is_empty());
+    }
+
     /////////////////////////////////////////////////////////////////////////////////////////
     // HELPERS
     /////////////////////////////////////////////////////////////////////////////////////////